@@ -8,6 +8,24 @@ FINAL_BACKENDS="${FINAL_BACKENDS:-http://localhost:11434}"
 PORT="${PORT:-11435}"
 TIMEOUT="${TIMEOUT:-300}"
 
+# Optional per-route-class timeouts
+if [ -n "$GENERATE_TIMEOUT" ]; then
+    set -- --generate-timeout "$GENERATE_TIMEOUT" "$@"
+fi
+if [ -n "$EMBED_TIMEOUT" ]; then
+    set -- --embed-timeout "$EMBED_TIMEOUT" "$@"
+fi
+
+# Name clients after their containers; needs the Docker socket mounted
+case "$DOCKER_NAMES" in
+    1|true|yes) set -- --docker-names "$@" ;;
+esac
+
+# Don't listen before a backend answers, e.g. while Ollama is still starting
+if [ -n "$WAIT_FOR_BACKENDS" ]; then
+    set -- --wait-for-backends "$WAIT_FOR_BACKENDS" "$@"
+fi
+
 echo "Starting ollamaMQ with backends: $FINAL_BACKENDS"
 
 exec /app/ollamaMQ --port "$PORT" --backend-urls "$FINAL_BACKENDS" --timeout "$TIMEOUT" "$@"
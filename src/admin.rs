@@ -0,0 +1,631 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{ConnectInfo, MatchedPath, Path, Query, Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::{BTreeMap, HashMap}, net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::blocked_store::BlockInfo;
+use crate::dispatcher::{AppState, CancelStage, UserStats, normalize_backend_url};
+use crate::experiments::ArmStats;
+use crate::ip::IpPrefix;
+use crate::jail::BlockedAttempt;
+use crate::namespace::in_namespace;
+use crate::timings::Stage;
+use crate::user_ids::UserIdRules;
+
+/// Access level of an admin token; each role includes the ones below it.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminRole {
+    /// Read-only access for dashboards.
+    Viewer,
+    /// Blocking, notes, and cancelling or reordering queued requests.
+    Operator,
+    /// Everything, including state snapshots.
+    Root,
+}
+
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminRole::Viewer => "viewer",
+            AdminRole::Operator => "operator",
+            AdminRole::Root => "root",
+        }
+    }
+}
+
+/// Role needed for a request to an admin route (`path` as registered, e.g. `/admin/queue/{user_id}`).
+fn required_role(method: &Method, path: &str) -> AdminRole {
+    match (method, path) {
+        // Snapshots contain every queued prompt
+        (&Method::POST, "/admin/snapshot") => AdminRole::Root,
+        // Registering a backend decides where prompts are sent
+        (&Method::POST | &Method::DELETE, "/admin/backends") => AdminRole::Root,
+        (&Method::GET, _) => AdminRole::Viewer,
+        _ => AdminRole::Operator,
+    }
+}
+
+/// Routes under `/admin`, guarded by `--admin-token` (root) and the role
+/// tokens from the config file.
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/status", get(status))
+        .route("/admin/queues", get(queues))
+        .route("/admin/namespaces", get(namespaces))
+        .route("/admin/blocks", get(list_blocks))
+        .route("/admin/block", post(block))
+        .route("/admin/unblock", post(unblock))
+        .route("/admin/notes", get(list_notes))
+        .route("/admin/users/{user_id}/note", put(set_note).get(get_note).delete(delete_note))
+        .route("/admin/watched", get(list_watched))
+        .route("/admin/users/{user_id}/watch", put(watch).delete(unwatch))
+        .route("/admin/snapshot", post(crate::snapshot::snapshot_handler))
+        .route("/admin/scale", get(crate::autoscale::scale_handler))
+        .route("/admin/models", get(models))
+        .route("/admin/slos", get(slos))
+        .route("/admin/auth-failures", get(auth_failures))
+        .route("/admin/structured-output", get(structured_output))
+        .route("/admin/timeouts", get(timeouts))
+        .route("/admin/connections", get(connections))
+        .route("/admin/websockets", get(websockets))
+        .route("/admin/config", get(config))
+        .route("/admin/fairness", get(fairness))
+        .route("/admin/forecast", get(forecast))
+        .route("/admin/experiments", get(experiments))
+        .route("/admin/backends", post(add_backend).delete(remove_backend))
+        .merge(crate::queue_api::admin_routes())
+        .route_layer(middleware::from_fn_with_state(state, require_token))
+}
+
+/// Role of the admin token sent as `Authorization: Bearer <token>`, if any.
+pub fn token_role(state: &AppState, headers: &HeaderMap) -> Option<AdminRole> {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))?;
+    if state.config.admin_token.as_deref() == Some(provided) {
+        return Some(AdminRole::Root);
+    }
+    state.config.file.admin_tokens.iter().find(|t| t.token == provided).map(|t| t.role)
+}
+
+/// Who made an admin request, for the block metadata: the token's name, else
+/// its role.
+fn token_label(state: &AppState, headers: &HeaderMap) -> String {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let named = state.config.file.admin_tokens.iter()
+        .find(|t| Some(t.token.as_str()) == provided)
+        .and_then(|t| t.name.clone());
+    named.or_else(|| token_role(state, headers).map(|r| r.as_str().to_string())).unwrap_or_else(|| "admin".to_string())
+}
+
+async fn require_token(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let tokens = &state.config.file.admin_tokens;
+    if state.config.admin_token.is_none() && tokens.is_empty() {
+        return (StatusCode::FORBIDDEN, "Admin API disabled (start with --admin-token)").into_response();
+    }
+    // Guessing the admin token is what bans are mostly for
+    let banned = req.extensions().get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(addr)| state.jail.lock().unwrap().is_banned(&state.block_target(addr.ip())));
+    if banned {
+        return (StatusCode::FORBIDDEN, Extension(BlockedAttempt), "IP blocked").into_response();
+    }
+    let Some(role) = token_role(&state, req.headers()) else {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    };
+    let path = req.extensions().get::<MatchedPath>().map_or(req.uri().path(), |p| p.as_str());
+    let required = required_role(req.method(), path);
+    if role < required {
+        return (
+            StatusCode::FORBIDDEN,
+            format!("Requires the {} role (token has {})", required.as_str(), role.as_str()),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+/// `?namespace=` filter of the status, queue and block listings.
+#[derive(Deserialize)]
+struct NamespaceFilter {
+    namespace: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NoteBody {
+    note: String,
+}
+
+async fn list_notes(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.user_notes.lock().unwrap().clone())
+}
+
+/// A user id from a route path, normalized by the `user_ids` rules like the
+/// ids of requests, so `/admin/users/Alice/note` finds `alice`.
+pub fn path_user(state: &AppState, user_id: &str) -> Result<String, (StatusCode, String)> {
+    state.config.file.user_ids.apply_qualified(user_id).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn get_note(State(state): State<Arc<AppState>>, Path(user_id): Path<String>) -> Response {
+    let user_id = match path_user(&state, &user_id) {
+        Ok(user_id) => user_id,
+        Err(rejection) => return rejection.into_response(),
+    };
+    match state.user_note(&user_id) {
+        Some(note) => Json(serde_json::json!({ "user_id": user_id, "note": note })).into_response(),
+        None => (StatusCode::NOT_FOUND, "No note for this user").into_response(),
+    }
+}
+
+async fn set_note(State(state): State<Arc<AppState>>, Path(user_id): Path<String>, Json(body): Json<NoteBody>) -> Response {
+    match path_user(&state, &user_id) {
+        Ok(user_id) => {
+            state.set_user_note(&user_id, &body.note);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+async fn delete_note(State(state): State<Arc<AppState>>, Path(user_id): Path<String>) -> Response {
+    match path_user(&state, &user_id) {
+        Ok(user_id) => {
+            state.set_user_note(&user_id, "");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+async fn list_watched(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut users: Vec<String> = state.watched_users.lock().unwrap().iter().cloned().collect();
+    users.sort();
+    Json(users)
+}
+
+async fn watch(State(state): State<Arc<AppState>>, Path(user_id): Path<String>) -> Response {
+    match path_user(&state, &user_id) {
+        Ok(user_id) => {
+            state.set_watched(&user_id, true);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+async fn unwatch(State(state): State<Arc<AppState>>, Path(user_id): Path<String>) -> Response {
+    match path_user(&state, &user_id) {
+        Ok(user_id) if state.set_watched(&user_id, false) => StatusCode::NO_CONTENT.into_response(),
+        Ok(_) => StatusCode::NOT_FOUND.into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+async fn status(State(state): State<Arc<AppState>>, Query(filter): Query<NamespaceFilter>) -> impl IntoResponse {
+    let backends: Vec<_> = state.backends.lock().unwrap().iter()
+        .map(|b| {
+            let mut models: Vec<&String> = b.available_models.iter().collect();
+            models.sort();
+            json!({
+                "url": b.url,
+                "online": b.is_online,
+                "canary": b.is_canary,
+                "active_requests": b.active_requests,
+                "processed": b.processed_count,
+                "failed": b.failed_count,
+                "client_errors": b.client_errors,
+                "avg_latency_ms": b.avg_latency_ms(),
+                "error_budget": state.error_budget(b),
+                "models": models,
+                "model_pins": b.model_pins,
+                "api": b.api_type.as_str(),
+                "dynamic": b.dynamic,
+                "ollama_version": b.features.as_ref().map(|f| &f.version),
+                "missing_features": b.features.as_ref().map(|f| f.missing()).unwrap_or_default(),
+                "slots": b.slots.summary(),
+            })
+        })
+        .collect();
+    let users: Vec<UserStats> = state.user_stats().into_iter()
+        .filter(|u| in_namespace(&u.user_id, filter.namespace.as_deref()))
+        .collect();
+    let (dead_letters, dead_letter_bytes) = {
+        let letters = state.dead_letters.lock().unwrap();
+        (letters.len(), letters.bytes())
+    };
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.started_at.elapsed().as_secs(),
+        "requests_per_sec": state.request_rate.lock().unwrap().per_sec(),
+        "expected_next_hour": state.forecast.lock().unwrap().forecast(chrono::Local::now()).next_hour,
+        "in_flight": state.in_flight.load(std::sync::atomic::Ordering::Relaxed),
+        "cancelled": {
+            "waiting": state.cancellations[CancelStage::Waiting as usize].load(std::sync::atomic::Ordering::Relaxed),
+            "streaming": state.cancellations[CancelStage::Streaming as usize].load(std::sync::atomic::Ordering::Relaxed),
+            "backend_secs_saved": state.cancel_saved_ms.load(std::sync::atomic::Ordering::Relaxed) as f64 / 1000.0,
+        },
+        "queued": users.iter().map(|u| u.queued).sum::<usize>(),
+        "processing": users.iter().map(|u| u.processing).sum::<usize>(),
+        "processed": users.iter().map(|u| u.processed).sum::<usize>(),
+        "dropped": users.iter().map(|u| u.dropped).sum::<usize>(),
+        "bytes_in": users.iter().map(|u| u.bytes_in).sum::<u64>(),
+        "bytes_out": users.iter().map(|u| u.bytes_out).sum::<u64>(),
+        "queued_body_bytes": state.queued_bytes(),
+        "max_queued_body_bytes": state.config.max_queued_bytes,
+        "spooled_body_bytes": state.spooled_bytes(),
+        "spooled_requests": state.spooled_tasks.load(std::sync::atomic::Ordering::Relaxed),
+        "detached": state.detached.lock().unwrap().len(),
+        "reattached": state.reattached.load(std::sync::atomic::Ordering::Relaxed),
+        "model_fallbacks": state.model_fallbacks.load(std::sync::atomic::Ordering::Relaxed),
+        "dead_letters": dead_letters,
+        "dead_letter_bytes": dead_letter_bytes,
+        "users": users.len(),
+        "namespace": filter.namespace,
+        "vip_user": *state.vip_user.lock().unwrap(),
+        "boost_user": *state.boost_user.lock().unwrap(),
+        "dispatch_next": *state.dispatch_next.lock().unwrap(),
+        "backends": backends,
+    }))
+}
+
+async fn slos(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(json!({ "slos": state.slo_status() }))
+}
+
+async fn auth_failures(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let window = state.config.jail.as_ref().map_or(Duration::from_secs(crate::jail::DEFAULT_WINDOW_SECS), |p| p.window);
+    let jail = state.jail.lock().unwrap();
+    Json(json!({
+        "failed_total": jail.failed_total,
+        "blocked_total": jail.blocked_total,
+        "bans_total": jail.bans_total,
+        "ips": jail.report(window),
+    }))
+}
+
+async fn connections(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let ips: Vec<_> = state.sockets.by_ip().into_iter().map(|(ip, open)| json!({ "ip": ip, "open": open })).collect();
+    Json(json!({
+        "limit_per_ip": state.config.max_connections_per_ip,
+        "open": state.sockets.total(),
+        "rejected_total": state.sockets.rejected.load(std::sync::atomic::Ordering::Relaxed),
+        "ips": ips,
+    }))
+}
+
+/// Open WebSocket connections, oldest first.
+async fn websockets(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let sockets = state.websockets.lock().unwrap();
+    let open: Vec<_> = sockets.open().into_iter()
+        .map(|t| json!({
+            "user_id": t.user_id,
+            "ip": state.describe_ip(&t.ip),
+            "backend": t.backend,
+            "path": t.path,
+            "opened_at": t.opened_at,
+            "open_secs": t.open_secs(),
+        }))
+        .collect();
+    Json(json!({
+        "enabled": state.config.websockets,
+        "limit_per_user": state.config.websocket_max_per_user,
+        "opened_total": sockets.opened_total,
+        "rejected_total": sockets.rejected_total,
+        "bytes_in": sockets.bytes_in,
+        "bytes_out": sockets.bytes_out,
+        "open": open,
+    }))
+}
+
+async fn fairness(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.fairness.lock().unwrap().report())
+}
+
+async fn forecast(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.forecast.lock().unwrap().forecast(chrono::Local::now()))
+}
+
+/// Per-arm results of every configured experiment, arms without traffic included.
+async fn experiments(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let results = state.experiments.lock().unwrap();
+    let none = ArmStats::default();
+    let mut names: Vec<&String> = state.config.file.experiments.keys().collect();
+    names.sort();
+    let list: Vec<_> = names.into_iter()
+        .map(|name| {
+            let experiment = &state.config.file.experiments[name];
+            let arms: Vec<_> = experiment.arms.iter()
+                .map(|(arm, config)| {
+                    let s = results.arms.get(&(name.clone(), arm.clone())).unwrap_or(&none);
+                    let ms = |stage| s.timings.percentiles(stage, &[0.5, 0.95])
+                        .map(|v| json!({ "p50": v[0].as_millis() as u64, "p95": v[1].as_millis() as u64 }));
+                    json!({
+                        "arm": arm,
+                        "model": config.model,
+                        "backend": config.backend,
+                        "weight": config.weight,
+                        "users": s.users.len(),
+                        "requests": s.requests,
+                        "completed": s.completed,
+                        "failed": s.failed,
+                        "rejected": s.rejected,
+                        "error_rate": s.error_rate(),
+                        "prompt_tokens": s.prompt_tokens,
+                        "completion_tokens": s.completion_tokens,
+                        "avg_completion_tokens": (s.completed > 0).then(|| s.completion_tokens as f64 / s.completed as f64),
+                        "avg_duration_ms": (s.completed > 0).then(|| s.backend_time.as_millis() as u64 / s.completed),
+                        "tokens_per_sec": s.tokens_per_sec(),
+                        "first_chunk_ms": ms(Stage::FirstChunk),
+                        "streaming_ms": ms(Stage::Streaming),
+                    })
+                })
+                .collect();
+            json!({ "experiment": name, "model": experiment.model, "routes": experiment.routes, "arms": arms })
+        })
+        .collect();
+    Json(json!({ "experiments": list }))
+}
+
+async fn config(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(crate::config_view::effective(&state))
+}
+
+async fn structured_output(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let stats = state.structured.lock().unwrap();
+    let mut names: Vec<&String> = stats.keys().collect();
+    names.sort();
+    let models: Vec<_> = names.into_iter()
+        .map(|name| {
+            let s = &stats[name];
+            json!({
+                "model": name,
+                "checked": s.checked,
+                "invalid": s.invalid,
+                "retries": s.retries,
+                "valid_ratio": (s.checked > 0).then(|| (s.checked - s.invalid) as f64 / s.checked as f64),
+            })
+        })
+        .collect();
+    Json(json!({ "mode": state.config.structured_output.as_str(), "models": models }))
+}
+
+/// Timeout baselines per (backend, model) with the config file override that wins over them.
+async fn timeouts(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let config = &state.config;
+    let baselines = state.learned_timeouts.lock().unwrap().baselines(config.learned_timeout_factor, config.learned_timeout_max);
+    let list: Vec<_> = baselines.into_iter()
+        .map(|b| {
+            let configured = crate::timeouts::configured(&config.file, &b.backend, &b.model).map(|d| d.as_secs());
+            json!({
+                "backend": b.backend,
+                "model": b.model,
+                "samples": b.samples,
+                "p99_ms": b.p99_ms,
+                "learned_secs": b.learned_secs,
+                "configured_secs": configured,
+            })
+        })
+        .collect();
+    Json(json!({
+        "factor": config.learned_timeout_factor,
+        "max_secs": config.learned_timeout_max.as_secs(),
+        "min_samples": crate::timeouts::MIN_SAMPLES,
+        "baselines": list,
+    }))
+}
+
+/// Context length and vision support of the models found on Ollama backends.
+async fn models(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let infos = state.model_info.lock().unwrap();
+    let mut names: Vec<&String> = infos.keys().collect();
+    names.sort();
+    let models: Vec<_> = names.into_iter()
+        .map(|name| {
+            let info = &infos[name];
+            json!({
+                "model": name,
+                "context_length": info.context_length,
+                "vision": info.vision,
+            })
+        })
+        .collect();
+    Json(json!({ "models": models }))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackendBody {
+    url: String,
+}
+
+/// Registers a backend at runtime, e.g. one started by an autoscaler. It takes
+/// traffic once the next health check finds it online.
+async fn add_backend(State(state): State<Arc<AppState>>, Json(body): Json<BackendBody>) -> Response {
+    let url = normalize_backend_url(body.url.trim());
+    if reqwest::Url::parse(&url).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid backend URL").into_response();
+    }
+    if state.add_backend(&url) {
+        StatusCode::CREATED.into_response()
+    } else {
+        (StatusCode::CONFLICT, "Backend already registered").into_response()
+    }
+}
+
+async fn remove_backend(State(state): State<Arc<AppState>>, Json(body): Json<BackendBody>) -> Response {
+    if state.remove_backend(body.url.trim()) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, "No such backend").into_response()
+    }
+}
+
+async fn queues(State(state): State<Arc<AppState>>, Query(filter): Query<NamespaceFilter>) -> impl IntoResponse {
+    let mut queued_ids: HashMap<String, Vec<String>> = state.queues.lock().unwrap().iter()
+        .map(|(user, q)| (user.clone(), q.iter().map(|t| t.id.clone()).collect()))
+        .collect();
+    let mut running_ids: HashMap<String, Vec<String>> = HashMap::new();
+    for (task_id, user) in state.running_tasks.lock().unwrap().iter() {
+        running_ids.entry(user.clone()).or_default().push(task_id.clone());
+    }
+    let users: Vec<_> = state.user_stats().into_iter()
+        .filter(|u| in_namespace(&u.user_id, filter.namespace.as_deref()))
+        .map(|u| {
+            let mut running = running_ids.remove(&u.user_id).unwrap_or_default();
+            // ULIDs sort by admission time
+            running.sort();
+            json!({
+                "user_id": u.user_id,
+                "queued": u.queued,
+                "processing": u.processing,
+                "processed": u.processed,
+                "dropped": u.dropped,
+                "multi_ip_flagged": u.multi_ip_flagged,
+                "bytes_in": u.bytes_in,
+                "bytes_out": u.bytes_out,
+                "tool_requests": u.tool_requests,
+                "tool_calls": u.tool_calls,
+                "errors": u.errors,
+                "watched": state.is_watched(&u.user_id),
+                "queued_task_ids": queued_ids.remove(&u.user_id).unwrap_or_default(),
+                "running_task_ids": running,
+            })
+        })
+        .collect();
+    Json(users)
+}
+
+/// Blocked users and IPs; a namespace filter lists only that namespace's
+/// users, since IP blocks apply to everyone.
+async fn list_blocks(State(state): State<Arc<AppState>>, Query(filter): Query<NamespaceFilter>) -> impl IntoResponse {
+    let mut users: Vec<String> = state.blocked_users.lock().unwrap().iter()
+        .filter(|u| in_namespace(u, filter.namespace.as_deref()))
+        .cloned()
+        .collect();
+    let mut ips: Vec<IpPrefix> = state.blocked_ips.lock().unwrap().iter().copied().collect();
+    users.sort();
+    ips.sort();
+    let user_blocks = state.user_blocks.lock().unwrap();
+    let ip_blocks = state.ip_blocks.lock().unwrap();
+    let details = json!({
+        "users": users.iter().filter_map(|u| user_blocks.get(u).map(|i| (u.clone(), i))).collect::<BTreeMap<_, _>>(),
+        "ips": ips.iter().filter_map(|p| ip_blocks.get(p).map(|i| (p.to_string(), i))).collect::<BTreeMap<_, _>>(),
+    });
+    Json(json!({ "users": users, "ips": ips, "details": details }))
+}
+
+/// Totals per namespace; users outside any namespace are listed under `null`.
+async fn namespaces(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let blocked = state.blocked_users.lock().unwrap().clone();
+    let mut totals: BTreeMap<Option<String>, (UserStats, usize, usize)> = BTreeMap::new();
+    for u in state.user_stats() {
+        let ns = crate::namespace::namespace_of(&u.user_id).map(str::to_string);
+        let (sum, users, _) = totals.entry(ns).or_insert_with(|| (UserStats::default(), 0, 0));
+        sum.absorb(&u);
+        *users += 1;
+    }
+    for user in &blocked {
+        let ns = crate::namespace::namespace_of(user).map(str::to_string);
+        totals.entry(ns).or_insert_with(|| (UserStats::default(), 0, 0)).2 += 1;
+    }
+    for name in state.config.file.namespaces.keys() {
+        totals.entry(Some(name.clone())).or_insert_with(|| (UserStats::default(), 0, 0));
+    }
+    let list: Vec<_> = totals.into_iter()
+        .map(|(ns, (sum, users, blocked))| {
+            let max_queued = ns.as_ref().and_then(|ns| state.config.file.namespaces.get(ns)).and_then(|c| c.max_queued);
+            json!({
+                "namespace": ns,
+                "users": users,
+                "queued": sum.queued,
+                "processing": sum.processing,
+                "processed": sum.processed,
+                "dropped": sum.dropped,
+                "bytes_in": sum.bytes_in,
+                "bytes_out": sum.bytes_out,
+                "blocked_users": blocked,
+                "max_queued": max_queued,
+            })
+        })
+        .collect();
+    Json(json!({ "namespaces": list }))
+}
+
+/// Exactly one of `user` or `ip` (an address or prefix such as `2001:db8::/64`).
+/// A user can be given with its `namespace` or already qualified (`team/user`).
+/// `reason` and `expires_in_secs` only apply when blocking.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockTarget {
+    user: Option<String>,
+    ip: Option<String>,
+    namespace: Option<String>,
+    reason: Option<String>,
+    expires_in_secs: Option<u64>,
+}
+
+enum Target {
+    User(String),
+    Ip(IpPrefix),
+}
+
+impl BlockTarget {
+    fn parse(&self, rules: &UserIdRules) -> Result<Target, String> {
+        match (self.user.as_deref(), self.ip.as_deref()) {
+            (Some(user), None) => {
+                let user = rules.apply(user).map_err(|e| e.to_string())?;
+                Ok(Target::User(crate::namespace::qualify(self.namespace.as_deref(), &user)))
+            }
+            (None, Some(_)) if self.namespace.is_some() => Err("IP blocks apply to all namespaces".to_string()),
+            (None, Some(ip)) => ip.parse().map(Target::Ip),
+            _ => Err("Expected exactly one of 'user' or 'ip'".to_string()),
+        }
+    }
+}
+
+async fn block(State(state): State<Arc<AppState>>, headers: HeaderMap, Json(body): Json<BlockTarget>) -> Response {
+    if body.expires_in_secs == Some(0) {
+        return (StatusCode::BAD_REQUEST, "'expires_in_secs' must be positive").into_response();
+    }
+    let info = BlockInfo::new(token_label(&state, &headers))
+        .with_reason(body.reason.clone())
+        .expiring_in(body.expires_in_secs.map(Duration::from_secs));
+    match body.parse(&state.config.file.user_ids) {
+        Ok(Target::User(user)) => state.block_user(user, info),
+        Ok(Target::Ip(prefix)) => state.block_ip(prefix, info),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn unblock(State(state): State<Arc<AppState>>, Json(body): Json<BlockTarget>) -> Response {
+    let found = match body.parse(&state.config.file.user_ids) {
+        Ok(Target::User(user)) => {
+            let found = state.is_user_blocked(&user);
+            state.unblock_user(&user);
+            found
+        }
+        Ok(Target::Ip(prefix)) => {
+            let found = state.blocked_ips.lock().unwrap().contains(&prefix);
+            state.unblock_ip(prefix);
+            found
+        }
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if found {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, "Not blocked").into_response()
+    }
+}
@@ -0,0 +1,228 @@
+//! Scale signals for external autoscalers: "up" when the estimated queue wait
+//! stays above a threshold, "down" hints when backends sit idle.
+//!
+//! Signals are exposed on `GET /admin/scale`, written to an optional file, and
+//! POSTed to an optional webhook whenever they change.
+
+use axum::{Json, extract::State, response::IntoResponse};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::dispatcher::AppState;
+
+const EVALUATE_INTERVAL: Duration = Duration::from_secs(5);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct AutoscaleConfig {
+    /// Signal scale-up when the estimated queue wait exceeds this many seconds...
+    pub up_wait_secs: Option<u64>,
+    /// ...continuously for this long.
+    pub up_sustain_secs: u64,
+    /// Hint scale-down for backends idle this long while nothing is queued.
+    pub down_idle_secs: Option<u64>,
+    /// URL that receives every signal change as a JSON POST.
+    pub webhook: Option<String>,
+    /// File that always holds the current signal as JSON.
+    pub signal_file: Option<PathBuf>,
+    /// Repeat an unchanged up/down signal to the webhook after this long.
+    pub repeat_secs: u64,
+}
+
+impl Default for AutoscaleConfig {
+    fn default() -> Self {
+        Self {
+            up_wait_secs: None,
+            up_sustain_secs: 60,
+            down_idle_secs: None,
+            webhook: None,
+            signal_file: None,
+            repeat_secs: 300,
+        }
+    }
+}
+
+impl AutoscaleConfig {
+    pub fn enabled(&self) -> bool {
+        self.up_wait_secs.is_some() || self.down_idle_secs.is_some()
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleSignal {
+    #[default]
+    Steady,
+    Up,
+    Down,
+}
+
+impl ScaleSignal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScaleSignal::Steady => "steady",
+            ScaleSignal::Up => "up",
+            ScaleSignal::Down => "down",
+        }
+    }
+}
+
+/// The current signal and why it was raised.
+#[derive(Clone, Debug)]
+pub struct ScaleStatus {
+    pub signal: ScaleSignal,
+    pub since: DateTime<Local>,
+    pub reason: String,
+    /// Backends that could be shut down, for `down`.
+    pub idle_backends: Vec<String>,
+}
+
+impl Default for ScaleStatus {
+    fn default() -> Self {
+        Self {
+            signal: ScaleSignal::Steady,
+            since: Local::now(),
+            reason: String::new(),
+            idle_backends: Vec::new(),
+        }
+    }
+}
+
+/// Backends that have been idle for `idle` while nothing is queued. One backend
+/// is always left out, preferring to keep statically configured ones.
+fn idle_backends(state: &AppState, idle: Duration) -> Vec<String> {
+    if state.total_queued() > 0 {
+        return Vec::new();
+    }
+    let backends = state.backends.lock().unwrap();
+    let mut idle: Vec<(bool, &String)> = backends.iter()
+        .filter(|b| b.is_online && b.idle_since.is_some_and(|t| t.elapsed() >= idle))
+        .map(|b| (b.dynamic, &b.url))
+        .collect();
+    let online = backends.iter().filter(|b| b.is_online).count();
+    // Dynamic backends first, so the one kept is a static one when possible
+    idle.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    let keep = usize::from(idle.len() == online);
+    idle.truncate(idle.len().saturating_sub(keep));
+    idle.into_iter().map(|(_, url)| url.clone()).collect()
+}
+
+fn payload(state: &AppState, status: &ScaleStatus) -> serde_json::Value {
+    let (online, total) = {
+        let backends = state.backends.lock().unwrap();
+        (backends.iter().filter(|b| b.is_online).count(), backends.len())
+    };
+    json!({
+        "signal": status.signal,
+        "since": status.since.to_rfc3339(),
+        "reason": status.reason,
+        "idle_backends": status.idle_backends,
+        "queued": state.total_queued(),
+        "estimated_wait_secs": state.estimated_wait().filter(|w| *w != Duration::MAX).map(|w| w.as_secs()),
+        "backends_online": online,
+        "backends_total": total,
+    })
+}
+
+fn write_signal_file(path: &Path, body: &serde_json::Value) {
+    let tmp = path.with_extension("tmp");
+    let result = std::fs::write(&tmp, body.to_string()).and_then(|_| std::fs::rename(&tmp, path));
+    if let Err(e) = result {
+        warn!("Failed to write scale signal file {}: {}", path.display(), e);
+    }
+}
+
+pub async fn run_autoscaler(state: Arc<AppState>) {
+    let config = state.config.autoscale.clone();
+    if !config.enabled() {
+        return;
+    }
+    let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build().unwrap();
+    let mut over_since: Option<Instant> = None;
+    let mut last_emit: Option<Instant> = None;
+
+    if let Some(ref path) = config.signal_file {
+        write_signal_file(path, &payload(&state, &ScaleStatus::default()));
+    }
+
+    loop {
+        let wait = state.estimated_wait();
+        let up_reason = config.up_wait_secs.and_then(|limit| {
+            let wait = wait.filter(|w| w.as_secs() > limit)?;
+            let since = *over_since.get_or_insert_with(Instant::now);
+            (since.elapsed() >= Duration::from_secs(config.up_sustain_secs)).then(|| {
+                if wait == Duration::MAX {
+                    format!("no backend online for {}s", since.elapsed().as_secs())
+                } else {
+                    format!("estimated queue wait {}s above {}s for {}s", wait.as_secs(), limit, since.elapsed().as_secs())
+                }
+            })
+        });
+        if wait.is_none_or(|w| config.up_wait_secs.is_none_or(|limit| w.as_secs() <= limit)) {
+            over_since = None;
+        }
+        let idle = config.down_idle_secs
+            .map(|secs| idle_backends(&state, Duration::from_secs(secs)))
+            .unwrap_or_default();
+
+        let (signal, reason) = match up_reason {
+            Some(reason) => (ScaleSignal::Up, reason),
+            None if !idle.is_empty() => (
+                ScaleSignal::Down,
+                format!("{} backend(s) idle for over {}s", idle.len(), config.down_idle_secs.unwrap_or(0)),
+            ),
+            None => (ScaleSignal::Steady, String::new()),
+        };
+
+        let emit = {
+            let mut status = state.scale.lock().unwrap();
+            let changed = status.signal != signal || (signal == ScaleSignal::Down && status.idle_backends != idle);
+            if changed {
+                *status = ScaleStatus { signal, since: Local::now(), reason, idle_backends: idle };
+            } else {
+                status.reason = reason;
+            }
+            let repeat = signal != ScaleSignal::Steady
+                && last_emit.is_some_and(|t| t.elapsed() >= Duration::from_secs(config.repeat_secs));
+            (changed || repeat).then(|| status.clone())
+        };
+
+        if let Some(status) = emit {
+            last_emit = Some(Instant::now());
+            if status.signal == ScaleSignal::Steady {
+                info!("Scale signal: steady");
+            } else {
+                info!("Scale signal: {} ({})", status.signal.as_str(), status.reason);
+            }
+            let body = payload(&state, &status);
+            if let Some(ref path) = config.signal_file {
+                write_signal_file(path, &body);
+            }
+            if let Some(ref url) = config.webhook {
+                let request = client.post(url).json(&body);
+                let url = url.clone();
+                tokio::spawn(async move {
+                    match request.send().await {
+                        Ok(res) if !res.status().is_success() => warn!("Scale webhook {} answered {}", url, res.status()),
+                        Err(e) => warn!("Scale webhook {} failed: {}", url, e),
+                        Ok(_) => {}
+                    }
+                });
+            }
+        }
+
+        tokio::time::sleep(EVALUATE_INTERVAL).await;
+    }
+}
+
+pub async fn scale_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let status = state.scale.lock().unwrap().clone();
+    Json(payload(&state, &status))
+}
@@ -0,0 +1,263 @@
+//! Batched submission: `POST /api/batch` takes many generation requests at
+//! once. Each one is queued as a regular task of the caller, so batches are
+//! scheduled as fairly as separate requests, while the batch is tracked as a
+//! whole: results are streamed back as NDJSON lines as they finish, or
+//! collected for `GET /api/batch/{batch_id}`.
+
+use axum::{
+    Json, Router,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Path, State},
+    http::{Extensions, HeaderMap, Method, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Local};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::info;
+
+use crate::dispatcher::{AppState, caller_id, handle_request};
+
+/// Most requests accepted in one batch.
+pub const MAX_ITEMS: usize = 1000;
+/// How long a finished batch can still be looked up.
+const KEEP_FINISHED: Duration = Duration::from_secs(60 * 60);
+/// Largest response kept per item.
+const MAX_RESULT_BYTES: usize = 1024 * 1024;
+/// Routes a batch may target.
+const ROUTES: &[&str] = &[
+    "/api/generate",
+    "/api/chat",
+    "/api/embed",
+    "/api/embeddings",
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/v1/embeddings",
+];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemState {
+    Queued,
+    Running,
+    Finished,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchItem {
+    pub index: usize,
+    pub state: ItemState,
+    /// Known once the request was dispatched to a backend.
+    pub task_id: Option<String>,
+    pub status: Option<u16>,
+    /// The backend's answer: its JSON, or the text if it isn't JSON.
+    pub response: Option<Value>,
+}
+
+pub struct Batch {
+    pub user_id: String,
+    pub path: String,
+    pub created_at: DateTime<Local>,
+    pub items: Vec<BatchItem>,
+    finished_at: Option<Instant>,
+}
+
+impl Batch {
+    fn count(&self, pred: impl Fn(&BatchItem) -> bool) -> usize {
+        self.items.iter().filter(|i| pred(i)).count()
+    }
+
+    fn summary(&self, batch_id: &str) -> Value {
+        let finished = |i: &BatchItem| i.state == ItemState::Finished;
+        let ok = |i: &BatchItem| i.status.is_some_and(|s| (200..300).contains(&s));
+        json!({
+            "batch_id": batch_id,
+            "path": self.path,
+            "created_at": self.created_at.to_rfc3339(),
+            "total": self.items.len(),
+            "queued": self.count(|i| i.state == ItemState::Queued),
+            "running": self.count(|i| i.state == ItemState::Running),
+            "completed": self.count(|i| finished(i) && ok(i)),
+            "failed": self.count(|i| finished(i) && !ok(i)),
+            "done": self.finished_at.is_some(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    #[serde(default = "default_path")]
+    path: String,
+    requests: Vec<Value>,
+    /// Answer right away with the batch id instead of streaming the results.
+    #[serde(default)]
+    detach: bool,
+}
+
+fn default_path() -> String {
+    "/api/generate".to_string()
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+/// Forgets batches that finished longer than `KEEP_FINISHED` ago.
+fn expire(state: &AppState) {
+    state.batches.lock().unwrap().retain(|_, b| b.finished_at.is_none_or(|at| at.elapsed() < KEEP_FINISHED));
+}
+
+async fn submit(state: Arc<AppState>, extensions: Extensions, mut headers: HeaderMap, body: Bytes) -> Response {
+    let user_id = match caller_id(&state, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let request: BatchRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return bad_request(format!("invalid batch: {}", e)),
+    };
+    if !ROUTES.contains(&request.path.as_str()) {
+        return bad_request(format!("path must be one of {}", ROUTES.join(", ")));
+    }
+    if request.requests.is_empty() || request.requests.len() > MAX_ITEMS {
+        return bad_request(format!("a batch holds 1 to {} requests", MAX_ITEMS));
+    }
+    if let Some(index) = request.requests.iter().position(|r| !r.is_object()) {
+        return bad_request(format!("request {} is not a JSON object", index));
+    }
+    let ip = extensions.get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| addr.ip());
+    // Each item gets its own body
+    headers.remove(header::CONTENT_LENGTH);
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+
+    expire(&state);
+    let batch_id = state.next_task_id();
+    let total = request.requests.len();
+    state.batches.lock().unwrap().insert(batch_id.clone(), Batch {
+        user_id: user_id.clone(),
+        path: request.path.clone(),
+        created_at: Local::now(),
+        items: (0..total).map(|index| BatchItem { index, state: ItemState::Queued, task_id: None, status: None, response: None }).collect(),
+        finished_at: None,
+    });
+    info!("Batch {} of user {}: {} request(s) to {}", batch_id, user_id, total, request.path);
+
+    let (tx, rx) = mpsc::channel::<Bytes>(total.min(64));
+    let generation = !request.path.contains("embed");
+    for (index, mut item) in request.requests.into_iter().enumerate() {
+        if generation {
+            // Results are collected whole, so streaming would only add overhead
+            item["stream"] = Value::Bool(false);
+        }
+        let tx = (!request.detach).then(|| tx.clone());
+        tokio::spawn(run_item(state.clone(), batch_id.clone(), index, ip, headers.clone(), request.path.clone(), item, tx));
+    }
+    drop(tx);
+
+    if request.detach {
+        let body = json!({ "batch_id": batch_id, "total": total, "status_url": format!("/api/batch/{}", batch_id) });
+        return (StatusCode::ACCEPTED, Json(body)).into_response();
+    }
+    let summary_state = state.clone();
+    let summary_id = batch_id.clone();
+    let done = futures_util::stream::once(async move {
+        let summary = summary_state.batches.lock().unwrap().get(&summary_id).map(|b| b.summary(&summary_id));
+        Bytes::from(format!("{}\n", summary.unwrap_or_default()))
+    });
+    let stream = ReceiverStream::new(rx).chain(done).map(Ok::<_, std::convert::Infallible>);
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson".to_string()), (header::HeaderName::from_static("x-batch-id"), batch_id)],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// Sends one item through the regular queue and records its answer.
+#[allow(clippy::too_many_arguments)]
+async fn run_item(
+    state: Arc<AppState>,
+    batch_id: String,
+    index: usize,
+    ip: IpAddr,
+    headers: HeaderMap,
+    path: String,
+    item: Value,
+    tx: Option<mpsc::Sender<Bytes>>,
+) {
+    let response = handle_request(state.clone(), ip, Method::POST, headers, path, Bytes::from(item.to_string())).await;
+    let status = response.status().as_u16();
+    let task_id = response.headers().get("X-Task-ID").and_then(|v| v.to_str().ok()).map(str::to_string);
+    update(&state, &batch_id, index, |item| {
+        item.state = ItemState::Running;
+        item.task_id = task_id.clone();
+        item.status = Some(status);
+    });
+    let answer = match axum::body::to_bytes(response.into_body(), MAX_RESULT_BYTES).await {
+        Ok(body) => serde_json::from_slice(&body).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned())),
+        Err(e) => json!({ "error": format!("response not kept: {}", e) }),
+    };
+    let line = json!({ "batch_id": batch_id, "index": index, "task_id": task_id, "status": status, "response": answer });
+    update(&state, &batch_id, index, |item| {
+        item.state = ItemState::Finished;
+        item.response = Some(answer);
+    });
+    if let Some(tx) = tx {
+        let _ = tx.send(Bytes::from(format!("{}\n", line))).await;
+    }
+}
+
+fn update(state: &AppState, batch_id: &str, index: usize, f: impl FnOnce(&mut BatchItem)) {
+    let mut batches = state.batches.lock().unwrap();
+    let Some(batch) = batches.get_mut(batch_id) else {
+        return;
+    };
+    if let Some(item) = batch.items.get_mut(index) {
+        f(item);
+    }
+    if batch.finished_at.is_none() && batch.items.iter().all(|i| i.state == ItemState::Finished) {
+        batch.finished_at = Some(Instant::now());
+        info!("Batch {} of user {} finished", batch_id, batch.user_id);
+    }
+}
+
+/// Progress of one of the caller's batches, with the results so far.
+async fn status(state: Arc<AppState>, headers: HeaderMap, batch_id: String) -> Response {
+    let user_id = match caller_id(&state, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    expire(&state);
+    let batches = state.batches.lock().unwrap();
+    match batches.get(&batch_id).filter(|b| b.user_id == user_id) {
+        Some(batch) => {
+            let mut body = batch.summary(&batch_id);
+            body["items"] = json!(batch.items);
+            Json(body).into_response()
+        }
+        None => {
+            let body = json!({ "error": "unknown batch (or finished more than an hour ago)", "batch_id": batch_id });
+            (StatusCode::NOT_FOUND, Json(body)).into_response()
+        }
+    }
+}
+
+/// `/api/batch` routes, scoped to the caller's `X-User-ID` (or resolved identity).
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/batch", post(|State(state): State<Arc<AppState>>, extensions: Extensions, headers: HeaderMap, body: Bytes| async move {
+            submit(state, extensions, headers, body).await
+        }))
+        .route("/api/batch/{batch_id}", get(|State(state): State<Arc<AppState>>, headers: HeaderMap, Path(batch_id): Path<String>| async move {
+            status(state, headers, batch_id).await
+        }))
+}
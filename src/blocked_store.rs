@@ -0,0 +1,248 @@
+//! Persistence of blocked users and IPs with why and until when they are
+//! blocked, operator notes and the watchlist.
+//! Changes only mark the list dirty; a background task writes the file after a
+//! short debounce, atomically (temp file + rename), keeping the previous
+//! version as `<file>.bak` to recover from a damaged file.
+
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::dispatcher::AppState;
+use crate::ip::IpPrefix;
+use crate::user_ids::UserIdRules;
+
+pub const DEFAULT_PATH: &str = "blocked_items.json";
+
+/// Changes arriving within this window are written together.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Keeps the background task and a final flush from writing at the same time.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Checks for blocks past their expiry this often.
+const EXPIRY_SWEEP: Duration = Duration::from_secs(5);
+
+/// Why, by whom and until when a user or IP is blocked.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// `tui`, `script`, `jail`, or the admin token's name (its role if unnamed).
+    pub by: String,
+    /// RFC 3339.
+    pub created_at: String,
+    /// RFC 3339; the block is lifted automatically after this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+impl BlockInfo {
+    pub fn new(by: impl Into<String>) -> Self {
+        Self { reason: None, by: by.into(), created_at: Local::now().to_rfc3339(), expires_at: None }
+    }
+
+    pub fn with_reason(mut self, reason: Option<String>) -> Self {
+        self.reason = reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+        self
+    }
+
+    pub fn expiring_in(mut self, duration: Option<Duration>) -> Self {
+        self.expires_at = duration
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .map(|d| (Local::now() + d).to_rfc3339());
+        self
+    }
+
+    pub fn expires(&self) -> Option<DateTime<FixedOffset>> {
+        self.expires_at.as_deref().and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+    }
+
+    /// Log suffix such as ` by ops (spam) until 2026-01-02T03:04:05+00:00`.
+    pub fn describe(&self) -> String {
+        let mut text = format!(" by {}", self.by);
+        if let Some(reason) = &self.reason {
+            text.push_str(&format!(" ({})", reason));
+        }
+        if let Some(at) = &self.expires_at {
+            text.push_str(&format!(" until {}", at));
+        }
+        text
+    }
+
+    pub fn is_expired(&self, now: DateTime<Local>) -> bool {
+        self.expires().is_some_and(|at| at <= now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct BlockedItems {
+    /// Single addresses or prefixes such as `2001:db8::/64`.
+    pub ips: HashSet<IpPrefix>,
+    pub users: HashSet<String>,
+    /// Metadata of blocked users; blocks from older files have none.
+    #[serde(default)]
+    pub user_blocks: HashMap<String, BlockInfo>,
+    #[serde(default)]
+    pub ip_blocks: HashMap<IpPrefix, BlockInfo>,
+    /// Operator notes per user id, kept here so they survive restarts.
+    #[serde(default)]
+    pub notes: HashMap<String, String>,
+    /// User ids pinned to the top of the TUI users table.
+    #[serde(default)]
+    pub watched: HashSet<String>,
+}
+
+impl BlockedItems {
+    /// Re-keys the user ids by the current `user_ids` rules, which may have
+    /// changed since the file was written.
+    pub fn normalize_users(&mut self, rules: &UserIdRules) {
+        if !rules.is_active() {
+            return;
+        }
+        self.users = self.users.drain().map(|u| rules.key(&u)).collect();
+        self.user_blocks = self.user_blocks.drain().map(|(u, info)| (rules.key(&u), info)).collect();
+        self.notes = self.notes.drain().map(|(u, note)| (rules.key(&u), note)).collect();
+        self.watched = self.watched.drain().map(|u| rules.key(&u)).collect();
+    }
+}
+
+/// `<path><suffix>`, e.g. `blocked_items.json.bak`.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn read(path: &Path) -> io::Result<BlockedItems> {
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads the list; a damaged file is moved aside to `<file>.corrupt` and the
+/// backup of the previous write is used instead.
+pub fn load(path: &Path) -> BlockedItems {
+    let err = match read(path) {
+        Ok(items) => return items,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return BlockedItems::default(),
+        Err(e) => e,
+    };
+    if err.kind() != io::ErrorKind::InvalidData {
+        error!("Cannot read {}: {}; starting without blocked items", path.display(), err);
+        return BlockedItems::default();
+    }
+    let corrupt = sibling(path, ".corrupt");
+    error!("{} is damaged ({}); moving it to {}", path.display(), err, corrupt.display());
+    if let Err(e) = fs::rename(path, &corrupt) {
+        warn!("Cannot move {} aside: {}", path.display(), e);
+    }
+    let backup = sibling(path, ".bak");
+    match read(&backup) {
+        Ok(items) => {
+            warn!("Restored blocked items from {}", backup.display());
+            items
+        }
+        Err(e) => {
+            warn!("No usable backup {} ({}); starting without blocked items", backup.display(), e);
+            BlockedItems::default()
+        }
+    }
+}
+
+fn capture(state: &AppState) -> BlockedItems {
+    // Temporary bans end with the process
+    let banned = state.jail.lock().unwrap().banned();
+    let ips: HashSet<IpPrefix> = state.blocked_ips.lock().unwrap().difference(&banned).copied().collect();
+    BlockedItems {
+        ip_blocks: state.ip_blocks.lock().unwrap().iter()
+            .filter(|(prefix, _)| ips.contains(prefix))
+            .map(|(prefix, info)| (*prefix, info.clone()))
+            .collect(),
+        ips,
+        users: state.blocked_users.lock().unwrap().clone(),
+        user_blocks: state.user_blocks.lock().unwrap().clone(),
+        notes: state.user_notes.lock().unwrap().clone(),
+        watched: state.watched_users.lock().unwrap().clone(),
+    }
+}
+
+/// Replaces `path` with `content` so readers see either the old or the new
+/// file, never a half-written one.
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let _guard = WRITE_LOCK.lock().unwrap();
+    let tmp = sibling(path, ".tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    if path.exists() {
+        fs::copy(path, sibling(path, ".bak"))?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// Writes the current list right away, e.g. before the process exits.
+pub fn save_now(state: &AppState) {
+    let path = &state.config.blocked_file;
+    let result = serde_json::to_vec_pretty(&capture(state))
+        .map_err(io::Error::other)
+        .and_then(|content| write_atomic(path, &content));
+    if let Err(e) = result {
+        error!("Cannot save blocked items to {}: {}", path.display(), e);
+    }
+}
+
+/// Writes the list whenever it changed, at most once per debounce window.
+pub async fn run_blocked_store(state: Arc<AppState>) {
+    loop {
+        state.blocked_dirty.notified().await;
+        tokio::time::sleep(DEBOUNCE).await;
+        let content = match serde_json::to_vec_pretty(&capture(&state)) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Cannot serialize blocked items: {}", e);
+                continue;
+            }
+        };
+        let path = state.config.blocked_file.clone();
+        let written = tokio::task::spawn_blocking(move || write_atomic(&path, &content).map(|_| path)).await;
+        match written {
+            Ok(Ok(path)) => debug!("Saved blocked items to {}", path.display()),
+            Ok(Err(e)) => error!("Cannot save blocked items to {}: {}", state.config.blocked_file.display(), e),
+            Err(e) => error!("Saving blocked items failed: {}", e),
+        }
+    }
+}
+
+/// Lifts blocks whose expiry has passed.
+pub async fn run_block_expiry(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(EXPIRY_SWEEP).await;
+        let now = Local::now();
+        let users: Vec<String> = state.user_blocks.lock().unwrap().iter()
+            .filter(|(_, info)| info.is_expired(now))
+            .map(|(user, _)| user.clone())
+            .collect();
+        let ips: Vec<IpPrefix> = state.ip_blocks.lock().unwrap().iter()
+            .filter(|(_, info)| info.is_expired(now))
+            .map(|(prefix, _)| *prefix)
+            .collect();
+        for user in users {
+            info!("Block of user {} expired", user);
+            state.unblock_user(&user);
+        }
+        for prefix in ips {
+            info!("Block of {} expired", prefix);
+            state.unblock_ip(prefix);
+        }
+    }
+}
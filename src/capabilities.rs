@@ -0,0 +1,174 @@
+//! Per-model limits read from Ollama's `/api/show` (context window, vision
+//! support), used to reject requests a model cannot serve before they take a
+//! dispatch slot.
+
+use serde_json::Value;
+use tracing::debug;
+
+use crate::dispatcher::{AppState, model_name_matches};
+use crate::policy::PolicyViolation;
+
+/// Rough prompt size estimate; real tokenizers average 3-4 characters per
+/// token for English text, so this errs on the side of letting requests through.
+const CHARS_PER_TOKEN: usize = 4;
+
+#[derive(Clone, Debug, Default)]
+pub struct ModelInfo {
+    /// Digest from `/api/tags`; the metadata is fetched again when it changes.
+    pub digest: Option<String>,
+    /// Maximum context length in tokens.
+    pub context_length: Option<u64>,
+    pub vision: bool,
+}
+
+impl ModelInfo {
+    /// Reads the metadata from an `/api/show` response.
+    pub fn from_show(json: &Value, digest: Option<String>) -> Self {
+        let context_length = json.get("model_info")
+            .and_then(Value::as_object)
+            .and_then(|info| {
+                info.iter()
+                    .find(|(key, _)| key.ends_with(".context_length"))
+                    .and_then(|(_, v)| v.as_u64())
+            });
+        // Ollama 0.6+ lists capabilities; older versions only show the vision projector
+        let vision = match json.get("capabilities").and_then(Value::as_array) {
+            Some(caps) => caps.iter().any(|c| c.as_str() == Some("vision")),
+            None => {
+                json.get("projector_info").is_some()
+                    || json.pointer("/details/families").and_then(Value::as_array)
+                        .is_some_and(|f| f.iter().any(|f| matches!(f.as_str(), Some("clip" | "mllama"))))
+            }
+        };
+        Self { digest, context_length, vision }
+    }
+}
+
+/// Fetches metadata for the models of an Ollama backend that are new or have
+/// changed since the last health check, from the model catalogue when it
+/// already holds them.
+pub async fn refresh(state: &AppState, client: &reqwest::Client, url: &str, models: &[(String, Option<String>)]) {
+    for (name, digest) in models {
+        let known = state.model_info.lock().unwrap().get(name).is_some_and(|i| i.digest == *digest);
+        if known {
+            continue;
+        }
+        let cached = state.catalogue.lock().unwrap().cached_show(url, name, digest.as_deref(), state.config.model_cache_ttl);
+        let json = match cached {
+            Some(json) => Some(json),
+            None => crate::catalogue::fetch_show(client, url, name).await,
+        };
+        if let Some(json) = json {
+            let info = ModelInfo::from_show(&json, digest.clone());
+            debug!("Model {}: context {:?}, vision {}", name, info.context_length, info.vision);
+            state.model_info.lock().unwrap().insert(name.clone(), info);
+        }
+    }
+}
+
+/// Metadata of the model a request names. When several known models match
+/// (e.g. `llama3` vs `llama3:latest`), the most permissive limits apply.
+fn lookup(state: &AppState, model: &str) -> Option<ModelInfo> {
+    let infos = state.model_info.lock().unwrap();
+    if let Some(info) = infos.get(model) {
+        return Some(info.clone());
+    }
+    infos.iter()
+        .filter(|(name, _)| model_name_matches(model, name))
+        .map(|(_, info)| info.clone())
+        .reduce(|a, b| ModelInfo {
+            digest: None,
+            context_length: a.context_length.zip(b.context_length).map(|(a, b)| a.max(b)),
+            vision: a.vision || b.vision,
+        })
+}
+
+fn has_images(json: &Value) -> bool {
+    let non_empty = |v: Option<&Value>| v.and_then(Value::as_array).is_some_and(|a| !a.is_empty());
+    if non_empty(json.get("images")) {
+        return true;
+    }
+    json.get("messages").and_then(Value::as_array).is_some_and(|messages| {
+        messages.iter().any(|m| {
+            non_empty(m.get("images"))
+                // OpenAI content parts
+                || m.get("content").and_then(Value::as_array).is_some_and(|parts| {
+                    parts.iter().any(|p| p.get("type").and_then(Value::as_str) == Some("image_url"))
+                })
+        })
+    })
+}
+
+/// Characters of prompt text in a generate, chat, completion or embedding request.
+fn prompt_chars(json: &Value) -> (usize, &'static str) {
+    let len = |v: Option<&Value>| v.and_then(Value::as_str).map_or(0, |s| s.chars().count());
+    if let Some(messages) = json.get("messages").and_then(Value::as_array) {
+        let chars = messages.iter()
+            .map(|m| match m.get("content") {
+                Some(Value::Array(parts)) => parts.iter().map(|p| len(p.get("text"))).sum(),
+                content => len(content),
+            } + m.get("tool_calls").map_or(0, json_chars))
+            .sum();
+        return (chars, "messages");
+    }
+    // `/api/embed` and `/v1/embeddings` take one string or a list of them
+    match json.get("input") {
+        Some(Value::Array(inputs)) => return (inputs.iter().map(|i| len(Some(i))).sum(), "input"),
+        Some(input @ Value::String(_)) => return (len(Some(input)), "input"),
+        _ => {}
+    }
+    (len(json.get("prompt")) + len(json.get("system")) + len(json.get("suffix")), "prompt")
+}
+
+/// Characters of a JSON value as the model template renders it.
+fn json_chars(value: &Value) -> usize {
+    value.to_string().chars().count()
+}
+
+/// Prompt size in tokens, estimated before the request is queued (Ollama's
+/// own count only arrives with the response). The `context` of a continued
+/// `/api/generate` conversation is already tokens and counted one by one;
+/// tool definitions are rendered into the prompt as JSON.
+pub fn estimate_prompt_tokens(json: &Value) -> u64 {
+    let context = json.get("context").and_then(Value::as_array).map_or(0, Vec::len);
+    let tools = json.get("tools").map_or(0, json_chars);
+    ((prompt_chars(json).0 + tools).div_ceil(CHARS_PER_TOKEN) + context) as u64
+}
+
+/// Rejects requests that send images to a text-only model, ask for a larger
+/// context than the model supports, or whose prompt (`prompt_tokens`, from
+/// [`estimate_prompt_tokens`]) clearly exceeds it. Models without metadata
+/// (OpenAI-compatible backends, not yet probed) pass.
+pub fn check(state: &AppState, json: &Value, model: &str, prompt_tokens: u64) -> Result<(), PolicyViolation> {
+    let Some(info) = lookup(state, model) else {
+        return Ok(());
+    };
+
+    if !info.vision && has_images(json) {
+        return Err(PolicyViolation::new(
+            "images",
+            format!("model '{}' does not support images", model),
+        ));
+    }
+
+    if let Some(max) = info.context_length {
+        if let Some(num_ctx) = json.pointer("/options/num_ctx").and_then(Value::as_u64)
+            && num_ctx > max
+        {
+            return Err(PolicyViolation::new(
+                "options.num_ctx",
+                format!("num_ctx {} exceeds the context length of model '{}' ({} tokens)", num_ctx, model, max),
+            ));
+        }
+        if prompt_tokens > max {
+            return Err(PolicyViolation::new(
+                prompt_chars(json).1,
+                format!(
+                    "prompt of about {} tokens exceeds the context length of model '{}' ({} tokens)",
+                    prompt_tokens, model, max
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
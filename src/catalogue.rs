@@ -0,0 +1,194 @@
+//! Cached `/api/tags` and `/api/show` results per backend. The health check
+//! keeps them fresh in the background, so client calls to these endpoints are
+//! answered without queuing and without a round trip to a backend.
+
+use serde_json::{Value, json};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+use crate::dispatcher::AppState;
+
+pub const DEFAULT_TTL_SECS: u64 = 300;
+
+#[derive(Default)]
+struct BackendCatalogue {
+    /// Entries of the last `/api/tags` answer and when it arrived.
+    tags: Option<(Vec<Value>, Instant)>,
+    /// `/api/show` answer per model name, with the digest it belongs to.
+    show: HashMap<String, (Value, Option<String>, Instant)>,
+}
+
+#[derive(Default)]
+pub struct ModelCatalogue {
+    backends: HashMap<String, BackendCatalogue>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Whether a model listed as `name` is what a request for `model` gets;
+/// Ollama resolves names without a tag to `:latest`.
+fn resolves_to(model: &str, name: &str) -> bool {
+    name == model || (!model.contains(':') && name.strip_suffix(":latest") == Some(model))
+}
+
+fn digest_of(entry: &Value) -> Option<&str> {
+    entry.get("digest").and_then(Value::as_str)
+}
+
+impl ModelCatalogue {
+    pub fn set_tags(&mut self, url: &str, models: Vec<Value>) {
+        let backend = self.backends.entry(url.to_string()).or_default();
+        // Models that were removed or replaced need a fresh `/api/show`
+        backend.show.retain(|name, (_, digest, _)| {
+            models.iter().any(|m| m.get("name").and_then(Value::as_str) == Some(name) && digest_of(m) == digest.as_deref())
+        });
+        backend.tags = Some((models, Instant::now()));
+    }
+
+    pub fn set_show(&mut self, url: &str, model: &str, digest: Option<String>, body: Value) {
+        let backend = self.backends.entry(url.to_string()).or_default();
+        backend.show.insert(model.to_string(), (body, digest, Instant::now()));
+    }
+
+    /// Drops backends that were removed.
+    pub fn retain(&mut self, urls: &[String]) {
+        self.backends.retain(|url, _| urls.contains(url));
+    }
+
+    /// The `/api/show` answer for `model` on `url` if it is younger than `ttl`
+    /// and matches `digest`.
+    pub fn cached_show(&self, url: &str, model: &str, digest: Option<&str>, ttl: Duration) -> Option<Value> {
+        let (body, cached_digest, at) = self.backends.get(url)?.show.get(model)?;
+        (cached_digest.as_deref() == digest && at.elapsed() < ttl).then(|| body.clone())
+    }
+
+    /// Whether the background refresh should fetch `/api/show` for `model`
+    /// again: missing, replaced, or past half its TTL.
+    fn show_due(&self, url: &str, model: &str, digest: Option<&str>, ttl: Duration) -> bool {
+        self.backends.get(url)
+            .and_then(|b| b.show.get(model))
+            .is_none_or(|(_, cached_digest, at)| cached_digest.as_deref() != digest || at.elapsed() >= ttl / 2)
+    }
+
+    /// Models of all `online` backends with fresh tags, each name listed
+    /// once; `None` when no backend has any.
+    fn tags(&self, online: &[String], ttl: Duration) -> Option<Value> {
+        let mut seen = HashSet::new();
+        let mut models = Vec::new();
+        let mut any = false;
+        for url in online {
+            let Some((entries, at)) = self.backends.get(url).and_then(|b| b.tags.as_ref()) else {
+                continue;
+            };
+            if at.elapsed() >= ttl {
+                continue;
+            }
+            any = true;
+            for entry in entries {
+                let name = entry.get("name").and_then(Value::as_str).unwrap_or_default();
+                if seen.insert(name.to_string()) {
+                    models.push(entry.clone());
+                }
+            }
+        }
+        any.then(|| json!({ "models": models }))
+    }
+
+    /// Online backends listing `model` in fresh tags, with the resolved name
+    /// and its digest.
+    fn holders(&self, online: &[String], model: &str, ttl: Duration) -> Vec<(String, String, Option<String>)> {
+        online.iter()
+            .filter_map(|url| {
+                let (entries, at) = self.backends.get(url)?.tags.as_ref()?;
+                if at.elapsed() >= ttl {
+                    return None;
+                }
+                let entry = entries.iter().find(|e| e.get("name").and_then(Value::as_str).is_some_and(|n| resolves_to(model, n)))?;
+                let name = entry.get("name").and_then(Value::as_str)?.to_string();
+                Some((url.clone(), name, digest_of(entry).map(str::to_string)))
+            })
+            .collect()
+    }
+}
+
+pub async fn fetch_show(client: &reqwest::Client, url: &str, model: &str) -> Option<Value> {
+    let res = client.post(format!("{}/api/show", url))
+        .json(&json!({ "model": model }))
+        .send()
+        .await;
+    match res {
+        Ok(res) if res.status().is_success() => res.json::<Value>().await.ok(),
+        Ok(res) => {
+            debug!("Backend {} /api/show for {} returned status: {}", url, model, res.status());
+            None
+        }
+        Err(e) => {
+            debug!("Backend {} /api/show for {} error: {}", url, model, e);
+            None
+        }
+    }
+}
+
+/// Background refresh from the health check: fetches `/api/show` for the
+/// models of an Ollama backend that are new, changed, or half way to expiry.
+pub async fn refresh(state: &AppState, client: &reqwest::Client, url: &str, models: &[(String, Option<String>)]) {
+    let ttl = state.config.model_cache_ttl;
+    for (name, digest) in models {
+        if !state.catalogue.lock().unwrap().show_due(url, name, digest.as_deref(), ttl) {
+            continue;
+        }
+        if let Some(body) = fetch_show(client, url, name).await {
+            state.catalogue.lock().unwrap().set_show(url, name, digest.clone(), body);
+        }
+    }
+}
+
+/// Answers `GET /api/tags` and `POST /api/show` from the cache. `/api/show`
+/// misses are read through from a backend listing the model; `None` leaves
+/// the request to the queue.
+pub async fn answer(state: &AppState, method: &axum::http::Method, path: &str, body: &[u8]) -> Option<Value> {
+    let ttl = state.config.model_cache_ttl;
+    if ttl.is_zero() || state.shares_queue() {
+        return None;
+    }
+    let online: Vec<String> = state.backends.lock().unwrap().iter()
+        .filter(|b| b.is_online)
+        .map(|b| b.url.clone())
+        .collect();
+
+    match (method.as_str(), path) {
+        ("GET", "/api/tags") => {
+            let mut catalogue = state.catalogue.lock().unwrap();
+            let tags = catalogue.tags(&online, ttl);
+            if tags.is_some() { catalogue.hits += 1 } else { catalogue.misses += 1 }
+            tags
+        }
+        ("POST", "/api/show") => {
+            let json = serde_json::from_slice::<Value>(body).ok()?;
+            // Verbose answers include the full tensor list and aren't cached
+            if json.get("verbose").and_then(Value::as_bool) == Some(true) {
+                return None;
+            }
+            let model = json.get("model").or_else(|| json.get("name")).and_then(Value::as_str)?;
+            let holders = state.catalogue.lock().unwrap().holders(&online, model, ttl);
+            {
+                let mut catalogue = state.catalogue.lock().unwrap();
+                let cached = holders.iter().find_map(|(url, name, digest)| catalogue.cached_show(url, name, digest.as_deref(), ttl));
+                if cached.is_some() {
+                    catalogue.hits += 1;
+                    return cached;
+                }
+                catalogue.misses += 1;
+            }
+            let (url, name, digest) = holders.into_iter().next()?;
+            let client = crate::dispatcher::backend_client(&state.config, &url);
+            let body = fetch_show(&client, &url, &name).await?;
+            state.catalogue.lock().unwrap().set_show(&url, &name, digest, body.clone());
+            Some(body)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,542 @@
+//! Client subcommands that manage a running instance through its admin API.
+
+use chrono::NaiveDate;
+use clap::Subcommand;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+
+use ollamamq::config::FileConfig;
+use ollamamq::dispatcher::{BackendApiType, normalize_backend_url, smart_model_match};
+use ollamamq::ip::IpPrefix;
+use ollamamq::probe::{BackendCheck, CheckLevel, Generation, check_backend, client_for, pick_model, try_generation};
+use ollamamq::timings::{format_bytes, format_duration, format_uptime};
+use ollamamq::usage::{ReportGroup, UsageSummary, summarize};
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show backends and request totals of a running instance
+    Status(ClientArgs),
+    /// List per-user queue counters of a running instance
+    Queues(ClientArgs),
+    /// Block a user id or an IP address/prefix
+    Block(BlockArgs),
+    /// Lift a block on a user id or an IP address/prefix
+    Unblock(TargetArgs),
+    /// Print the autoscaling signal; exits 0 for steady, 10 for up, 11 for down
+    Scale(ClientArgs),
+    /// Summarize per-user/per-model usage from the usage log (--usage-log)
+    Report(ReportArgs),
+    /// Start the dispatcher in front of fake backends with synthetic users, to try out the dashboard
+    Demo(DemoArgs),
+    /// Run the dispatcher's health checks and a one-token generation against a backend
+    Probe(ProbeArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ProbeArgs {
+    /// Backend URL, as given to --backend-urls
+    url: String,
+
+    /// Model for the generation check; defaults to a loaded model, else the first one listed
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Skip the generation check
+    #[arg(long)]
+    no_generate: bool,
+
+    /// Timeout for each check in seconds
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// HTTP or SOCKS proxy for reaching the backend, as for the dispatcher
+    #[arg(long)]
+    backend_proxy: Option<String>,
+
+    /// Config file whose `backends` entry (TLS, proxy) applies to the URL
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Print the diagnosis as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DemoArgs {
+    /// Synthetic users sending requests
+    #[arg(long, default_value_t = 12, value_parser = clap::value_parser!(u16).range(1..))]
+    pub users: u16,
+
+    /// Fake Ollama backends to start
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..))]
+    pub backends: u8,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Table,
+    Csv,
+    Json,
+    Markdown,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ReportArgs {
+    /// First day to include (YYYY-MM-DD, local time)
+    #[arg(long)]
+    from: Option<NaiveDate>,
+
+    /// Last day to include (YYYY-MM-DD, local time)
+    #[arg(long)]
+    to: Option<NaiveDate>,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    format: ReportFormat,
+
+    /// One row per user, per model, or per user and model
+    #[arg(long, value_enum, default_value_t = ReportGroup::UserModel)]
+    by: ReportGroup,
+
+    /// Usage log written by the dispatcher
+    #[arg(long, env = "OLLAMAMQ_USAGE_LOG", default_value = "ollamamq-usage.jsonl")]
+    usage_log: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ClientArgs {
+    /// Base URL of the running instance
+    #[arg(long, env = "OLLAMAMQ_URL", default_value = "http://localhost:11435")]
+    url: String,
+
+    /// Token the instance was started with (--admin-token)
+    #[arg(long, env = "OLLAMAMQ_ADMIN_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Print the raw JSON response
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TargetArgs {
+    /// User id, IP address, or prefix such as 2001:db8::/64
+    target: String,
+
+    /// Treat TARGET as a user id even if it looks like an IP address
+    #[arg(long)]
+    user: bool,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BlockArgs {
+    #[command(flatten)]
+    target: TargetArgs,
+
+    /// Why the target is blocked, shown in the TUI and the admin API
+    #[arg(long)]
+    reason: Option<String>,
+
+    /// Lift the block automatically after this long, e.g. 90m, 12h or 7d
+    #[arg(long, value_parser = parse_expiry)]
+    expires_in: Option<u64>,
+}
+
+/// Seconds of a `--expires-in` duration.
+fn parse_expiry(s: &str) -> Result<u64, String> {
+    match ollamamq::maintenance::parse_duration(s)?.num_seconds() {
+        secs if secs > 0 => Ok(secs as u64),
+        _ => Err("must be positive".to_string()),
+    }
+}
+
+impl BlockArgs {
+    fn body(&self) -> Value {
+        let mut body = self.target.body();
+        if let Some(reason) = &self.reason {
+            body["reason"] = json!(reason);
+        }
+        if let Some(secs) = self.expires_in {
+            body["expires_in_secs"] = json!(secs);
+        }
+        body
+    }
+}
+
+impl TargetArgs {
+    fn body(&self) -> Value {
+        match self.target.parse::<IpPrefix>() {
+            Ok(prefix) if !self.user => json!({ "ip": prefix }),
+            _ => json!({ "user": self.target }),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.body().get("ip") {
+            Some(ip) => format!("IP {}", ip.as_str().unwrap_or_default()),
+            None => format!("user '{}'", self.target),
+        }
+    }
+}
+
+/// Runs a subcommand and returns the process exit code.
+pub async fn run(command: Command) -> i32 {
+    let result = match command {
+        Command::Status(c) => get(&c, "/admin/status").await.map(|v| print_status(&c, &v)),
+        Command::Queues(c) => get(&c, "/admin/queues").await.map(|v| print_queues(&c, &v)),
+        Command::Block(b) => post(&b.target.client, "/admin/block", b.body()).await
+            .map(|_| println!("Blocked {}", b.target.describe())),
+        Command::Unblock(t) => post(&t.client, "/admin/unblock", t.body()).await
+            .map(|_| println!("Unblocked {}", t.describe())),
+        Command::Scale(c) => match get(&c, "/admin/scale").await {
+            Ok(v) => return print_scale(&c, &v),
+            Err(e) => Err(e),
+        },
+        Command::Report(r) => report(&r),
+        Command::Probe(p) => match probe(&p).await {
+            Ok(healthy) => return if healthy { 0 } else { 1 },
+            Err(e) => Err(e),
+        },
+        Command::Demo(_) => unreachable!("the demo runs the dispatcher itself"),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn request(c: &ClientArgs, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    let url = format!("{}{}", c.url.trim_end_matches('/'), path);
+    let req = reqwest::Client::new().request(method, url);
+    match c.token {
+        Some(ref token) => req.bearer_auth(token),
+        None => req,
+    }
+}
+
+async fn send(c: &ClientArgs, req: reqwest::RequestBuilder) -> Result<String, String> {
+    let res = req.send().await.map_err(|e| format!("cannot reach {}: {}", c.url, e))?;
+    let status = res.status();
+    let text = res.text().await.map_err(|e| e.to_string())?;
+    match status {
+        s if s.is_success() => Ok(text),
+        StatusCode::UNAUTHORIZED if c.token.is_none() => Err("missing admin token (use --token or OLLAMAMQ_ADMIN_TOKEN)".to_string()),
+        _ => Err(format!("{} {}", status, text.trim())),
+    }
+}
+
+async fn get(c: &ClientArgs, path: &str) -> Result<Value, String> {
+    let text = send(c, request(c, reqwest::Method::GET, path)).await?;
+    serde_json::from_str(&text).map_err(|e| format!("unexpected response: {}", e))
+}
+
+async fn post(c: &ClientArgs, path: &str, body: Value) -> Result<(), String> {
+    send(c, request(c, reqwest::Method::POST, path).json(&body)).await.map(|_| ())
+}
+
+fn num(v: &Value, key: &str) -> u64 {
+    v.get(key).and_then(Value::as_u64).unwrap_or(0)
+}
+
+fn print_status(c: &ClientArgs, v: &Value) {
+    if c.json {
+        println!("{}", serde_json::to_string_pretty(v).unwrap_or_default());
+        return;
+    }
+    println!(
+        "ollamaMQ {} at {}: {} queued, {} processing, {} processed, {} dropped, {} users",
+        v["version"].as_str().unwrap_or("?"),
+        c.url,
+        num(v, "queued"),
+        num(v, "processing"),
+        num(v, "processed"),
+        num(v, "dropped"),
+        num(v, "users"),
+    );
+    println!(
+        "Up {}, {:.1} requests/s, {} in flight",
+        format_uptime(std::time::Duration::from_secs(num(v, "uptime_secs"))),
+        v["requests_per_sec"].as_f64().unwrap_or(0.0),
+        num(v, "in_flight"),
+    );
+    if let Some(user) = v["vip_user"].as_str() {
+        println!("VIP: {}", user);
+    }
+    if let Some(user) = v["boost_user"].as_str() {
+        println!("Boost: {}", user);
+    }
+    println!();
+    println!("{:<40} {:<8} {:>6} {:>10} {:>7} {:>8}", "BACKEND", "STATE", "ACTIVE", "PROCESSED", "FAILED", "AVG");
+    for b in v["backends"].as_array().into_iter().flatten() {
+        let mut url = b["url"].as_str().unwrap_or_default().to_string();
+        if b["canary"].as_bool() == Some(true) {
+            url.push_str(" (canary)");
+        }
+        let avg = b["avg_latency_ms"].as_u64()
+            .map(|ms| format_duration(std::time::Duration::from_millis(ms)))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<40} {:<8} {:>6} {:>10} {:>7} {:>8}",
+            url,
+            if b["online"].as_bool() == Some(true) { "online" } else { "offline" },
+            num(b, "active_requests"),
+            num(b, "processed"),
+            num(b, "failed"),
+            avg,
+        );
+    }
+}
+
+fn print_queues(c: &ClientArgs, v: &Value) {
+    if c.json {
+        println!("{}", serde_json::to_string_pretty(v).unwrap_or_default());
+        return;
+    }
+    println!("{:<30} {:>7} {:>10} {:>10} {:>8} {:>9} {:>9}", "USER", "QUEUED", "PROCESSING", "PROCESSED", "DROPPED", "BYTES IN", "BYTES OUT");
+    for u in v.as_array().into_iter().flatten() {
+        println!(
+            "{:<30} {:>7} {:>10} {:>10} {:>8} {:>9} {:>9}",
+            u["user_id"].as_str().unwrap_or_default(),
+            num(u, "queued"),
+            num(u, "processing"),
+            num(u, "processed"),
+            num(u, "dropped"),
+            format_bytes(num(u, "bytes_in")),
+            format_bytes(num(u, "bytes_out")),
+        );
+    }
+}
+
+/// Prints the scale signal and returns the exit code that encodes it.
+fn print_scale(c: &ClientArgs, v: &Value) -> i32 {
+    let signal = v["signal"].as_str().unwrap_or("steady");
+    if c.json {
+        println!("{}", serde_json::to_string_pretty(v).unwrap_or_default());
+    } else {
+        match v["reason"].as_str().filter(|r| !r.is_empty()) {
+            Some(reason) => println!("{}: {}", signal, reason),
+            None => println!("{}", signal),
+        }
+        for url in v["idle_backends"].as_array().into_iter().flatten() {
+            println!("  idle: {}", url.as_str().unwrap_or_default());
+        }
+    }
+    match signal {
+        "up" => 10,
+        "down" => 11,
+        _ => 0,
+    }
+}
+
+fn report(r: &ReportArgs) -> Result<(), String> {
+    if r.from.zip(r.to).is_some_and(|(from, to)| from > to) {
+        return Err("--from is after --to".to_string());
+    }
+    let (rows, total) = summarize(&r.usage_log, r.from, r.to, r.by)
+        .map_err(|e| format!("cannot read usage log {}: {}", r.usage_log.display(), e))?;
+    let period = format!(
+        "{} to {}",
+        r.from.map_or("start".to_string(), |d| d.to_string()),
+        r.to.map_or("end".to_string(), |d| d.to_string()),
+    );
+
+    let key_columns: &[&str] = match r.by {
+        ReportGroup::User => &["user"],
+        ReportGroup::Model => &["model"],
+        ReportGroup::UserModel => &["user", "model"],
+    };
+    let keys = |s: &UsageSummary| -> Vec<String> {
+        let mut keys = Vec::new();
+        if let Some(ref user) = s.user {
+            keys.push(user.clone());
+        }
+        if let Some(ref model) = s.model {
+            keys.push(model.clone());
+        }
+        keys
+    };
+    let values = |s: &UsageSummary| -> Vec<String> {
+        vec![
+            s.requests.to_string(),
+            s.completed.to_string(),
+            s.failed.to_string(),
+            s.other.to_string(),
+            s.prompt_tokens.to_string(),
+            s.completion_tokens.to_string(),
+            format!("{:.1}", s.backend_secs),
+            format!("{:.1}", s.queue_secs),
+        ]
+    };
+    let value_columns = [
+        "requests", "completed", "failed", "other", "prompt_tokens", "completion_tokens", "backend_secs", "queue_secs",
+    ];
+    let header: Vec<&str> = key_columns.iter().chain(value_columns.iter()).copied().collect();
+
+    match r.format {
+        ReportFormat::Json => {
+            let body = json!({
+                "from": r.from.map(|d| d.to_string()),
+                "to": r.to.map(|d| d.to_string()),
+                "rows": rows,
+                "total": total,
+            });
+            println!("{}", serde_json::to_string_pretty(&body).unwrap_or_default());
+        }
+        ReportFormat::Csv => {
+            println!("{}", header.join(","));
+            for s in &rows {
+                let fields: Vec<String> = keys(s).iter().map(|k| csv_field(k)).chain(values(s)).collect();
+                println!("{}", fields.join(","));
+            }
+        }
+        ReportFormat::Markdown => {
+            println!("## ollamaMQ usage, {}", period);
+            println!();
+            println!("| {} |", header.join(" | "));
+            println!("|{}", header.iter().enumerate()
+                .map(|(i, _)| if i < key_columns.len() { " --- |" } else { " ---: |" })
+                .collect::<String>());
+            for s in &rows {
+                let fields: Vec<String> = keys(s).iter().map(|k| k.replace('|', "\\|")).chain(values(s)).collect();
+                println!("| {} |", fields.join(" | "));
+            }
+            let mut fields = vec!["**total**".to_string()];
+            fields.extend(std::iter::repeat_n(String::new(), key_columns.len() - 1));
+            fields.extend(values(&total));
+            println!("| {} |", fields.join(" | "));
+        }
+        ReportFormat::Table => {
+            println!("Usage {} ({} requests)", period, total.requests);
+            println!();
+            let key_width = 30;
+            let line = |keys: Vec<String>, values: Vec<String>| {
+                let mut out: String = keys.iter().map(|k| format!("{:<w$} ", k, w = key_width)).collect();
+                out.push_str(&values.iter().map(|v| format!("{:>10}", v)).collect::<Vec<_>>().join(" "));
+                println!("{}", out.trim_end());
+            };
+            let upper = |cols: &[&str]| cols.iter().map(|c| c.to_uppercase()).collect::<Vec<_>>();
+            let short = ["REQUESTS", "COMPLETED", "FAILED", "OTHER", "PROMPT", "COMPLETION", "BACKEND_S", "QUEUE_S"];
+            line(upper(key_columns), short.iter().map(|c| c.to_string()).collect());
+            for s in &rows {
+                line(keys(s), values(s));
+            }
+            let mut total_keys = vec!["TOTAL".to_string()];
+            total_keys.extend(std::iter::repeat_n(String::new(), key_columns.len() - 1));
+            line(total_keys, values(&total));
+        }
+    }
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Probes a backend and prints the diagnosis; returns whether it is usable.
+async fn probe(p: &ProbeArgs) -> Result<bool, String> {
+    let url = normalize_backend_url(&p.url);
+    let file = match p.config {
+        Some(ref path) => FileConfig::load(path)?,
+        None => FileConfig::default(),
+    };
+    let client = client_for(file.backend_config(&url), p.backend_proxy.as_deref(), std::time::Duration::from_secs(p.timeout))
+        .map_err(|e| format!("invalid TLS or proxy settings for {}: {}", url, e))?;
+
+    let check = check_backend(&client, &url).await;
+    let model = p.model.clone().or_else(|| pick_model(&check));
+    let generation = match model {
+        Some(ref model) if check.online && !p.no_generate => Some(try_generation(&client, &url, check.api_type, model).await),
+        _ => None,
+    };
+    let (healthy, verdict) = diagnose(&check, generation.as_ref(), p.no_generate);
+
+    if p.json {
+        let body = json!({
+            "url": url,
+            "healthy": healthy,
+            "verdict": verdict,
+            "check": check,
+            "version": check.features.as_ref().map(|f| f.version.clone()),
+            "missing_features": check.features.as_ref().map(|f| f.missing()).unwrap_or_default(),
+            "generation": generation,
+        });
+        println!("{}", serde_json::to_string_pretty(&body).unwrap_or_default());
+        return Ok(healthy);
+    }
+
+    println!("Probing {}", url);
+    println!();
+    for f in &check.findings {
+        let level = match f.level {
+            CheckLevel::Ok => "ok",
+            CheckLevel::Missing => "missing",
+            CheckLevel::Problem => "PROBLEM",
+        };
+        println!("  {:<14} {:<8} {}", f.endpoint, level, f.detail);
+    }
+    if let Some(ref g) = generation {
+        println!(
+            "  {:<14} {:<8} {} in {}: {}",
+            g.endpoint,
+            if g.ok { "ok" } else { "FAILED" },
+            g.model,
+            format_duration(std::time::Duration::from_millis(g.millis)),
+            g.detail,
+        );
+    }
+    println!();
+    println!("State:     {}", if check.online { "online" } else { "offline" });
+    println!("API:       {}", match check.api_type {
+        BackendApiType::Unknown => "unknown",
+        BackendApiType::Ollama => "Ollama",
+        BackendApiType::OpenAi => "OpenAI",
+        BackendApiType::Both => "Ollama and OpenAI",
+    });
+    if let Some(ref features) = check.features {
+        let missing = features.missing();
+        if missing.is_empty() {
+            println!("Version:   Ollama {}", features.version);
+        } else {
+            println!("Version:   Ollama {} (without {})", features.version, missing.join(", "));
+        }
+    }
+    let mut models: Vec<String> = check.models.iter()
+        .map(|m| if check.loaded.contains(m) { format!("{} (loaded)", m) } else { m.clone() })
+        .collect();
+    models.sort();
+    println!("Models:    {}", if models.is_empty() { "none".to_string() } else { models.join(", ") });
+    println!();
+    println!("{}", verdict);
+    Ok(healthy)
+}
+
+/// Whether the probed backend can serve requests, and a one-line explanation.
+fn diagnose(check: &BackendCheck, generation: Option<&Generation>, skipped: bool) -> (bool, String) {
+    if !check.online {
+        return (false, "Unhealthy: the dispatcher marks it offline, as none of /api/tags, /v1/models and / answered 200".to_string());
+    }
+    if check.api_type == BackendApiType::Unknown {
+        return (false, "Unhealthy: it answers, but neither /api/tags nor /v1/models returned a model list, so no API route will be sent to it".to_string());
+    }
+    if check.models.is_empty() {
+        return (false, "Unhealthy: online, but it lists no models, so no request will be routed to it".to_string());
+    }
+    match generation {
+        Some(g) if !g.ok => (false, format!("Unhealthy: online, but generating with {} failed: {}", g.model, g.detail)),
+        Some(g) if !smart_model_match(&g.model, &check.models) => (true, format!("Healthy, but {} is not in its model list, so requests for it are routed to other backends", g.model)),
+        Some(g) => (true, format!("Healthy: online and generating ({} answered in {})", g.model, format_duration(std::time::Duration::from_millis(g.millis)))),
+        None if skipped => (true, "Healthy: online (generation check skipped)".to_string()),
+        None => (true, "Healthy: online".to_string()),
+    }
+}
@@ -0,0 +1,261 @@
+//! Extra users-table columns defined in the config file (`tui_columns`), each
+//! computed from a user's counters by a small arithmetic expression such as
+//! `dropped / (processed + dropped) * 100`.
+
+use serde::Deserialize;
+
+/// Counters an expression can refer to, per user.
+pub const VARIABLES: &[&str] = &[
+    "queued",
+    "processing",
+    "processed",
+    "dropped",
+    "recent_5m",
+    "recent_1h",
+    "recent_24h",
+    "bytes_in",
+    "bytes_out",
+    "tool_requests",
+    "tool_calls",
+    "prompt_tokens",
+    "completion_tokens",
+    "tokens",
+];
+
+fn default_decimals() -> usize {
+    1
+}
+
+/// A column under `tui_columns` in the config file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnConfig {
+    /// Header of the column.
+    pub name: String,
+    /// Value of the column, e.g. `completion_tokens / processed`.
+    pub expr: Expr,
+    /// Digits after the decimal point.
+    #[serde(default = "default_decimals")]
+    pub decimals: usize,
+}
+
+impl ColumnConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("has an empty name".to_string());
+        }
+        if self.decimals > 6 {
+            return Err(format!("'{}' has more than 6 decimals", self.name));
+        }
+        Ok(())
+    }
+
+    /// The column's value for the counters `var` gives; `-` where the
+    /// expression is undefined, e.g. a division by zero.
+    pub fn format(&self, var: impl Fn(&str) -> f64) -> String {
+        match self.expr.eval(&var) {
+            Some(v) => format!("{:.*}", self.decimals, v),
+            None => "-".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Func {
+    Min,
+    Max,
+}
+
+/// A parsed expression: numbers, the counters in `VARIABLES`, `+ - * /`,
+/// parentheses, and `min(...)`/`max(...)`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub enum Expr {
+    Num(f64),
+    /// Index into `VARIABLES`.
+    Var(usize),
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+impl TryFrom<String> for Expr {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Expr::parse(&source)
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+        let expr = parser.sum()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(t) => Err(format!("unexpected '{}' in '{}'", t, source)),
+        }
+    }
+
+    /// Value of the expression; `None` on a division by zero or a result
+    /// that isn't a finite number.
+    pub fn eval(&self, var: &dyn Fn(&str) -> f64) -> Option<f64> {
+        let v = match self {
+            Expr::Num(n) => *n,
+            Expr::Var(i) => var(VARIABLES[*i]),
+            Expr::Neg(e) => -e.eval(var)?,
+            Expr::Bin(op, a, b) => {
+                let (a, b) = (a.eval(var)?, b.eval(var)?);
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div if b == 0.0 => return None,
+                    Op::Div => a / b,
+                }
+            }
+            Expr::Call(func, args) => {
+                let values = args.iter().map(|a| a.eval(var)).collect::<Option<Vec<f64>>>()?;
+                match func {
+                    Func::Min => values.into_iter().fold(f64::INFINITY, f64::min),
+                    Func::Max => values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                }
+            }
+        };
+        v.is_finite().then_some(v)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Sym(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Sym(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, d)) = chars.peek().filter(|(_, d)| d.is_ascii_digit() || *d == '.') {
+                end = i + d.len_utf8();
+                chars.next();
+            }
+            let number = &source[start..end];
+            tokens.push(Token::Num(number.parse().map_err(|_| format!("invalid number '{}'", number))?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, d)) = chars.peek().filter(|(_, d)| d.is_ascii_alphanumeric() || *d == '_') {
+                end = i + d.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(source[start..end].to_string()));
+        } else if "+-*/(),".contains(c) {
+            tokens.push(Token::Sym(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}' in '{}'", c, source));
+        }
+    }
+    if tokens.is_empty() {
+        return Err("empty expression".to_string());
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, sym: char) -> bool {
+        if self.tokens.get(self.pos) == Some(&Token::Sym(sym)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = if self.eat('+') { Op::Add } else if self.eat('-') { Op::Sub } else { return Ok(expr) };
+            expr = Expr::Bin(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat('*') { Op::Mul } else if self.eat('/') { Op::Div } else { return Ok(expr) };
+            expr = Expr::Bin(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Sym('(')) => {
+                let expr = self.sum()?;
+                if !self.eat(')') {
+                    return Err("missing ')'".to_string());
+                }
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if self.eat('(') => {
+                let func = match name.as_str() {
+                    "min" => Func::Min,
+                    "max" => Func::Max,
+                    _ => return Err(format!("unknown function '{}' (min and max are available)", name)),
+                };
+                let mut args = vec![self.sum()?];
+                while self.eat(',') {
+                    args.push(self.sum()?);
+                }
+                if !self.eat(')') {
+                    return Err(format!("missing ')' after the arguments of {}", name));
+                }
+                Ok(Expr::Call(func, args))
+            }
+            Some(Token::Ident(name)) => match VARIABLES.iter().position(|v| *v == name) {
+                Some(i) => Ok(Expr::Var(i)),
+                None => Err(format!("unknown counter '{}' (available: {})", name, VARIABLES.join(", "))),
+            },
+            Some(t) => Err(format!("unexpected '{}'", t)),
+            None => Err("expression ends too early".to_string()),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Ollama version detection and per-backend request adaptation, so clients
+//! written against a current Ollama keep working on older backends.
+
+use bytes::{Bytes, BytesMut};
+use serde_json::{Value, json};
+
+/// `/api/embed` (batch embeddings) appeared in Ollama 0.3.0.
+const EMBED_SINCE: (u32, u32, u32) = (0, 3, 0);
+/// JSON schemas in `format` (structured outputs) appeared in Ollama 0.5.0.
+const STRUCTURED_OUTPUTS_SINCE: (u32, u32, u32) = (0, 5, 0);
+/// `format: "json"` appeared in Ollama 0.1.9.
+const FORMAT_JSON_SINCE: (u32, u32, u32) = (0, 1, 9);
+
+/// What an Ollama backend supports, derived from `/api/version`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackendFeatures {
+    pub version: String,
+    pub embed: bool,
+    pub structured_outputs: bool,
+    pub format_json: bool,
+}
+
+impl BackendFeatures {
+    /// Features of an Ollama version string such as `0.5.7` or `0.1.32-rc1`.
+    /// Unparseable versions (custom builds) are assumed to support everything.
+    pub fn from_version(version: &str) -> Self {
+        let parsed = parse_version(version);
+        let since = |min: (u32, u32, u32)| parsed.is_none_or(|v| v >= min);
+        Self {
+            version: version.to_string(),
+            embed: since(EMBED_SINCE),
+            structured_outputs: since(STRUCTURED_OUTPUTS_SINCE),
+            format_json: since(FORMAT_JSON_SINCE),
+        }
+    }
+
+    /// Features the backend lacks, for logs and the admin API.
+    pub fn missing(&self) -> Vec<&'static str> {
+        [(self.embed, "/api/embed"), (self.structured_outputs, "structured outputs"), (self.format_json, "format: json")]
+            .into_iter()
+            .filter(|(supported, _)| !supported)
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let core = version.trim().trim_start_matches('v').split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??, parts.next().flatten().unwrap_or(0)))
+}
+
+/// A request rewritten for an older backend.
+pub struct Adapted {
+    /// Path sent to the backend.
+    pub path: String,
+    pub body: Bytes,
+    /// The response has to be translated back with [`LegacyEmbed`].
+    pub legacy_embed: bool,
+    /// What was changed, for the debug log.
+    pub changes: Vec<&'static str>,
+}
+
+/// Rewrites a request the backend can't handle as sent, or returns `None`
+/// when it can be forwarded unchanged (or can't be adapted).
+pub fn adapt(path: &str, body: &[u8], features: &BackendFeatures) -> Option<Adapted> {
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let mut changes = Vec::new();
+    let mut backend_path = path.to_string();
+    let mut legacy_embed = false;
+
+    if path == "/api/embed" && !features.embed {
+        // The legacy endpoint embeds one prompt per call
+        let input = match json.get("input") {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Array(items)) if items.len() == 1 => items[0].as_str()?.to_string(),
+            _ => return None,
+        };
+        let obj = json.as_object_mut()?;
+        obj.remove("input");
+        obj.remove("truncate");
+        obj.insert("prompt".to_string(), Value::String(input));
+        backend_path = "/api/embeddings".to_string();
+        legacy_embed = true;
+        changes.push("/api/embed -> /api/embeddings");
+    }
+
+    if matches!(path, "/api/generate" | "/api/chat") {
+        match json.get("format") {
+            Some(Value::Object(_)) if !features.structured_outputs && features.format_json => {
+                // Still JSON, just not held to the schema
+                json["format"] = json!("json");
+                changes.push("format schema -> \"json\"");
+            }
+            Some(Value::Object(_) | Value::String(_)) if !features.format_json => {
+                json.as_object_mut()?.remove("format");
+                changes.push("format removed");
+            }
+            _ => {}
+        }
+    }
+
+    if changes.is_empty() {
+        return None;
+    }
+    Some(Adapted {
+        path: backend_path,
+        body: Bytes::from(serde_json::to_vec(&json).ok()?),
+        legacy_embed,
+        changes,
+    })
+}
+
+/// Turns an `/api/embeddings` answer back into the `/api/embed` shape.
+pub struct LegacyEmbed {
+    model: Option<String>,
+    buffer: BytesMut,
+}
+
+impl LegacyEmbed {
+    pub fn new(model: Option<String>) -> Self {
+        Self { model, buffer: BytesMut::new() }
+    }
+
+    /// Buffers the response; it is only complete once the stream ends.
+    pub fn push(&mut self, chunk: Bytes) -> Bytes {
+        self.buffer.extend_from_slice(&chunk);
+        Bytes::new()
+    }
+
+    /// The translated response, or the original one if it isn't an embedding
+    /// (e.g. an error).
+    pub fn finish(&mut self) -> Bytes {
+        let raw = self.buffer.split().freeze();
+        let Ok(json) = serde_json::from_slice::<Value>(&raw) else {
+            return raw;
+        };
+        let Some(embedding) = json.get("embedding") else {
+            return raw;
+        };
+        let translated = json!({
+            "model": self.model,
+            "embeddings": [embedding],
+        });
+        serde_json::to_vec(&translated).map(Bytes::from).unwrap_or(raw)
+    }
+}
@@ -0,0 +1,32 @@
+//! Zstd compression of request bodies and logs the dispatcher persists. JSON
+//! and base64 images shrink enough that large multimodal bodies don't fill the
+//! disk as fast.
+
+use std::io::{self, Read, Write};
+
+/// Fast enough to sit on the request path, and most of the gain of higher levels.
+pub const LEVEL: i32 = 3;
+
+/// File name suffix of compressed files.
+pub const SUFFIX: &str = "zst";
+
+pub fn encode(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::encode_all(data, LEVEL)
+}
+
+pub fn decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::decode_all(data)
+}
+
+/// Compresses everything `reader` yields into `writer`, e.g. file to file.
+pub fn copy_compressed(reader: &mut impl Read, writer: impl Write) -> io::Result<u64> {
+    let mut encoder = zstd::Encoder::new(writer, LEVEL)?;
+    let copied = io::copy(reader, &mut encoder)?;
+    encoder.finish()?;
+    Ok(copied)
+}
+
+/// Reads a compressed stream, e.g. a rotated log file.
+pub fn reader<R: io::BufRead>(reader: R) -> io::Result<impl Read> {
+    zstd::Decoder::with_buffer(reader)
+}
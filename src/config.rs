@@ -0,0 +1,294 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::identity::IdentityConfig;
+use crate::admin::AdminRole;
+use crate::dispatcher::{BackendApiType, MultiIpPolicy};
+use crate::columns::ColumnConfig;
+use crate::experiments::ExperimentConfig;
+use crate::hooks::ResponseHookConfig;
+use crate::namespace::NamespaceConfig;
+use crate::policy::RoutePolicy;
+use crate::slo::SloConfig;
+use crate::templates::PromptTemplate;
+use crate::user_ids::UserIdRules;
+
+/// Per-tier overrides. Unset fields fall back to the global CLI settings.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierConfig {
+    /// User ids belonging to this tier.
+    pub users: Vec<String>,
+    /// Consecutive failed requests before a cooldown kicks in (0 disables).
+    pub cooldown_failures: Option<u32>,
+    /// Length of the cooldown in seconds.
+    pub cooldown_secs: Option<u64>,
+    /// Requests a user may have running at once (0 = unlimited).
+    pub max_concurrent: Option<usize>,
+    /// Requests a user may have waiting in the queue (0 = unlimited).
+    pub max_queued: Option<usize>,
+    /// Estimated prompt tokens a user may have waiting in the queue (0 = unlimited).
+    pub max_queued_tokens: Option<u64>,
+    /// Handling of a user id connected from several IPs at once.
+    pub multi_ip: Option<MultiIpPolicy>,
+    /// Reject the tier's requests with 503 while the estimated queue wait
+    /// exceeds this many seconds (load shedding).
+    pub shed_wait_secs: Option<u64>,
+    /// Latency objectives, e.g. p95 of the first chunk under 2 seconds.
+    pub slos: Vec<SloConfig>,
+}
+
+/// Per-backend settings, keyed by backend URL.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    /// Model patterns this backend may serve (`*` wildcards, e.g. `llama3*`);
+    /// empty means any model.
+    pub models: Vec<String>,
+    /// PEM bundle of extra CA certificates trusted for this backend.
+    pub ca_cert: Option<PathBuf>,
+    /// Skip certificate verification (self-signed homelab setups).
+    pub accept_invalid_certs: bool,
+    /// PEM client certificate for mTLS; the key may be in the same file.
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key for `client_cert`, if kept in a separate file.
+    pub client_key: Option<PathBuf>,
+    /// HTTP or SOCKS proxy for this backend, overriding `--backend-proxy`;
+    /// `direct` connects without one.
+    pub proxy: Option<String>,
+    /// API the backend speaks, instead of what the health check detects.
+    /// `openai` backends get `/api/chat` and `/api/generate` translated.
+    pub api: Option<BackendApi>,
+    /// Timeout in seconds per model pattern on this backend, ahead of
+    /// `model_timeouts` and learned timeouts.
+    pub timeouts: HashMap<String, u64>,
+}
+
+/// API of a backend as set in the config file.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendApi {
+    Ollama,
+    OpenAi,
+}
+
+impl BackendApi {
+    pub fn api_type(self) -> BackendApiType {
+        match self {
+            BackendApi::Ollama => BackendApiType::Ollama,
+            BackendApi::OpenAi => BackendApiType::OpenAi,
+        }
+    }
+}
+
+impl BackendConfig {
+    /// Applies the TLS settings to an HTTP client builder.
+    pub fn apply_tls(&self, mut builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder, String> {
+        let read = |path: &PathBuf| fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e));
+        if let Some(ref path) = self.ca_cert {
+            let certs = reqwest::Certificate::from_pem_bundle(&read(path)?)
+                .map_err(|e| format!("invalid CA bundle {}: {}", path.display(), e))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(ref path) = self.client_cert {
+            let mut pem = read(path)?;
+            if let Some(ref key) = self.client_key {
+                pem.push(b'\n');
+                pem.extend(read(key)?);
+            }
+            let identity = reqwest::Identity::from_pem(&pem)
+                .map_err(|e| format!("invalid client certificate {}: {}", path.display(), e))?;
+            builder = builder.identity(identity);
+        } else if self.client_key.is_some() {
+            return Err("client_key is set without client_cert".to_string());
+        }
+        Ok(builder.tls_danger_accept_invalid_certs(self.accept_invalid_certs))
+    }
+}
+
+/// Value of a backend's `proxy` that bypasses the global proxy.
+pub const DIRECT_PROXY: &str = "direct";
+
+/// Routes a backend client through `proxy` (`http://`, `https://`, `socks5://`,
+/// `socks5h://` or `socks4://`, credentials in the URL); `direct` disables any
+/// proxy, including ones from the environment.
+pub fn apply_proxy(builder: reqwest::ClientBuilder, proxy: Option<&str>) -> Result<reqwest::ClientBuilder, String> {
+    match proxy.map(str::trim) {
+        None => Ok(builder),
+        Some(DIRECT_PROXY) => Ok(builder.no_proxy()),
+        Some(url) => {
+            let proxy = reqwest::Proxy::all(url).map_err(|e| format!("invalid proxy '{}': {}", url, e))?;
+            Ok(builder.proxy(proxy))
+        }
+    }
+}
+
+/// An additional admin API token with a restricted role.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AdminToken {
+    /// Label for logs and documentation only.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub token: String,
+    pub role: AdminRole,
+}
+
+/// Structured settings loaded from the `--config` JSON file.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub tiers: HashMap<String, TierConfig>,
+    /// Tier applied to users not listed in any tier.
+    pub default_tier: Option<String>,
+    /// Request-body rules keyed by route path (e.g. `/api/chat`).
+    pub policies: HashMap<String, RoutePolicy>,
+    /// Post-processing applied to backend responses, in order.
+    pub response_hooks: Vec<ResponseHookConfig>,
+    /// Named prompt templates injected into `/api/chat` requests.
+    pub templates: HashMap<String, PromptTemplate>,
+    /// Per-backend settings keyed by URL (`http://gpu1:11434`).
+    pub backends: HashMap<String, BackendConfig>,
+    /// Role-restricted admin API tokens, in addition to `--admin-token` (root).
+    pub admin_tokens: Vec<AdminToken>,
+    /// Resolve user ids, tiers and quotas through an external endpoint.
+    pub identity: Option<IdentityConfig>,
+    /// Team namespaces by name, with their API keys and namespace-wide caps.
+    pub namespaces: HashMap<String, NamespaceConfig>,
+    /// A/B experiments by name, each splitting users between model or backend arms.
+    pub experiments: HashMap<String, ExperimentConfig>,
+    /// Extra users-table columns computed from each user's counters.
+    pub tui_columns: Vec<ColumnConfig>,
+    /// Models to try, in order, when a backend can't serve the requested one.
+    pub model_fallbacks: HashMap<String, Vec<String>>,
+    /// Backend timeout in seconds per model pattern, ahead of learned timeouts.
+    pub model_timeouts: HashMap<String, u64>,
+    /// Normalization and validation of user ids before they become queue keys.
+    pub user_ids: UserIdRules,
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config: FileConfig = serde_json::from_str(&content).map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+        if let Some(ref t) = config.default_tier
+            && !config.tiers.contains_key(t)
+        {
+            return Err(format!("default_tier '{}' is not defined in tiers", t));
+        }
+        for (name, tier) in &config.tiers {
+            for slo in &tier.slos {
+                slo.validate().map_err(|e| format!("tier '{}' slo: {}", name, e))?;
+            }
+        }
+        for (name, template) in &config.templates {
+            if let Some(t) = template.tiers.iter().find(|t| !config.tiers.contains_key(*t)) {
+                return Err(format!("template '{}' refers to unknown tier '{}'", name, t));
+            }
+            if template.messages.iter().any(|m| m.get("role").and_then(|r| r.as_str()).is_none()) {
+                return Err(format!("template '{}' has a message without a 'role'", name));
+            }
+        }
+        for (i, t) in config.admin_tokens.iter().enumerate() {
+            let label = t.name.clone().unwrap_or_else(|| format!("#{}", i + 1));
+            if t.token.trim().is_empty() {
+                return Err(format!("admin token {} is empty", label));
+            }
+            if config.admin_tokens[..i].iter().any(|other| other.token == t.token) {
+                return Err(format!("admin token {} is used more than once", label));
+            }
+        }
+        for (name, ns) in &config.namespaces {
+            if !crate::namespace::is_valid(name) {
+                return Err(format!("namespace '{}' may only use letters, digits, '-', '_' and '.'", name));
+            }
+            if ns.api_keys.iter().any(|k| k.trim().is_empty()) {
+                return Err(format!("namespace '{}' has an empty API key", name));
+            }
+        }
+        let mut keys: Vec<&String> = config.namespaces.values().flat_map(|ns| &ns.api_keys).collect();
+        keys.sort();
+        if keys.windows(2).any(|w| w[0] == w[1]) {
+            return Err("a namespace API key is used more than once".to_string());
+        }
+        if config.admin_tokens.iter().any(|t| keys.contains(&&t.token)) {
+            return Err("a namespace API key is also an admin token".to_string());
+        }
+        for (i, hook) in config.response_hooks.iter().enumerate() {
+            hook.validate().map_err(|e| format!("response hook #{}: {}", i + 1, e))?;
+        }
+        for (name, experiment) in &config.experiments {
+            experiment.validate().map_err(|e| format!("experiment '{}': {}", name, e))?;
+        }
+        for (i, column) in config.tui_columns.iter().enumerate() {
+            column.validate().map_err(|e| format!("tui column #{}: {}", i + 1, e))?;
+            if config.tui_columns[..i].iter().any(|other| other.name == column.name) {
+                return Err(format!("tui column '{}' is defined more than once", column.name));
+            }
+        }
+        for (model, chain) in &config.model_fallbacks {
+            if chain.is_empty() {
+                return Err(format!("model_fallbacks '{}' lists no fallback", model));
+            }
+            if chain.iter().any(|m| crate::dispatcher::model_name_matches(m, model)) {
+                return Err(format!("model_fallbacks '{}' falls back to itself", model));
+            }
+        }
+        if let Some(pattern) = config.model_timeouts.iter().find(|(_, secs)| **secs == 0).map(|(p, _)| p) {
+            return Err(format!("model_timeouts '{}' must be at least 1 second", pattern));
+        }
+        for (url, backend) in &config.backends {
+            if let Some(pattern) = backend.timeouts.iter().find(|(_, secs)| **secs == 0).map(|(p, _)| p) {
+                return Err(format!("backend {} timeouts '{}' must be at least 1 second", url, pattern));
+            }
+        }
+        config.user_ids.validate().map_err(|e| format!("user_ids: {}", e))?;
+        // Normalized ids would never match these
+        let unchanged = |user: &String| config.user_ids.apply_qualified(user).as_ref() == Ok(user);
+        for (name, tier) in &config.tiers {
+            if let Some(user) = tier.users.iter().find(|u| !unchanged(u)) {
+                return Err(format!("tier '{}' lists user '{}', which user_ids does not accept as is", name, user));
+            }
+        }
+        for (name, template) in &config.templates {
+            if let Some(user) = template.users.iter().find(|u| !unchanged(u)) {
+                return Err(format!("template '{}' lists user '{}', which user_ids does not accept as is", name, user));
+            }
+        }
+        if let Some(ref identity) = config.identity {
+            identity.validate().map_err(|e| format!("identity: {}", e))?;
+        }
+        for (url, backend) in &config.backends {
+            backend.apply_tls(reqwest::Client::builder())
+                .and_then(|b| apply_proxy(b, backend.proxy.as_deref()))
+                .and_then(|b| b.build().map_err(|e| e.to_string()))
+                .map_err(|e| format!("backend '{}': {}", url, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Name of the tier `user_id` belongs to, if any.
+    pub fn tier_of(&self, user_id: &str) -> Option<&str> {
+        self.tiers.iter()
+            .find(|(_, t)| t.users.iter().any(|u| u == user_id))
+            .map(|(name, _)| name.as_str())
+            .or(self.default_tier.as_deref())
+    }
+
+    pub fn tier_config(&self, user_id: &str) -> Option<&TierConfig> {
+        self.tier_of(user_id).and_then(|name| self.tiers.get(name))
+    }
+
+    /// Settings for a backend; keys are compared after URL normalization.
+    pub fn backend_config(&self, url: &str) -> Option<&BackendConfig> {
+        self.backends.iter()
+            .find(|(key, _)| crate::dispatcher::normalize_backend_url(key) == url)
+            .map(|(_, c)| c)
+    }
+}
@@ -0,0 +1,232 @@
+//! The configuration the running dispatcher actually uses, flattened into
+//! rows for the TUI config view and `GET /admin/config`. Each row carries the
+//! built-in default it is compared with, and backends added or removed through
+//! the admin API since startup are marked as changed.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{fmt::Display, path::Path, time::Duration};
+
+use crate::dispatcher::{AppState, DispatcherConfig};
+
+/// One setting of the effective configuration.
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigEntry {
+    pub section: &'static str,
+    pub key: String,
+    pub value: String,
+    /// Built-in value, `None` where there's nothing to compare with.
+    pub default: Option<String>,
+    /// Changed at runtime since startup.
+    pub changed: bool,
+}
+
+impl ConfigEntry {
+    pub fn is_default(&self) -> bool {
+        self.default.as_ref().is_none_or(|d| *d == self.value)
+    }
+}
+
+struct Entries {
+    section: &'static str,
+    list: Vec<ConfigEntry>,
+}
+
+impl Entries {
+    fn section(&mut self, name: &'static str) {
+        self.section = name;
+    }
+
+    fn add(&mut self, key: impl Into<String>, value: String, default: impl Into<Option<String>>) {
+        self.list.push(ConfigEntry { section: self.section, key: key.into(), value, default: default.into(), changed: false });
+    }
+
+    fn changed(&mut self, key: impl Into<String>, value: String) {
+        self.list.push(ConfigEntry { section: self.section, key: key.into(), value, default: None, changed: true });
+    }
+}
+
+fn choice<T: ValueEnum>(v: &T) -> String {
+    v.to_possible_value().map(|p| p.get_name().to_string()).unwrap_or_default()
+}
+
+fn opt<T: Display>(v: &Option<T>) -> String {
+    v.as_ref().map_or("-".to_string(), |v| v.to_string())
+}
+
+fn secs(d: Duration) -> String {
+    format!("{}s", d.as_secs())
+}
+
+/// Like `secs`, but keeps fractions of a second, which library users can set.
+fn duration(d: Duration) -> String {
+    format!("{:?}", d)
+}
+
+fn opt_secs(v: Option<u64>) -> String {
+    v.map_or("-".to_string(), |s| format!("{}s", s))
+}
+
+/// `0` means no limit for most limits.
+fn limit<T: Display + PartialEq + Default>(v: T) -> String {
+    if v == T::default() { "unlimited".to_string() } else { v.to_string() }
+}
+
+fn path(p: &Option<impl AsRef<Path>>) -> String {
+    p.as_ref().map_or("-".to_string(), |p| p.as_ref().display().to_string())
+}
+
+/// The effective configuration of `state`, grouped by section.
+pub fn effective(state: &AppState) -> Vec<ConfigEntry> {
+    let c = &state.config;
+    let d = DispatcherConfig::default();
+    let mut e = Entries { section: "", list: Vec::new() };
+
+    e.section("Backends");
+    let current: Vec<String> = state.backends.lock().unwrap().iter().map(|b| b.url.clone()).collect();
+    let drained = state.maintenance.lock().unwrap().drained_backends.clone();
+    for url in &current {
+        if !c.backend_urls.contains(url) {
+            e.changed(url.clone(), "added at runtime".to_string());
+        } else if drained.contains(url) {
+            e.add(url.clone(), "drained (maintenance)".to_string(), None);
+        } else {
+            e.add(url.clone(), "configured".to_string(), None);
+        }
+    }
+    for url in c.backend_urls.iter().filter(|u| !current.contains(u)) {
+        e.changed(url.clone(), "removed at runtime".to_string());
+    }
+    e.add("proxy", opt(&c.backend_proxy), opt(&d.backend_proxy));
+    let opt_duration = |v: Option<Duration>| v.map_or("-".to_string(), duration);
+    e.add("timeout", duration(c.timeout), duration(d.timeout));
+    e.add("embed timeout", opt_duration(c.embed_timeout), opt_duration(d.embed_timeout));
+    e.add("generate timeout", opt_duration(c.generate_timeout), opt_duration(d.generate_timeout));
+    let factor = |f: Option<f64>| f.map_or("off".to_string(), |f| format!("p99 x {}", f));
+    e.add("learned timeouts", factor(c.learned_timeout_factor), factor(d.learned_timeout_factor));
+    e.add("learned timeout max", secs(c.learned_timeout_max), secs(d.learned_timeout_max));
+    let mut overrides: Vec<_> = c.file.model_timeouts.iter().collect();
+    overrides.sort();
+    for (pattern, timeout) in overrides {
+        e.add(format!("timeout {}", pattern), format!("{}s", timeout), "-".to_string());
+    }
+    let failures = |n: u32| if n == 0 { "off".to_string() } else { n.to_string() };
+    e.add("cooldown failures", failures(c.cooldown_failures), failures(d.cooldown_failures));
+    e.add("cooldown", format!("{}s", c.cooldown_secs), format!("{}s", d.cooldown_secs));
+    e.add("offline grace", opt_secs(c.offline_grace_secs), opt_secs(d.offline_grace_secs));
+    e.add("disconnect grace", opt_secs(c.disconnect_grace_secs), opt_secs(d.disconnect_grace_secs));
+    e.add("model checks", c.model_checks.to_string(), d.model_checks.to_string());
+    e.add("model cache ttl", secs(c.model_cache_ttl), secs(d.model_cache_ttl));
+    let canary = |c: &DispatcherConfig| c.canary.as_ref().map_or("-".to_string(), |k| {
+        format!("{}% of {} to {}", k.percent, k.model.as_deref().unwrap_or("every model"), k.backend_url)
+    });
+    e.add("canary", canary(c), canary(&d));
+    e.add("docker socket", path(&c.docker_socket), path(&d.docker_socket));
+
+    e.section("Scheduler");
+    e.add("shortest job first", c.shortest_job_first.to_string(), d.shortest_job_first.to_string());
+    e.add("scheduler trace", c.scheduler_trace.to_string(), d.scheduler_trace.to_string());
+    e.add("start", choice(&c.scheduler_start), choice(&d.scheduler_start));
+    e.add("tie break", choice(&c.tie_break), choice(&d.tie_break));
+    e.add("session affinity", choice(&c.session_affinity), choice(&d.session_affinity));
+    e.add("affinity ttl", format!("{}s", c.affinity_ttl_secs), format!("{}s", d.affinity_ttl_secs));
+    e.add("warmup on miss", c.warmup_on_miss.to_string(), d.warmup_on_miss.to_string());
+    e.add("batch window", format!("{}ms", c.batch_window_ms), format!("{}ms", d.batch_window_ms));
+    e.add("shed batch after", opt_secs(c.shed_batch_wait_secs), opt_secs(d.shed_batch_wait_secs));
+    e.add("stream slot release", choice(&c.slot_policy.streaming), choice(&d.slot_policy.streaming));
+    e.add("non-stream slot release", choice(&c.slot_policy.non_streaming), choice(&d.slot_policy.non_streaming));
+    e.add("state file", c.scheduler_state_file.display().to_string(), d.scheduler_state_file.display().to_string());
+
+    e.section("Limits");
+    e.add("per-user concurrent", limit(c.user_max_concurrent), limit(d.user_max_concurrent));
+    e.add("per-user queued", limit(c.user_max_queued), limit(d.user_max_queued));
+    e.add("per-user queued tokens", limit(c.user_max_queued_tokens), limit(d.user_max_queued_tokens));
+    e.add("idle boost concurrent", opt(&c.idle_boost_concurrent), opt(&d.idle_boost_concurrent));
+    e.add("idle boost queued", opt(&c.idle_boost_queued), opt(&d.idle_boost_queued));
+    e.add("queued bytes", limit(c.max_queued_bytes), limit(d.max_queued_bytes));
+    e.add("spool dir", path(&c.spool_dir), path(&d.spool_dir));
+    e.add("spooled bytes", limit(c.max_spooled_bytes), limit(d.max_spooled_bytes));
+    e.add("connections per IP", limit(c.max_connections_per_ip), limit(d.max_connections_per_ip));
+    e.add("websockets", c.websockets.to_string(), d.websockets.to_string());
+    e.add("websockets per user", limit(c.websocket_max_per_user), limit(d.websocket_max_per_user));
+    e.add("ready max queued", opt(&c.ready_max_queued), opt(&d.ready_max_queued));
+    e.add("ready max wait", opt_secs(c.ready_max_wait_secs), opt_secs(d.ready_max_wait_secs));
+    e.add("wait for backends", opt_secs(c.wait_for_backends_secs), opt_secs(d.wait_for_backends_secs));
+    e.add("multi-IP policy", choice(&c.multi_ip_policy), choice(&d.multi_ip_policy));
+
+    e.section("Tiers");
+    let mut tiers: Vec<_> = c.file.tiers.iter().collect();
+    tiers.sort_by_key(|(name, _)| *name);
+    for (name, tier) in tiers {
+        let mut parts = vec![format!("{} user(s)", tier.users.len())];
+        if let Some(n) = tier.max_concurrent {
+            parts.push(format!("concurrent {}", n));
+        }
+        if let Some(n) = tier.max_queued {
+            parts.push(format!("queued {}", n));
+        }
+        if let Some(n) = tier.max_queued_tokens {
+            parts.push(format!("queued tokens {}", n));
+        }
+        if let Some(n) = tier.shed_wait_secs {
+            parts.push(format!("shed after {}s", n));
+        }
+        if let Some(p) = tier.multi_ip {
+            parts.push(format!("multi-IP {}", choice(&p)));
+        }
+        if !tier.slos.is_empty() {
+            parts.push(format!("{} SLO(s)", tier.slos.len()));
+        }
+        e.add(name.clone(), parts.join(", "), "-".to_string());
+    }
+    e.add("default tier", opt(&c.file.default_tier), opt(&d.file.default_tier));
+    e.add("user ids", c.file.user_ids.summary(), d.file.user_ids.summary());
+
+    e.section("Responses");
+    e.add("stream validation", choice(&c.stream_validation), choice(&d.stream_validation));
+    e.add("structured output", choice(&c.structured_output), choice(&d.structured_output));
+    e.add("slow clients", choice(&c.slow_client_policy), choice(&d.slow_client_policy));
+    e.add("slow client timeout", format!("{}s", c.slow_client_timeout_secs), format!("{}s", d.slow_client_timeout_secs));
+
+    e.section("Security");
+    let set = |token: &Option<String>| if token.is_some() { "set" } else { "-" }.to_string();
+    e.add("admin token", set(&c.admin_token), set(&d.admin_token));
+    e.add("IPv6 block prefix", format!("/{}", c.ipv6_block_prefix), format!("/{}", d.ipv6_block_prefix));
+    e.add("blocked file", c.blocked_file.display().to_string(), d.blocked_file.display().to_string());
+    let jail = |c: &DispatcherConfig| c.jail.as_ref().map_or("-".to_string(), |j| {
+        format!("{} failures in {}, ban {} (max {})", j.max_failures, secs(j.window), secs(j.ban), secs(j.max_ban))
+    });
+    e.add("auth-failure bans", jail(c), jail(&d));
+    let origins = |o: &[String]| if o.is_empty() { "-".to_string() } else { o.join(",") };
+    e.add("CORS origins", origins(&c.cors_origins), origins(&d.cors_origins));
+
+    e.section("Operations");
+    for (i, w) in c.maintenance_windows.iter().enumerate() {
+        e.add(format!("maintenance {}", i + 1), format!("{} for {}m: {}", w.start, w.duration.num_minutes(), w.action), "-".to_string());
+    }
+    e.add("history size", c.history_size.to_string(), d.history_size.to_string());
+    e.add("dead letter size", c.dead_letter_size.to_string(), d.dead_letter_size.to_string());
+    e.add("dead letter bytes", limit(c.dead_letter_max_bytes), limit(d.dead_letter_max_bytes));
+    e.add("usage log", path(&c.usage_log), path(&d.usage_log));
+    let rotation = |bytes: u64| if bytes == 0 { "never".to_string() } else { format!("past {} bytes", bytes) };
+    e.add("usage log rotation", rotation(c.usage_log_max_bytes), rotation(d.usage_log_max_bytes));
+    e.add("usage log archives", c.usage_log_max_files.to_string(), d.usage_log_max_files.to_string());
+    e.add("metrics top users", c.metrics_top_users.to_string(), d.metrics_top_users.to_string());
+    e.add("error budget target", c.error_budget_target.to_string(), d.error_budget_target.to_string());
+    e.add("error budget window", secs(c.error_budget_window), secs(d.error_budget_window));
+    e.add("fairness window", secs(c.fairness_window), secs(d.fairness_window));
+    e.add("scale up wait", opt_secs(c.autoscale.up_wait_secs), opt_secs(d.autoscale.up_wait_secs));
+    e.add("scale down idle", opt_secs(c.autoscale.down_idle_secs), opt_secs(d.autoscale.down_idle_secs));
+    e.add("scale webhook", opt(&c.autoscale.webhook), opt(&d.autoscale.webhook));
+    e.add("statsd addr", opt(&c.statsd.addr), opt(&d.statsd.addr));
+    e.add("statsd prefix", c.statsd.prefix.clone(), d.statsd.prefix.clone());
+    let tags = |t: &[String]| if t.is_empty() { "-".to_string() } else { t.join(",") };
+    e.add("statsd tags", tags(&c.statsd.tags), tags(&d.statsd.tags));
+    e.add("statsd format", choice(&c.statsd.format), choice(&d.statsd.format));
+    e.add("statsd interval", secs(c.statsd.interval), secs(d.statsd.interval));
+    e.add("shutdown drain", choice(&c.shutdown_drain), choice(&d.shutdown_drain));
+    e.add("shutdown timeout", secs(c.shutdown_timeout), secs(d.shutdown_timeout));
+    e.add("shutdown save", path(&c.shutdown_save), path(&d.shutdown_save));
+
+    e.list
+}
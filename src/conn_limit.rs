@@ -0,0 +1,153 @@
+//! Open TCP connections per client IP (IPv6 per `--ipv6-block-prefix`
+//! network), capped at the listener: connections over the limit are closed
+//! as soon as they are accepted, before any request is read, so idle
+//! keep-alive connections can't use up the dispatcher's file descriptors.
+
+use axum::serve::Listener;
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, warn};
+
+use crate::dispatcher::AppState;
+use crate::ip::IpPrefix;
+
+#[derive(Default)]
+struct Entry {
+    open: usize,
+    /// Whether hitting the limit was logged since a connection last closed.
+    warned: bool,
+}
+
+/// Open connections per client network.
+#[derive(Default)]
+pub struct ConnectionTable {
+    open: Mutex<HashMap<IpPrefix, Entry>>,
+    /// Connections closed right after accept for being over the limit.
+    pub rejected: AtomicU64,
+}
+
+impl ConnectionTable {
+    /// Counts a new connection of `prefix`, or `None` when it already has
+    /// `limit` open (0 = unlimited).
+    fn open(self: &Arc<Self>, prefix: IpPrefix, limit: usize) -> Option<ConnectionSlot> {
+        let mut open = self.open.lock().unwrap();
+        let entry = open.entry(prefix).or_default();
+        if limit > 0 && entry.open >= limit {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            if !entry.warned {
+                warn!("Closing new connections from {}: {} already open", prefix, entry.open);
+                entry.warned = true;
+            }
+            return None;
+        }
+        entry.open += 1;
+        Some(ConnectionSlot { table: self.clone(), prefix })
+    }
+
+    pub fn total(&self) -> usize {
+        self.open.lock().unwrap().values().map(|e| e.open).sum()
+    }
+
+    /// Client networks with open connections, most first.
+    pub fn by_ip(&self) -> Vec<(IpPrefix, usize)> {
+        let mut open: Vec<(IpPrefix, usize)> = self.open.lock().unwrap().iter().map(|(p, e)| (*p, e.open)).collect();
+        open.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.to_string().cmp(&b.0.to_string())));
+        open
+    }
+}
+
+/// One open connection; given back when the connection closes.
+struct ConnectionSlot {
+    table: Arc<ConnectionTable>,
+    prefix: IpPrefix,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open = self.table.open.lock().unwrap();
+        if let Some(entry) = open.get_mut(&self.prefix) {
+            entry.open -= 1;
+            entry.warned = false;
+            if entry.open == 0 {
+                open.remove(&self.prefix);
+            }
+        }
+    }
+}
+
+/// A `TcpListener` enforcing `--max-connections-per-ip`. Wrap it with
+/// `ListenerExt::tap_io` to serve it with `ConnectInfo<SocketAddr>`.
+pub struct LimitedListener {
+    inner: TcpListener,
+    state: Arc<AppState>,
+}
+
+impl LimitedListener {
+    pub fn new(inner: TcpListener, state: Arc<AppState>) -> Self {
+        Self { inner, state }
+    }
+}
+
+impl Listener for LimitedListener {
+    type Io = LimitedStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let (stream, addr) = Listener::accept(&mut self.inner).await;
+            let prefix = self.state.block_target(addr.ip());
+            match self.state.sockets.open(prefix, self.state.config.max_connections_per_ip) {
+                Some(slot) => return (LimitedStream { inner: stream, _slot: slot }, addr),
+                None => debug!("Closed connection from {}: over the per-IP limit", addr),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+/// An accepted connection holding its slot in the `ConnectionTable`.
+pub struct LimitedStream {
+    inner: TcpStream,
+    _slot: ConnectionSlot,
+}
+
+impl AsyncRead for LimitedStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for LimitedStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,233 @@
+//! Dead letters: requests that failed because of a backend (5xx, timeout,
+//! connection error), kept with their body so an operator can replay them
+//! from the TUI history view once the backend has recovered, optionally on
+//! another model, instead of asking the user to send them again. The answer
+//! to a replay is kept for the user at `GET /api/jobs/{task_id}/replay`.
+//! Bodies are kept zstd-compressed, and the oldest dead letters make way once
+//! they add up to `--dead-letter-max-mb`.
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, Method, header},
+};
+use chrono::Local;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+use crate::compress;
+use crate::dispatcher::{AppState, handle_request};
+
+/// How long the answer to a replay can still be fetched.
+const KEEP_REPLAYED: Duration = Duration::from_secs(60 * 60);
+/// Largest replay answer kept.
+const MAX_RESULT_BYTES: usize = 1024 * 1024;
+
+/// A failed request as the client sent it.
+pub struct DeadLetter {
+    pub task_id: String,
+    pub user_id: String,
+    pub ip: IpAddr,
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    /// Compressed once kept in `DeadLetters`.
+    pub body: Bytes,
+    pub model: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Replay {
+    /// Task id of the replayed request, once it was queued.
+    pub task_id: Option<String>,
+    pub model: Option<String>,
+    /// RFC 3339.
+    pub replayed_at: String,
+    pub finished: bool,
+    pub status: Option<u16>,
+    /// The answer: JSON when it parses as such, otherwise the raw text.
+    pub response: Option<Value>,
+    #[serde(skip)]
+    user_id: String,
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+/// The last `capacity` dead letters within `max_bytes` of compressed bodies,
+/// and the replays started from them.
+pub struct DeadLetters {
+    entries: VecDeque<DeadLetter>,
+    capacity: usize,
+    /// Compressed bodies kept at most; 0 for no limit.
+    max_bytes: u64,
+    bytes: u64,
+    replays: HashMap<String, Replay>,
+}
+
+impl DeadLetters {
+    pub fn new(capacity: usize, max_bytes: u64) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            max_bytes,
+            bytes: 0,
+            replays: HashMap::new(),
+        }
+    }
+
+    /// Adds a dead letter whose body is already compressed.
+    fn push(&mut self, letter: DeadLetter) {
+        let len = letter.body.len() as u64;
+        if self.capacity == 0 {
+            return;
+        }
+        if self.max_bytes > 0 && len > self.max_bytes {
+            debug!("Dead letter {} not kept: {} compressed bytes exceed the limit", letter.task_id, len);
+            return;
+        }
+        while self.entries.len() >= self.capacity || (self.max_bytes > 0 && self.bytes + len > self.max_bytes) {
+            let Some(oldest) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.body.len() as u64;
+        }
+        self.bytes += len;
+        self.entries.push_back(letter);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Compressed size of the bodies kept.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the failed request `task_id` can be replayed.
+    pub fn contains(&self, task_id: &str) -> bool {
+        self.entries.iter().any(|l| l.task_id == task_id)
+    }
+
+    /// `ready` for a dead letter, `sent` or `done` once it was replayed.
+    pub fn replay_state(&self, task_id: &str) -> Option<&'static str> {
+        if self.contains(task_id) {
+            return Some("ready");
+        }
+        self.replays.get(task_id).map(|r| if r.finished { "done" } else { "sent" })
+    }
+
+    /// The replay of `task_id` if it belongs to `user_id`.
+    pub fn replay(&mut self, user_id: &str, task_id: &str) -> Option<Replay> {
+        self.replays.retain(|_, r| r.finished_at.is_none_or(|at| at.elapsed() < KEEP_REPLAYED));
+        self.replays.get(task_id).filter(|r| r.user_id == user_id).cloned()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// Not a dead letter (anymore), e.g. already replayed.
+    Unknown,
+    /// The kept body can't be read back.
+    Corrupt,
+    /// A new model was asked for, but the body is not a JSON object.
+    NotJson,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Unknown => write!(f, "not a replayable failed request"),
+            ReplayError::Corrupt => write!(f, "the kept request body is damaged"),
+            ReplayError::NotJson => write!(f, "the request body has no model to change"),
+        }
+    }
+}
+
+/// Keeps a failed request for replay. The body is compressed off the async
+/// runtime, since multimodal requests can be large.
+pub fn keep(state: &Arc<AppState>, mut letter: DeadLetter) {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || match compress::encode(&letter.body) {
+        Ok(body) => {
+            letter.body = Bytes::from(body);
+            state.dead_letters.lock().unwrap().push(letter);
+        }
+        Err(e) => warn!("Dead letter {} not kept: {}", letter.task_id, e),
+    });
+}
+
+/// Queues the failed request `task_id` again as its user, on `model` if given.
+pub fn replay(state: &Arc<AppState>, task_id: &str, model: Option<&str>) -> Result<(), ReplayError> {
+    let mut letters = state.dead_letters.lock().unwrap();
+    let index = letters.entries.iter().position(|l| l.task_id == task_id).ok_or(ReplayError::Unknown)?;
+    let body = compress::decode(&letters.entries[index].body).map_err(|_| ReplayError::Corrupt)?;
+    let model = model.filter(|m| Some(*m) != letters.entries[index].model.as_deref());
+    let switched = match model {
+        Some(model) => Some(crate::fallback::substitute(&body, model).ok_or(ReplayError::NotJson)?),
+        None => None,
+    };
+    let Some(mut letter) = letters.entries.remove(index) else {
+        return Err(ReplayError::Unknown);
+    };
+    letters.bytes -= letter.body.len() as u64;
+    match switched {
+        Some(body) => {
+            letter.headers.remove(header::CONTENT_LENGTH);
+            letter.body = body;
+        }
+        None => letter.body = Bytes::from(body),
+    }
+    let model = model.map(str::to_string).or(letter.model.clone());
+    info!(
+        "Replaying failed task {} of user {}{}",
+        task_id,
+        letter.user_id,
+        model.as_deref().map(|m| format!(" on model {}", m)).unwrap_or_default()
+    );
+    letters.replays.insert(task_id.to_string(), Replay {
+        task_id: None,
+        model,
+        replayed_at: Local::now().to_rfc3339(),
+        finished: false,
+        status: None,
+        response: None,
+        user_id: letter.user_id.clone(),
+        finished_at: None,
+    });
+    drop(letters);
+
+    let state = state.clone();
+    let original = task_id.to_string();
+    tokio::spawn(async move {
+        let response = handle_request(state.clone(), letter.ip, letter.method, letter.headers, letter.path, letter.body).await;
+        let status = response.status().as_u16();
+        let new_id = response.headers().get("X-Task-ID").and_then(|v| v.to_str().ok()).map(str::to_string);
+        if let Some(replay) = state.dead_letters.lock().unwrap().replays.get_mut(&original) {
+            replay.task_id = new_id.clone();
+            replay.status = Some(status);
+        }
+        let answer = match axum::body::to_bytes(response.into_body(), MAX_RESULT_BYTES).await {
+            Ok(body) => serde_json::from_slice(&body).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned())),
+            Err(e) => serde_json::json!({ "error": format!("response not kept: {}", e) }),
+        };
+        info!("Replay of task {} finished as {} with status {}", original, new_id.as_deref().unwrap_or("-"), status);
+        if let Some(replay) = state.dead_letters.lock().unwrap().replays.get_mut(&original) {
+            replay.finished = true;
+            replay.response = Some(answer);
+            replay.finished_at = Some(Instant::now());
+        }
+    });
+    Ok(())
+}
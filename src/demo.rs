@@ -0,0 +1,229 @@
+//! `ollamaMQ demo`: fake Ollama backends and synthetic users sending them
+//! traffic through the dispatcher, so the dashboard can be tried out without
+//! real clients or GPUs.
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::State,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use bytes::Bytes;
+use serde_json::{Value, json};
+use std::{
+    convert::Infallible,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tracing::debug;
+
+/// Models every demo backend offers.
+const MODELS: [&str; 3] = ["llama3.2:3b", "qwen2.5:7b", "nomic-embed-text:latest"];
+const EMBED_MODEL: &str = "nomic-embed-text:latest";
+const CONTEXT_LENGTH: u64 = 8192;
+const WORDS: [&str; 16] = [
+    "the", "queue", "is", "fair", "and", "every", "user", "gets", "a", "turn", "while", "backends", "stream", "tokens", "back", "quickly",
+];
+const USER_NAMES: [&str; 24] = [
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy", "mallory", "niaj",
+    "olivia", "peggy", "rupert", "sybil", "trent", "victor", "walter", "xena", "yusuf", "zoe", "ci-bot", "nightly-eval",
+];
+/// Requests of impatient users give up after this long, so the dashboard shows dropped requests too.
+const IMPATIENT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Time for the dispatcher to start and find the backends before users show up.
+const STARTUP_DELAY: Duration = Duration::from_secs(2);
+
+/// Small xorshift generator; the demo needs variety, not good randomness.
+struct Rng(u64);
+
+impl Rng {
+    fn seeded(salt: u64) -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Self((nanos ^ salt.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len() as u64) as usize]
+    }
+}
+
+struct MockBackend {
+    /// Pause between two generated tokens; backends differ like mixed GPUs do.
+    token_delay: Duration,
+    requests: AtomicU64,
+}
+
+/// Starts `count` fake Ollama backends on local ports and returns their URLs.
+pub async fn start_backends(count: usize) -> std::io::Result<Vec<String>> {
+    let mut urls = Vec::with_capacity(count);
+    for i in 0..count {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let backend = Arc::new(MockBackend {
+            token_delay: Duration::from_millis(20 + 15 * i as u64),
+            requests: AtomicU64::new(0),
+        });
+        let app = Router::new()
+            .route("/api/tags", get(tags))
+            .route("/api/ps", get(|| async { Json(json!({ "models": [] })) }))
+            .route("/api/version", get(|| async { Json(json!({ "version": "0.6.0" })) }))
+            .route("/api/show", post(show))
+            .route("/api/generate", post(|state, body| complete(state, body, false)))
+            .route("/api/chat", post(|state, body| complete(state, body, true)))
+            .route("/api/embed", post(embed))
+            .with_state(backend);
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        urls.push(url);
+    }
+    Ok(urls)
+}
+
+async fn tags() -> Json<Value> {
+    let models: Vec<Value> = MODELS.iter()
+        .map(|m| json!({ "name": m, "model": m, "digest": format!("demo-{}", m) }))
+        .collect();
+    Json(json!({ "models": models }))
+}
+
+async fn show(Json(body): Json<Value>) -> Json<Value> {
+    let capability = if body.get("model").and_then(Value::as_str) == Some(EMBED_MODEL) { "embedding" } else { "completion" };
+    Json(json!({
+        "model_info": { "demo.context_length": CONTEXT_LENGTH },
+        "capabilities": [capability],
+    }))
+}
+
+/// `/api/generate` and `/api/chat`: a few dozen words, streamed unless `"stream": false`.
+async fn complete(State(backend): State<Arc<MockBackend>>, Json(body): Json<Value>, chat: bool) -> Response {
+    let mut rng = Rng::seeded(backend.requests.fetch_add(1, Ordering::Relaxed));
+    let model = body.get("model").and_then(Value::as_str).unwrap_or(MODELS[0]).to_string();
+    let stream = body.get("stream").and_then(Value::as_bool).unwrap_or(true);
+    let words: Vec<&str> = (0..6 + rng.below(25)).map(|_| rng.pick(&WORDS)).collect();
+    let eval_count = words.len();
+    let piece = move |text: String, done: bool| {
+        let mut line = if chat {
+            json!({ "model": model, "message": { "role": "assistant", "content": text }, "done": done })
+        } else {
+            json!({ "model": model, "response": text, "done": done })
+        };
+        if done {
+            line["done_reason"] = json!("stop");
+            line["prompt_eval_count"] = json!(12);
+            line["eval_count"] = json!(eval_count);
+        }
+        line
+    };
+
+    if !stream {
+        tokio::time::sleep(backend.token_delay * words.len() as u32).await;
+        return Json(piece(words.join(" "), true)).into_response();
+    }
+    let (tx, rx) = mpsc::channel::<Bytes>(4);
+    let delay = backend.token_delay;
+    tokio::spawn(async move {
+        for word in &words {
+            tokio::time::sleep(delay).await;
+            let line = format!("{}\n", piece(format!("{} ", word), false));
+            if tx.send(Bytes::from(line)).await.is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Bytes::from(format!("{}\n", piece(String::new(), true)))).await;
+    });
+    let stream = ReceiverStream::new(rx).map(Ok::<_, Infallible>);
+    ([(axum::http::header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(stream)).into_response()
+}
+
+async fn embed(State(backend): State<Arc<MockBackend>>, Json(body): Json<Value>) -> Json<Value> {
+    tokio::time::sleep(backend.token_delay).await;
+    let inputs = match body.get("input") {
+        Some(Value::Array(items)) => items.len(),
+        _ => 1,
+    };
+    Json(json!({
+        "model": body.get("model").cloned().unwrap_or(json!(EMBED_MODEL)),
+        "embeddings": vec![[0.12, -0.04, 0.33, 0.08]; inputs],
+    }))
+}
+
+/// A synthetic client of the dispatcher.
+struct DemoUser {
+    name: String,
+    /// Average pause between two rounds of requests.
+    interval: Duration,
+    /// Requests sent per round; bursty users send several at once.
+    burst: u64,
+    /// Gives up on slow answers.
+    impatient: bool,
+}
+
+/// Starts `count` synthetic users sending requests to the dispatcher at
+/// `dispatcher` (e.g. `http://127.0.0.1:11435`), at rates from chatty to occasional.
+pub fn spawn_traffic(dispatcher: String, count: usize) {
+    let client = reqwest::Client::new();
+    for i in 0..count {
+        let mut rng = Rng::seeded(i as u64 + 1);
+        let base = USER_NAMES[i % USER_NAMES.len()];
+        let user = DemoUser {
+            name: if i < USER_NAMES.len() { base.to_string() } else { format!("{}-{}", base, i / USER_NAMES.len() + 1) },
+            interval: Duration::from_millis(1_000 + rng.below(11_000)),
+            burst: if i % 4 == 3 { 2 + rng.below(3) } else { 1 },
+            impatient: i % 7 == 5,
+        };
+        let (client, dispatcher) = (client.clone(), dispatcher.clone());
+        tokio::spawn(async move {
+            // Staggered start, so the users don't arrive in lockstep
+            tokio::time::sleep(STARTUP_DELAY + Duration::from_millis(rng.below(3_000))).await;
+            loop {
+                for _ in 0..user.burst {
+                    let request = demo_request(&client, &dispatcher, &user, &mut rng);
+                    tokio::spawn(async move {
+                        // The answer is read to the end, like a real client would
+                        match request.send().await {
+                            Ok(res) => {
+                                let _ = res.bytes().await;
+                            }
+                            Err(e) => debug!("Demo request failed: {}", e),
+                        }
+                    });
+                }
+                // 50-150% of the user's average pause
+                let pause = user.interval.mul_f64(0.5 + rng.below(1_000) as f64 / 1_000.0);
+                tokio::time::sleep(pause).await;
+            }
+        });
+    }
+}
+
+fn demo_request(client: &reqwest::Client, dispatcher: &str, user: &DemoUser, rng: &mut Rng) -> reqwest::RequestBuilder {
+    let prompt = (0..3 + rng.below(20)).map(|_| rng.pick(&WORDS)).collect::<Vec<_>>().join(" ");
+    let model = rng.pick(&MODELS[..2]);
+    let (path, body) = match rng.below(10) {
+        0..=4 => ("/api/chat", json!({ "model": model, "messages": [{ "role": "user", "content": prompt }] })),
+        5..=7 => ("/api/generate", json!({ "model": model, "prompt": prompt, "stream": false })),
+        _ => ("/api/embed", json!({ "model": EMBED_MODEL, "input": prompt })),
+    };
+    let request = client.post(format!("{}{}", dispatcher, path))
+        .header("X-User-ID", &user.name)
+        .json(&body);
+    if user.impatient { request.timeout(IMPATIENT_TIMEOUT) } else { request }
+}
@@ -2,41 +2,364 @@ use axum::{
     body::{Body, Bytes},
     extract::{ConnectInfo, State},
     http::{HeaderMap, Method, StatusCode},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
+use bytes::BytesMut;
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    fs,
+    collections::{HashMap, HashSet, VecDeque, hash_map::RandomState},
+    hash::BuildHasher,
     net::{IpAddr, SocketAddr},
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
-use tokio::sync::{Notify, mpsc};
+use tokio::sync::{Notify, broadcast, mpsc};
 use tokio_stream::wrappers::ReceiverStream;
-use tracing::{debug, info, warn};
+use tracing::{Instrument, debug, error, info, warn};
+
+use crate::admin::AdminRole;
+use crate::autoscale::{AutoscaleConfig, ScaleStatus};
+use crate::blocked_store::BlockInfo;
+use crate::capabilities::ModelInfo;
+use crate::catalogue::ModelCatalogue;
+use crate::compat::{BackendFeatures, LegacyEmbed};
+use crate::shutdown::{DrainPolicy, ShutdownStatus};
+use crate::slow_client::{ResponseSink, SinkError, SlowClientPolicy};
+use crate::scheduler_trace::{Candidate, Decision, Skipped};
+use crate::slo::{SloStatus, SloTracker};
+use crate::slots::{ResponseKind, SlotOccupancy, SlotPolicy, SlotRelease};
+use crate::spool::SpooledBody;
+use crate::statsd::StatsdConfig;
+use crate::structured::{BackendAnswer, RetryRequest, StructuredCheck, StructuredOutputMode, StructuredStats};
+use crate::config::{FileConfig, TierConfig};
+use crate::conn_limit::ConnectionTable;
+use crate::dead_letter::{DeadLetter, DeadLetters};
+use crate::errors::{ErrorClass, ErrorCounts};
+use crate::events::{EVENT_BUFFER, Event};
+use crate::docker::DockerNames;
+use crate::error_budget::{self, BudgetStatus, ErrorBudget};
+use crate::identity::{IdentityError, IdentityResolver};
+use crate::namespace::NamespaceError;
+use crate::history::{History, HistoryEntry, Outcome, UsageTail};
+use crate::tools::ToolCallCounter;
+use crate::translate::translatable;
+use crate::experiments::{Assignment, ExperimentResults};
+use crate::hooks::{HookContext, ResponseTransformer};
+use crate::maintenance::{MaintenanceStatus, MaintenanceWindow};
+use crate::ndjson::{NdjsonValidator, StreamValidation};
+use crate::probe::CheckLevel;
+use crate::ip::IpPrefix;
+use crate::jail::{BlockedAttempt, Jail, JailPolicy};
+use crate::timeouts::{LearnedTimeouts, TimeoutSource};
+use crate::timings::{RateMeter, Stage, StageStats, StageTiming, format_bytes, format_duration};
+use crate::ulid::UlidGenerator;
+use crate::fairness::ShareTimeline;
+use crate::forecast::DemandCurves;
+use crate::rollup::Rollup;
+use crate::usage::UsageLog;
+use crate::websocket::WebSockets;
+
+/// Window of the rolling requests/sec figure.
+const REQUEST_RATE_WINDOW: Duration = Duration::from_secs(10);
+/// Pause between two rounds of backend health probes.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// 307/308 redirects followed per backend request.
+const MAX_REDIRECTS: usize = 5;
+/// Bytes of an HTML error page read to find its title.
+const HTML_PAGE_LIMIT: usize = 16 * 1024;
+pub const SCHEDULER_STATE_FILE: &str = "scheduler_state.json";
+/// How often detached tasks past `--disconnect-grace-secs` are dropped.
+const DETACHED_SWEEP: Duration = Duration::from_secs(1);
+/// How often the last served user is saved with `--scheduler-start resume`.
+const SCHEDULER_STATE_INTERVAL: Duration = Duration::from_secs(5);
+/// `Retry-After` for requests rejected by `--max-queued-mb`.
+const MEMORY_RETRY_AFTER_SECS: u64 = 5;
+/// A model isn't warmed up on the same backend again within this time.
+const WARMUP_COOLDOWN: Duration = Duration::from_secs(60);
+/// Queued tasks older than this are no longer overtaken under shortest-job-first.
+const SJF_MAX_BYPASS_WAIT: Duration = Duration::from_secs(30);
+
+/// Who caused a failed request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    /// The request itself was bad (unknown model, malformed body, ...).
+    Client,
+    /// The backend errored, timed out, or was unreachable.
+    Backend,
+}
 
-const BLOCKED_FILE: &str = "blocked_items.json";
+impl FailureKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::Client => "client",
+            FailureKind::Backend => "backend",
+        }
+    }
 
-#[derive(Serialize, Deserialize, Default)]
-struct BlockedConfig {
-    ips: HashSet<IpAddr>,
-    users: HashSet<String>,
+    /// Classifies a backend response status; `None` for success.
+    /// Timeouts and rate limiting are the backend's doing even though they are 4xx.
+    pub fn from_status(status: StatusCode) -> Option<Self> {
+        match status {
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => Some(FailureKind::Backend),
+            s if s.is_client_error() => Some(FailureKind::Client),
+            s if s.is_server_error() => Some(FailureKind::Backend),
+            _ => None,
+        }
+    }
+
+    /// Classifies an error sending the request: only a request that could not
+    /// even be built is the client's fault.
+    pub fn from_error(e: &reqwest::Error) -> Self {
+        if e.is_builder() { FailureKind::Client } else { FailureKind::Backend }
+    }
+}
+
+/// Where a request was when its client left and the backend request was dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelStage {
+    /// Waiting for the backend's answer to start.
+    Waiting,
+    /// Streaming the answer.
+    Streaming,
+}
+
+impl CancelStage {
+    pub const ALL: [CancelStage; 2] = [CancelStage::Waiting, CancelStage::Streaming];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancelStage::Waiting => "waiting",
+            CancelStage::Streaming => "streaming",
+        }
+    }
 }
 
 pub enum ResponsePart {
     Status(StatusCode, HeaderMap),
     Chunk(Bytes),
     Error(reqwest::Error),
+    /// A failure reported by another instance (see `nats`).
+    Failed(String),
 }
 
 pub struct Task {
+    /// Identifier returned to the client in `X-Task-ID`.
+    pub id: String,
     pub method: Method,
     pub path: String,
     pub headers: HeaderMap,
     pub body: Bytes,
     pub responder: mpsc::Sender<ResponsePart>,
     pub requested_model: Option<String>,
+    pub canary: bool,
+    pub lane: Lane,
+    /// Session key used to keep follow-up requests on the same backend.
+    pub affinity_key: Option<String>,
+    /// Prompt size estimated at admission, 0 for bodies without a prompt.
+    pub prompt_tokens: u64,
+    /// Operator probe (`X-Debug-Trace`): runs next, traced, with stage timings in the response headers.
+    pub debug_trace: bool,
+    /// Non-streaming generation or embedding request, which `--batch-window-ms` may hold back.
+    pub batchable: bool,
+    /// The body offers the model tools to call; their calls are counted per user.
+    pub tools: bool,
+    /// Experiment arm the request was assigned to.
+    pub experiment: Option<Assignment>,
+    pub enqueued_at: Instant,
+    /// Counts the body towards the queued-bytes total until dispatch.
+    pub queued_bytes: QueuedBytes,
+    /// Where the body is while it waits in the disk spool; `body` is empty then.
+    pub spooled: Option<SpooledBody>,
+    /// Sent with `X-Resumable`: a client that went away may re-attach within
+    /// `--disconnect-grace-secs` instead of the task being dropped.
+    pub resumable: bool,
+    /// Model the client asked for, once a fallback model replaced it.
+    pub fallback_from: Option<String>,
+}
+
+/// A resumable task whose client was gone when it came up for dispatch,
+/// waiting for the client to re-attach.
+pub struct DetachedTask {
+    pub user_id: String,
+    pub task: Task,
+    pub since: Instant,
+}
+
+/// Result of `AppState::reattach_task`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reattach {
+    /// Still queued; the new connection gets its response.
+    Queued,
+    /// Put back at the front of its user's queue.
+    Requeued,
+    /// The original client is still connected.
+    Connected,
+    /// Not queued or detached, or not resumable.
+    Unknown,
+}
+
+impl Task {
+    /// The body, read from the spool if it is there.
+    pub fn stored_body(&self) -> Bytes {
+        match self.spooled {
+            Some(ref spooled) => spooled.read().unwrap_or_else(|e| {
+                warn!("Cannot read spooled body of task {}: {}", self.id, e);
+                Bytes::new()
+            }),
+            None => self.body.clone(),
+        }
+    }
+}
+
+/// A queued body's share of `AppState::queued_bytes`, given back when the
+/// task is dispatched or dropped (cancelled, purged, failed while queued).
+pub struct QueuedBytes {
+    total: Arc<AtomicU64>,
+    len: u64,
+}
+
+impl QueuedBytes {
+    /// Gives the bytes back early, once the body is on its way to a backend.
+    pub fn release(&mut self) {
+        self.total.fetch_sub(std::mem::take(&mut self.len), Ordering::Relaxed);
+    }
+}
+
+impl Drop for QueuedBytes {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Traffic class of a request, chosen by the client via the `X-Lane` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Lane {
+    #[default]
+    Interactive,
+    Batch,
+}
+
+impl Lane {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lane::Interactive => "interactive",
+            Lane::Batch => "batch",
+        }
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        match headers.get("X-Lane").and_then(|h| h.to_str().ok()) {
+            Some(v) if v.eq_ignore_ascii_case("batch") => Lane::Batch,
+            _ => Lane::Interactive,
+        }
+    }
+}
+
+/// How requests are grouped for backend affinity.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SessionAffinity {
+    #[default]
+    Off,
+    /// One session per user id.
+    User,
+    /// Sessions named by the client's `X-Session-ID` header.
+    Session,
+}
+
+impl SessionAffinity {
+    pub fn key(&self, user_id: &str, headers: &HeaderMap) -> Option<String> {
+        match self {
+            SessionAffinity::Off => None,
+            SessionAffinity::User => Some(user_id.to_string()),
+            SessionAffinity::Session => headers
+                .get("X-Session-ID")
+                .and_then(|h| h.to_str().ok())
+                .filter(|s| !s.is_empty())
+                .map(|s| format!("{}/{}", user_id, s)),
+        }
+    }
+}
+
+/// What happens when a user id sends requests from a second IP while
+/// requests from another IP are still open.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiIpPolicy {
+    /// Only show it in the dashboard.
+    #[default]
+    Allow,
+    /// Queue the request but log it and count it per user.
+    Flag,
+    /// Reject the request with `409 Conflict`.
+    Reject,
+}
+
+/// Where round-robin starts after a restart.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SchedulerStart {
+    /// With the first user in scheduling order.
+    #[default]
+    First,
+    /// At a random position, so no user is favored after every deploy.
+    Random,
+    /// After the user served last before the restart (see `--scheduler-state-file`).
+    Resume,
+}
+
+/// Order of users with the same number of processed requests, which is
+/// everyone right after a restart.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// Alphabetical by user id.
+    #[default]
+    UserId,
+    /// The user whose next request has waited longest goes first.
+    OldestRequest,
+    /// A random order drawn at startup and kept until the next restart.
+    Shuffled,
+}
+
+/// What `--scheduler-start resume` keeps across restarts.
+#[derive(Serialize, Deserialize, Default)]
+struct SchedulerState {
+    last_served: Option<String>,
+}
+
+/// Counts an open request of a user from one IP until dropped, which happens
+/// once its response has been fully streamed (or the client went away).
+struct ConnectionGuard {
+    state: Arc<AppState>,
+    user_id: String,
+    ip: IpAddr,
+}
+
+impl ConnectionGuard {
+    fn new(state: Arc<AppState>, user_id: String, ip: IpAddr) -> Self {
+        *state.connections.lock().unwrap().entry(user_id.clone()).or_default().entry(ip).or_insert(0) += 1;
+        Self { state, user_id, ip }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.state.connections.lock().unwrap();
+        if let Some(ips) = connections.get_mut(&self.user_id) {
+            if let Some(n) = ips.get_mut(&self.ip) {
+                *n = n.saturating_sub(1);
+                if *n == 0 {
+                    ips.remove(&self.ip);
+                }
+            }
+            if ips.is_empty() {
+                connections.remove(&self.user_id);
+            }
+        }
+    }
 }
 
 /// Which API flavours this backend speaks.
@@ -69,6 +392,16 @@ impl BackendApiType {
         }
     }
 
+    /// Name in the admin API and `ollamaMQ probe --json`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendApiType::Unknown => "unknown",
+            BackendApiType::Ollama => "ollama",
+            BackendApiType::OpenAi => "openai",
+            BackendApiType::Both => "ollama+openai",
+        }
+    }
+
     pub fn display(&self) -> &'static str {
         match self {
             BackendApiType::Unknown => "???",
@@ -97,6 +430,61 @@ pub fn detect_api_family(path: &str) -> ApiFamily {
     }
 }
 
+/// Kind of work a route asks for, used to pick its backend timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteClass {
+    /// `/api/embed`, `/api/embeddings`, `/v1/embeddings`
+    Embedding,
+    /// `/api/generate`, `/api/chat`, `/v1/chat/completions`, `/v1/completions`
+    Generation,
+    /// Model management and everything else.
+    Other,
+}
+
+impl RouteClass {
+    pub fn from_path(path: &str) -> Self {
+        match path {
+            "/api/embed" | "/api/embeddings" | "/v1/embeddings" => RouteClass::Embedding,
+            "/api/generate" | "/api/chat" | "/v1/chat/completions" | "/v1/completions" => RouteClass::Generation,
+            _ => RouteClass::Other,
+        }
+    }
+}
+
+/// Whether a request can be grouped with others for the same model: a
+/// generation or embedding request whose answer isn't streamed. Ollama streams
+/// `/api/chat` and `/api/generate` unless `"stream": false` is sent.
+/// Fill-in-the-middle completions are never held, since an editor waits on them.
+pub fn is_batchable(path: &str, json: Option<&serde_json::Value>) -> bool {
+    let stream = json.and_then(|j| j.get("stream")).and_then(|s| s.as_bool());
+    match RouteClass::from_path(path) {
+        RouteClass::Embedding => true,
+        RouteClass::Generation => !stream.unwrap_or(path.starts_with("/api/")) && !is_fill_in_middle(path, json),
+        RouteClass::Other => false,
+    }
+}
+
+/// A code completion with text after the cursor: `/api/generate` or
+/// `/v1/completions` with a `suffix`. The body is forwarded as sent, `raw`,
+/// `suffix`, `images` and `context` included.
+pub fn is_fill_in_middle(path: &str, json: Option<&serde_json::Value>) -> bool {
+    matches!(path, "/api/generate" | "/v1/completions")
+        && json.and_then(|j| j.get("suffix")).and_then(|s| s.as_str()).is_some_and(|s| !s.is_empty())
+}
+
+/// Non-streaming requests for one model that are dispatched back-to-back.
+struct Batch {
+    model: String,
+    /// Requests queued up to this point belong to the batch.
+    cutoff: Instant,
+}
+
+impl Batch {
+    fn contains(&self, task: &Task) -> bool {
+        task.batchable && task.requested_model.as_deref() == Some(self.model.as_str()) && task.enqueued_at <= self.cutoff
+    }
+}
+
 #[derive(Clone)]
 pub struct BackendStatus {
     pub url: String,
@@ -107,6 +495,350 @@ pub struct BackendStatus {
     pub available_models: HashSet<String>,
     pub loaded_models: HashSet<String>,
     pub current_model: Option<String>,
+    pub is_canary: bool,
+    /// Failures caused by the backend (5xx, timeouts, connection errors).
+    pub failed_count: usize,
+    /// Requests the backend rejected as invalid (4xx), not held against it.
+    pub client_errors: usize,
+    pub total_latency_ms: u64,
+    pub latency_samples: u64,
+    pub malformed_responses: usize,
+    /// Model patterns this backend is pinned to; empty means any model.
+    pub model_pins: Vec<String>,
+    /// Registered at runtime through the admin API (e.g. by an autoscaler).
+    pub dynamic: bool,
+    /// When the backend last became idle; `None` while it serves requests.
+    pub idle_since: Option<Instant>,
+    /// Ollama version and what it supports, once `/api/version` answered.
+    pub features: Option<BackendFeatures>,
+    /// Recent outcomes, for the success target of `--error-budget-target`.
+    pub error_budget: ErrorBudget,
+    /// How long requests held the backend, and responses relayed after releasing it.
+    pub slots: SlotOccupancy,
+}
+
+impl BackendStatus {
+    fn new(url: String, config: &DispatcherConfig) -> Self {
+        Self {
+            is_canary: config.canary.as_ref().is_some_and(|c| c.backend_url == url),
+            failed_count: 0,
+            client_errors: 0,
+            total_latency_ms: 0,
+            latency_samples: 0,
+            malformed_responses: 0,
+            model_pins: config.file.backend_config(&url).map(|c| c.models.clone()).unwrap_or_default(),
+            api_type: config.file.backend_config(&url).and_then(|c| c.api).map_or(BackendApiType::Unknown, |api| api.api_type()),
+            dynamic: false,
+            idle_since: Some(Instant::now()),
+            features: None,
+            error_budget: ErrorBudget::default(),
+            slots: SlotOccupancy::default(),
+            url,
+            active_requests: 0,
+            processed_count: 0,
+            is_online: true,
+            available_models: HashSet::new(),
+            loaded_models: HashSet::new(),
+            current_model: None,
+        }
+    }
+
+    /// Average wall-clock time of successful requests, including streaming.
+    pub fn avg_latency_ms(&self) -> Option<u64> {
+        self.total_latency_ms.checked_div(self.latency_samples)
+    }
+}
+
+/// Sends a fixed share of one model's traffic to a designated backend.
+#[derive(Clone, Debug)]
+pub struct CanaryConfig {
+    pub backend_url: String,
+    /// Model the canary applies to; `None` means every model.
+    pub model: Option<String>,
+    pub percent: u32,
+}
+
+/// Runtime settings for the dispatcher, built from CLI args or the layer builder.
+#[derive(Clone, Debug)]
+pub struct DispatcherConfig {
+    pub backend_urls: Vec<String>,
+    /// Backend request timeout.
+    pub timeout: Duration,
+    /// Timeout for embedding requests; `timeout` when unset.
+    pub embed_timeout: Option<Duration>,
+    /// Timeout for generate/chat/completion requests; `timeout` when unset.
+    pub generate_timeout: Option<Duration>,
+    /// Generation and embedding timeouts become p99 × this factor of the
+    /// model's recent durations on each backend; off when unset.
+    pub learned_timeout_factor: Option<f64>,
+    /// Longest learned timeout.
+    pub learned_timeout_max: Duration,
+    /// HTTP or SOCKS proxy for backends without their own `proxy` setting.
+    pub backend_proxy: Option<String>,
+    /// Header carrying the resolved user id to backends; stripped from client requests.
+    pub forward_user_header: Option<axum::http::HeaderName>,
+    /// Origins whose browser requests may reach proxy routes (`*` for any).
+    pub cors_origins: Vec<String>,
+    /// Docker socket used to name clients after their containers; off when unset.
+    pub docker_socket: Option<PathBuf>,
+    /// Where blocked users and IPs, notes and the watchlist are kept.
+    pub blocked_file: PathBuf,
+    pub canary: Option<CanaryConfig>,
+    /// Users exported individually on `/metrics`; the rest share the `_other` label.
+    pub metrics_top_users: usize,
+    pub stream_validation: StreamValidation,
+    /// Validation of responses to requests asking for JSON or a JSON schema.
+    pub structured_output: StructuredOutputMode,
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// Consecutive failed requests before a user is put on cooldown (0 disables).
+    pub cooldown_failures: u32,
+    pub cooldown_secs: u64,
+    pub session_affinity: SessionAffinity,
+    /// Idle time after which a session forgets its backend.
+    pub affinity_ttl_secs: u64,
+    /// `/health` reports not ready above this many queued requests.
+    pub ready_max_queued: Option<usize>,
+    /// `/health` reports not ready when the estimated wait exceeds this.
+    pub ready_max_wait_secs: Option<u64>,
+    /// How long startup waits for a backend to answer before listening.
+    pub wait_for_backends_secs: Option<u64>,
+    /// Reject batch-lane requests with 503 while the estimated wait exceeds this.
+    pub shed_batch_wait_secs: Option<u64>,
+    /// Bearer token for the `/admin` API; the API is disabled without one.
+    pub admin_token: Option<String>,
+    /// Prefix length blocked when blocking an IPv6 client (128 = the single address).
+    pub ipv6_block_prefix: u8,
+    /// Requests one user may have running at once (0 = unlimited).
+    pub user_max_concurrent: usize,
+    /// Requests one user may have queued; more are rejected with 429 (0 = unlimited).
+    pub user_max_queued: usize,
+    /// Estimated prompt tokens one user may have queued; more are rejected with 429 (0 = unlimited).
+    pub user_max_queued_tokens: u64,
+    /// Order each user's queue by estimated prompt size instead of arrival.
+    pub shortest_job_first: bool,
+    pub scheduler_start: SchedulerStart,
+    pub tie_break: TieBreak,
+    /// Load a queued request's model on the backend it will likely run on,
+    /// while it waits, if no suitable backend has it loaded.
+    pub warmup_on_miss: bool,
+    /// Where the last served user is kept for `SchedulerStart::Resume`.
+    pub scheduler_state_file: PathBuf,
+    /// Hold non-streaming requests this long so requests for the same model
+    /// can be dispatched back-to-back (0 disables).
+    pub batch_window_ms: u64,
+    /// Raised concurrency cap while a single user is the only one with work.
+    pub idle_boost_concurrent: Option<usize>,
+    /// Raised queue cap while a single user is the only one with work.
+    pub idle_boost_queued: Option<usize>,
+    /// Fail queued requests with 503 once every backend has been offline this long.
+    pub offline_grace_secs: Option<u64>,
+    /// Bytes queued request bodies may hold in total; new requests get 503 beyond (0 = unlimited).
+    pub max_queued_bytes: u64,
+    /// Directory the oldest queued bodies are moved to instead of rejecting
+    /// requests at `max_queued_bytes`.
+    pub spool_dir: Option<PathBuf>,
+    /// Bytes the spool may hold (0 = unlimited).
+    pub max_spooled_bytes: u64,
+    /// How long a resumable task whose client went away waits for it to re-attach.
+    pub disconnect_grace_secs: Option<u64>,
+    /// Log every dispatch decision and send it as a `schedule` event.
+    pub scheduler_trace: bool,
+    /// Open connections one client IP may hold; more are closed on accept (0 = unlimited).
+    pub max_connections_per_ip: usize,
+    /// Proxy WebSocket upgrades on any path to a backend.
+    pub websockets: bool,
+    /// Open WebSocket connections one user may hold (0 = unlimited).
+    pub websocket_max_per_user: usize,
+    /// Share of requests, in percent, each backend should serve without failing.
+    pub error_budget_target: f64,
+    /// Rolling window the error budget is measured over.
+    pub error_budget_window: Duration,
+    /// Time span of the per-user share timeline.
+    pub fairness_window: Duration,
+    /// Finished requests kept for the TUI history view.
+    pub history_size: usize,
+    /// Requests failed by a backend kept for replay from the TUI (0 disables it).
+    pub dead_letter_size: usize,
+    /// Compressed bytes of dead letter bodies kept at most (0 = unlimited).
+    pub dead_letter_max_bytes: u64,
+    /// File every finished request is appended to, for `ollamaMQ report`.
+    pub usage_log: Option<PathBuf>,
+    /// Size past which the usage log is rotated into a compressed archive (0 = never).
+    pub usage_log_max_bytes: u64,
+    /// Compressed usage log archives kept.
+    pub usage_log_max_files: usize,
+    /// Handling of one user id connected from several IPs at once.
+    pub multi_ip_policy: MultiIpPolicy,
+    /// Temporary IP blocks after repeated failed authentication; `None` only counts.
+    pub jail: Option<JailPolicy>,
+    /// Scale-up/down signals for external autoscalers.
+    pub autoscale: AutoscaleConfig,
+    /// StatsD/DogStatsD push of gauges, counters and timers.
+    pub statsd: StatsdConfig,
+    /// Reject requests exceeding a model's context length or sending images to text-only models.
+    pub model_checks: bool,
+    /// How long cached `/api/tags` and `/api/show` answers are served; zero
+    /// proxies these endpoints like any other request.
+    pub model_cache_ttl: Duration,
+    /// What to do when a client stops reading a response for `slow_client_timeout_secs`.
+    pub slow_client_policy: SlowClientPolicy,
+    pub slow_client_timeout_secs: u64,
+    /// When streaming and non-streaming responses give their backend slot back.
+    pub slot_policy: SlotPolicy,
+    /// Which queued requests are still served on graceful shutdown.
+    pub shutdown_drain: DrainPolicy,
+    /// Longest graceful shutdown; whatever is still queued then is set aside.
+    pub shutdown_timeout: Duration,
+    /// Snapshot file receiving the requests set aside on shutdown.
+    pub shutdown_save: Option<PathBuf>,
+    /// Settings from the optional `--config` file (tiers, ...).
+    pub file: FileConfig,
+}
+
+impl DispatcherConfig {
+    /// Backend timeout for a request to `path`.
+    pub fn timeout_for(&self, path: &str) -> Duration {
+        let timeout = match RouteClass::from_path(path) {
+            RouteClass::Embedding => self.embed_timeout,
+            RouteClass::Generation => self.generate_timeout,
+            RouteClass::Other => None,
+        };
+        timeout.unwrap_or(self.timeout)
+    }
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        Self {
+            backend_urls: vec!["http://localhost:11434".to_string()],
+            timeout: Duration::from_secs(300),
+            embed_timeout: None,
+            generate_timeout: None,
+            learned_timeout_factor: None,
+            learned_timeout_max: Duration::from_secs(3600),
+            backend_proxy: None,
+            forward_user_header: None,
+            cors_origins: Vec::new(),
+            docker_socket: None,
+            blocked_file: PathBuf::from(crate::blocked_store::DEFAULT_PATH),
+            canary: None,
+            metrics_top_users: 20,
+            stream_validation: StreamValidation::Off,
+            structured_output: StructuredOutputMode::Off,
+            maintenance_windows: Vec::new(),
+            cooldown_failures: 0,
+            cooldown_secs: 30,
+            session_affinity: SessionAffinity::Off,
+            affinity_ttl_secs: 600,
+            ready_max_queued: None,
+            ready_max_wait_secs: None,
+            wait_for_backends_secs: None,
+            shed_batch_wait_secs: None,
+            admin_token: None,
+            ipv6_block_prefix: 64,
+            user_max_concurrent: 0,
+            user_max_queued: 0,
+            user_max_queued_tokens: 0,
+            shortest_job_first: false,
+            scheduler_start: SchedulerStart::First,
+            tie_break: TieBreak::UserId,
+            warmup_on_miss: false,
+            scheduler_state_file: PathBuf::from(SCHEDULER_STATE_FILE),
+            batch_window_ms: 0,
+            idle_boost_concurrent: None,
+            idle_boost_queued: None,
+            offline_grace_secs: None,
+            max_queued_bytes: 0,
+            spool_dir: None,
+            max_spooled_bytes: 0,
+            disconnect_grace_secs: None,
+            scheduler_trace: false,
+            max_connections_per_ip: 0,
+            websockets: false,
+            websocket_max_per_user: 4,
+            error_budget_target: error_budget::DEFAULT_TARGET,
+            error_budget_window: Duration::from_secs(error_budget::DEFAULT_WINDOW_MINS * 60),
+            fairness_window: Duration::from_secs(crate::fairness::DEFAULT_WINDOW_MINS * 60),
+            history_size: 500,
+            dead_letter_size: 100,
+            dead_letter_max_bytes: 256 * 1024 * 1024,
+            usage_log: None,
+            usage_log_max_bytes: 0,
+            usage_log_max_files: 5,
+            multi_ip_policy: MultiIpPolicy::Allow,
+            jail: None,
+            autoscale: AutoscaleConfig::default(),
+            statsd: StatsdConfig::default(),
+            model_checks: true,
+            model_cache_ttl: Duration::from_secs(crate::catalogue::DEFAULT_TTL_SECS),
+            slow_client_policy: SlowClientPolicy::Wait,
+            slow_client_timeout_secs: 10,
+            slot_policy: SlotPolicy::default(),
+            shutdown_drain: DrainPolicy::All,
+            shutdown_timeout: Duration::from_secs(crate::shutdown::DEFAULT_TIMEOUT_SECS),
+            shutdown_save: None,
+            file: FileConfig::default(),
+        }
+    }
+}
+
+/// Point-in-time counters for one user.
+#[derive(Clone, Debug, Default)]
+pub struct UserStats {
+    pub user_id: String,
+    pub queued: usize,
+    pub processing: usize,
+    pub processed: usize,
+    pub dropped: usize,
+    /// Requests flagged for arriving while the user was connected from another IP.
+    pub multi_ip_flagged: usize,
+    /// Request body bytes received from the user.
+    pub bytes_in: u64,
+    /// Response bytes streamed to the user.
+    pub bytes_out: u64,
+    /// Completed requests that offered the model tools.
+    pub tool_requests: u64,
+    /// Tool calls returned in answers to the user.
+    pub tool_calls: u64,
+    /// Failed and rejected requests by what went wrong.
+    pub errors: ErrorCounts,
+}
+
+impl UserStats {
+    /// Adds another user's counters into this one (used for aggregate buckets).
+    pub fn absorb(&mut self, other: &UserStats) {
+        self.queued += other.queued;
+        self.processing += other.processing;
+        self.processed += other.processed;
+        self.dropped += other.dropped;
+        self.multi_ip_flagged += other.multi_ip_flagged;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.tool_requests += other.tool_requests;
+        self.tool_calls += other.tool_calls;
+        self.errors.absorb(&other.errors);
+    }
+}
+
+/// Requests with tools a user has completed, and the tool calls answered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ToolUse {
+    pub requests: u64,
+    pub calls: u64,
+}
+
+/// Tokens reported for a user's completed requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenTotals {
+    pub prompt: u64,
+    pub completion: u64,
+}
+
+/// Bytes a user has sent in request bodies and received in responses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bandwidth {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
 }
 
 pub struct AppState {
@@ -114,113 +846,1099 @@ pub struct AppState {
     pub processing_counts: Mutex<HashMap<String, usize>>,
     pub processed_counts: Mutex<HashMap<String, usize>>,
     pub dropped_counts: Mutex<HashMap<String, usize>>,
+    /// Failed and rejected requests per user, by class.
+    pub error_counts: Mutex<HashMap<String, ErrorCounts>>,
     pub user_ips: Mutex<HashMap<String, IpAddr>>,
-    pub blocked_ips: Mutex<HashSet<IpAddr>>,
+    pub blocked_ips: Mutex<HashSet<IpPrefix>>,
+    /// Failed and blocked attempts per address, and the temporary blocks they led to.
+    pub jail: Mutex<Jail>,
     pub blocked_users: Mutex<HashSet<String>>,
+    /// Reason, author and expiry of user and IP blocks.
+    pub user_blocks: Mutex<HashMap<String, BlockInfo>>,
+    pub ip_blocks: Mutex<HashMap<IpPrefix, BlockInfo>>,
+    pub user_notes: Mutex<HashMap<String, String>>,
+    /// Users on the operator's watchlist.
+    pub watched_users: Mutex<HashSet<String>>,
     pub vip_user: Mutex<Option<String>>,
     pub boost_user: Mutex<Option<String>>,
+    /// User whose next request goes to the next free backend slot, ahead of
+    /// the rotation, set by the operator from the TUI; cleared once dispatched.
+    pub dispatch_next: Mutex<Option<String>>,
     pub global_counter: Mutex<usize>,
     pub notify: Notify,
     pub backend_freed: Notify,
     pub backends: Mutex<Vec<BackendStatus>>,
     pub last_backend_idx: Mutex<usize>,
-    pub timeout: u64,
+    pub config: DispatcherConfig,
+    pub maintenance: Mutex<MaintenanceStatus>,
+    /// Dispatch times of batch-lane tasks while a `limit-batch` window is active.
+    batch_dispatches: Mutex<VecDeque<Instant>>,
+    /// Consecutive failed requests per user, reset on success.
+    pub failure_streaks: Mutex<HashMap<String, u32>>,
+    /// Users rejected with 429 until the given instant.
+    pub cooldowns: Mutex<HashMap<String, Instant>>,
+    /// Session key -> (backend URL, last use).
+    pub affinity: Mutex<HashMap<String, (String, Instant)>>,
+    /// Queue/backend/streaming time of recently completed requests.
+    pub stage_stats: Mutex<StageStats>,
+    /// Stage timings and latency objective verdicts per tier.
+    pub slos: Mutex<SloTracker>,
+    /// Dispatches per user over the last `fairness_window`.
+    pub fairness: Mutex<ShareTimeline>,
+    /// Completed requests per user and model over the last 5m/1h/24h.
+    pub rollup: Mutex<Rollup>,
+    /// Requests per model and hour of the day, for the demand forecast.
+    pub forecast: Mutex<DemandCurves>,
+    /// Results of the configured A/B experiments, per arm.
+    pub experiments: Mutex<ExperimentResults>,
+    /// Failed requests per (user, backend URL, cause).
+    pub failures: Mutex<HashMap<(String, String, FailureKind), usize>>,
+    pub started_at: Instant,
+    /// Requests currently running on a backend.
+    pub in_flight: AtomicUsize,
+    /// Bytes held by the bodies of queued requests.
+    queued_bytes: Arc<AtomicU64>,
+    /// Requests rejected because `--max-queued-mb` was reached.
+    pub memory_rejected: AtomicUsize,
+    /// Bytes of queued bodies moved to the `--spool-dir`.
+    spooled_bytes: Arc<AtomicU64>,
+    /// Bodies moved to the spool since startup.
+    pub spooled_tasks: AtomicU64,
+    /// Debug-trace requests running; only one at a time jumps the queue.
+    pub debug_in_flight: AtomicUsize,
+    /// Owner of each task currently running on a backend, by task id.
+    pub running_tasks: Mutex<HashMap<String, String>>,
+    /// Resumable tasks waiting for their client to re-attach, by task id.
+    pub detached: Mutex<HashMap<String, DetachedTask>>,
+    /// Tasks a reconnecting client re-attached to since startup.
+    pub reattached: AtomicU64,
+    /// Tasks switched to a fallback model since startup.
+    pub model_fallbacks: AtomicU64,
+    /// Responses the slow-client policy disconnected, spilled or thinned out.
+    pub slow_client_responses: AtomicUsize,
+    /// Backend requests dropped because their client left, by `CancelStage`.
+    pub cancellations: [AtomicUsize; 2],
+    /// Estimated backend time those cancellations saved, in milliseconds.
+    pub cancel_saved_ms: AtomicU64,
+    /// Incoming requests over the last few seconds.
+    pub request_rate: Mutex<RateMeter>,
+    /// Set while the last health round found no backend online.
+    pub offline_since: Mutex<Option<Instant>>,
+    last_health_round: Mutex<Instant>,
+    /// Recently finished requests, oldest first.
+    pub history: Mutex<History>,
+    /// Requests failed by a backend, replayable from the TUI history view.
+    pub dead_letters: Mutex<DeadLetters>,
+    /// Recent request durations per (backend, model), for learned timeouts.
+    pub learned_timeouts: Mutex<LearnedTimeouts>,
+    /// Batches of `POST /api/batch` by batch id, kept for a while after finishing.
+    pub batches: Mutex<HashMap<String, crate::batch::Batch>>,
+    usage_log: Option<UsageLog>,
+    /// Open requests per user and client IP.
+    pub connections: Mutex<HashMap<String, HashMap<IpAddr, usize>>>,
+    /// Requests flagged by the `flag` multi-IP policy, per user.
+    pub multi_ip_flags: Mutex<HashMap<String, usize>>,
+    /// Request and response bytes per user.
+    pub bandwidth: Mutex<HashMap<String, Bandwidth>>,
+    /// Requests with tools and the tool calls answered, per user.
+    pub tool_use: Mutex<HashMap<String, ToolUse>>,
+    /// Prompt and completion tokens of each user's completed requests.
+    pub token_totals: Mutex<HashMap<String, TokenTotals>>,
+    /// Woken when the blocked items need to be written again.
+    pub blocked_dirty: Notify,
+    /// User the round-robin cursor last picked.
+    pub last_served: Mutex<Option<String>>,
+    /// Seed of the `TieBreak::Shuffled` order.
+    tie_seed: RandomState,
+    /// Last warmup per (backend URL, model), for `--warmup-on-miss`.
+    warmups: Mutex<HashMap<(String, String), Instant>>,
+    /// Requests rejected by load shedding, per (tier, lane).
+    pub shed_counts: Mutex<HashMap<(String, Lane), usize>>,
+    /// Dispatcher events for automation; see `emit`.
+    pub events: broadcast::Sender<Event>,
+    /// External identity lookup, when the config file has an `identity` section.
+    pub identity: Option<IdentityResolver>,
+    /// Proxied WebSocket connections, with `--websockets`.
+    pub websockets: Mutex<WebSockets>,
+    /// Container names of client IPs, with `--docker-names`.
+    pub docker: Option<DockerNames>,
+    /// Latest autoscaling signal.
+    pub scale: Mutex<ScaleStatus>,
+    /// Cached `/api/tags` and `/api/show` answers per backend.
+    pub catalogue: Mutex<ModelCatalogue>,
+    /// Context length and vision support per model name, from `/api/show`.
+    pub model_info: Mutex<HashMap<String, ModelInfo>>,
+    /// Progress of the graceful shutdown, once it started.
+    pub shutdown: Mutex<Option<ShutdownStatus>>,
+    /// Structured-output checks per model, with `--structured-output`.
+    pub structured: Mutex<HashMap<String, StructuredStats>>,
+    /// Open client connections per IP, counted by `LimitedListener`.
+    pub sockets: Arc<ConnectionTable>,
+    /// Shared work queue, set at startup when `--nats-url` is given.
+    #[cfg(feature = "nats")]
+    pub nats: std::sync::OnceLock<crate::nats::NatsQueue>,
+    task_ids: UlidGenerator,
+    canary_accumulator: Mutex<u32>,
 }
 
 impl AppState {
-    pub fn new(backend_urls: Vec<String>, timeout: u64) -> Self {
-        let (blocked_ips, blocked_users) = Self::load_blocked_items();
+    pub fn new(config: DispatcherConfig) -> Self {
+        let mut persisted = crate::blocked_store::load(&config.blocked_file);
+        persisted.normalize_users(&config.file.user_ids);
+        let identity = config.file.identity.clone().map(IdentityResolver::new);
+        let docker = config.docker_socket.as_deref().map(DockerNames::new);
+        let canary = &config.canary;
+        let mut backend_urls = config.backend_urls.clone();
+        if let Some(c) = canary
+            && !backend_urls.contains(&c.backend_url)
+        {
+            backend_urls.push(c.backend_url.clone());
+        }
         let backends = backend_urls.into_iter()
-            .map(|url| BackendStatus {
-                url,
-                active_requests: 0,
-                processed_count: 0,
-                is_online: true,
-                api_type: BackendApiType::Unknown,
-                available_models: HashSet::new(),
-                loaded_models: HashSet::new(),
-                current_model: None,
-            })
+            .map(|url| BackendStatus::new(url, &config))
             .collect();
 
+        let history = History::new(config.history_size);
+        let dead_letters = DeadLetters::new(config.dead_letter_size, config.dead_letter_max_bytes);
+        let fairness = ShareTimeline::new(config.fairness_window);
+        let usage_log = config.usage_log.as_ref().and_then(|path| match UsageLog::open(path, config.usage_log_max_bytes, config.usage_log_max_files) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                warn!("Failed to open usage log {}: {}", path.display(), e);
+                None
+            }
+        });
+
         Self {
             queues: Mutex::new(HashMap::new()),
             processing_counts: Mutex::new(HashMap::new()),
             processed_counts: Mutex::new(HashMap::new()),
             dropped_counts: Mutex::new(HashMap::new()),
+            error_counts: Mutex::new(HashMap::new()),
             user_ips: Mutex::new(HashMap::new()),
-            blocked_ips: Mutex::new(blocked_ips),
-            blocked_users: Mutex::new(blocked_users),
+            blocked_ips: Mutex::new(persisted.ips),
+            jail: Mutex::new(Jail::default()),
+            blocked_users: Mutex::new(persisted.users),
+            user_blocks: Mutex::new(persisted.user_blocks),
+            ip_blocks: Mutex::new(persisted.ip_blocks),
+            user_notes: Mutex::new(persisted.notes),
+            watched_users: Mutex::new(persisted.watched),
             vip_user: Mutex::new(None),
             boost_user: Mutex::new(None),
+            dispatch_next: Mutex::new(None),
             global_counter: Mutex::new(0),
             notify: Notify::new(),
             backend_freed: Notify::new(),
             backends: Mutex::new(backends),
             last_backend_idx: Mutex::new(0),
-            timeout,
+            config,
+            maintenance: Mutex::new(MaintenanceStatus::default()),
+            batch_dispatches: Mutex::new(VecDeque::new()),
+            failure_streaks: Mutex::new(HashMap::new()),
+            cooldowns: Mutex::new(HashMap::new()),
+            affinity: Mutex::new(HashMap::new()),
+            stage_stats: Mutex::new(StageStats::default()),
+            slos: Mutex::new(SloTracker::default()),
+            fairness: Mutex::new(fairness),
+            rollup: Mutex::new(Rollup::default()),
+            forecast: Mutex::new(DemandCurves::default()),
+            experiments: Mutex::new(ExperimentResults::default()),
+            failures: Mutex::new(HashMap::new()),
+            started_at: Instant::now(),
+            in_flight: AtomicUsize::new(0),
+            queued_bytes: Arc::new(AtomicU64::new(0)),
+            memory_rejected: AtomicUsize::new(0),
+            spooled_bytes: Arc::new(AtomicU64::new(0)),
+            spooled_tasks: AtomicU64::new(0),
+            debug_in_flight: AtomicUsize::new(0),
+            running_tasks: Mutex::new(HashMap::new()),
+            detached: Mutex::new(HashMap::new()),
+            reattached: AtomicU64::new(0),
+            model_fallbacks: AtomicU64::new(0),
+            slow_client_responses: AtomicUsize::new(0),
+            cancellations: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            cancel_saved_ms: AtomicU64::new(0),
+            request_rate: Mutex::new(RateMeter::new(REQUEST_RATE_WINDOW)),
+            offline_since: Mutex::new(None),
+            last_health_round: Mutex::new(Instant::now()),
+            history: Mutex::new(history),
+            dead_letters: Mutex::new(dead_letters),
+            learned_timeouts: Mutex::new(LearnedTimeouts::default()),
+            batches: Mutex::new(HashMap::new()),
+            usage_log,
+            connections: Mutex::new(HashMap::new()),
+            multi_ip_flags: Mutex::new(HashMap::new()),
+            bandwidth: Mutex::new(HashMap::new()),
+            tool_use: Mutex::new(HashMap::new()),
+            token_totals: Mutex::new(HashMap::new()),
+            blocked_dirty: Notify::new(),
+            last_served: Mutex::new(None),
+            warmups: Mutex::new(HashMap::new()),
+            tie_seed: RandomState::new(),
+            shed_counts: Mutex::new(HashMap::new()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            identity,
+            websockets: Mutex::new(WebSockets::default()),
+            docker,
+            scale: Mutex::new(ScaleStatus::default()),
+            catalogue: Mutex::new(ModelCatalogue::default()),
+            model_info: Mutex::new(HashMap::new()),
+            shutdown: Mutex::new(None),
+            structured: Mutex::new(HashMap::new()),
+            sockets: Arc::new(ConnectionTable::default()),
+            #[cfg(feature = "nats")]
+            nats: std::sync::OnceLock::new(),
+            task_ids: UlidGenerator::new(),
+            canary_accumulator: Mutex::new(0),
         }
     }
 
-    fn load_blocked_items() -> (HashSet<IpAddr>, HashSet<String>) {
-        if let Ok(content) = fs::read_to_string(BLOCKED_FILE) {
-            if let Ok(config) = serde_json::from_str::<BlockedConfig>(&content) {
-                return (config.ips, config.users);
-            }
+    /// Decides whether a request for `model` goes to the canary backend.
+    /// Uses an accumulator instead of randomness so the split is exact.
+    pub fn roll_canary(&self, model: Option<&str>) -> bool {
+        let Some(ref canary) = self.config.canary else {
+            return false;
+        };
+        let applies = match (&canary.model, model) {
+            (None, _) => true,
+            (Some(c), Some(m)) => model_name_matches(m, c),
+            (Some(_), None) => false,
+        };
+        if !applies {
+            return false;
+        }
+
+        let mut acc = self.canary_accumulator.lock().unwrap();
+        *acc += canary.percent.min(100);
+        if *acc >= 100 {
+            *acc -= 100;
+            true
+        } else {
+            false
         }
-        (HashSet::new(), HashSet::new())
     }
 
-    fn save_blocked_items(&self) {
-        let config = BlockedConfig {
-            ips: self.blocked_ips.lock().unwrap().clone(),
-            users: self.blocked_users.lock().unwrap().clone(),
+    /// Tier of `user_id`: the one assigned by the identity endpoint if it names
+    /// a configured tier, otherwise the one from the config file.
+    pub fn tier_of(&self, user_id: &str) -> Option<String> {
+        let assigned = self.identity.as_ref()
+            .and_then(|r| r.identity_of(user_id))
+            .and_then(|i| i.tier)
+            .filter(|t| self.config.file.tiers.contains_key(t));
+        assigned.or_else(|| self.config.file.tier_of(user_id).map(str::to_string))
+    }
+
+    pub fn tier_config(&self, user_id: &str) -> Option<TierConfig> {
+        self.tier_of(user_id).and_then(|name| self.config.file.tiers.get(&name).cloned())
+    }
+
+    /// Counts a backend request dropped at `stage` after `used` on the
+    /// backend, and returns the backend time that likely saved: what a typical
+    /// recent request took beyond `used`.
+    fn record_cancellation(&self, stage: CancelStage, used: Duration) -> Duration {
+        self.cancellations[stage as usize].fetch_add(1, Ordering::Relaxed);
+        let typical = {
+            let stats = self.stage_stats.lock().unwrap();
+            stats.percentiles(Stage::FirstChunk, &[0.5]).zip(stats.percentiles(Stage::Streaming, &[0.5]))
+                .map(|(first, streaming)| first[0] + streaming[0])
+        };
+        let saved = typical.map_or(Duration::ZERO, |t| t.saturating_sub(used));
+        self.cancel_saved_ms.fetch_add(saved.as_millis() as u64, Ordering::Relaxed);
+        saved
+    }
+
+    /// Adds a completed request's stage timings to the totals and to the
+    /// latency objectives of its tier.
+    fn record_timing(&self, user_id: &str, timing: StageTiming) {
+        self.stage_stats.lock().unwrap().record(timing);
+        let Some(tier) = self.tier_of(user_id) else {
+            return;
+        };
+        let Some(slos) = self.config.file.tiers.get(&tier).map(|t| &t.slos).filter(|s| !s.is_empty()) else {
+            return;
         };
-        if let Ok(content) = serde_json::to_string_pretty(&config) {
-            let _ = fs::write(BLOCKED_FILE, content);
+        let changed = self.slos.lock().unwrap().record(&tier, slos, timing);
+        for status in changed {
+            let observed = status.observed_ms.map(|ms| format_duration(Duration::from_millis(ms))).unwrap_or_default();
+            if status.violated {
+                warn!("SLO {} violated: {} over the last {} requests", status.label(), observed, status.samples);
+            } else {
+                info!("SLO {} met again: {}", status.label(), observed);
+            }
+            self.emit(|| Event::Slo(Box::new(status)));
         }
     }
 
-    pub fn block_ip(&self, ip: IpAddr) {
-        {
-            let mut ips = self.blocked_ips.lock().unwrap();
-            ips.insert(ip);
+    /// Failure threshold and cooldown length for `user_id`, honouring tier overrides.
+    pub fn cooldown_policy(&self, user_id: &str) -> (u32, Duration) {
+        let tier = self.tier_config(user_id);
+        let failures = tier.as_ref().and_then(|t| t.cooldown_failures).unwrap_or(self.config.cooldown_failures);
+        let secs = tier.and_then(|t| t.cooldown_secs).unwrap_or(self.config.cooldown_secs);
+        (failures, Duration::from_secs(secs))
+    }
+
+    /// Concurrency and queue caps `(max_concurrent, max_queued)` for `user_id`
+    /// (0 = unlimited), honouring tier overrides. While the user is the only one
+    /// with queued or running work (`sole_user`), the idle boost limits apply.
+    pub fn user_caps(&self, user_id: &str, sole_user: bool) -> (usize, usize) {
+        let tier = self.tier_config(user_id);
+        let quota = self.identity.as_ref().and_then(|r| r.identity_of(user_id));
+        let mut concurrent = quota.as_ref().and_then(|q| q.max_concurrent)
+            .or(tier.as_ref().and_then(|t| t.max_concurrent))
+            .unwrap_or(self.config.user_max_concurrent);
+        let mut queued = quota.as_ref().and_then(|q| q.max_queued)
+            .or(tier.as_ref().and_then(|t| t.max_queued))
+            .unwrap_or(self.config.user_max_queued);
+        if sole_user {
+            if let Some(limit) = self.config.idle_boost_concurrent
+                && concurrent > 0
+            {
+                concurrent = concurrent.max(limit);
+            }
+            if let Some(limit) = self.config.idle_boost_queued
+                && queued > 0
+            {
+                queued = queued.max(limit);
+            }
+        }
+        (concurrent, queued)
+    }
+
+    /// Estimated wait above which a request of `user_id` in `lane` is shed:
+    /// the tier's `shed_wait_secs`, lowered to `--shed-batch-wait-secs` for
+    /// batch traffic. `None` means the request is always admitted.
+    pub fn shed_limit(&self, user_id: &str, lane: Lane) -> Option<u64> {
+        let tier = self.tier_config(user_id).and_then(|t| t.shed_wait_secs);
+        let batch = self.config.shed_batch_wait_secs.filter(|_| lane == Lane::Batch);
+        match (tier, batch) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn record_bandwidth(&self, user_id: &str, bytes_in: u64, bytes_out: u64) {
+        let mut bandwidth = self.bandwidth.lock().unwrap();
+        let entry = bandwidth.entry(user_id.to_string()).or_default();
+        entry.bytes_in += bytes_in;
+        entry.bytes_out += bytes_out;
+    }
+
+    /// Counts a completed request that offered tools and the calls in its answer.
+    pub fn record_tool_use(&self, user_id: &str, calls: u64) {
+        let mut tool_use = self.tool_use.lock().unwrap();
+        let entry = tool_use.entry(user_id.to_string()).or_default();
+        entry.requests += 1;
+        entry.calls += calls;
+    }
+
+    /// Adds the tokens a backend reported for a completed request.
+    pub fn record_tokens(&self, user_id: &str, prompt: Option<u64>, completion: Option<u64>) {
+        if prompt.is_none() && completion.is_none() {
+            return;
         }
+        let mut totals = self.token_totals.lock().unwrap();
+        let entry = totals.entry(user_id.to_string()).or_default();
+        entry.prompt += prompt.unwrap_or(0);
+        entry.completion += completion.unwrap_or(0);
+    }
+
+    /// Frees a backend for the next request while the response of the request
+    /// that held it for `held` is still being relayed.
+    pub fn release_slot(&self, url: &str, kind: ResponseKind, held: Duration) {
+        if let Some(backend) = self.backends.lock().unwrap().iter_mut().find(|b| b.url == url) {
+            backend.active_requests = backend.active_requests.saturating_sub(1);
+            backend.slots.relaying += 1;
+            backend.slots.early_releases += 1;
+            backend.slots.record(kind, held);
+        }
+        self.backend_freed.notify_one();
+    }
+
+    /// Records how a request ended and starts a cooldown once the user's streak
+    /// of client-caused failures reaches its threshold. Backend failures leave
+    /// the streak alone, so an unhealthy backend doesn't get its users cooled down.
+    pub fn record_outcome(&self, user_id: &str, failure: Option<FailureKind>) {
+        let mut streaks = self.failure_streaks.lock().unwrap();
+        match failure {
+            None => {
+                streaks.remove(user_id);
+                return;
+            }
+            Some(FailureKind::Backend) => return,
+            Some(FailureKind::Client) => {}
+        }
+        let streak = streaks.entry(user_id.to_string()).or_insert(0);
+        *streak += 1;
+
+        let (threshold, cooldown) = self.cooldown_policy(user_id);
+        if threshold > 0 && *streak >= threshold {
+            streaks.remove(user_id);
+            self.cooldowns.lock().unwrap().insert(user_id.to_string(), Instant::now() + cooldown);
+            warn!("User {} failed {} requests in a row; cooling down for {}s", user_id, threshold, cooldown.as_secs());
+        }
+    }
+
+    pub fn record_failure(&self, user_id: &str, backend_url: &str, kind: FailureKind) {
+        *self.failures.lock().unwrap()
+            .entry((user_id.to_string(), backend_url.to_string(), kind))
+            .or_insert(0) += 1;
+    }
+
+    /// Time left on the user's cooldown, if one is active.
+    pub fn cooldown_remaining(&self, user_id: &str) -> Option<Duration> {
+        let mut cooldowns = self.cooldowns.lock().unwrap();
+        let until = *cooldowns.get(user_id)?;
+        let now = Instant::now();
+        if until > now {
+            Some(until - now)
+        } else {
+            cooldowns.remove(user_id);
+            None
+        }
+    }
+
+    /// Backend the session last ran on, unless the mapping has expired.
+    pub fn pinned_backend(&self, key: &str) -> Option<String> {
+        let ttl = Duration::from_secs(self.config.affinity_ttl_secs);
+        let affinity = self.affinity.lock().unwrap();
+        affinity.get(key)
+            .filter(|(_, last_used)| last_used.elapsed() < ttl)
+            .map(|(url, _)| url.clone())
+    }
+
+    pub fn pin_session(&self, key: String, backend_url: String) {
+        self.affinity.lock().unwrap().insert(key, (backend_url, Instant::now()));
+    }
+
+    /// Drops sessions idle for longer than the affinity TTL.
+    pub fn prune_sessions(&self) {
+        let ttl = Duration::from_secs(self.config.affinity_ttl_secs);
+        self.affinity.lock().unwrap().retain(|_, (_, last_used)| last_used.elapsed() < ttl);
+    }
+
+    /// Schedules a write of the blocked items file (see `blocked_store`).
+    pub(crate) fn save_blocked_items(&self) {
+        self.blocked_dirty.notify_one();
+    }
+
+    /// What blocking `ip` covers: the address itself for IPv4, its
+    /// `--ipv6-block-prefix` network for IPv6.
+    pub fn block_target(&self, ip: IpAddr) -> IpPrefix {
+        match ip.to_canonical() {
+            ip @ IpAddr::V4(_) => IpPrefix::single(ip),
+            ip @ IpAddr::V6(_) => IpPrefix::new(ip, self.config.ipv6_block_prefix),
+        }
+    }
+
+    /// Publishes an event to subscribers; the event is only built if anyone listens.
+    pub fn emit(&self, event: impl FnOnce() -> Event) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+
+    pub fn block_ip(&self, prefix: IpPrefix, info: BlockInfo) {
+        self.jail.lock().unwrap().forget_ban(&prefix);
+        let added = {
+            let mut ips = self.blocked_ips.lock().unwrap();
+            ips.insert(prefix)
+        };
+        self.ip_blocks.lock().unwrap().insert(prefix, info.clone());
         self.save_blocked_items();
-        warn!("IP blocked: {}", ip);
+        warn!("IP blocked: {}{}", prefix, info.describe());
+        if added {
+            self.emit(|| Event::Blocked { user: None, ip: Some(prefix) });
+        }
     }
 
-    pub fn block_user(&self, user_id: String) {
-        {
+    pub fn block_user(&self, user_id: String, info: BlockInfo) {
+        // Queue keys are normalized; a block on anything else would never match
+        let user_id = self.config.file.user_ids.key(&user_id);
+        let added = {
             let mut users = self.blocked_users.lock().unwrap();
-            users.insert(user_id.clone());
-        }
+            users.insert(user_id.clone())
+        };
+        self.user_blocks.lock().unwrap().insert(user_id.clone(), info.clone());
         self.save_blocked_items();
-        warn!("User blocked: {}", user_id);
+        warn!("User blocked: {}{}", user_id, info.describe());
+        if added {
+            self.emit(|| Event::Blocked { user: Some(user_id), ip: None });
+        }
     }
 
     #[allow(dead_code)]
-    pub fn unblock_ip(&self, ip: IpAddr) {
-        {
+    pub fn unblock_ip(&self, prefix: IpPrefix) {
+        self.jail.lock().unwrap().forget_ban(&prefix);
+        let removed = {
             let mut ips = self.blocked_ips.lock().unwrap();
-            ips.remove(&ip);
-        }
+            ips.remove(&prefix)
+        };
+        self.ip_blocks.lock().unwrap().remove(&prefix);
         self.save_blocked_items();
-        info!("IP unblocked: {}", ip);
+        info!("IP unblocked: {}", prefix);
+        if removed {
+            self.emit(|| Event::Unblocked { user: None, ip: Some(prefix) });
+        }
+    }
+
+    /// Blocked addresses and prefixes that contain `ip`.
+    pub fn blocks_covering(&self, ip: &IpAddr) -> Vec<IpPrefix> {
+        self.blocked_ips.lock().unwrap().iter().filter(|p| p.contains(ip)).copied().collect()
     }
 
     #[allow(dead_code)]
     pub fn unblock_user(&self, user_id: &str) {
-        {
+        let user_id = &self.config.file.user_ids.key(user_id);
+        let removed = {
             let mut users = self.blocked_users.lock().unwrap();
-            users.remove(user_id);
-        }
+            users.remove(user_id)
+        };
+        self.user_blocks.lock().unwrap().remove(user_id);
         self.save_blocked_items();
         info!("User unblocked: {}", user_id);
+        if removed {
+            self.emit(|| Event::Unblocked { user: Some(user_id.to_string()), ip: None });
+        }
+    }
+
+    /// Removes every queued task for `user_id`, handing them to the caller.
+    pub fn purge_queue(&self, user_id: &str) -> Vec<Task> {
+        let tasks: Vec<Task> = {
+            let mut queues = self.queues.lock().unwrap();
+            queues.get_mut(user_id).map(|q| q.drain(..).collect()).unwrap_or_default()
+        };
+        if !tasks.is_empty() {
+            warn!("Queue purged for user {}: {} task(s)", user_id, tasks.len());
+        }
+        tasks
+    }
+
+    /// Backend timeout for a request for `model` on `backend` to `path`: a
+    /// config file override, a learned one, or the route's static timeout.
+    pub fn timeout_for(&self, backend: &str, model: Option<&str>, path: &str) -> (Duration, TimeoutSource) {
+        crate::timeouts::resolve(&self.config, &self.learned_timeouts.lock().unwrap(), backend, model, path)
+    }
+
+    /// Bytes held by the bodies of queued requests.
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Counts a body of `len` bytes as queued until the returned guard is
+    /// released or dropped.
+    pub fn hold_queued_bytes(&self, len: usize) -> QueuedBytes {
+        self.queued_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        QueuedBytes { total: self.queued_bytes.clone(), len: len as u64 }
+    }
+
+    /// Bytes of queued bodies waiting in the spool.
+    pub fn spooled_bytes(&self) -> u64 {
+        self.spooled_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Counts a spooled body of `len` bytes until the returned guard is dropped.
+    pub fn hold_spooled_bytes(&self, path: PathBuf, len: u64) -> SpooledBody {
+        self.spooled_bytes.fetch_add(len, Ordering::Relaxed);
+        SpooledBody::new(path, len, self.spooled_bytes.clone())
+    }
+
+    /// A new task id (a ULID), issued at admission.
+    pub fn next_task_id(&self) -> String {
+        self.task_ids.next()
+    }
+
+    /// Removes one queued task and answers its client with `410 Gone`.
+    /// Returns `false` when the task is not (or no longer) queued.
+    pub fn cancel_task(&self, user_id: &str, task_id: &str) -> bool {
+        let task = {
+            let mut queues = self.queues.lock().unwrap();
+            let Some(queue) = queues.get_mut(user_id) else {
+                return false;
+            };
+            let Some(pos) = queue.iter().position(|t| t.id == task_id) else {
+                return false;
+            };
+            queue.remove(pos).unwrap()
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(axum::http::header::CONTENT_TYPE, "application/json".parse().unwrap());
+        let body = serde_json::json!({ "error": "task cancelled", "task_id": task_id });
+        let _ = task.responder.try_send(ResponsePart::Status(StatusCode::GONE, headers));
+        let _ = task.responder.try_send(ResponsePart::Chunk(Bytes::from(body.to_string())));
+        self.record_dropped(user_id, 1);
+        self.record_history(HistoryEntry::unsent(
+            task_id, user_id, &task.path, task.requested_model.clone(), task.enqueued_at.elapsed(),
+            Outcome::Cancelled, "cancelled while queued",
+        ));
+        info!("Task {} of user {} cancelled", task_id, user_id);
+        true
+    }
+
+    /// Moves a queued task to `position` (0 = next) within its user's queue.
+    /// Returns the resulting position, or `None` when the task is not queued.
+    pub fn move_task(&self, user_id: &str, task_id: &str, position: usize) -> Option<usize> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(user_id)?;
+        let pos = queue.iter().position(|t| t.id == task_id)?;
+        let task = queue.remove(pos).unwrap();
+        let position = position.min(queue.len());
+        queue.insert(position, task);
+        debug!("Task {} of user {} moved from {} to {}", task_id, user_id, pos, position);
+        Some(position)
+    }
+
+    /// Holds a resumable task whose client is gone until it re-attaches or
+    /// `--disconnect-grace-secs` passes.
+    pub fn detach_task(&self, user_id: &str, task: Task) {
+        info!("Task {} of user {}: client disconnected, waiting for it to re-attach", task.id, user_id);
+        let detached = DetachedTask { user_id: user_id.to_string(), task, since: Instant::now() };
+        self.detached.lock().unwrap().insert(detached.task.id.clone(), detached);
+    }
+
+    /// Gives a queued or detached resumable task of `user_id` a new client
+    /// connection; a detached one goes back to the front of the queue.
+    pub fn reattach_task(&self, user_id: &str, task_id: &str, responder: mpsc::Sender<ResponsePart>) -> Reattach {
+        {
+            let mut queues = self.queues.lock().unwrap();
+            if let Some(task) = queues.get_mut(user_id).and_then(|q| q.iter_mut().find(|t| t.id == task_id && t.resumable)) {
+                if !task.responder.is_closed() {
+                    return Reattach::Connected;
+                }
+                task.responder = responder;
+                self.reattached.fetch_add(1, Ordering::Relaxed);
+                return Reattach::Queued;
+            }
+        }
+        let detached = {
+            let mut detached = self.detached.lock().unwrap();
+            match detached.get(task_id) {
+                Some(d) if d.user_id == user_id => detached.remove(task_id),
+                _ => None,
+            }
+        };
+        let Some(DetachedTask { mut task, .. }) = detached else {
+            return Reattach::Unknown;
+        };
+        task.responder = responder;
+        self.requeue_task(user_id, task);
+        self.reattached.fetch_add(1, Ordering::Relaxed);
+        Reattach::Requeued
+    }
+
+    /// Puts a task taken out for dispatch back at the front of its user's queue.
+    pub fn requeue_task(&self, user_id: &str, mut task: Task) {
+        task.queued_bytes = self.hold_queued_bytes(task.body.len());
+        self.queues.lock().unwrap().entry(user_id.to_string()).or_default().push_front(task);
+        self.notify.notify_one();
+    }
+
+    /// Drops detached tasks whose grace period is over.
+    pub fn expire_detached(&self) {
+        let Some(grace) = self.config.disconnect_grace_secs.map(Duration::from_secs) else {
+            return;
+        };
+        let expired: Vec<DetachedTask> = {
+            let mut detached = self.detached.lock().unwrap();
+            let ids: Vec<String> = detached.iter()
+                .filter(|(_, d)| d.since.elapsed() >= grace)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().filter_map(|id| detached.remove(id)).collect()
+        };
+        for DetachedTask { user_id, task, .. } in expired {
+            info!("Task {} of user {} dropped: client did not re-attach within {}s", task.id, user_id, grace.as_secs());
+            self.record_dropped(&user_id, 1);
+            self.record_history(HistoryEntry::unsent(
+                &task.id, &user_id, &task.path, task.requested_model.clone(), task.enqueued_at.elapsed(),
+                Outcome::Dropped, "client disconnected and did not re-attach",
+            ));
+        }
+    }
+
+    /// Puts previously purged tasks back at the front of the user's queue,
+    /// skipping those whose client has gone away. Returns how many were restored.
+    pub fn restore_tasks(&self, user_id: &str, tasks: Vec<Task>) -> usize {
+        let (alive, gone): (Vec<Task>, Vec<Task>) = tasks.into_iter().partition(|t| !t.responder.is_closed());
+        self.record_dropped(user_id, gone.len());
+        let restored = alive.len();
+        {
+            let mut queues = self.queues.lock().unwrap();
+            let queue = queues.entry(user_id.to_string()).or_default();
+            for task in alive.into_iter().rev() {
+                queue.push_front(task);
+            }
+        }
+        if restored > 0 {
+            info!("Restored {} task(s) to queue of user {}", restored, user_id);
+            self.notify.notify_one();
+        }
+        restored
+    }
+
+    /// Estimated prompt tokens one user may have queued (0 = unlimited), honouring tier overrides.
+    pub fn max_queued_tokens(&self, user_id: &str) -> u64 {
+        self.tier_config(user_id)
+            .and_then(|t| t.max_queued_tokens)
+            .unwrap_or(self.config.user_max_queued_tokens)
+    }
+
+    /// Multi-IP policy for `user_id`, honouring tier overrides.
+    pub fn multi_ip_policy(&self, user_id: &str) -> MultiIpPolicy {
+        self.tier_config(user_id)
+            .and_then(|t| t.multi_ip)
+            .unwrap_or(self.config.multi_ip_policy)
+    }
+
+    /// IPs other than `ip` that `user_id` currently has open requests from.
+    pub fn other_connection_ips(&self, user_id: &str, ip: &IpAddr) -> Vec<IpAddr> {
+        let connections = self.connections.lock().unwrap();
+        let mut ips: Vec<IpAddr> = connections.get(user_id)
+            .map(|ips| ips.keys().filter(|i| *i != ip).copied().collect())
+            .unwrap_or_default();
+        ips.sort();
+        ips
+    }
+
+    pub fn record_history(&self, entry: HistoryEntry) {
+        if let Some(ref log) = self.usage_log {
+            log.append(&entry);
+        }
+        if let Some(ref model) = entry.model {
+            self.forecast.lock().unwrap().record(model, entry.finished_at);
+        }
+        self.emit(|| Event::Finished(Box::new(entry.clone())));
+        self.history.lock().unwrap().push(entry);
+    }
+
+    pub fn record_dropped(&self, user_id: &str, count: usize) {
+        if count > 0 {
+            let mut dropped = self.dropped_counts.lock().unwrap();
+            *dropped.entry(user_id.to_string()).or_insert(0) += count;
+        }
+    }
+
+    pub fn record_error(&self, user_id: &str, class: ErrorClass) {
+        self.error_counts.lock().unwrap().entry(user_id.to_string()).or_default().add(class, 1);
+    }
+
+    /// Collects per-user counters, busiest users first (pending work, then lifetime total).
+    pub fn user_stats(&self) -> Vec<UserStats> {
+        let queued: HashMap<String, usize> = {
+            let q = self.queues.lock().unwrap();
+            q.iter().map(|(k, v)| (k.clone(), v.len())).collect()
+        };
+        let processing = self.processing_counts.lock().unwrap().clone();
+        let processed = self.processed_counts.lock().unwrap().clone();
+        let dropped = self.dropped_counts.lock().unwrap().clone();
+        let flagged = self.multi_ip_flags.lock().unwrap().clone();
+        let bandwidth = self.bandwidth.lock().unwrap().clone();
+        let tool_use = self.tool_use.lock().unwrap().clone();
+        let errors = self.error_counts.lock().unwrap().clone();
+
+        let mut users: HashSet<&String> = queued.keys().collect();
+        users.extend(processing.keys());
+        users.extend(processed.keys());
+        users.extend(dropped.keys());
+        // Rejected requests count too: their bodies were still received
+        users.extend(bandwidth.keys());
+        users.extend(errors.keys());
+
+        let mut stats: Vec<UserStats> = users.into_iter()
+            .map(|u| UserStats {
+                user_id: u.clone(),
+                queued: queued.get(u).cloned().unwrap_or(0),
+                processing: processing.get(u).cloned().unwrap_or(0),
+                processed: processed.get(u).cloned().unwrap_or(0),
+                dropped: dropped.get(u).cloned().unwrap_or(0),
+                multi_ip_flagged: flagged.get(u).cloned().unwrap_or(0),
+                bytes_in: bandwidth.get(u).map_or(0, |b| b.bytes_in),
+                bytes_out: bandwidth.get(u).map_or(0, |b| b.bytes_out),
+                tool_requests: tool_use.get(u).map_or(0, |t| t.requests),
+                tool_calls: tool_use.get(u).map_or(0, |t| t.calls),
+                errors: errors.get(u).copied().unwrap_or_default(),
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            (b.queued + b.processing).cmp(&(a.queued + a.processing))
+                .then_with(|| (b.processed + b.dropped).cmp(&(a.processed + a.dropped)))
+                .then_with(|| a.user_id.cmp(&b.user_id))
+        });
+        stats
+    }
+
+    /// Attaches a note to a user, or removes it when `note` is empty.
+    pub fn set_user_note(&self, user_id: &str, note: &str) {
+        let note = note.trim();
+        {
+            let mut notes = self.user_notes.lock().unwrap();
+            if note.is_empty() {
+                notes.remove(user_id);
+            } else {
+                notes.insert(user_id.to_string(), note.to_string());
+            }
+        }
+        self.save_blocked_items();
+        info!("Note for user {} set to: {:?}", user_id, note);
+    }
+
+    /// When the next batch-lane task may go under a limit of `jobs` per `per`;
+    /// `None` if one may go now.
+    fn batch_slot_at(&self, jobs: u32, per: Duration) -> Option<Instant> {
+        let mut recent = self.batch_dispatches.lock().unwrap();
+        while recent.front().is_some_and(|t| t.elapsed() >= per) {
+            recent.pop_front();
+        }
+        let jobs = jobs as usize;
+        (recent.len() >= jobs).then(|| recent[recent.len() - jobs] + per)
+    }
+
+    /// Order of two users with the same processed count under `--tie-break`.
+    fn tie_order(&self, a: &str, b: &str, queues: &HashMap<String, VecDeque<Task>>) -> std::cmp::Ordering {
+        match self.config.tie_break {
+            TieBreak::UserId => a.cmp(b),
+            TieBreak::OldestRequest => {
+                // Users without a queued request sort last
+                let front = |u: &str| queues.get(u).and_then(|q| q.front()).map(|t| t.enqueued_at);
+                match (front(a), front(b)) {
+                    (Some(x), Some(y)) => x.cmp(&y),
+                    (x, y) => y.is_some().cmp(&x.is_some()),
+                }
+                .then_with(|| a.cmp(b))
+            }
+            TieBreak::Shuffled => self.tie_seed.hash_one(a).cmp(&self.tie_seed.hash_one(b)).then_with(|| a.cmp(b)),
+        }
+    }
+
+    /// Whether `model` may be warmed up on `url` now; claims the warmup if so.
+    fn claim_warmup(&self, url: &str, model: &str) -> bool {
+        let mut warmups = self.warmups.lock().unwrap();
+        warmups.retain(|_, at| at.elapsed() < WARMUP_COOLDOWN);
+        match warmups.entry((url.to_string(), model.to_string())) {
+            std::collections::hash_map::Entry::Occupied(_) => false,
+            std::collections::hash_map::Entry::Vacant(slot) => {
+                slot.insert(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// User served last before the restart, for `--scheduler-start resume`.
+    fn load_scheduler_state(&self) -> Option<String> {
+        let path = &self.config.scheduler_state_file;
+        let content = std::fs::read_to_string(path).ok()?;
+        match serde_json::from_str::<SchedulerState>(&content) {
+            Ok(s) => s.last_served,
+            Err(e) => {
+                warn!("Ignoring unreadable scheduler state {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Saves the last served user with `--scheduler-start resume`.
+    pub fn save_scheduler_state(&self) {
+        if self.config.scheduler_start != SchedulerStart::Resume {
+            return;
+        }
+        let saved = SchedulerState { last_served: self.last_served.lock().unwrap().clone() };
+        let path = &self.config.scheduler_state_file;
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec(&saved)
+            .map_err(std::io::Error::other)
+            .and_then(|content| std::fs::write(&tmp, content))
+            .and_then(|_| std::fs::rename(&tmp, path));
+        if let Err(e) = result {
+            warn!("Cannot save scheduler state to {}: {}", path.display(), e);
+        }
+    }
+
+    /// How `backend` stands against `--error-budget-target`.
+    pub fn error_budget(&self, backend: &BackendStatus) -> BudgetStatus {
+        backend.error_budget.status(self.config.error_budget_target, self.config.error_budget_window)
+    }
+
+    /// Every tier latency objective with its current verdict.
+    pub fn slo_status(&self) -> Vec<SloStatus> {
+        self.slos.lock().unwrap().status(&self.config.file)
+    }
+
+    pub fn user_note(&self, user_id: &str) -> Option<String> {
+        self.user_notes.lock().unwrap().get(user_id).cloned()
+    }
+
+    /// Adds a user to or removes them from the watchlist; false if nothing changed.
+    pub fn set_watched(&self, user_id: &str, watched: bool) -> bool {
+        let changed = {
+            let mut users = self.watched_users.lock().unwrap();
+            if watched { users.insert(user_id.to_string()) } else { users.remove(user_id) }
+        };
+        if changed {
+            self.save_blocked_items();
+            info!("User {} {} the watchlist", user_id, if watched { "added to" } else { "removed from" });
+        }
+        changed
+    }
+
+    pub fn is_watched(&self, user_id: &str) -> bool {
+        self.watched_users.lock().unwrap().contains(user_id)
+    }
+
+    /// Requests waiting in all user queues (not yet dispatched).
+    pub fn total_queued(&self) -> usize {
+        self.queues.lock().unwrap().values().map(|q| q.len()).sum()
+    }
+
+    /// Rough time until a newly queued request would start: the queue drained in
+    /// parallel across online backends at their observed average latency. `None`
+    /// until some backend has completed a request.
+    pub fn estimated_wait(&self) -> Option<Duration> {
+        self.estimated_wait_after(self.total_queued())
+    }
+
+    /// Like `estimated_wait`, for a request with `ahead` requests before it.
+    pub fn estimated_wait_after(&self, ahead: usize) -> Option<Duration> {
+        let backends = self.backends.lock().unwrap();
+        let online = backends.iter().filter(|b| b.is_online).count();
+        let (total_ms, samples) = backends.iter()
+            .fold((0u64, 0u64), |(ms, n), b| (ms + b.total_latency_ms, n + b.latency_samples));
+        if samples == 0 {
+            return None;
+        }
+        if online == 0 {
+            return Some(Duration::MAX);
+        }
+        let rounds = ahead.div_ceil(online) as u64;
+        Some(Duration::from_millis(rounds * (total_ms / samples)))
+    }
+
+    /// Registers a backend at runtime. It stays offline until its first health
+    /// check passes. Returns `false` if the URL is already registered.
+    pub fn add_backend(&self, url: &str) -> bool {
+        let url = normalize_backend_url(url);
+        let mut backends = self.backends.lock().unwrap();
+        if backends.iter().any(|b| b.url == url) {
+            return false;
+        }
+        info!("Backend {} registered", url);
+        backends.push(BackendStatus {
+            dynamic: true,
+            is_online: false,
+            ..BackendStatus::new(url, &self.config)
+        });
+        true
+    }
+
+    /// Removes a backend. Requests already running on it finish normally.
+    pub fn remove_backend(&self, url: &str) -> bool {
+        let url = normalize_backend_url(url);
+        let mut backends = self.backends.lock().unwrap();
+        let before = backends.len();
+        backends.retain(|b| b.url != url);
+        let removed = backends.len() < before;
+        if removed {
+            info!("Backend {} removed", url);
+        }
+        removed
+    }
+
+    /// Seconds until the next health round when no backend is online, `None` otherwise.
+    pub fn offline_retry_after(&self) -> Option<u64> {
+        self.offline_since.lock().unwrap().as_ref()?;
+        let next = HEALTH_CHECK_INTERVAL.saturating_sub(self.last_health_round.lock().unwrap().elapsed());
+        Some(next.as_secs().max(1))
+    }
+
+    /// Records the outcome of a health round and, after the configured grace
+    /// period without any online backend, fails all queued requests.
+    fn finish_health_round(&self) {
+        let any_online = self.backends.lock().unwrap().iter().any(|b| b.is_online);
+        *self.last_health_round.lock().unwrap() = Instant::now();
+        let offline_for = {
+            let mut since = self.offline_since.lock().unwrap();
+            match (any_online, *since) {
+                (true, Some(_)) => {
+                    info!("A backend is online again, accepting requests");
+                    *since = None;
+                    None
+                }
+                (true, None) => None,
+                (false, None) => {
+                    warn!("No backend online, rejecting new requests with 503");
+                    *since = Some(Instant::now());
+                    Some(Duration::ZERO)
+                }
+                (false, Some(t)) => Some(t.elapsed()),
+            }
+        };
+        if let (Some(offline_for), Some(grace)) = (offline_for, self.config.offline_grace_secs)
+            && offline_for >= Duration::from_secs(grace)
+        {
+            self.fail_queued_offline();
+        }
+    }
+
+    /// Answers every queued request with `503 Service Unavailable`.
+    fn fail_queued_offline(&self) {
+        let drained: Vec<(String, Vec<Task>)> = self.queues.lock().unwrap().iter_mut()
+            .filter(|(_, q)| !q.is_empty())
+            .map(|(user, q)| (user.clone(), q.drain(..).collect()))
+            .collect();
+        let retry_after = HEALTH_CHECK_INTERVAL.as_secs();
+        for (user, tasks) in drained {
+            for task in &tasks {
+                let mut headers = HeaderMap::new();
+                headers.insert(axum::http::header::CONTENT_TYPE, "application/json".parse().unwrap());
+                headers.insert(axum::http::header::RETRY_AFTER, retry_after.into());
+                let body = no_backend_body(retry_after);
+                let _ = task.responder.try_send(ResponsePart::Status(StatusCode::SERVICE_UNAVAILABLE, headers));
+                let _ = task.responder.try_send(ResponsePart::Chunk(Bytes::from(body.to_string())));
+                self.record_history(HistoryEntry::unsent(
+                    &task.id, &user, &task.path, task.requested_model.clone(), task.enqueued_at.elapsed(),
+                    Outcome::Dropped, "no backend online",
+                ));
+            }
+            warn!("Failed {} queued request(s) of user {}: no backend online", tasks.len(), user);
+            self.record_dropped(&user, tasks.len());
+        }
+    }
+
+    /// Counts a structured-output check of an answer from `model`.
+    pub fn record_structured(&self, model: &str, valid: bool, retried: bool) {
+        let mut stats = self.structured.lock().unwrap();
+        let entry = stats.entry(model.to_string()).or_default();
+        entry.checked += 1;
+        entry.invalid += u64::from(!valid);
+        entry.retries += u64::from(retried);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.lock().unwrap().is_some()
+    }
+
+    /// Whether requests go to a work queue shared with other instances.
+    pub fn shares_queue(&self) -> bool {
+        #[cfg(feature = "nats")]
+        return self.nats.get().is_some();
+        #[cfg(not(feature = "nats"))]
+        false
+    }
+
+    /// Container name of `ip` with `--docker-names`, if it belongs to one.
+    pub fn source_name(&self, ip: &IpAddr) -> Option<String> {
+        self.docker.as_ref().and_then(|d| d.name_of(ip))
+    }
+
+    /// `ip` for logs, led by its container name when known.
+    pub fn describe_ip(&self, ip: &IpAddr) -> String {
+        match self.source_name(ip) {
+            Some(name) => format!("{} ({})", name, ip),
+            None => ip.to_string(),
+        }
     }
 
     pub fn is_ip_blocked(&self, ip: &IpAddr) -> bool {
-        self.blocked_ips.lock().unwrap().contains(ip)
+        self.blocked_ips.lock().unwrap().iter().any(|p| p.contains(ip))
     }
 
     pub fn is_user_blocked(&self, user_id: &str) -> bool {
@@ -228,165 +1946,309 @@ impl AppState {
     }
 }
 
-fn smart_model_match(requested: &str, available: &HashSet<String>) -> bool {
+fn no_backend_body(retry_after: u64) -> serde_json::Value {
+    serde_json::json!({
+        "error": "no healthy backend available",
+        "retry_after_secs": retry_after,
+    })
+}
+
+/// Trims trailing slashes and defaults to `http://` when no scheme is given.
+pub fn normalize_backend_url(url: &str) -> String {
+    let trimmed = url.trim_end_matches('/').to_string();
+    if !trimmed.starts_with("http://") && !trimmed.starts_with("https://") {
+        format!("http://{}", trimmed)
+    } else {
+        trimmed
+    }
+}
+
+pub fn smart_model_match(requested: &str, available: &HashSet<String>) -> bool {
     // 1. Exact match
     if available.contains(requested) {
         return true;
     }
 
     // 2. Normalized match (handle :latest and case sensitivity)
+    available.iter().any(|model| model_name_matches(requested, model))
+}
+
+/// True when nobody but `user_id` has requests queued or running.
+fn is_sole_user(queues: &HashMap<String, VecDeque<Task>>, processing: &HashMap<String, usize>, user_id: &str) -> bool {
+    queues.iter().all(|(u, q)| u == user_id || q.is_empty())
+        && processing.iter().all(|(u, n)| u == user_id || *n == 0)
+}
+
+pub fn model_name_matches(requested: &str, model: &str) -> bool {
     let requested_low = requested.to_lowercase();
     let requested_no_tag = requested_low.split(':').next().unwrap_or(&requested_low);
+    let model_low = model.to_lowercase();
+    let model_no_tag = model_low.split(':').next().unwrap_or(&model_low);
 
-    for model in available {
-        let model_low = model.to_lowercase();
-        let model_no_tag = model_low.split(':').next().unwrap_or(&model_low);
+    // Match if names match (ignoring tags if requested has no tag, or if one is :latest)
+    requested_no_tag == model_no_tag
+}
 
-        // Match if names match (ignoring tags if requested has no tag, or if one is :latest)
-        if requested_no_tag == model_no_tag {
-            return true;
+/// Matches a model against a pin pattern: `*` matches any run of characters,
+/// and patterns without wildcards follow `model_name_matches`.
+pub fn model_pattern_matches(model: &str, pattern: &str) -> bool {
+    if !pattern.contains('*') {
+        return model_name_matches(model, pattern);
+    }
+    let model = model.to_lowercase();
+    let pattern = pattern.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !model.starts_with(first) || model.len() < first.len() + last.len() || !model.ends_with(last) {
+        return false;
+    }
+    let mut rest = &model[first.len()..model.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
         }
     }
+    true
+}
 
-    false
+/// Lazily built HTTP clients per backend URL, since backends can be added at runtime.
+struct BackendClients {
+    clients: HashMap<String, reqwest::Client>,
 }
 
-pub async fn run_worker(state: Arc<AppState>) {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(state.timeout))
-        .build()
-        .unwrap();
-    let mut current_idx = 0;
+impl BackendClients {
+    fn get(&mut self, config: &DispatcherConfig, url: &str) -> reqwest::Client {
+        self.clients.entry(url.to_string())
+            .or_insert_with(|| backend_client(config, url))
+            .clone()
+    }
+}
 
-    // Background Health Check
-    let health_state = state.clone();
-    let health_client = client.clone();
+/// Loads `model` on the backend at `url` with an empty generation, so a
+/// request waiting for it doesn't pay the load time once dispatched.
+fn spawn_warmup(state: Arc<AppState>, client: reqwest::Client, url: String, model: String) {
+    info!("Warming up {} on {} while its request waits", model, url);
     tokio::spawn(async move {
-        loop {
-            let backends_to_check: Vec<(usize, String)> = {
-                let backends = health_state.backends.lock().unwrap();
-                backends.iter().enumerate().map(|(i, b)| (i, b.url.clone())).collect()
-            };
+        let started = Instant::now();
+        let body = serde_json::json!({ "model": model, "prompt": "", "stream": false });
+        match client.post(format!("{}/api/generate", url)).json(&body).send().await {
+            Ok(res) if res.status().is_success() => {
+                debug!("Warmed up {} on {} in {}ms", model, url, started.elapsed().as_millis());
+                // Routing prefers it until the next health check reports what is loaded
+                if let Some(backend) = state.backends.lock().unwrap().iter_mut().find(|b| b.url == url) {
+                    backend.loaded_models.insert(model);
+                }
+            }
+            Ok(res) => warn!("Warmup of {} on {} failed: {}", model, url, res.status()),
+            Err(e) => warn!("Warmup of {} on {} failed: {}", model, url, e),
+        }
+    });
+}
 
-            for (idx, url) in backends_to_check {
-                let mut is_online = false;
-                let mut detected_type = BackendApiType::Unknown;
-                let mut models = HashSet::new();
-                let mut loaded = HashSet::new();
+/// Follows only redirects that keep the method and body (307/308). A 301/302/303
+/// would turn a POST into a body-less GET, so it is returned to the worker,
+/// which reports it to the client instead.
+pub fn redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if matches!(attempt.status().as_u16(), 307 | 308) {
+            debug!("Following backend redirect to {}", attempt.url());
+            attempt.follow()
+        } else {
+            attempt.stop()
+        }
+    })
+}
 
-                // Probe Ollama API: /api/tags → expects {"models": [...]}
-                {
-                    let check_url = format!("{}/api/tags", url);
-                    match health_client.get(&check_url).send().await {
-                        Ok(res) if res.status().is_success() => {
-                            is_online = true;
-                            if let Ok(body) = res.text().await {
-                                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
-                                    if let Some(models_json) = json.get("models").and_then(|m| m.as_array()) {
-                                        detected_type = detected_type.merge(BackendApiType::Ollama);
-                                        debug!("Backend {} confirmed Ollama API via /api/tags", url);
-                                        for m in models_json {
-                                            if let Some(name) = m.get("name").and_then(|n| n.as_str()) {
-                                                models.insert(name.to_string());
-                                            }
-                                        }
-                                    } else {
-                                        warn!("Backend {} responded 200 to /api/tags but 'models' array not found or invalid. Body: {}", url, body);
-                                    }
-                                } else {
-                                    warn!("Backend {} responded 200 to /api/tags but body is not valid JSON", url);
-                                }
-                            }
-                        }
-                        Ok(res) => {
-                            debug!("Backend {} /api/tags returned status: {}", url, res.status());
-                        }
-                        Err(e) => {
-                            debug!("Backend {} /api/tags error: {}", url, e);
-                        }
-                    }
+/// HTTP client for one backend, with the TLS settings from its config entry.
+pub fn backend_client(config: &DispatcherConfig, url: &str) -> reqwest::Client {
+    let backend = config.file.backend_config(url);
+    match crate::probe::client_for(backend, config.backend_proxy.as_deref(), config.timeout) {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Invalid TLS or proxy settings for backend {}: {}; using defaults", url, e);
+            reqwest::Client::builder()
+                .timeout(config.timeout)
+                .redirect(redirect_policy())
+                .build()
+                .unwrap()
+        }
+    }
+}
 
-                    // Also check for loaded models via /api/ps if it was an Ollama-like response
-                    if is_online {
-                        let ps_url = format!("{}/api/ps", url);
-                        if let Ok(res) = health_client.get(&ps_url).send().await {
-                            if res.status().is_success() {
-                                if let Ok(body) = res.text().await {
-                                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
-                                        if let Some(models_json) = json.get("models").and_then(|m| m.as_array()) {
-                                            for m in models_json {
-                                                if let Some(name) = m.get("name").and_then(|n| n.as_str()) {
-                                                    loaded.insert(name.to_string());
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+/// A backend answer an API client can't consume: a redirect that wasn't
+/// followed, or an HTML page (typically a reverse proxy error page).
+pub fn is_unusable_response(path: &str, response: &reqwest::Response) -> bool {
+    if detect_api_family(path) == ApiFamily::Unknown {
+        return false;
+    }
+    let html = response.headers().get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.trim_start().to_ascii_lowercase().starts_with("text/html"));
+    html || response.status().is_redirection()
+}
 
-                // Probe OpenAI API: /v1/models → expects {"data": [...]}
-                {
-                    let check_url = format!("{}/v1/models", url);
-                    match health_client.get(&check_url).send().await {
-                        Ok(res) if res.status().is_success() => {
-                            is_online = true;
-                            if let Ok(body) = res.text().await {
-                                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) {
-                                    if let Some(data_json) = json.get("data").and_then(|d| d.as_array()) {
-                                        detected_type = detected_type.merge(BackendApiType::OpenAi);
-                                        debug!("Backend {} confirmed OpenAI API via /v1/models", url);
-                                        for m in data_json {
-                                            if let Some(id) = m.get("id").and_then(|i| i.as_str()) {
-                                                models.insert(id.to_string());
-                                            }
-                                        }
-                                    } else {
-                                        warn!("Backend {} responded 200 to /v1/models but 'data' array not found or invalid. Body: {}", url, body);
-                                    }
-                                } else {
-                                    warn!("Backend {} responded 200 to /v1/models but body is not valid JSON", url);
-                                }
-                            }
-                        }
-                        Ok(res) => {
-                            debug!("Backend {} /v1/models returned status: {}", url, res.status());
-                        }
-                        Err(e) => {
-                            debug!("Backend {} /v1/models error: {}", url, e);
-                        }
-                    }
+/// Turns a redirect or HTML page into a JSON error: the status for the client,
+/// the body, and a description for the log and history.
+pub async fn unusable_response_error(backend_url: &str, mut response: reqwest::Response) -> (StatusCode, serde_json::Value, String) {
+    let status = response.status();
+    if status.is_redirection() {
+        let location = response.headers().get(axum::http::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        let message = format!("backend {} redirected the request ({}) to '{}'; update its URL", backend_url, status, location);
+        let body = serde_json::json!({
+            "error": message,
+            "backend_status": status.as_u16(),
+            "location": location,
+        });
+        return (StatusCode::BAD_GATEWAY, body, format!("redirected ({}) to {}", status, location));
+    }
+
+    let mut page = Vec::new();
+    while page.len() < HTML_PAGE_LIMIT {
+        match response.chunk().await {
+            Ok(Some(chunk)) => page.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    let page = String::from_utf8_lossy(&page);
+    let title = html_title(&page);
+    let kind = if status.is_success() { "an HTML page instead of JSON" } else { "an HTML error page" };
+    let message = match title {
+        Some(ref title) => format!("backend returned {} ({}): {}", kind, status, title),
+        None => format!("backend returned {} ({})", kind, status),
+    };
+    let body = serde_json::json!({
+        "error": message,
+        "backend_status": status.as_u16(),
+    });
+    // Keep 4xx/5xx from the proxy; a 2xx HTML page still isn't an API answer
+    let client_status = if status.is_client_error() || status.is_server_error() { status } else { StatusCode::BAD_GATEWAY };
+    (client_status, body, message)
+}
+
+/// Text of the `<title>` element, with whitespace collapsed.
+fn html_title(page: &str) -> Option<String> {
+    let lower = page.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = page[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then_some(title)
+}
+
+pub async fn run_worker(state: Arc<AppState>) {
+    let mut clients = BackendClients { clients: HashMap::new() };
+    let mut current_idx = match state.config.scheduler_start {
+        SchedulerStart::Random => state.tie_seed.hash_one("scheduler start") as usize,
+        SchedulerStart::First | SchedulerStart::Resume => 0,
+    };
+    let mut resume_after = None;
+    if state.config.scheduler_start == SchedulerStart::Resume {
+        resume_after = state.load_scheduler_state();
+        if let Some(ref user) = resume_after {
+            info!("Resuming round-robin after user {}", user);
+        }
+        let save_state = state.clone();
+        let mut saved = resume_after.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SCHEDULER_STATE_INTERVAL).await;
+                let current = save_state.last_served.lock().unwrap().clone();
+                if current != saved {
+                    let writer = save_state.clone();
+                    let _ = tokio::task::spawn_blocking(move || writer.save_scheduler_state()).await;
+                    saved = current;
                 }
+            }
+        });
+    }
+    let batch_window = Duration::from_millis(state.config.batch_window_ms);
+    let mut batch: Option<Batch> = None;
 
-                // Fallback: just check root if both specific probes failed
-                if !is_online {
-                    let check_url = format!("{}/", url);
-                    if let Ok(res) = health_client.get(&check_url).send().await {
-                        if res.status().is_success() {
-                            is_online = true;
-                        }
+    // Background Health Check
+    let health_state = state.clone();
+    tokio::spawn(async move {
+        let mut health_clients = BackendClients { clients: HashMap::new() };
+        loop {
+            let backends_to_check: Vec<String> = {
+                let backends = health_state.backends.lock().unwrap();
+                backends.iter().map(|b| b.url.clone()).collect()
+            };
+            health_clients.clients.retain(|url, _| backends_to_check.contains(url));
+            health_state.catalogue.lock().unwrap().retain(&backends_to_check);
+
+            for url in backends_to_check {
+                let health_client = &health_clients.get(&health_state.config, &url);
+                let check = crate::probe::check_backend(health_client, &url).await;
+                for finding in &check.findings {
+                    match finding.level {
+                        CheckLevel::Problem => warn!("Backend {} {}: {}", url, finding.endpoint, finding.detail),
+                        _ => debug!("Backend {} {}: {}", url, finding.endpoint, finding.detail),
                     }
                 }
+                let crate::probe::BackendCheck { online: is_online, api_type: detected_type, models, loaded, digests, tag_entries, features, .. } = check;
+                // A configured API wins over the detected one
+                let detected_type = health_state.config.file.backend_config(&url)
+                    .and_then(|c| c.api)
+                    .map_or(detected_type, |api| api.api_type());
+
+                let cache_models = !health_state.config.model_cache_ttl.is_zero();
+                if let Some(entries) = tag_entries.filter(|_| cache_models) {
+                    health_state.catalogue.lock().unwrap().set_tags(&url, entries);
+                    crate::catalogue::refresh(&health_state, health_client, &url, &digests).await;
+                }
+                if health_state.config.model_checks && !digests.is_empty() {
+                    crate::capabilities::refresh(&health_state, health_client, &url, &digests).await;
+                }
 
                 let mut backends = health_state.backends.lock().unwrap();
-                if backends[idx].is_online != is_online {
+                // Removed through the admin API while being probed
+                let Some(backend) = backends.iter_mut().find(|b| b.url == url) else {
+                    continue;
+                };
+                if backend.is_online != is_online {
                     info!("Backend {} status changed to: {}", url, if is_online { "ONLINE" } else { "OFFLINE" });
-                    backends[idx].is_online = is_online;
+                    backend.is_online = is_online;
+                    health_state.emit(|| Event::Backend { url: url.clone(), online: is_online });
                 }
-                if backends[idx].api_type != detected_type {
+                if backend.api_type != detected_type {
                     info!("Backend {} API type detected: {}", url, detected_type.display());
-                    backends[idx].api_type = detected_type;
+                    backend.api_type = detected_type;
+                }
+                if let Some(features) = features
+                    && backend.features.as_ref() != Some(&features)
+                {
+                    let missing = features.missing();
+                    if missing.is_empty() {
+                        info!("Backend {} runs Ollama {}", url, features.version);
+                    } else {
+                        info!("Backend {} runs Ollama {} without {}; requests will be adapted", url, features.version, missing.join(", "));
+                    }
+                    backend.features = Some(features);
                 }
-                backends[idx].available_models = models;
-                backends[idx].loaded_models = loaded;
+                backend.available_models = models;
+                backend.loaded_models = loaded;
             }
-            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            health_state.finish_health_round();
+            health_state.prune_sessions();
+            // Requests queued while no backend could serve them (e.g. restored at startup) may be dispatchable now
+            health_state.notify.notify_one();
+            tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
         }
     });
 
     loop {
+        // When the next request held for batching or by a batch rate limit may go
+        let mut next_ripe: Option<Instant> = None;
+        // Backend and model to load ahead of a request that has to wait
+        let mut warmup: Option<(String, String)> = None;
+        // With --scheduler-trace, why the request dispatched below was picked
+        let mut decision: Option<Decision> = None;
         let selection_opt = {
             let mut queues = state.queues.lock().unwrap();
             let mut backends = state.backends.lock().unwrap();
@@ -395,12 +2257,68 @@ pub async fn run_worker(state: Arc<AppState>) {
             // 1. Pick a user and peek at their front task to know required API family
             let vip = state.vip_user.lock().unwrap().clone();
             let boost = state.boost_user.lock().unwrap().clone();
+            let manual = {
+                let mut next = state.dispatch_next.lock().unwrap();
+                // Purged or served otherwise in the meantime
+                if next.as_ref().is_some_and(|u| queues.get(u).is_none_or(|q| q.is_empty())) {
+                    *next = None;
+                }
+                next.clone()
+            };
             let mut counter = state.global_counter.lock().unwrap();
 
-            let mut active_users: Vec<String> = queues.keys()
-                .filter(|u| !queues.get(*u).unwrap().is_empty())
-                .cloned()
-                .collect();
+            let (batch_paused, batch_rate, drained_backends) = {
+                let m = state.maintenance.lock().unwrap();
+                (m.batch_paused, m.batch_rate.and_then(|(jobs, per)| Some((jobs, per.to_std().ok()?))), m.drained_backends.clone())
+            };
+            // Under a `limit-batch` window: when the next batch-lane task may go
+            let batch_slot_at = batch_rate.and_then(|(jobs, per)| state.batch_slot_at(jobs, per));
+            let mut processing = state.processing_counts.lock().unwrap();
+            let debug_busy = state.debug_in_flight.load(Ordering::Relaxed) > 0;
+            let debug_front = |u: &String| queues.get(u).and_then(|q| q.front()).is_some_and(|t| t.debug_trace);
+            if batch.as_ref().is_some_and(|b| !queues.values().filter_map(|q| q.front()).any(|t| b.contains(t))) {
+                batch = None;
+            }
+            let batch_front = |u: &String| batch.as_ref().is_some_and(|b| queues.get(u).and_then(|q| q.front()).is_some_and(|t| b.contains(t)));
+
+            let mut active_users: Vec<String> = Vec::new();
+            let mut skipped: Vec<Skipped> = Vec::new();
+            for (u, queue) in queues.iter() {
+                let Some(task) = queue.front() else {
+                    continue;
+                };
+                // The operator's pick isn't held back by the user's cap or the batching window
+                let picked = manual.as_ref() == Some(u);
+                let at_cap = || {
+                    let (max_concurrent, _) = state.user_caps(u, is_sole_user(&queues, &processing, u));
+                    !task.debug_trace && !picked && max_concurrent > 0 && processing.get(u).copied().unwrap_or(0) >= max_concurrent
+                };
+                let skip = if batch_paused && task.lane == Lane::Batch {
+                    Some("batch lane paused")
+                } else if let (Lane::Batch, Some(at)) = (task.lane, batch_slot_at) {
+                    next_ripe = Some(next_ripe.map_or(at, |t| t.min(at)));
+                    Some("batch lane rate limited")
+                } else if debug_busy && task.debug_trace {
+                    // A second probe waits until the running one is done
+                    Some("another debug probe is running")
+                } else if at_cap() {
+                    Some("at its concurrency cap")
+                } else {
+                    // Held for the batching window, so more requests for the same model can join
+                    let ripe_at = task.enqueued_at + batch_window;
+                    let held = !picked && !batch_window.is_zero() && task.batchable && task.requested_model.is_some()
+                        && ripe_at > Instant::now() && !batch_front(u);
+                    if held {
+                        next_ripe = Some(next_ripe.map_or(ripe_at, |at| at.min(ripe_at)));
+                    }
+                    held.then_some("held for the batching window")
+                };
+                match skip {
+                    None => active_users.push(u.clone()),
+                    Some(reason) if state.config.scheduler_trace => skipped.push(Skipped { user: u.clone(), reason }),
+                    Some(_) => {}
+                }
+            }
 
             if active_users.is_empty() {
                 None
@@ -408,20 +2326,55 @@ pub async fn run_worker(state: Arc<AppState>) {
                 active_users.sort_by(|a, b| {
                     let a_total = state.processed_counts.lock().unwrap().get(a).cloned().unwrap_or(0);
                     let b_total = state.processed_counts.lock().unwrap().get(b).cloned().unwrap_or(0);
-                    a_total.cmp(&b_total).then_with(|| a.cmp(b))
+                    a_total.cmp(&b_total).then_with(|| state.tie_order(a, b, &queues))
                 });
 
-                let mut target_user = None;
-                if let Some(ref v) = vip { if active_users.contains(v) { target_user = Some(v.clone()); } }
+                // Taken before the pick changes them
+                let candidates: Vec<Candidate> = if state.config.scheduler_trace {
+                    let processed = state.processed_counts.lock().unwrap();
+                    active_users.iter().map(|u| Candidate {
+                        user: u.clone(),
+                        processed: processed.get(u).copied().unwrap_or(0),
+                        running: processing.get(u).copied().unwrap_or(0),
+                        queued: queues.get(u).map_or(0, |q| q.len()),
+                    }).collect()
+                } else {
+                    Vec::new()
+                };
+
+                // Operator probes go before everyone, VIP included
+                let mut target_user = active_users.iter().find(|u| debug_front(u)).cloned();
+                let mut user_reason = "debug probe";
                 if target_user.is_none() {
-                    if let Some(ref b) = boost {
-                        if active_users.contains(b) && *counter % 2 == 0 { target_user = Some(b.clone()); }
-                    }
+                    user_reason = "manual dispatch";
+                    target_user = manual.clone().filter(|u| active_users.contains(u));
+                }
+                if target_user.is_none() {
+                    user_reason = "vip";
+                    if let Some(ref v) = vip && active_users.contains(v) { target_user = Some(v.clone()); }
+                }
+                // The rest of an open batch runs back-to-back
+                if target_user.is_none() {
+                    user_reason = "open batch";
+                    target_user = active_users.iter().find(|u| batch_front(u)).cloned();
+                }
+                if target_user.is_none() {
+                    user_reason = "boost";
+                    if let Some(ref b) = boost && active_users.contains(b) && counter.is_multiple_of(2) { target_user = Some(b.clone()); }
                 }
                 if target_user.is_none() {
-                    if current_idx >= active_users.len() { current_idx = 0; }
-                    target_user = Some(active_users[current_idx].clone());
-                    current_idx += 1;
+                    user_reason = "round robin";
+                    // Once users compete again, continue with whoever comes after
+                    // the user served last before the restart
+                    if active_users.len() > 1 && let Some(last) = resume_after.take() {
+                        current_idx = active_users.iter()
+                            .position(|u| state.tie_order(u, &last, &queues) == std::cmp::Ordering::Greater)
+                            .unwrap_or(0);
+                    }
+                    let idx = current_idx % active_users.len();
+                    target_user = Some(active_users[idx].clone());
+                    current_idx = idx + 1;
+                    *state.last_served.lock().unwrap() = target_user.clone();
                 }
 
                 // Peek at front task to determine required API family
@@ -430,9 +2383,32 @@ pub async fn run_worker(state: Arc<AppState>) {
                     let api_family = detect_api_family(&task_ref.path);
                     debug!("Request for user {}: path={} family={:?}", user_id, task_ref.path, api_family);
 
+                    // Canary tasks stick to the canary backend while it is online;
+                    // everything else stays off it.
+                    let canary_online = backends.iter().any(|b| b.is_canary && b.is_online);
+                    // So does an experiment arm with its own backend
+                    let arm_backend = task_ref.experiment.as_ref()
+                        .and_then(|a| a.backend.as_deref())
+                        .filter(|url| backends.iter().any(|b| b.url == *url && b.is_online));
+
                     // Find eligible backends: online, not busy, and support the required API + Model
                     let eligible_indices: Vec<usize> = backends.iter()
                         .enumerate()
+                        .filter(|(_, b)| {
+                            if b.is_canary {
+                                task_ref.canary
+                            } else {
+                                !(task_ref.canary && canary_online)
+                            }
+                        })
+                        .filter(|(_, b)| arm_backend.is_none_or(|url| b.url == url))
+                        .filter(|(_, b)| {
+                            let draining = drained_backends.contains(&b.url);
+                            if draining {
+                                debug!("Backend {} rejected: draining for maintenance", b.url);
+                            }
+                            !draining
+                        })
                         .filter(|(_, b)| {
                             let online = b.is_online;
                             let free = b.active_requests < 1;
@@ -444,7 +2420,15 @@ pub async fn run_worker(state: Arc<AppState>) {
                         .filter(|(_, b)| {
                             // If a specific model is requested, backend MUST have it.
                             // If no model is requested, fall back to API family check.
-                            let supported = if let Some(ref model) = task_ref.requested_model {
+                            if let Some(ref model) = task_ref.requested_model {
+                                if !b.model_pins.is_empty() && !b.model_pins.iter().any(|p| model_pattern_matches(model, p)) {
+                                    debug!("Backend {} rejected: model '{}' not in its pin list {:?}", b.url, model, b.model_pins);
+                                    return false;
+                                }
+                                if b.api_type == BackendApiType::OpenAi && api_family == ApiFamily::Ollama && !translatable(&task_ref.path) {
+                                    debug!("Backend {} rejected: OpenAI-only, and {} can't be translated", b.url, task_ref.path);
+                                    return false;
+                                }
                                 let has_model = smart_model_match(model, &b.available_models);
                                 if !has_model {
                                     debug!("Backend {} rejected: model '{}' not found. Available: {:?}", b.url, model, b.available_models);
@@ -453,39 +2437,142 @@ pub async fn run_worker(state: Arc<AppState>) {
                             } else {
                                 // Unknown type backends are allowed (health check will classify them)
                                 let family_supported = matches!(b.api_type, BackendApiType::Unknown | BackendApiType::Both)
-                                    || b.api_type.supports(api_family);
+                                    || b.api_type.supports(api_family)
+                                    || (b.api_type == BackendApiType::OpenAi && translatable(&task_ref.path));
                                 if !family_supported {
                                     debug!("Backend {} rejected: api_family {:?} not supported by {:?}", b.url, api_family, b.api_type);
                                 }
                                 family_supported
-                            };
-                            supported
+                            }
                         })
                         .map(|(i, _)| i)
                         .collect();
 
                     if eligible_indices.is_empty() {
-                        if let Some(ref model) = task_ref.requested_model {
+                        if state.config.warmup_on_miss
+                            && let Some(ref model) = task_ref.requested_model
+                        {
+                            // Backends that can take the request once one is free
+                            let able: Vec<&BackendStatus> = backends.iter()
+                                .filter(|b| b.is_online && !drained_backends.contains(&b.url))
+                                .filter(|b| if b.is_canary { task_ref.canary } else { !(task_ref.canary && canary_online) })
+                                .filter(|b| arm_backend.is_none_or(|url| b.url == url))
+                                .filter(|b| b.model_pins.is_empty() || b.model_pins.iter().any(|p| model_pattern_matches(model, p)))
+                                .filter(|b| smart_model_match(model, &b.available_models))
+                                .collect();
+                            if !able.iter().any(|b| smart_model_match(model, &b.loaded_models)) {
+                                // The least busy one is likely to free up first
+                                let target = able.iter()
+                                    .filter(|b| b.api_type.supports(ApiFamily::Ollama))
+                                    .min_by_key(|b| b.active_requests);
+                                if let Some(b) = target
+                                    && state.claim_warmup(&b.url, model)
+                                {
+                                    warmup = Some((b.url.clone(), model.clone()));
+                                }
+                            }
+                        }
+                        // Served nowhere while a fallback is: switch now rather than wait
+                        let online_with = |m: &str| backends.iter().any(|b| b.is_online && smart_model_match(m, &b.available_models));
+                        let fallback = task_ref.requested_model.as_deref()
+                            .filter(|m| !online_with(m))
+                            .and_then(|m| crate::fallback::next_model(&state.config.file, task_ref.fallback_from.as_deref().unwrap_or(m), m, online_with));
+                        if let Some(next) = fallback {
+                            let task = queues.get_mut(user_id).unwrap().front_mut().unwrap();
+                            let from = task.requested_model.clone().unwrap_or_default();
+                            if crate::fallback::apply(task, &next) {
+                                info!("Task {} of user {}: no online backend has model '{}', falling back to '{}'", task.id, user_id, from, next);
+                                state.model_fallbacks.fetch_add(1, Ordering::Relaxed);
+                                state.notify.notify_one();
+                            }
+                        } else if let Some(ref model) = task_ref.requested_model {
                             warn!("No backend available for model '{}' for user {}. Request stuck in queue.", model, user_id);
                         } else {
                             warn!("No backend available for API family {:?} for user {}. Request stuck in queue.", api_family, user_id);
                         }
                         None
                     } else {
-                        let task = queues.get_mut(user_id).unwrap().pop_front().unwrap();
+                        let mut task = queues.get_mut(user_id).unwrap().pop_front().unwrap();
+                        task.queued_bytes.release();
                         *counter += 1;
+                        if batch.is_none() && !batch_window.is_zero() && task.batchable
+                            && let Some(ref model) = task.requested_model
+                        {
+                            debug!("Batching non-streaming {} requests queued within {}ms of task {}", model, batch_window.as_millis(), task.id);
+                            batch = Some(Batch { model: model.clone(), cutoff: task.enqueued_at + batch_window });
+                        }
 
-                        // Round-Robin among eligible backends with min connections
-                        let min_conns = eligible_indices.iter().map(|&i| backends[i].active_requests).min().unwrap();
-                        let candidates: Vec<usize> = eligible_indices.iter().cloned().filter(|&i| backends[i].active_requests == min_conns).collect();
-                        let candidate_pos = candidates.iter().position(|&i| i > *last_idx).unwrap_or(0);
-                        let selected_backend_idx = candidates[candidate_pos];
+                        // Prefer the backend this session used last, if it is eligible right now
+                        let pinned_idx = task.affinity_key.as_deref()
+                            .and_then(|key| state.pinned_backend(key))
+                            .and_then(|url| eligible_indices.iter().copied().find(|&i| backends[i].url == url));
+
+                        let mut backend_reason = "session affinity";
+                        let selected_backend_idx = match pinned_idx {
+                            Some(idx) => {
+                                debug!("Session affinity keeps user {} on {}", user_id, backends[idx].url);
+                                idx
+                            }
+                            None => {
+                                // A batch stays on backends that last served its model, where it is still loaded
+                                let warm: Vec<usize> = eligible_indices.iter().copied()
+                                    .filter(|&i| !batch_window.is_zero() && task.batchable && backends[i].current_model == task.requested_model)
+                                    .collect();
+                                backend_reason = if warm.is_empty() { "fewest active requests" } else { "batch model still loaded" };
+                                let eligible_indices = if warm.is_empty() { &eligible_indices } else { &warm };
+                                // Round-Robin among eligible backends with min connections
+                                let min_conns = eligible_indices.iter().map(|&i| backends[i].active_requests).min().unwrap();
+                                let candidates: Vec<usize> = eligible_indices.iter().cloned().filter(|&i| backends[i].active_requests == min_conns).collect();
+                                // With warmups, stay on backends that have the model loaded or were just warmed up for it
+                                let loaded: Vec<usize> = candidates.iter().copied()
+                                    .filter(|&i| state.config.warmup_on_miss && task.requested_model.as_ref().is_some_and(|m| smart_model_match(m, &backends[i].loaded_models)))
+                                    .collect();
+                                if !loaded.is_empty() {
+                                    backend_reason = "model loaded";
+                                }
+                                let candidates = if loaded.is_empty() { candidates } else { loaded };
+                                let candidate_pos = candidates.iter().position(|&i| i > *last_idx).unwrap_or(0);
+                                *last_idx = candidates[candidate_pos];
+                                candidates[candidate_pos]
+                            }
+                        };
 
-                        *last_idx = selected_backend_idx;
+                        if let Some(ref key) = task.affinity_key {
+                            state.pin_session(key.clone(), backends[selected_backend_idx].url.clone());
+                        }
                         backends[selected_backend_idx].active_requests += 1;
+                        backends[selected_backend_idx].idle_since = None;
                         backends[selected_backend_idx].current_model = task.requested_model.clone();
+                        // Counted here rather than in the request task so the next pass sees the cap
+                        *processing.entry(user_id.clone()).or_insert(0) += 1;
+                        state.in_flight.fetch_add(1, Ordering::Relaxed);
+                        if task.lane == Lane::Batch && batch_rate.is_some() {
+                            state.batch_dispatches.lock().unwrap().push_back(Instant::now());
+                        }
+                        if task.debug_trace {
+                            state.debug_in_flight.fetch_add(1, Ordering::Relaxed);
+                        }
+                        state.running_tasks.lock().unwrap().insert(task.id.clone(), user_id.clone());
+                        if user_reason == "manual dispatch" {
+                            info!("Task {} of user {} dispatched to {} by the operator", task.id, user_id, backends[selected_backend_idx].url);
+                            *state.dispatch_next.lock().unwrap() = None;
+                        }
+                        state.fairness.lock().unwrap().record(user_id, &active_users);
+                        if state.config.scheduler_trace {
+                            decision = Some(Decision {
+                                task_id: task.id.clone(),
+                                user: user_id.clone(),
+                                user_reason,
+                                cursor: current_idx,
+                                candidates,
+                                skipped,
+                                backend: backends[selected_backend_idx].url.clone(),
+                                backend_reason,
+                                eligible: eligible_indices.iter().map(|&i| backends[i].url.clone()).collect(),
+                            });
+                        }
 
-                        Some((user_id.clone(), task, selected_backend_idx, backends[selected_backend_idx].url.clone()))
+                        Some((user_id.clone(), task, backends[selected_backend_idx].url.clone()))
                     }
                 } else {
                     None
@@ -493,66 +2580,475 @@ pub async fn run_worker(state: Arc<AppState>) {
             }
         };
 
+        if let Some((url, model)) = warmup {
+            spawn_warmup(state.clone(), clients.get(&state.config, &url), url, model);
+        }
+        if let Some(decision) = decision {
+            decision.log();
+            state.emit(|| Event::Schedule(Box::new(decision)));
+        }
+
         match selection_opt {
-            Some((user_id, task, backend_idx, backend_url)) => {
+            Some((user_id, task, backend_url)) => {
                 let state_clone = state.clone();
-                let client_clone = client.clone();
-                let url = format!("{}{}", backend_url, task.path);
+                let client_clone = clients.get(&state.config, &backend_url);
+                // Everything logged while the task runs carries its id
+                let span = tracing::info_span!("task", id = %task.id);
 
                 tokio::spawn(async move {
+                    let mut task = task;
+                    // Off the scheduling loop: reading a spooled body back takes disk I/O
+                    let body_lost = match crate::spool::rehydrate(&mut task).await {
+                        Ok(()) => None,
+                        Err(e) => {
+                            error!("Task {}: cannot read its spooled body back: {}", task.id, e);
+                            Some(e.to_string())
+                        }
+                    };
+                    let (adapted, openai_only) = {
+                        let backends = state_clone.backends.lock().unwrap();
+                        let backend = backends.iter().find(|b| b.url == backend_url);
+                        let adapted = backend
+                            .and_then(|b| b.features.as_ref())
+                            .and_then(|features| crate::compat::adapt(&task.path, &task.body, features));
+                        (adapted, backend.is_some_and(|b| b.api_type == BackendApiType::OpenAi))
+                    };
+                    // Before any rewrite: what the client asked for
+                    let client_body = task.body.clone();
+                    let dead_letter_parts = (state_clone.config.dead_letter_size > 0).then(|| (task.method.clone(), task.headers.clone()));
+                    let mut legacy_embed = None;
+                    let mut backend_path = task.path.clone();
+                    let mut translator = None;
+                    if let Some(translated) = openai_only.then(|| crate::translate::translate(&task.path, &task.body)).flatten() {
+                        debug!("Task {}: translating {} to {} for {}", task.id, task.path, translated.path, backend_url);
+                        task.headers.remove(axum::http::header::CONTENT_LENGTH);
+                        task.body = translated.body;
+                        backend_path = translated.path.to_string();
+                        translator = Some(translated.response);
+                    } else if let Some(adapted) = adapted {
+                        debug!("Task {}: adapting {} for {}: {}", task.id, task.path, backend_url, adapted.changes.join(", "));
+                        task.headers.remove(axum::http::header::CONTENT_LENGTH);
+                        task.body = adapted.body;
+                        if adapted.legacy_embed {
+                            legacy_embed = Some(LegacyEmbed::new(task.requested_model.clone()));
+                        }
+                        backend_path = adapted.path;
+                    }
+                    let url = format!("{}{}", backend_url, backend_path);
+                    let started = Instant::now();
+                    let queue_wait = started.duration_since(task.enqueued_at);
+                    let mut first_chunk_at: Option<Instant> = None;
+                    let mut completed = false;
+                    let mut malformed_response = false;
+                    let mut failure: Option<FailureKind> = None;
+                    let mut outcome = Outcome::Dropped;
+                    let mut status_code: Option<u16> = None;
+                    let mut detail: Option<String> = None;
+                    let mut usage = UsageTail::default();
+                    let mut cancelled: Option<CancelStage> = None;
+                    // Set where the cause is known; otherwise taken from the outcome
+                    let mut error_class: Option<ErrorClass> = None;
+                    let task_id = task.id.clone();
+                    let experiment = task.experiment.clone();
+                    let mut completed_timing: Option<StageTiming> = None;
+                    let mut slot_kind = ResponseKind::Unanswered;
+                    let mut released_at: Option<Instant> = None;
+                    // Detached or sent back for a fallback: recorded once it finishes later
+                    let mut unfinished = false;
+                    let task_path = task.path.clone();
+                    let requested_model = task.requested_model.clone();
+                    let structured_mode = state_clone.config.structured_output;
+                    let requested_format = (structured_mode != StructuredOutputMode::Off)
+                        .then(|| crate::structured::requested_format(&task.path, &client_body))
+                        .flatten();
+                    let (backend_timeout, timeout_source) = state_clone.timeout_for(&backend_url, requested_model.as_deref(), &task.path);
+                    let mut retry_request = requested_format.as_ref()
+                        .filter(|r| structured_mode == StructuredOutputMode::Retry && !r.stream)
+                        .map(|_| RetryRequest {
+                            method: task.method.clone(),
+                            headers: task.headers.clone(),
+                            body: task.body.clone(),
+                            timeout: backend_timeout,
+                        });
+                    let mut structured_check = requested_format.as_ref().map(|r| StructuredCheck::new(r.format.clone()));
                     let is_blocked = {
                         let user_ips = state_clone.user_ips.lock().unwrap();
                         let blocked_ips = state_clone.blocked_ips.lock().unwrap();
                         let blocked_users = state_clone.blocked_users.lock().unwrap();
-                        blocked_users.contains(&user_id) || user_ips.get(&user_id).map(|ip| blocked_ips.contains(ip)).unwrap_or(false)
+                        blocked_users.contains(&user_id) || user_ips.get(&user_id).is_some_and(|ip| blocked_ips.iter().any(|p| p.contains(ip)))
                     };
 
-                    if is_blocked || task.responder.is_closed() {
+                    if let Some(e) = body_lost {
+                        detail = Some(format!("spooled body lost: {}", e));
+                        let mut headers = HeaderMap::new();
+                        headers.insert(axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/json"));
+                        if task.responder.send(ResponsePart::Status(StatusCode::INTERNAL_SERVER_ERROR, headers)).await.is_ok() {
+                            let body = serde_json::json!({"error": "request body could not be read back from the spool"});
+                            let _ = task.responder.send(ResponsePart::Chunk(Bytes::from(body.to_string()))).await;
+                        }
                         let mut dropped = state_clone.dropped_counts.lock().unwrap();
                         *dropped.entry(user_id.clone()).or_insert(0) += 1;
-                    } else {
+                        let mut processing = state_clone.processing_counts.lock().unwrap();
+                        if let Some(count) = processing.get_mut(&user_id) { *count = count.saturating_sub(1); }
+                    } else if !is_blocked && task.resumable && task.responder.is_closed() && state_clone.config.disconnect_grace_secs.is_some() {
+                        unfinished = true;
                         {
                             let mut processing = state_clone.processing_counts.lock().unwrap();
-                            *processing.entry(user_id.clone()).or_insert(0) += 1;
+                            if let Some(count) = processing.get_mut(&user_id) { *count = count.saturating_sub(1); }
                         }
-
+                        // Kept as the client sent it, it may go to another backend next time
+                        state_clone.detach_task(&user_id, Task {
+                            id: task.id,
+                            method: task.method,
+                            path: task.path,
+                            headers: task.headers,
+                            body: client_body.clone(),
+                            responder: task.responder,
+                            requested_model: task.requested_model,
+                            canary: task.canary,
+                            lane: task.lane,
+                            affinity_key: task.affinity_key,
+                            prompt_tokens: task.prompt_tokens,
+                            debug_trace: task.debug_trace,
+                            batchable: task.batchable,
+                            tools: task.tools,
+                            experiment: task.experiment,
+                            enqueued_at: task.enqueued_at,
+                            queued_bytes: task.queued_bytes,
+                            spooled: None,
+                            resumable: true,
+                            fallback_from: task.fallback_from,
+                        });
+                    } else if is_blocked || task.responder.is_closed() {
+                        detail = Some(if is_blocked { "user or IP blocked" } else { "client disconnected while queued" }.to_string());
+                        error_class = (!is_blocked).then_some(ErrorClass::ClientAbort);
+                        let mut dropped = state_clone.dropped_counts.lock().unwrap();
+                        *dropped.entry(user_id.clone()).or_insert(0) += 1;
+                        let mut processing = state_clone.processing_counts.lock().unwrap();
+                        if let Some(count) = processing.get_mut(&user_id) { *count = count.saturating_sub(1); }
+                    } else {
+                        if task.debug_trace {
+                            info!(target: "ollamamq::trace", "Task {}: dispatched to {} after {}ms in queue", task_id, url, queue_wait.as_millis());
+                        }
+                        // What a fallback needs once method and headers are on their way
+                        let fallback_parts = task.requested_model.as_deref()
+                            .filter(|m| crate::fallback::next_model(&state_clone.config.file, task.fallback_from.as_deref().unwrap_or(m), m, |_| true).is_some())
+                            .map(|_| (task.method.clone(), task.headers.clone()));
                         let res_fut = client_clone.request(task.method, &url)
+                            .timeout(backend_timeout)
                             .headers(task.headers)
                             .body(task.body)
                             .send();
 
-                        match res_fut.await {
-                            Ok(response) => {
-                                let status = response.status();
-                                let mut headers = response.headers().clone();
+                        // Dropping the request future closes the backend connection, which stops the generation
+                        let res = tokio::select! {
+                            res = res_fut => Some(res),
+                            _ = task.responder.closed() => None,
+                        };
+                        // A model the backend can't serve sends the request back to the queue for its fallback
+                        let res = match (res, fallback_parts) {
+                            (Some(Ok(response)), Some((method, headers))) if crate::fallback::may_be_model_failure(response.status()) => {
+                                match crate::fallback::model_failure(response).await {
+                                    (Some(reason), response) => {
+                                        let current = task.requested_model.clone().unwrap_or_default();
+                                        let original = task.fallback_from.clone().unwrap_or_else(|| current.clone());
+                                        let online_with = |m: &str| state_clone.backends.lock().unwrap().iter()
+                                            .any(|b| b.is_online && smart_model_match(m, &b.available_models));
+                                        let next = crate::fallback::next_model(&state_clone.config.file, &original, &current, online_with);
+                                        let mut retry = Task {
+                                            id: task.id.clone(),
+                                            method,
+                                            path: task.path.clone(),
+                                            headers,
+                                            body: client_body.clone(),
+                                            responder: task.responder.clone(),
+                                            requested_model: task.requested_model.clone(),
+                                            canary: task.canary,
+                                            lane: task.lane,
+                                            affinity_key: task.affinity_key.clone(),
+                                            prompt_tokens: task.prompt_tokens,
+                                            debug_trace: task.debug_trace,
+                                            batchable: task.batchable,
+                                            tools: task.tools,
+                                            experiment: task.experiment.clone(),
+                                            enqueued_at: task.enqueued_at,
+                                            queued_bytes: state_clone.hold_queued_bytes(0),
+                                            spooled: None,
+                                            resumable: task.resumable,
+                                            fallback_from: task.fallback_from.clone(),
+                                        };
+                                        match next {
+                                            Some(next) if crate::fallback::apply(&mut retry, &next) => {
+                                                warn!("Backend {} can't serve model '{}' for user {} ({}), falling back to '{}'", backend_url, current, user_id, reason, next);
+                                                state_clone.model_fallbacks.fetch_add(1, Ordering::Relaxed);
+                                                state_clone.requeue_task(&user_id, retry);
+                                                unfinished = true;
+                                                failure = Some(FailureKind::Backend);
+                                                None
+                                            }
+                                            _ => {
+                                                // No fallback online: the client gets the failure as it was
+                                                warn!("Backend {} can't serve model '{}' for user {} ({}) and no fallback is online", backend_url, current, user_id, reason);
+                                                Some(Ok(response))
+                                            }
+                                        }
+                                    }
+                                    (None, response) => Some(Ok(response)),
+                                }
+                            }
+                            (res, _) => res,
+                        };
+                        match res {
+                            None if unfinished => {}
+                            None => {
+                                outcome = Outcome::Disconnected;
+                                cancelled = Some(CancelStage::Waiting);
+                                let mut dropped = state_clone.dropped_counts.lock().unwrap();
+                                *dropped.entry(user_id.clone()).or_insert(0) += 1;
+                            }
+                            Some(Ok(response)) if is_unusable_response(&task.path, &response) => {
+                                let backend_status = response.status();
+                                status_code = Some(backend_status.as_u16());
+                                let (status, body, reason) = unusable_response_error(&backend_url, response).await;
+                                warn!("Backend {} gave an unusable answer to {} for user {}: {}", backend_url, task.path, user_id, reason);
+                                let kind = FailureKind::from_status(backend_status).unwrap_or(FailureKind::Backend);
+                                failure = Some(kind);
+                                outcome = Outcome::Failed(kind);
+                                detail = Some(reason);
+                                let mut headers = HeaderMap::new();
+                                headers.insert(axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/json"));
+                                if task.responder.send(ResponsePart::Status(status, headers)).await.is_ok() {
+                                    let _ = task.responder.send(ResponsePart::Chunk(Bytes::from(body.to_string()))).await;
+                                }
+                                let mut dropped = state_clone.dropped_counts.lock().unwrap();
+                                *dropped.entry(user_id.clone()).or_insert(0) += 1;
+                            }
+                            Some(Ok(response)) => {
+                                let model = requested_model.as_deref().unwrap_or("-");
+                                let response = match (retry_request.take(), requested_format.as_ref()) {
+                                    (Some(retry), Some(requested)) => {
+                                        let retried = crate::structured::retry_invalid(
+                                            &client_clone, &url, retry, response, &requested.format, &state_clone, model,
+                                        );
+                                        tokio::select! {
+                                            answer = retried => answer,
+                                            // Nothing is forwarded once the client is gone, so the empty answer only ends the exchange
+                                            _ = task.responder.closed() => {
+                                                cancelled = Some(CancelStage::Waiting);
+                                                BackendAnswer { status: StatusCode::OK, headers: HeaderMap::new(), body: futures_util::stream::empty().boxed() }
+                                            }
+                                        }
+                                    }
+                                    _ => BackendAnswer::from(response),
+                                };
+                                let response = match translator.take() {
+                                    Some(t) => t.answer(response),
+                                    None => response,
+                                };
+                                let status = response.status;
+                                status_code = cancelled.is_none().then_some(status.as_u16());
+                                failure = FailureKind::from_status(status);
+                                outcome = Outcome::Disconnected;
+                                let mut headers = response.headers;
                                 headers.remove(axum::http::header::TRANSFER_ENCODING);
                                 headers.remove(axum::http::header::CONTENT_LENGTH);
+                                if let Some(ref from) = task.fallback_from {
+                                    let to = task.requested_model.as_deref().unwrap_or("-");
+                                    if let Ok(value) = axum::http::HeaderValue::from_str(&crate::fallback::describe(from, to)) {
+                                        headers.insert(crate::fallback::HEADER, value);
+                                    }
+                                }
+                                if task.debug_trace {
+                                    let backend_ms = started.elapsed().as_millis();
+                                    info!(target: "ollamamq::trace", "Task {}: {} answered {} after {}ms", task_id, backend_url, status, backend_ms);
+                                    for (name, value) in [
+                                        ("X-Debug-Backend", backend_url.clone()),
+                                        ("X-Debug-Queue-Ms", queue_wait.as_millis().to_string()),
+                                        ("X-Debug-Backend-Ms", backend_ms.to_string()),
+                                    ] {
+                                        if let Ok(value) = axum::http::HeaderValue::from_str(&value) {
+                                            headers.insert(name, value);
+                                        }
+                                    }
+                                }
+
+                                let is_ndjson = headers.get(axum::http::header::CONTENT_TYPE)
+                                    .and_then(|v| v.to_str().ok())
+                                    .is_some_and(|ct| ct.contains("ndjson"));
+                                let mut validator = (is_ndjson && state_clone.config.stream_validation != StreamValidation::Off)
+                                    .then(|| NdjsonValidator::new(state_clone.config.stream_validation));
+                                let content_type = headers.get(axum::http::header::CONTENT_TYPE)
+                                    .and_then(|v| v.to_str().ok())
+                                    .unwrap_or("");
+                                slot_kind = ResponseKind::of(content_type);
+                                let release_at = state_clone.config.slot_policy.for_kind(slot_kind);
+                                let mut tool_calls = task.tools
+                                    .then(|| ToolCallCounter::new(slot_kind == ResponseKind::Streaming));
+                                let mut transformer = ResponseTransformer::new(
+                                    &state_clone.config.file.response_hooks,
+                                    &task.path,
+                                    content_type,
+                                    HookContext {
+                                        requested_model: task.requested_model.clone(),
+                                        backend_url: backend_url.clone(),
+                                        started,
+                                    },
+                                );
 
                                 if task.responder.send(ResponsePart::Status(status, headers)).await.is_ok() {
-                                    let mut stream = response.bytes_stream();
+                                    let mut stream = response.body;
                                     let mut client_disconnected = false;
-                                    while let Some(chunk_res) = stream.next().await {
+                                    let mut too_slow = false;
+                                    let mut bytes_out = 0u64;
+                                    let mut sink = ResponseSink::new(
+                                        task.responder.clone(),
+                                        state_clone.config.slow_client_policy,
+                                        Duration::from_secs(state_clone.config.slow_client_timeout_secs),
+                                        &task_id,
+                                    );
+                                    loop {
+                                        // A client leaving while the backend thinks is noticed right away, not at the next chunk
+                                        let chunk_res = tokio::select! {
+                                            chunk_res = stream.next() => chunk_res,
+                                            _ = task.responder.closed() => {
+                                                client_disconnected = true;
+                                                break;
+                                            }
+                                        };
+                                        let Some(chunk_res) = chunk_res else {
+                                            break;
+                                        };
                                         match chunk_res {
                                             Ok(chunk) => {
-                                                if task.responder.send(ResponsePart::Chunk(chunk)).await.is_err() {
+                                                first_chunk_at.get_or_insert_with(Instant::now);
+                                                if release_at == SlotRelease::FirstChunk && released_at.is_none() {
+                                                    state_clone.release_slot(&backend_url, slot_kind, started.elapsed());
+                                                    released_at = Some(Instant::now());
+                                                }
+                                                usage.push(&chunk);
+                                                if let Some(ref mut counter) = tool_calls {
+                                                    counter.push(&chunk);
+                                                }
+                                                if let Some(ref mut check) = structured_check {
+                                                    check.push(&chunk);
+                                                }
+                                                let chunk = match validator {
+                                                    Some(ref mut v) => v.push(chunk),
+                                                    None => chunk,
+                                                };
+                                                let chunk = match legacy_embed {
+                                                    Some(ref mut e) => e.push(chunk),
+                                                    None => chunk,
+                                                };
+                                                let chunk = match transformer {
+                                                    Some(ref mut t) => t.push(chunk),
+                                                    None => chunk,
+                                                };
+                                                if chunk.is_empty() {
+                                                    continue;
+                                                }
+                                                bytes_out += chunk.len() as u64;
+                                                if let Err(e) = sink.send(chunk).await {
                                                     client_disconnected = true;
+                                                    too_slow = e == SinkError::TooSlow;
                                                     break;
                                                 }
                                             }
-                                            Err(_) => break,
+                                            Err(e) => {
+                                                failure = Some(FailureKind::Backend);
+                                                error_class = Some(if e.is_timeout() { ErrorClass::Timeout } else { ErrorClass::Connection });
+                                                let reason = if e.is_timeout() { crate::timeouts::timed_out(backend_timeout, timeout_source) } else { e.to_string() };
+                                                detail = Some(format!("response stream interrupted: {}", reason));
+                                                break;
+                                            }
+                                        }
+                                    }
+
+                                    let mut tail = BytesMut::new();
+                                    if let Some(ref mut v) = validator {
+                                        tail.extend_from_slice(&v.finish());
+                                        if v.malformed() > 0 {
+                                            malformed_response = true;
+                                            warn!("Malformed NDJSON from {} for user {}: {} line(s)", url, user_id, v.malformed());
+                                        }
+                                    }
+                                    if let Some(ref mut e) = legacy_embed {
+                                        tail.extend_from_slice(&e.finish());
+                                    }
+                                    if let Some(ref mut t) = transformer {
+                                        let flushed = t.push(tail.split().freeze());
+                                        tail.extend_from_slice(&flushed);
+                                        tail.extend_from_slice(&t.finish());
+                                    }
+                                    if !client_disconnected && !tail.is_empty() {
+                                        bytes_out += tail.len() as u64;
+                                        if let Err(e) = sink.send(tail.freeze()).await {
+                                            client_disconnected = true;
+                                            too_slow = e == SinkError::TooSlow;
                                         }
                                     }
+                                    let slow_detail = if too_slow {
+                                        Some(format!("client too slow; disconnected after {}s", state_clone.config.slow_client_timeout_secs))
+                                    } else {
+                                        sink.detail()
+                                    };
+                                    if let Some(slow_detail) = slow_detail {
+                                        state_clone.slow_client_responses.fetch_add(1, Ordering::Relaxed);
+                                        detail.get_or_insert(slow_detail);
+                                    }
+                                    // Closes the backend connection now if the client left mid-stream
+                                    drop(stream);
+                                    sink.finish().await;
+                                    state_clone.record_bandwidth(&user_id, 0, bytes_out);
+                                    if client_disconnected && !too_slow {
+                                        cancelled = Some(CancelStage::Streaming);
+                                    }
 
                                     if !client_disconnected {
+                                        outcome = failure.map_or(Outcome::Completed, Outcome::Failed);
+                                        completed = failure != Some(FailureKind::Backend);
+                                        if let Some(result) = structured_check.as_ref().filter(|_| failure.is_none()).and_then(StructuredCheck::finish) {
+                                            state_clone.record_structured(model, result.is_ok(), false);
+                                            if let Err(reason) = result {
+                                                warn!("Invalid structured output from {} for model {}: {}", backend_url, model, reason);
+                                                detail.get_or_insert(format!("structured output invalid: {}", reason));
+                                            }
+                                        }
+                                        if completed {
+                                            let first_chunk_at = first_chunk_at.unwrap_or_else(Instant::now);
+                                            let timing = StageTiming {
+                                                queue_wait,
+                                                first_chunk: first_chunk_at.duration_since(started),
+                                                streaming: first_chunk_at.elapsed(),
+                                            };
+                                            debug!(
+                                                "Request for user {} on {}: queued {}ms, first chunk {}ms, streamed {}ms",
+                                                user_id, backend_url,
+                                                timing.queue_wait.as_millis(), timing.first_chunk.as_millis(), timing.streaming.as_millis()
+                                            );
+                                            state_clone.record_timing(&user_id, timing);
+                                            completed_timing = Some(timing);
+                                        }
+                                        if let Some(counter) = tool_calls.as_mut().filter(|_| failure.is_none()) {
+                                            state_clone.record_tool_use(&user_id, counter.finish());
+                                        }
                                         let mut counts = state_clone.processed_counts.lock().unwrap();
                                         *counts.entry(user_id.clone()).or_insert(0) += 1;
+                                        state_clone.rollup.lock().unwrap().record(&user_id, requested_model.as_deref());
                                     } else {
                                         let mut dropped = state_clone.dropped_counts.lock().unwrap();
                                         *dropped.entry(user_id.clone()).or_insert(0) += 1;
                                     }
+                                } else {
+                                    let mut dropped = state_clone.dropped_counts.lock().unwrap();
+                                    *dropped.entry(user_id.clone()).or_insert(0) += 1;
                                 }
                             }
-                            Err(e) => {
+                            Some(Err(e)) => {
+                                failure = Some(FailureKind::from_error(&e));
+                                outcome = Outcome::Failed(FailureKind::from_error(&e));
+                                error_class = Some(if e.is_timeout() { ErrorClass::Timeout } else { ErrorClass::Connection });
+                                detail = Some(if e.is_timeout() { crate::timeouts::timed_out(backend_timeout, timeout_source) } else { e.to_string() });
                                 let _ = task.responder.send(ResponsePart::Error(e)).await;
                                 let mut dropped = state_clone.dropped_counts.lock().unwrap();
                                 *dropped.entry(user_id.clone()).or_insert(0) += 1;
@@ -563,20 +3059,144 @@ pub async fn run_worker(state: Arc<AppState>) {
                             let mut processing = state_clone.processing_counts.lock().unwrap();
                             if let Some(count) = processing.get_mut(&user_id) { *count = count.saturating_sub(1); }
                         }
+
+                        if let Some(stage) = cancelled {
+                            let used = started.elapsed();
+                            let saved = state_clone.record_cancellation(stage, used);
+                            debug!("Client of task {} left while {}, backend request on {} cancelled after {}ms", task_id, stage.as_str(), backend_url, used.as_millis());
+                            let saved = if saved.is_zero() { String::new() } else { format!(" (~{} saved)", format_duration(saved)) };
+                            detail.get_or_insert(format!(
+                                "client disconnected while {}; backend request cancelled after {}{}",
+                                stage.as_str(), format_duration(used), saved
+                            ));
+                        }
+
+                        state_clone.record_outcome(&user_id, failure);
+                        if let Some(kind) = failure {
+                            debug!("Request of user {} on {} failed ({} error)", user_id, backend_url, kind.as_str());
+                            state_clone.record_failure(&user_id, &backend_url, kind);
+                        }
                     }
 
                     {
+                        state_clone.in_flight.fetch_sub(1, Ordering::Relaxed);
+                        if task.debug_trace {
+                            state_clone.debug_in_flight.fetch_sub(1, Ordering::Relaxed);
+                        }
                         let mut backends = state_clone.backends.lock().unwrap();
-                        backends[backend_idx].active_requests = backends[backend_idx].active_requests.saturating_sub(1);
-                        backends[backend_idx].processed_count += 1;
+                        // Gone if it was removed through the admin API meanwhile
+                        if let Some(backend) = backends.iter_mut().find(|b| b.url == backend_url) {
+                            match released_at {
+                                Some(at) => {
+                                    backend.slots.relaying = backend.slots.relaying.saturating_sub(1);
+                                    backend.slots.relay_time += at.elapsed();
+                                }
+                                None => {
+                                    backend.active_requests = backend.active_requests.saturating_sub(1);
+                                    backend.slots.record(slot_kind, started.elapsed());
+                                }
+                            }
+                            if backend.active_requests == 0 && backend.slots.relaying == 0 {
+                                backend.idle_since = Some(Instant::now());
+                            }
+                            backend.processed_count += 1;
+                            // Client errors aren't held against the backend's budget
+                            backend.error_budget.record(failure == Some(FailureKind::Backend), state_clone.config.error_budget_window);
+                            match failure {
+                                Some(FailureKind::Backend) => backend.failed_count += 1,
+                                Some(FailureKind::Client) => backend.client_errors += 1,
+                                None => {}
+                            }
+                            if malformed_response {
+                                backend.malformed_responses += 1;
+                            }
+                            if completed {
+                                let elapsed_ms = started.elapsed().as_millis() as u64;
+                                backend.total_latency_ms += elapsed_ms;
+                                backend.latency_samples += 1;
+                                if backend.is_canary {
+                                    debug!("Canary backend {} completed request in {}ms", backend.url, elapsed_ms);
+                                }
+                            }
+                        }
+                    }
+                    let (prompt_tokens, completion_tokens) = usage.counts();
+                    if outcome == Outcome::Completed {
+                        state_clone.record_tokens(&user_id, prompt_tokens, completion_tokens);
+                        if let Some(ref model) = requested_model
+                            && RouteClass::from_path(&task_path) != RouteClass::Other
+                        {
+                            state_clone.learned_timeouts.lock().unwrap().record(&backend_url, model, started.elapsed());
+                        }
+                    }
+                    if task.debug_trace {
+                        info!(
+                            target: "ollamamq::trace",
+                            "Task {}: {} after {}ms (queue {}ms, first chunk {}ms), tokens {}/{}{}",
+                            task_id,
+                            outcome.as_str(),
+                            started.elapsed().as_millis(),
+                            queue_wait.as_millis(),
+                            first_chunk_at.map_or("-".to_string(), |t| t.duration_since(started).as_millis().to_string()),
+                            prompt_tokens.map_or("-".to_string(), |n| n.to_string()),
+                            completion_tokens.map_or("-".to_string(), |n| n.to_string()),
+                            detail.as_deref().map(|d| format!(": {}", d)).unwrap_or_default()
+                        );
+                    }
+                    if let Some(ref a) = experiment.filter(|_| outcome != Outcome::Dropped) {
+                        state_clone.experiments.lock().unwrap().record(a, &user_id, outcome, completed_timing, (prompt_tokens, completion_tokens));
+                    }
+                    // Kept as the client sent it, for a replay once the backend recovered
+                    if let Some((method, headers)) = dead_letter_parts.filter(|_| failure == Some(FailureKind::Backend) && !unfinished)
+                        && let Some(ip) = state_clone.user_ips.lock().unwrap().get(&user_id).copied()
+                    {
+                        crate::dead_letter::keep(&state_clone, DeadLetter {
+                            task_id: task_id.clone(),
+                            user_id: user_id.clone(),
+                            ip,
+                            method,
+                            path: task_path.clone(),
+                            headers,
+                            body: client_body.clone(),
+                            model: requested_model.clone(),
+                        });
+                    }
+                    if !unfinished {
+                        if let Some(class) = error_class.or_else(|| ErrorClass::from_outcome(outcome, status_code)) {
+                            state_clone.record_error(&user_id, class);
+                        }
+                        state_clone.record_history(HistoryEntry {
+                            task_id: task_id.clone(),
+                            user_id,
+                            path: task_path,
+                            model: requested_model,
+                            backend: (outcome != Outcome::Dropped).then_some(backend_url),
+                            outcome,
+                            status: status_code,
+                            queue_wait,
+                            duration: if outcome == Outcome::Dropped { Duration::ZERO } else { started.elapsed() },
+                            prompt_tokens,
+                            completion_tokens,
+                            finished_at: chrono::Local::now(),
+                            detail,
+                        });
                     }
+                    // Removed only now so a waiter always finds the task in one place or the other
+                    state_clone.running_tasks.lock().unwrap().remove(&task_id);
                     state_clone.backend_freed.notify_one();
-                });
+                }.instrument(span));
             }
             None => {
+                let ripe = async {
+                    match next_ripe {
+                        Some(at) => tokio::time::sleep_until(at.into()).await,
+                        None => std::future::pending().await,
+                    }
+                };
                 tokio::select! {
                     _ = state.notify.notified() => {},
                     _ = state.backend_freed.notified() => {},
+                    _ = ripe => {},
                 }
             }
         }
@@ -591,65 +3211,556 @@ pub async fn proxy_handler(
     axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
     body: Bytes,
 ) -> impl IntoResponse {
-    let path = uri.path().to_string();
-    let ip = addr.ip();
-    let user_id = headers
-        .get("X-User-ID")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("anonymous")
-        .to_string();
+    handle_request(state, addr.ip(), method, headers, uri.path().to_string(), body).await
+}
+
+/// Where a task with a prompt of `tokens` goes in its user's queue under
+/// shortest-job-first: ahead of larger prompts, but never ahead of a task that
+/// has already waited `SJF_MAX_BYPASS_WAIT`, so big prompts can't starve.
+fn sjf_position(queue: &VecDeque<Task>, tokens: u64) -> usize {
+    let mut position = queue.len();
+    while position > 0 {
+        let ahead = &queue[position - 1];
+        if ahead.prompt_tokens <= tokens || ahead.enqueued_at.elapsed() >= SJF_MAX_BYPASS_WAIT {
+            break;
+        }
+        position -= 1;
+    }
+    position
+}
+
+/// User id of a request: resolved from its credential when an identity
+/// endpoint is configured (X-User-ID is then ignored), otherwise X-User-ID;
+/// normalized by the `user_ids` rules and qualified with the request's
+/// namespace, if any.
+pub async fn caller_id(state: &AppState, headers: &HeaderMap) -> Result<String, Response> {
+    let user_id = match state.identity {
+        None => headers
+            .get("X-User-ID")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("anonymous")
+            .to_string(),
+        Some(ref resolver) => match resolver.resolve(headers).await {
+            Ok(identity) => identity.user_id,
+            Err(IdentityError::Unauthorized) => {
+                debug!("Rejected request: missing or unknown credential");
+                let body = serde_json::json!({ "error": "missing or invalid credentials" });
+                return Err((StatusCode::UNAUTHORIZED, axum::Json(body)).into_response());
+            }
+            Err(IdentityError::Unavailable(e)) => {
+                warn!("Identity lookup failed: {}", e);
+                let body = serde_json::json!({ "error": "identity service unavailable" });
+                return Err((StatusCode::SERVICE_UNAVAILABLE, axum::Json(body)).into_response());
+            }
+        },
+    };
+    let user_id = match state.config.file.user_ids.apply(&user_id) {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            debug!("Rejected request: user id {:?}: {}", user_id, e);
+            let body = serde_json::json!({ "error": e.to_string() });
+            return Err((StatusCode::BAD_REQUEST, axum::Json(body)).into_response());
+        }
+    };
+    // Otherwise a user id could pose as a member of another namespace
+    if user_id.contains(crate::namespace::SEPARATOR) {
+        debug!("Rejected request: user id '{}' contains '{}'", user_id, crate::namespace::SEPARATOR);
+        let body = serde_json::json!({ "error": format!("user id must not contain '{}'", crate::namespace::SEPARATOR) });
+        return Err((StatusCode::BAD_REQUEST, axum::Json(body)).into_response());
+    }
+    match crate::namespace::resolve(&state.config.file, headers) {
+        Ok(namespace) => Ok(crate::namespace::qualify(namespace.as_deref(), &user_id)),
+        Err(NamespaceError::Invalid(name)) => {
+            debug!("Rejected request of user {}: invalid namespace '{}'", user_id, name);
+            let body = serde_json::json!({ "error": format!("invalid namespace '{}'", name) });
+            Err((StatusCode::BAD_REQUEST, axum::Json(body)).into_response())
+        }
+        Err(NamespaceError::KeyRequired(name)) => {
+            debug!("Rejected request of user {}: namespace '{}' without its API key", user_id, name);
+            let body = serde_json::json!({ "error": format!("namespace '{}' requires an API key", name) });
+            Err((StatusCode::UNAUTHORIZED, axum::Json(body)).into_response())
+        }
+    }
+}
+
+/// Removes what the client sent for the dispatcher alone before its headers go
+/// on to a backend: credentials the dispatcher checked, the namespace, and any
+/// forwarded user header, which only the dispatcher may set.
+pub fn strip_client_headers(state: &AppState, headers: &mut HeaderMap) {
+    if let Some(ref resolver) = state.identity {
+        // The backend has no use for the client's API key
+        headers.remove(resolver.header());
+    }
+    if crate::namespace::key_namespace(&state.config.file, headers).is_some() {
+        headers.remove(axum::http::header::AUTHORIZATION);
+    }
+    headers.remove(crate::namespace::HEADER);
+    if let Some(ref name) = state.config.forward_user_header {
+        // Only the dispatcher may vouch for the user
+        headers.remove(name);
+    }
+}
+
+/// Queues one request for its user and streams the backend response back once
+/// the scheduler has dispatched it. Shared by `proxy_handler` and the tower layer.
+pub async fn handle_request(
+    state: Arc<AppState>,
+    ip: IpAddr,
+    method: Method,
+    headers: HeaderMap,
+    path: String,
+    body: Bytes,
+) -> Response {
+    let origins = &state.config.cors_origins;
+    if let Some(response) = crate::methods::answer(&method, &path, &headers, origins) {
+        return response;
+    }
+    let origin = crate::methods::allowed_origin(origins, &headers).cloned();
+    let mut response = if method == Method::HEAD && crate::methods::allowed(&path).is_some_and(|m| !m.contains(&Method::HEAD)) {
+        // Run as the GET it stands for, the backend may not take HEAD
+        crate::methods::head_of(queue_request(state.clone(), ip, Method::GET, headers, path, body).await).await
+    } else {
+        queue_request(state.clone(), ip, method, headers, path, body).await
+    };
+    if let Some(origin) = origin {
+        crate::methods::allow_origin(&mut response, origin);
+    }
+    response
+}
+
+async fn queue_request(
+    state: Arc<AppState>,
+    ip: IpAddr,
+    method: Method,
+    mut headers: HeaderMap,
+    path: String,
+    body: Bytes,
+) -> Response {
+    // Dual-stack listeners report IPv4 clients as `::ffff:a.b.c.d`
+    let ip = ip.to_canonical();
+    let user_id = match caller_id(&state, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    strip_client_headers(&state, &mut headers);
+    let resumable = headers.get("X-Resumable")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1");
+    headers.remove("X-Resumable");
+    let debug_trace = headers.get("X-Debug-Trace")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1");
+    let debug_trace = debug_trace && crate::admin::token_role(&state, &headers).is_some_and(|role| role >= AdminRole::Operator);
+    if debug_trace {
+        // The admin token must not reach the backend
+        headers.remove(axum::http::header::AUTHORIZATION);
+        headers.remove("X-Debug-Trace");
+    }
 
     if state.is_ip_blocked(&ip) {
-        warn!("Blocked request from IP: {} for user: {}", ip, user_id);
-        return (StatusCode::FORBIDDEN, "IP blocked").into_response();
+        warn!("Blocked request from IP: {} for user: {}", state.describe_ip(&ip), user_id);
+        return (StatusCode::FORBIDDEN, axum::Extension(BlockedAttempt), "IP blocked").into_response();
     }
 
     if state.is_user_blocked(&user_id) {
-        warn!("Blocked request from user: {} (IP: {})", user_id, ip);
-        return (StatusCode::FORBIDDEN, "User blocked").into_response();
+        warn!("Blocked request from user: {} ({})", user_id, state.describe_ip(&ip));
+        return (StatusCode::FORBIDDEN, axum::Extension(BlockedAttempt), "User blocked").into_response();
+    }
+
+    if let Some(remaining) = state.cooldown_remaining(&user_id) {
+        let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        debug!("Rejected request from user {} during cooldown ({}s left)", user_id, secs);
+        state.record_error(&user_id, ErrorClass::Rejected);
+        let body = serde_json::json!({
+            "error": format!(
+                "too many consecutive failed requests for user '{}'; retry in {}s",
+                user_id, secs
+            ),
+            "retry_after_secs": secs,
+        });
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(axum::http::header::RETRY_AFTER, secs.to_string())],
+            axum::Json(body),
+        )
+            .into_response();
+    }
+
+    if state.is_shutting_down() {
+        debug!("Rejected request from user {}: shutting down", user_id);
+        let secs = crate::shutdown::RETRY_AFTER_SECS;
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, secs.to_string())],
+            axum::Json(serde_json::json!({ "error": "dispatcher shutting down", "retry_after_secs": secs })),
+        )
+            .into_response();
+    }
+
+    // With a shared NATS queue, other instances' backends may take the request
+    if let Some(secs) = state.offline_retry_after().filter(|_| !state.shares_queue()) {
+        debug!("Rejected request from user {}: no backend online", user_id);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, secs.to_string())],
+            axum::Json(no_backend_body(secs)),
+        )
+            .into_response();
+    }
+
+    let other_ips = state.other_connection_ips(&user_id, &ip);
+    if !other_ips.is_empty() {
+        match state.multi_ip_policy(&user_id) {
+            MultiIpPolicy::Allow => {}
+            MultiIpPolicy::Flag => {
+                warn!("User {} sent a request from {} while connected from {:?}", user_id, state.describe_ip(&ip), other_ips);
+                *state.multi_ip_flags.lock().unwrap().entry(user_id.clone()).or_insert(0) += 1;
+            }
+            MultiIpPolicy::Reject => {
+                warn!("Rejected request of user {} from {}: already connected from {:?}", user_id, state.describe_ip(&ip), other_ips);
+                state.record_error(&user_id, ErrorClass::Rejected);
+                let body = serde_json::json!({
+                    "error": format!("user '{}' is already connected from another IP address", user_id),
+                    "active_ips": other_ips,
+                });
+                return (StatusCode::CONFLICT, axum::Json(body)).into_response();
+            }
+        }
+    }
+    let lane = Lane::from_headers(&headers);
+    if let Some(limit) = state.shed_limit(&user_id, lane).filter(|_| !debug_trace)
+        && let Some(wait) = state.estimated_wait().filter(|w| w.as_secs() > limit)
+    {
+        let tier = state.tier_of(&user_id).unwrap_or_else(|| "-".to_string());
+        debug!("Shed {} request from user {} (tier {}): estimated wait {}s above {}s", lane.as_str(), user_id, tier, wait.as_secs(), limit);
+        *state.shed_counts.lock().unwrap().entry((tier, lane)).or_insert(0) += 1;
+        state.record_error(&user_id, ErrorClass::Rejected);
+        // Clients retry once the queue is expected to be back under the limit
+        let secs = wait.as_secs().saturating_sub(limit).clamp(1, 3600);
+        let body = serde_json::json!({
+            "error": format!("dispatcher overloaded: {} requests are shed while the estimated wait exceeds {}s", lane.as_str(), limit),
+            "retry_after_secs": secs,
+        });
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, secs.to_string())],
+            axum::Json(body),
+        )
+            .into_response();
     }
 
+    let max_bytes = state.config.max_queued_bytes;
+    let over_memory = |state: &AppState| max_bytes > 0 && !debug_trace && state.queued_bytes() + body.len() as u64 > max_bytes;
+    if over_memory(&state) && !crate::spool::make_room(&state, body.len() as u64).await && over_memory(&state) {
+        warn!(
+            "Rejected request from user {}: queued bodies hold {} of {}",
+            user_id, format_bytes(state.queued_bytes()), format_bytes(max_bytes)
+        );
+        state.memory_rejected.fetch_add(1, Ordering::Relaxed);
+        state.record_error(&user_id, ErrorClass::Rejected);
+        let body = serde_json::json!({
+            "error": "dispatcher overloaded: queued requests use all memory set aside for them",
+            "retry_after_secs": MEMORY_RETRY_AFTER_SECS,
+        });
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, MEMORY_RETRY_AFTER_SECS.to_string())],
+            axum::Json(body),
+        )
+            .into_response();
+    }
+
+    let connection = ConnectionGuard::new(state.clone(), user_id.clone(), ip);
+
     {
         let mut ips = state.user_ips.lock().unwrap();
         ips.insert(user_id.clone(), ip);
     }
+    state.request_rate.lock().unwrap().record();
+    state.record_bandwidth(&user_id, body.len() as u64, 0);
+
+    if let Some(policy) = state.config.file.policies.get(&path)
+        && let Err(v) = policy.check(&body)
+    {
+        debug!("Policy rejected {} request from user {}: {}", path, user_id, v.message);
+        state.record_outcome(&user_id, Some(FailureKind::Client));
+        state.record_error(&user_id, ErrorClass::Rejected);
+        let body = serde_json::json!({ "error": v.message, "field": v.field });
+        return (StatusCode::BAD_REQUEST, axum::Json(body)).into_response();
+    }
+
+    if !debug_trace
+        && let Some(answer) = crate::catalogue::answer(&state, &method, &path, &body).await
+    {
+        debug!("Answered {} for user {} from the model catalogue", path, user_id);
+        let answer = answer.to_string();
+        state.record_bandwidth(&user_id, 0, answer.len() as u64);
+        return ([(axum::http::header::CONTENT_TYPE, "application/json")], answer).into_response();
+    }
 
     let (tx, rx) = mpsc::channel(32);
     let mut task_headers = headers.clone();
     task_headers.remove(axum::http::header::HOST);
+    if let Some(ref name) = state.config.forward_user_header {
+        match axum::http::HeaderValue::from_str(&user_id) {
+            Ok(value) => {
+                task_headers.insert(name.clone(), value);
+            }
+            Err(_) => debug!("User id {:?} can't be sent in {}", user_id, name),
+        }
+    }
 
-    let requested_model = if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&body) {
-        json.get("model").and_then(|m| m.as_str()).map(|s| s.to_string())
+    let templated = if path == "/api/chat" {
+        crate::templates::apply(&state.config.file, &user_id, state.tier_of(&user_id).as_deref(), &body)
     } else {
         None
     };
+    let body = match templated {
+        Some((rewritten, names)) => {
+            debug!("Applied prompt template(s) {} to request from user {}", names.join(", "), user_id);
+            task_headers.remove(axum::http::header::CONTENT_LENGTH);
+            Bytes::from(rewritten)
+        }
+        None => body,
+    };
+
+    let (experiment, body) = match crate::experiments::assign(&state.config.file, &user_id, &path, &body) {
+        Some((assignment, rewritten)) => {
+            debug!("User {} is in arm {} of experiment {}", user_id, assignment.arm, assignment.experiment);
+            let body = match rewritten {
+                Some(rewritten) => {
+                    task_headers.remove(axum::http::header::CONTENT_LENGTH);
+                    Bytes::from(rewritten)
+                }
+                None => body,
+            };
+            (Some(assignment), body)
+        }
+        None => (None, body),
+    };
+
+    let json = serde_json::from_slice::<serde_json::Value>(&body).ok();
+    let requested_model = json.as_ref()
+        .and_then(|json| json.get("model").and_then(|m| m.as_str()).map(|s| s.to_string()));
+    let prompt_tokens = json.as_ref().map_or(0, crate::capabilities::estimate_prompt_tokens);
 
+    if let (Some(model), Some(json)) = (requested_model.as_deref().filter(|_| state.config.model_checks), &json)
+        && let Err(v) = crate::capabilities::check(&state, json, model, prompt_tokens)
+    {
+        debug!("Rejected {} request from user {}: {}", path, user_id, v.message);
+        state.record_outcome(&user_id, Some(FailureKind::Client));
+        state.record_error(&user_id, ErrorClass::Rejected);
+        let body = serde_json::json!({ "error": v.message, "field": v.field });
+        return (StatusCode::BAD_REQUEST, axum::Json(body)).into_response();
+    }
+
+    let canary = state.roll_canary(requested_model.as_deref());
+    let affinity_key = state.config.session_affinity.key(&user_id, &headers);
+
+    let batchable = is_batchable(&path, json.as_ref());
+    let tools = json.as_ref().is_some_and(crate::tools::offers_tools);
+    let task_id = state.next_task_id();
+    let queued_bytes = state.hold_queued_bytes(body.len());
     let task = Task {
+        id: task_id.clone(),
         path,
         method,
         headers: task_headers,
         responder: tx,
         body,
         requested_model,
+        canary,
+        lane,
+        affinity_key,
+        prompt_tokens,
+        debug_trace,
+        batchable,
+        tools,
+        experiment,
+        enqueued_at: Instant::now(),
+        queued_bytes,
+        spooled: None,
+        resumable,
+        fallback_from: None,
     };
+    if debug_trace {
+        info!(
+            target: "ollamamq::trace",
+            "Task {}: debug probe of user {} from {}: {} {} (model {}, ~{} prompt tokens)",
+            task_id, user_id, ip, task.method, task.path, task.requested_model.as_deref().unwrap_or("-"), task.prompt_tokens
+        );
+    }
 
-    {
+    // Jumping the queue takes the local one, so debug probes stay on this instance
+    #[cfg(feature = "nats")]
+    if !debug_trace && let Some(nats) = state.nats.get() {
+        let limited = {
+            let mut pending = nats.pending();
+            let sole_user = pending.keys().all(|u| *u == user_id);
+            let own = pending.get(&user_id).copied().unwrap_or_default();
+            let namespace_queued = |ns: &str| pending.iter()
+                .filter(|(u, _)| crate::namespace::in_namespace(u, Some(ns)))
+                .map(|(_, p)| p.requests)
+                .sum();
+            let limited = queue_limit(&state, &user_id, sole_user, own.requests, own.prompt_tokens, namespace_queued, task.prompt_tokens);
+            if limited.is_none() {
+                let own = pending.entry(user_id.clone()).or_default();
+                own.requests += 1;
+                own.prompt_tokens += task.prompt_tokens;
+            }
+            limited
+        };
+        if let Some(body) = limited {
+            state.record_error(&user_id, ErrorClass::Rejected);
+            return (StatusCode::TOO_MANY_REQUESTS, axum::Json(body)).into_response();
+        }
+        if let Err(e) = nats.submit(&user_id, task).await {
+            warn!("Failed to publish request of user {} to NATS: {}", user_id, e);
+            return (StatusCode::SERVICE_UNAVAILABLE, "Shared queue unavailable").into_response();
+        }
+        return await_response(rx, connection, task_id).await;
+    }
+
+    let queue_full = {
         let mut queues = state.queues.lock().unwrap();
-        queues
-            .entry(user_id.clone())
-            .or_insert_with(VecDeque::new)
-            .push_back(task);
+        let sole_user = is_sole_user(&queues, &state.processing_counts.lock().unwrap(), &user_id);
+        if task.debug_trace {
+            // Ahead of the user's own backlog too
+            queues.entry(user_id.clone()).or_default().push_front(task);
+            None
+        } else {
+            let queue = queues.get(&user_id);
+            let queued = queue.map_or(0, |q| q.len());
+            let queued_tokens: u64 = queue.into_iter().flatten().map(|t| t.prompt_tokens).sum();
+            let namespace_queued = |ns: &str| queues.iter()
+                .filter(|(u, _)| crate::namespace::in_namespace(u, Some(ns)))
+                .map(|(_, q)| q.len())
+                .sum();
+            let limited = queue_limit(&state, &user_id, sole_user, queued, queued_tokens, namespace_queued, task.prompt_tokens);
+            if limited.is_none() {
+                let queue = queues.entry(user_id.clone()).or_default();
+                let position = if state.config.shortest_job_first {
+                    sjf_position(queue, task.prompt_tokens)
+                } else {
+                    queue.len()
+                };
+                queue.insert(position, task);
+            }
+            limited
+        }
+    };
+    if let Some(body) = queue_full {
+        state.record_error(&user_id, ErrorClass::Rejected);
+        return (StatusCode::TOO_MANY_REQUESTS, axum::Json(body)).into_response();
     }
 
     state.notify.notify_one();
+    await_response(rx, connection, task_id).await
+}
+
+/// Body of the `429` for a request of `user_id` that would go past its queue,
+/// prompt token or namespace limit, given the `queued` requests with about
+/// `queued_tokens` prompt tokens the user already has waiting.
+fn queue_limit(
+    state: &AppState,
+    user_id: &str,
+    sole_user: bool,
+    queued: usize,
+    queued_tokens: u64,
+    namespace_queued: impl FnOnce(&str) -> usize,
+    prompt_tokens: u64,
+) -> Option<serde_json::Value> {
+    let (_, max_queued) = state.user_caps(user_id, sole_user);
+    let max_tokens = state.max_queued_tokens(user_id);
+    if let Some((ns, max)) = crate::namespace::max_queued(&state.config.file, user_id)
+        && namespace_queued(ns) >= max
+    {
+        debug!("Rejected request from user {}: namespace {} already has {} queued requests", user_id, ns, max);
+        Some(serde_json::json!({
+            "error": format!("namespace '{}' already has {} queued requests", ns, max),
+            "max_queued": max,
+        }))
+    } else if max_queued > 0 && queued >= max_queued {
+        debug!("Rejected request from user {}: {} requests already queued", user_id, max_queued);
+        Some(serde_json::json!({
+            "error": format!("user '{}' already has {} queued requests", user_id, max_queued),
+            "max_queued": max_queued,
+        }))
+    } else if max_tokens > 0 && queued > 0 && queued_tokens + prompt_tokens > max_tokens {
+        // A single oversized prompt is still admitted into an empty queue
+        debug!("Rejected request from user {}: about {} prompt tokens already queued", user_id, queued_tokens);
+        Some(serde_json::json!({
+            "error": format!(
+                "user '{}' already has about {} prompt tokens queued; this request adds about {} (limit {})",
+                user_id, queued_tokens, prompt_tokens, max_tokens
+            ),
+            "max_queued_tokens": max_tokens,
+        }))
+    } else {
+        None
+    }
+}
+
+/// Re-attaches the caller to one of their resumable requests whose client went
+/// away: answers with its response as the original connection would have.
+pub async fn attach_handler(
+    State(state): State<Arc<AppState>>,
+    extensions: axum::http::Extensions,
+    headers: HeaderMap,
+    axum::extract::Path(task_id): axum::extract::Path<String>,
+) -> Response {
+    let user_id = match caller_id(&state, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let ip = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED));
+    let (tx, rx) = mpsc::channel(32);
+    match state.reattach_task(&user_id, &task_id, tx) {
+        Reattach::Queued | Reattach::Requeued => {
+            info!("Task {} of user {}: client re-attached from {}", task_id, user_id, ip);
+            let connection = ConnectionGuard::new(state.clone(), user_id, ip);
+            await_response(rx, connection, task_id).await
+        }
+        Reattach::Connected => {
+            let body = serde_json::json!({ "error": "the request's original client is still connected", "task_id": task_id });
+            (StatusCode::CONFLICT, axum::Json(body)).into_response()
+        }
+        Reattach::Unknown => {
+            let body = serde_json::json!({
+                "error": "no resumable request with this id is queued or waiting to re-attach",
+                "task_id": task_id,
+            });
+            (StatusCode::NOT_FOUND, axum::Json(body)).into_response()
+        }
+    }
+}
+
+/// Drops detached tasks once `--disconnect-grace-secs` has passed without
+/// their client re-attaching.
+pub async fn run_detached_expiry(state: Arc<AppState>) {
+    if state.config.disconnect_grace_secs.is_none() {
+        return;
+    }
+    loop {
+        tokio::time::sleep(DETACHED_SWEEP).await;
+        state.expire_detached();
+    }
+}
 
-    let mut rx = rx;
-    match rx.recv().await {
+/// Turns the parts a worker sends for a task into the HTTP response.
+async fn await_response(mut rx: mpsc::Receiver<ResponsePart>, connection: ConnectionGuard, task_id: String) -> Response {
+    let mut response = match rx.recv().await {
         Some(ResponsePart::Status(status, headers)) => {
-            let stream = ReceiverStream::new(rx).map(|part| {
+            // The connection counts as open until the response has been streamed
+            let stream = ReceiverStream::new(rx).map(move |part| {
+                let _ = &connection;
                 match part {
                     ResponsePart::Chunk(chunk) => Ok(chunk),
-                    ResponsePart::Error(e) => Err(e),
+                    ResponsePart::Error(e) => Err(axum::BoxError::from(e)),
+                    ResponsePart::Failed(e) => Err(e.into()),
                     _ => Ok(Bytes::new()),
                 }
             });
@@ -662,6 +3773,11 @@ pub async fn proxy_handler(
         Some(ResponsePart::Error(e)) => {
             (StatusCode::INTERNAL_SERVER_ERROR, format!("Backend error: {}", e)).into_response()
         }
+        Some(ResponsePart::Failed(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
         _ => (StatusCode::INTERNAL_SERVER_ERROR, "Worker failed to respond").into_response(),
+    };
+    if let Ok(value) = task_id.parse() {
+        response.headers_mut().insert("X-Task-ID", value);
     }
+    response
 }
@@ -0,0 +1,142 @@
+//! Names for clients running as Docker containers: with `--docker-names`, the
+//! container list is read from the Docker socket and connecting bridge IPs are
+//! shown as their Compose service (or container) names.
+
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::dispatcher::AppState;
+
+pub const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// How often the container list is re-read without being asked to.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Least time between two reads, however many unknown IPs show up.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+const COMPOSE_NUMBER_LABEL: &str = "com.docker.compose.container-number";
+
+/// IP-to-name map of the local Docker containers.
+pub struct DockerNames {
+    client: reqwest::Client,
+    names: Mutex<HashMap<IpAddr, String>>,
+    /// Woken when a request comes from an IP that isn't in the map yet.
+    refresh: Notify,
+    /// Unknown IPs that already asked for a re-read, so clients that aren't
+    /// containers (e.g. the host) don't cause one per request.
+    asked: Mutex<HashSet<IpAddr>>,
+}
+
+impl DockerNames {
+    pub fn new(socket: &Path) -> Self {
+        let client = reqwest::Client::builder()
+            .unix_socket(socket)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            client,
+            names: Mutex::new(HashMap::new()),
+            refresh: Notify::new(),
+            asked: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Name of the container behind `ip`. An unknown non-loopback IP asks for
+    /// the container list to be re-read, so new containers get named quickly.
+    pub fn name_of(&self, ip: &IpAddr) -> Option<String> {
+        let name = self.names.lock().unwrap().get(ip).cloned();
+        if name.is_none() && !ip.is_loopback() && self.asked.lock().unwrap().insert(*ip) {
+            self.refresh.notify_one();
+        }
+        name
+    }
+
+    /// All known names, for the dashboard.
+    pub fn names(&self) -> HashMap<IpAddr, String> {
+        self.names.lock().unwrap().clone()
+    }
+
+    async fn fetch(&self) -> Result<HashMap<IpAddr, String>, String> {
+        let res = self.client.get("http://docker/containers/json")
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            return Err(format!("Docker API answered {}", res.status()));
+        }
+        let containers: Vec<Value> = res.json().await.map_err(|e| format!("invalid container list: {}", e))?;
+        Ok(containers.iter().flat_map(container_ips).collect())
+    }
+}
+
+/// Display name of a container: its Compose service (with the replica number
+/// from the second replica on), otherwise its container name.
+fn container_name(container: &Value) -> Option<String> {
+    let labels = container.get("Labels");
+    let label = |key: &str| labels.and_then(|l| l.get(key)).and_then(Value::as_str);
+    if let Some(service) = label(COMPOSE_SERVICE_LABEL) {
+        return Some(match label(COMPOSE_NUMBER_LABEL) {
+            Some(n) if n != "1" => format!("{}-{}", service, n),
+            _ => service.to_string(),
+        });
+    }
+    container.get("Names")?.as_array()?.first()?.as_str().map(|n| n.trim_start_matches('/').to_string())
+}
+
+/// The container's name for each of its network addresses.
+fn container_ips(container: &Value) -> Vec<(IpAddr, String)> {
+    let Some(name) = container_name(container) else {
+        return Vec::new();
+    };
+    let Some(networks) = container.pointer("/NetworkSettings/Networks").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    networks.values()
+        .flat_map(|n| ["IPAddress", "GlobalIPv6Address"].map(|key| n.get(key).and_then(Value::as_str)))
+        .flatten()
+        .filter_map(|ip| ip.parse().ok())
+        .map(|ip| (ip, name.clone()))
+        .collect()
+}
+
+/// Keeps the names up to date while `--docker-names` is on.
+pub async fn run_docker_names(state: Arc<AppState>) {
+    let Some(ref docker) = state.docker else {
+        return;
+    };
+    let mut reachable = None;
+    loop {
+        match docker.fetch().await {
+            Ok(names) => {
+                if reachable != Some(true) {
+                    info!("Naming clients after Docker containers ({} addresses known)", names.len());
+                }
+                debug!("Read {} container addresses from Docker", names.len());
+                *docker.names.lock().unwrap() = names;
+                reachable = Some(true);
+            }
+            Err(e) => {
+                if reachable != Some(false) {
+                    warn!("Cannot read containers from the Docker socket: {}", e);
+                }
+                reachable = Some(false);
+            }
+        }
+        tokio::time::sleep(MIN_REFRESH_INTERVAL).await;
+        if tokio::time::timeout(REFRESH_INTERVAL, docker.refresh.notified()).await.is_err() {
+            // Unknown IPs may ask again after a regular re-read
+            docker.asked.lock().unwrap().clear();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Error budgets per backend: the share of requests that failed because of the
+//! backend over a rolling window, against a success target such as 99%. The
+//! burn rate says how fast the allowed failures are used up: 1.0 uses exactly
+//! the budget over the window, 10 uses it up in a tenth of the window.
+
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Default success target, in percent.
+pub const DEFAULT_TARGET: f64 = 99.0;
+pub const DEFAULT_WINDOW_MINS: u64 = 60;
+/// Window of the fast burn rate, which catches a backend that just went bad
+/// long before its failures show in the long window.
+pub const SHORT_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Burn rate from which a backend is worth pulling from rotation.
+pub const FAST_BURN: f64 = 10.0;
+
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Request outcomes of one backend in one-minute buckets.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorBudget {
+    /// Bucket start, requests and backend failures, oldest first.
+    buckets: VecDeque<(Instant, u32, u32)>,
+}
+
+/// Where a backend stands against its target.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct BudgetStatus {
+    pub requests: u32,
+    pub failures: u32,
+    /// Share of requests over the window that didn't fail, 0.0 to 1.0.
+    pub success_rate: Option<f64>,
+    /// Burn rate over the whole window.
+    pub burn_rate: Option<f64>,
+    /// Burn rate over the last five minutes.
+    pub short_burn_rate: Option<f64>,
+    /// Share of the window's budget still left; negative once overspent.
+    pub remaining: Option<f64>,
+}
+
+impl BudgetStatus {
+    /// The budget is overspent or burning too fast to last.
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining.is_some_and(|r| r <= 0.0) || self.short_burn_rate.is_some_and(|b| b >= FAST_BURN)
+    }
+
+    /// Failures come in faster than the target allows.
+    pub fn is_burning(&self) -> bool {
+        self.burn_rate.is_some_and(|b| b > 1.0) || self.short_burn_rate.is_some_and(|b| b > 1.0)
+    }
+}
+
+impl ErrorBudget {
+    /// Counts a finished request; buckets older than `window` are dropped.
+    pub fn record(&mut self, failed: bool, window: Duration) {
+        let now = Instant::now();
+        while self.buckets.front().is_some_and(|(at, _, _)| now.duration_since(*at) > window) {
+            self.buckets.pop_front();
+        }
+        match self.buckets.back_mut() {
+            Some((at, requests, failures)) if now.duration_since(*at) < BUCKET => {
+                *requests += 1;
+                *failures += failed as u32;
+            }
+            _ => self.buckets.push_back((now, 1, failed as u32)),
+        }
+    }
+
+    fn counts(&self, window: Duration) -> (u32, u32) {
+        let now = Instant::now();
+        self.buckets.iter()
+            .filter(|(at, _, _)| now.duration_since(*at) <= window)
+            .fold((0, 0), |(r, f), (_, requests, failures)| (r + requests, f + failures))
+    }
+
+    /// Success rate and burn rates against `target` percent over `window`.
+    pub fn status(&self, target: f64, window: Duration) -> BudgetStatus {
+        let allowed = 1.0 - target / 100.0;
+        let burn = |(requests, failures): (u32, u32)| {
+            (requests > 0).then(|| failures as f64 / requests as f64 / allowed)
+        };
+        let (requests, failures) = self.counts(window);
+        let burn_rate = burn((requests, failures));
+        BudgetStatus {
+            requests,
+            failures,
+            success_rate: (requests > 0).then(|| 1.0 - failures as f64 / requests as f64),
+            burn_rate,
+            short_burn_rate: burn(self.counts(SHORT_WINDOW.min(window))),
+            remaining: burn_rate.map(|b| 1.0 - b),
+        }
+    }
+}
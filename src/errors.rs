@@ -0,0 +1,130 @@
+//! Per-user error breakdown: instead of a single dropped count, failed and
+//! rejected requests are counted by what went wrong, so support can tell a user
+//! whose client gives up early from one hitting a failing backend or a policy.
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::history::Outcome;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The client went away while queued, waiting or streaming.
+    ClientAbort,
+    /// The backend answered with a 4xx (unknown model, bad request, ...).
+    Backend4xx,
+    /// The backend answered with a 5xx or an unusable response.
+    Backend5xx,
+    /// The backend ran into the request's timeout.
+    Timeout,
+    /// The backend couldn't be reached or the response stream broke off.
+    Connection,
+    /// Turned away by the dispatcher before queuing: request policies, model
+    /// checks, queue caps, cooldowns, load shedding.
+    Rejected,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 6] = [
+        ErrorClass::ClientAbort,
+        ErrorClass::Backend4xx,
+        ErrorClass::Backend5xx,
+        ErrorClass::Timeout,
+        ErrorClass::Connection,
+        ErrorClass::Rejected,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::ClientAbort => "client_abort",
+            ErrorClass::Backend4xx => "backend_4xx",
+            ErrorClass::Backend5xx => "backend_5xx",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Connection => "connection",
+            ErrorClass::Rejected => "rejected",
+        }
+    }
+
+    /// Short label for the dashboard.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ErrorClass::ClientAbort => "abort",
+            ErrorClass::Backend4xx => "4xx",
+            ErrorClass::Backend5xx => "5xx",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Connection => "conn",
+            ErrorClass::Rejected => "rejected",
+        }
+    }
+
+    /// Class of a finished request from its outcome and the backend's status;
+    /// `None` for requests that didn't fail. Timeouts and broken connections
+    /// are told apart where they happen, not here.
+    pub fn from_outcome(outcome: Outcome, status: Option<u16>) -> Option<Self> {
+        match outcome {
+            Outcome::Completed | Outcome::Cancelled | Outcome::Dropped => None,
+            Outcome::Disconnected => Some(ErrorClass::ClientAbort),
+            Outcome::Failed(_) => match status.and_then(|s| StatusCode::from_u16(s).ok()) {
+                Some(s) if s.is_client_error() => Some(ErrorClass::Backend4xx),
+                Some(_) => Some(ErrorClass::Backend5xx),
+                None => Some(ErrorClass::Connection),
+            },
+        }
+    }
+}
+
+/// A user's failed and rejected requests by class.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ErrorCounts {
+    pub client_aborts: u64,
+    pub backend_4xx: u64,
+    pub backend_5xx: u64,
+    pub timeouts: u64,
+    pub connection: u64,
+    pub rejected: u64,
+}
+
+impl ErrorCounts {
+    pub fn get(&self, class: ErrorClass) -> u64 {
+        match class {
+            ErrorClass::ClientAbort => self.client_aborts,
+            ErrorClass::Backend4xx => self.backend_4xx,
+            ErrorClass::Backend5xx => self.backend_5xx,
+            ErrorClass::Timeout => self.timeouts,
+            ErrorClass::Connection => self.connection,
+            ErrorClass::Rejected => self.rejected,
+        }
+    }
+
+    pub fn add(&mut self, class: ErrorClass, n: u64) {
+        let count = match class {
+            ErrorClass::ClientAbort => &mut self.client_aborts,
+            ErrorClass::Backend4xx => &mut self.backend_4xx,
+            ErrorClass::Backend5xx => &mut self.backend_5xx,
+            ErrorClass::Timeout => &mut self.timeouts,
+            ErrorClass::Connection => &mut self.connection,
+            ErrorClass::Rejected => &mut self.rejected,
+        };
+        *count += n;
+    }
+
+    pub fn absorb(&mut self, other: &ErrorCounts) {
+        for class in ErrorClass::ALL {
+            self.add(class, other.get(class));
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        ErrorClass::ALL.iter().map(|c| self.get(*c)).sum()
+    }
+
+    /// `2 abort, 1 timeout` for the classes seen, `none` without errors.
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = ErrorClass::ALL.iter()
+            .filter(|c| self.get(**c) > 0)
+            .map(|c| format!("{} {}", self.get(*c), c.label()))
+            .collect();
+        if parts.is_empty() { "none".to_string() } else { parts.join(", ") }
+    }
+}
@@ -0,0 +1,74 @@
+//! Dispatcher events (requests finishing, blocks, backend state changes)
+//! broadcast to in-process subscribers such as automation scripts.
+
+use serde_json::{Value, json};
+
+use crate::history::{HistoryEntry, Outcome};
+use crate::ip::IpPrefix;
+use crate::scheduler_trace::Decision;
+use crate::slo::SloStatus;
+
+/// Events buffered per subscriber before the slowest one starts missing some.
+pub const EVENT_BUFFER: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// A request finished, successfully or not.
+    Finished(Box<HistoryEntry>),
+    Blocked { user: Option<String>, ip: Option<IpPrefix> },
+    Unblocked { user: Option<String>, ip: Option<IpPrefix> },
+    /// A backend went online or offline.
+    Backend { url: String, online: bool },
+    /// A tier's latency objective became violated or was met again.
+    Slo(Box<SloStatus>),
+    /// A dispatch decision, with `--scheduler-trace`.
+    Schedule(Box<Decision>),
+}
+
+impl Event {
+    /// Event name as seen by subscribers: `completed`, `failed`, `dropped`
+    /// (also covers cancelled and disconnected requests), `blocked`,
+    /// `unblocked`, `backend`, `slo` or `schedule`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Finished(entry) => match entry.outcome {
+                Outcome::Completed => "completed",
+                Outcome::Failed(_) => "failed",
+                Outcome::Dropped | Outcome::Cancelled | Outcome::Disconnected => "dropped",
+            },
+            Event::Blocked { .. } => "blocked",
+            Event::Unblocked { .. } => "unblocked",
+            Event::Backend { .. } => "backend",
+            Event::Slo(_) => "slo",
+            Event::Schedule(_) => "schedule",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = match self {
+            Event::Finished(e) => json!({
+                "task_id": e.task_id,
+                "user": e.user_id,
+                "path": e.path,
+                "model": e.model,
+                "backend": e.backend,
+                "outcome": e.outcome.as_str(),
+                "status": e.status,
+                "queue_ms": e.queue_wait.as_millis() as u64,
+                "duration_ms": e.duration.as_millis() as u64,
+                "prompt_tokens": e.prompt_tokens,
+                "completion_tokens": e.completion_tokens,
+                "detail": e.detail,
+            }),
+            Event::Blocked { user, ip } | Event::Unblocked { user, ip } => json!({
+                "user": user,
+                "ip": ip.map(|ip| ip.to_string()),
+            }),
+            Event::Backend { url, online } => json!({ "url": url, "online": online }),
+            Event::Slo(status) => serde_json::to_value(status).unwrap_or_default(),
+            Event::Schedule(decision) => serde_json::to_value(decision).unwrap_or_default(),
+        };
+        value["event"] = json!(self.name());
+        value
+    }
+}
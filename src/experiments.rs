@@ -0,0 +1,203 @@
+//! A/B experiments on real traffic. Each user is assigned to one arm of an
+//! experiment for good (by a hash of experiment name and user id), the arm's
+//! model or backend serves their requests, and latency, tokens and errors are
+//! kept per arm so the arms can be compared.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use crate::config::FileConfig;
+use crate::dispatcher::FailureKind;
+use crate::history::Outcome;
+use crate::timings::{StageStats, StageTiming};
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// An experiment under `experiments` in the config file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentConfig {
+    /// Requested model the experiment applies to; every model when unset.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Routes the experiment applies to; empty means all.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Arms by name, e.g. `control` and `candidate`.
+    pub arms: BTreeMap<String, ArmConfig>,
+}
+
+/// One arm of an experiment. An arm without `model` or `backend` serves
+/// requests as sent, as a control group.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ArmConfig {
+    /// Model sent to the backend instead of the requested one.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Backend the arm's requests go to while it is online.
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Share of users assigned to the arm, relative to the other arms.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+impl ExperimentConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.arms.len() < 2 {
+            return Err("needs at least two arms".to_string());
+        }
+        if self.arms.values().all(|a| a.weight == 0) {
+            return Err("every arm has weight 0".to_string());
+        }
+        if let Some((name, _)) = self.arms.iter().find(|(_, a)| a.model.as_deref().is_some_and(|m| m.trim().is_empty())) {
+            return Err(format!("arm '{}' has an empty model", name));
+        }
+        Ok(())
+    }
+
+    fn applies(&self, path: &str, model: &str) -> bool {
+        (self.routes.is_empty() || self.routes.iter().any(|r| r == path))
+            && self.model.as_ref().is_none_or(|x| crate::dispatcher::model_name_matches(model, x))
+    }
+
+    /// The arm of `user_id`, stable across restarts as long as the arms and
+    /// their weights stay the same.
+    fn arm_of(&self, name: &str, user_id: &str) -> Option<(&String, &ArmConfig)> {
+        let total: u64 = self.arms.values().map(|a| a.weight as u64).sum();
+        let mut slot = stable_hash(format!("{}\0{}", name, user_id).as_bytes()) % total.max(1);
+        for (arm, config) in &self.arms {
+            if slot < config.weight as u64 {
+                return Some((arm, config));
+            }
+            slot -= config.weight as u64;
+        }
+        None
+    }
+}
+
+/// FNV-1a with a final mix, so ids differing only in their last character
+/// (`user1`, `user2`) still spread evenly over the arms.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut h = bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3));
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+/// The arm a request was assigned to.
+#[derive(Clone, Debug)]
+pub struct Assignment {
+    pub experiment: String,
+    pub arm: String,
+    /// Backend URL the request should go to, if the arm names one.
+    pub backend: Option<String>,
+}
+
+/// Assigns a request for a model to an arm of the first experiment (by name)
+/// that applies to it. Returns the assignment and, when the arm changes the
+/// model, the rewritten body.
+pub fn assign(config: &FileConfig, user_id: &str, path: &str, body: &[u8]) -> Option<(Assignment, Option<Vec<u8>>)> {
+    if config.experiments.is_empty() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let model = json.get("model").and_then(Value::as_str)?.to_string();
+    let mut names: Vec<&String> = config.experiments.keys().collect();
+    names.sort();
+    let (name, experiment) = names.into_iter()
+        .map(|name| (name, &config.experiments[name]))
+        .find(|(_, e)| e.applies(path, &model))?;
+    let (arm, arm_config) = experiment.arm_of(name, user_id)?;
+    let assignment = Assignment {
+        experiment: name.clone(),
+        arm: arm.clone(),
+        backend: arm_config.backend.as_deref().map(crate::dispatcher::normalize_backend_url),
+    };
+    let rewritten = match arm_config.model {
+        Some(ref m) if *m != model => {
+            json["model"] = Value::String(m.clone());
+            Some(serde_json::to_vec(&json).ok()?)
+        }
+        _ => None,
+    };
+    Some((assignment, rewritten))
+}
+
+/// The assignment of a request queued by another instance, if its experiment
+/// and arm are still configured here.
+pub fn lookup(config: &FileConfig, experiment: &str, arm: &str) -> Option<Assignment> {
+    let arm_config = config.experiments.get(experiment)?.arms.get(arm)?;
+    Some(Assignment {
+        experiment: experiment.to_string(),
+        arm: arm.to_string(),
+        backend: arm_config.backend.as_deref().map(crate::dispatcher::normalize_backend_url),
+    })
+}
+
+/// What one arm has served so far.
+#[derive(Default)]
+pub struct ArmStats {
+    pub users: HashSet<String>,
+    pub requests: u64,
+    pub completed: u64,
+    /// Failures caused by the backend (5xx, connection errors, interrupted streams).
+    pub failed: u64,
+    /// Requests the backend rejected (4xx).
+    pub rejected: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Time completed requests spent on a backend.
+    pub backend_time: Duration,
+    pub timings: StageStats,
+}
+
+impl ArmStats {
+    /// Share of dispatched requests that failed on the backend.
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 { 0.0 } else { self.failed as f64 / self.requests as f64 }
+    }
+
+    /// Completion tokens per second of backend time.
+    pub fn tokens_per_sec(&self) -> Option<f64> {
+        (!self.backend_time.is_zero()).then(|| self.completion_tokens as f64 / self.backend_time.as_secs_f64())
+    }
+}
+
+/// Results per (experiment, arm), since startup.
+#[derive(Default)]
+pub struct ExperimentResults {
+    pub arms: BTreeMap<(String, String), ArmStats>,
+}
+
+impl ExperimentResults {
+    /// Records a request that reached a backend; `timing` is set when it completed.
+    pub fn record(&mut self, a: &Assignment, user_id: &str, outcome: Outcome, timing: Option<StageTiming>, tokens: (Option<u64>, Option<u64>)) {
+        let stats = self.arms.entry((a.experiment.clone(), a.arm.clone())).or_default();
+        if !stats.users.contains(user_id) {
+            stats.users.insert(user_id.to_string());
+        }
+        stats.requests += 1;
+        match outcome {
+            Outcome::Completed => stats.completed += 1,
+            Outcome::Failed(FailureKind::Backend) => stats.failed += 1,
+            Outcome::Failed(FailureKind::Client) => stats.rejected += 1,
+            _ => {}
+        }
+        stats.prompt_tokens += tokens.0.unwrap_or(0);
+        stats.completion_tokens += tokens.1.unwrap_or(0);
+        if let Some(timing) = timing {
+            stats.backend_time += timing.first_chunk + timing.streaming;
+            stats.timings.record(timing);
+        }
+    }
+}
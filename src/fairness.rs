@@ -0,0 +1,138 @@
+//! Realized share of service per user: every dispatch is counted in short
+//! time buckets together with the users that were competing for it, so the
+//! share each user actually got can be compared with an equal split among
+//! the users waiting at the time.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_WINDOW_MINS: u64 = 10;
+/// Buckets the window is split into.
+pub const BUCKETS: u64 = 60;
+
+#[derive(Default)]
+struct Bucket {
+    dispatched: HashMap<String, u64>,
+    /// Users with work ready to dispatch at any dispatch in the bucket.
+    contenders: HashSet<String>,
+    /// Sum over dispatches of `1 / users competing`, per user: the share an
+    /// equal split would have given them.
+    fair: HashMap<String, f64>,
+}
+
+pub struct ShareTimeline {
+    started: Instant,
+    width: Duration,
+    /// Buckets by index since `started`, oldest first.
+    buckets: VecDeque<(u64, Bucket)>,
+}
+
+/// One user's service over the window.
+#[derive(Serialize, Clone, Debug)]
+pub struct UserShare {
+    pub user_id: String,
+    pub dispatched: u64,
+    /// Fraction of all dispatches in the window.
+    pub share: f64,
+    /// Dispatches an equal split among competing users would have given.
+    pub fair_dispatches: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BucketReport {
+    /// Seconds between the start of the bucket and now.
+    pub ago_secs: u64,
+    pub dispatched: BTreeMap<String, u64>,
+    pub contenders: usize,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct FairnessReport {
+    pub window_secs: u64,
+    pub bucket_secs: u64,
+    pub total: u64,
+    /// Most served first.
+    pub users: Vec<UserShare>,
+    /// Every bucket of the window, oldest first, empty ones included.
+    pub buckets: Vec<BucketReport>,
+}
+
+impl ShareTimeline {
+    pub fn new(window: Duration) -> Self {
+        let width = (window / BUCKETS as u32).max(Duration::from_secs(1));
+        Self { started: Instant::now(), width, buckets: VecDeque::new() }
+    }
+
+    fn index(&self, at: Instant) -> u64 {
+        (at.duration_since(self.started).as_millis() / self.width.as_millis()) as u64
+    }
+
+    /// Counts a dispatch to `user_id` while `contenders` had work ready.
+    pub fn record(&mut self, user_id: &str, contenders: &[String]) {
+        let index = self.index(Instant::now());
+        while self.buckets.front().is_some_and(|(i, _)| i + BUCKETS <= index) {
+            self.buckets.pop_front();
+        }
+        if self.buckets.back().is_none_or(|(i, _)| *i != index) {
+            self.buckets.push_back((index, Bucket::default()));
+        }
+        let bucket = &mut self.buckets.back_mut().unwrap().1;
+        *bucket.dispatched.entry(user_id.to_string()).or_insert(0) += 1;
+        let fair = 1.0 / contenders.len().max(1) as f64;
+        for user in contenders {
+            bucket.contenders.insert(user.clone());
+            *bucket.fair.entry(user.clone()).or_insert(0.0) += fair;
+        }
+    }
+
+    pub fn report(&self) -> FairnessReport {
+        let elapsed = self.started.elapsed().as_millis() as i64;
+        let width = self.width.as_millis() as i64;
+        let now = elapsed / width;
+        let mut dispatched: HashMap<&str, u64> = HashMap::new();
+        let mut fair: HashMap<&str, f64> = HashMap::new();
+        let mut buckets = Vec::new();
+        // Buckets before startup are reported empty, so the timeline always has the same length
+        for index in now + 1 - BUCKETS as i64..=now {
+            let bucket = self.buckets.iter().find(|(i, _)| *i as i64 == index).map(|(_, b)| b);
+            if let Some(b) = bucket {
+                for (user, n) in &b.dispatched {
+                    *dispatched.entry(user).or_insert(0) += n;
+                }
+                for (user, f) in &b.fair {
+                    *fair.entry(user).or_insert(0.0) += f;
+                }
+            }
+            buckets.push(BucketReport {
+                ago_secs: ((elapsed - index * width) / 1000) as u64,
+                dispatched: bucket.map(|b| b.dispatched.iter().map(|(u, n)| (u.clone(), *n)).collect()).unwrap_or_default(),
+                contenders: bucket.map_or(0, |b| b.contenders.len()),
+            });
+        }
+        let total: u64 = dispatched.values().sum();
+        let mut users: Vec<UserShare> = fair.keys().chain(dispatched.keys())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|user| {
+                let n = dispatched.get(user).copied().unwrap_or(0);
+                UserShare {
+                    user_id: user.to_string(),
+                    dispatched: n,
+                    share: if total == 0 { 0.0 } else { n as f64 / total as f64 },
+                    fair_dispatches: fair.get(user).copied().unwrap_or(0.0),
+                }
+            })
+            .collect();
+        users.sort_by(|a, b| b.dispatched.cmp(&a.dispatched).then_with(|| a.user_id.cmp(&b.user_id)));
+        FairnessReport {
+            window_secs: (self.width * BUCKETS as u32).as_secs(),
+            bucket_secs: self.width.as_secs(),
+            total,
+            users,
+            buckets,
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Fallback models from `model_fallbacks` in the config file: when a backend
+//! can't serve the requested model (not found, out of memory), the request is
+//! queued again for the next model of its chain, and the response names the
+//! substitution in `X-Model-Fallback`.
+
+use axum::{body::Bytes, http::StatusCode};
+use futures_util::StreamExt;
+use serde_json::Value;
+
+use crate::config::FileConfig;
+use crate::dispatcher::{Task, model_name_matches};
+
+/// Response header naming a substitution, e.g. `llama3.1:70b -> llama3.1:8b`.
+pub const HEADER: &str = "X-Model-Fallback";
+/// Bytes of an error answer read to tell why it failed.
+const ERROR_LIMIT: usize = 64 * 1024;
+
+/// The first model after `current` in the fallback chain of `original` that
+/// `available` accepts; `None` at the end of the chain or without one.
+pub fn next_model(config: &FileConfig, original: &str, current: &str, available: impl Fn(&str) -> bool) -> Option<String> {
+    let chain = config.model_fallbacks.iter()
+        .find(|(model, _)| model_name_matches(original, model))
+        .map(|(_, chain)| chain)?;
+    let start = if model_name_matches(current, original) {
+        0
+    } else {
+        chain.iter().position(|m| model_name_matches(current, m))? + 1
+    };
+    chain[start..].iter().find(|m| available(m)).cloned()
+}
+
+/// Switches a task to `model`, remembering what it asked for first. `false`
+/// when its body has no JSON object to rewrite.
+pub fn apply(task: &mut Task, model: &str) -> bool {
+    let Some(body) = substitute(&task.stored_body(), model) else {
+        return false;
+    };
+    if task.fallback_from.is_none() {
+        task.fallback_from = task.requested_model.clone();
+    }
+    task.body = body;
+    task.spooled = None;
+    task.requested_model = Some(model.to_string());
+    task.headers.remove(axum::http::header::CONTENT_LENGTH);
+    true
+}
+
+/// Value of `HEADER` for a task that was switched from `from` to `to`.
+pub fn describe(from: &str, to: &str) -> String {
+    format!("{} -> {}", from, to)
+}
+
+/// `body` asking for `model` instead.
+pub fn substitute(body: &[u8], model: &str) -> Option<Bytes> {
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    json.as_object_mut()?.insert("model".to_string(), Value::String(model.to_string()));
+    serde_json::to_vec(&json).ok().map(Bytes::from)
+}
+
+/// Whether an answer with `status` may be a model failure; `model_failure` reads it.
+pub fn may_be_model_failure(status: StatusCode) -> bool {
+    status == StatusCode::NOT_FOUND || status.is_server_error()
+}
+
+/// Reads the start of an error answer and tells whether it says the model is
+/// missing or doesn't fit in memory; the answer comes back intact either way.
+pub async fn model_failure(mut response: reqwest::Response) -> (Option<String>, reqwest::Response) {
+    let status = response.status();
+    let mut head = Vec::new();
+    let mut ended = false;
+    while head.len() < ERROR_LIMIT {
+        match response.chunk().await {
+            Ok(Some(chunk)) => head.extend_from_slice(&chunk),
+            _ => {
+                ended = true;
+                break;
+            }
+        }
+    }
+    let text = String::from_utf8_lossy(&head).to_lowercase();
+    let missing = status == StatusCode::NOT_FOUND && text.contains("model") && text.contains("not found");
+    let out_of_memory = status.is_server_error() && (text.contains("out of memory") || text.contains("system memory"));
+    let reason = match (ended, missing, out_of_memory) {
+        (true, true, _) => Some(format!("model not found ({})", status)),
+        (true, _, true) => Some(format!("out of memory ({})", status)),
+        _ => None,
+    };
+
+    let mut rebuilt = axum::http::Response::builder().status(status);
+    if let Some(headers) = rebuilt.headers_mut() {
+        *headers = response.headers().clone();
+    }
+    let rest = futures_util::stream::iter([Ok(Bytes::from(head))]).chain(response.bytes_stream());
+    let body = reqwest::Body::wrap_stream(rest);
+    (reason, reqwest::Response::from(rebuilt.body(body).expect("status and headers come from a valid response")))
+}
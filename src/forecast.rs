@@ -0,0 +1,150 @@
+//! Demand forecast per model: requests per hour of the day, averaged over the
+//! last weeks of traffic (read from `--usage-log` at startup, then counted
+//! live), and the load that gives for the coming hour. Meant as a hint for
+//! scheduling maintenance or preloading models, not as a prediction.
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Timelike};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
+use tracing::{info, warn};
+
+use crate::dispatcher::AppState;
+use crate::usage::{self, UsageRecord};
+
+/// Days of traffic the curves are averaged over.
+pub const LOOKBACK_DAYS: i64 = 28;
+
+/// Requests per model in each hour of one day.
+type DayCounts = HashMap<String, [u64; 24]>;
+
+pub struct DemandCurves {
+    days: BTreeMap<NaiveDate, DayCounts>,
+    /// Usage log lines from this time on were counted live already.
+    live_since: DateTime<Local>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ModelForecast {
+    pub model: String,
+    /// Requests expected over the next 60 minutes.
+    pub next_hour: f64,
+    /// Average requests in each local hour of the day, from midnight.
+    pub per_hour: Vec<f64>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Forecast {
+    /// Full days of traffic the averages are based on, up to `LOOKBACK_DAYS`.
+    pub days: usize,
+    pub next_hour: f64,
+    /// Busiest first over the next hour.
+    pub models: Vec<ModelForecast>,
+}
+
+impl Default for DemandCurves {
+    fn default() -> Self {
+        Self { days: BTreeMap::new(), live_since: Local::now() }
+    }
+}
+
+impl DemandCurves {
+    pub fn record(&mut self, model: &str, at: DateTime<Local>) {
+        let date = at.date_naive();
+        let counts = self.days.entry(date).or_default().entry(model.to_string()).or_insert([0; 24]);
+        counts[at.hour() as usize] += 1;
+        let oldest = date - chrono::Duration::days(LOOKBACK_DAYS);
+        self.days.retain(|d, _| *d > oldest);
+    }
+
+    /// Expected demand at `now`. Each hour of the day is averaged over the
+    /// calendar days since the first request (quiet days included), today's
+    /// hours once they are over. The next hour blends the rest of the current
+    /// hour of the day with the start of the following one.
+    pub fn forecast(&self, now: DateTime<Local>) -> Forecast {
+        let today = now.date_naive();
+        let hour = now.hour() as usize;
+        let first = self.days.keys().next().copied().unwrap_or(today);
+        let past_days = (today - first).num_days().clamp(0, LOOKBACK_DAYS) as usize;
+        let oldest = today - chrono::Duration::days(past_days as i64);
+        let mut totals: HashMap<&str, [u64; 24]> = HashMap::new();
+        for (date, day) in self.days.range(oldest..) {
+            for (model, counts) in day {
+                let total = totals.entry(model).or_insert([0; 24]);
+                for (h, (t, n)) in total.iter_mut().zip(counts).enumerate() {
+                    if *date < today || h < hour {
+                        *t += n;
+                    }
+                }
+            }
+        }
+        let into_hour = now.minute() as f64 / 60.0;
+        let mut models: Vec<ModelForecast> = totals.into_iter()
+            .map(|(model, counts)| {
+                let per_hour: Vec<f64> = counts.iter().enumerate()
+                    .map(|(h, n)| {
+                        let days = past_days + usize::from(h < hour);
+                        if days == 0 { 0.0 } else { *n as f64 / days as f64 }
+                    })
+                    .collect();
+                ModelForecast {
+                    model: model.to_string(),
+                    next_hour: per_hour[hour] * (1.0 - into_hour) + per_hour[(hour + 1) % 24] * into_hour,
+                    per_hour,
+                }
+            })
+            .filter(|m| m.per_hour.iter().any(|n| *n > 0.0))
+            .collect();
+        models.sort_by(|a, b| b.next_hour.total_cmp(&a.next_hour).then_with(|| a.model.cmp(&b.model)));
+        Forecast {
+            days: past_days,
+            next_hour: models.iter().map(|m| m.next_hour).sum(),
+            models,
+        }
+    }
+}
+
+/// Reads the requests of the last `LOOKBACK_DAYS` finished before `until`
+/// from the usage log.
+fn read_usage_log(path: &Path, until: DateTime<Local>) -> std::io::Result<Vec<(String, DateTime<Local>)>> {
+    let oldest = until - chrono::Duration::days(LOOKBACK_DAYS);
+    let mut requests = Vec::new();
+    for line in usage::lines(path)? {
+        let line = line?;
+        let Ok(record) = serde_json::from_str::<UsageRecord>(&line) else {
+            continue;
+        };
+        let (Some(model), Ok(at)) = (record.model, DateTime::<FixedOffset>::parse_from_rfc3339(&record.ts)) else {
+            continue;
+        };
+        let at = at.with_timezone(&Local);
+        if at > oldest && at < until {
+            requests.push((model, at));
+        }
+    }
+    Ok(requests)
+}
+
+/// Seeds the demand curves from `--usage-log`, if there is one.
+pub async fn load_history(state: Arc<AppState>) {
+    let Some(path) = state.config.usage_log.clone() else {
+        return;
+    };
+    let until = state.forecast.lock().unwrap().live_since;
+    let read = tokio::task::spawn_blocking(move || read_usage_log(&path, until)).await;
+    match read {
+        Ok(Ok(requests)) => {
+            let mut curves = state.forecast.lock().unwrap();
+            for (model, at) in &requests {
+                curves.record(model, *at);
+            }
+            info!("Demand forecast based on {} request(s) over {} day(s) of usage log", requests.len(), curves.days.len());
+        }
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Ok(Err(e)) => warn!("Cannot read the usage log for the demand forecast: {}", e),
+        Err(e) => warn!("Reading the usage log for the demand forecast failed: {}", e),
+    }
+}
@@ -0,0 +1,159 @@
+//! Zero-downtime upgrades. The listening socket can come from systemd socket
+//! activation or from the previous ollamaMQ process, which hands it over when
+//! it receives SIGUSR2: it starts its own binary again with the socket
+//! inherited, stops accepting once the successor is up and drains its queues.
+//! With `--reuse-port`, a new process can also bind the port next to a running
+//! one on its own.
+
+use std::{io, net::SocketAddr};
+use tracing::warn;
+
+/// Descriptor of a listening socket handed over by the previous process.
+pub const LISTEN_FD_ENV: &str = "OLLAMAMQ_LISTEN_FD";
+/// First descriptor passed by systemd socket activation.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+/// How long a successor has to survive before the old process stops accepting.
+#[cfg(unix)]
+const STARTUP_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Where the listening socket came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListenerSource {
+    Bound,
+    /// Handed over by the previous ollamaMQ process.
+    Handover,
+    /// Passed by systemd socket activation.
+    Systemd,
+}
+
+/// Opens the listening socket: an inherited one if present, otherwise a new
+/// one bound to `addr`, with `SO_REUSEPORT` if `reuse_port` is set.
+pub fn listen(addr: SocketAddr, reuse_port: bool) -> io::Result<(tokio::net::TcpListener, ListenerSource)> {
+    let (listener, source) = match inherited()? {
+        Some((listener, source)) => (listener, source),
+        None => (bind(addr, reuse_port)?, ListenerSource::Bound),
+    };
+    listener.set_nonblocking(true)?;
+    Ok((tokio::net::TcpListener::from_std(listener)?, source))
+}
+
+fn bind(addr: SocketAddr, reuse_port: bool) -> io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, Some(socket2::Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(unix))]
+    if reuse_port {
+        warn!("--reuse-port is only supported on Unix, binding without it");
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn inherited() -> io::Result<Option<(std::net::TcpListener, ListenerSource)>> {
+    use std::os::fd::{FromRawFd, RawFd};
+
+    let fd_from = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<RawFd>().ok());
+    let (fd, source) = if let Some(fd) = fd_from(LISTEN_FD_ENV) {
+        (fd, ListenerSource::Handover)
+    } else if fd_from("LISTEN_PID") == Some(std::process::id() as RawFd) && fd_from("LISTEN_FDS").is_some_and(|n| n >= 1) {
+        (SD_LISTEN_FDS_START, ListenerSource::Systemd)
+    } else {
+        return Ok(None);
+    };
+    // SAFETY: the descriptor was passed to this process to be owned by it and
+    // nothing else in the process uses it.
+    let socket = unsafe { socket2::Socket::from_raw_fd(fd) };
+    if socket.local_addr()?.as_socket().is_none() || !socket.is_listener()? {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("inherited descriptor {} is not a listening TCP socket", fd)));
+    }
+    // Processes started later (event scripts, the next successor) mustn't keep it open by accident
+    socket.set_cloexec(true)?;
+    Ok(Some((socket.into(), source)))
+}
+
+#[cfg(not(unix))]
+fn inherited() -> io::Result<Option<(std::net::TcpListener, ListenerSource)>> {
+    Ok(None)
+}
+
+/// Resolves at the first SIGUSR2; never on other platforms.
+pub async fn upgrade_signal() {
+    #[cfg(unix)]
+    {
+        let mut usr2 = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined2()).expect("SIGUSR2 handler");
+        usr2.recv().await;
+    }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await;
+}
+
+/// A second descriptor of the listening socket, kept to pass it to a
+/// successor while the server owns the listener.
+pub struct Handover {
+    #[cfg(unix)]
+    socket: socket2::Socket,
+}
+
+impl Handover {
+    #[cfg(unix)]
+    pub fn new(listener: &tokio::net::TcpListener) -> io::Result<Self> {
+        use std::os::fd::AsFd;
+        Ok(Self { socket: listener.as_fd().try_clone_to_owned()?.into() })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(_listener: &tokio::net::TcpListener) -> io::Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Waits for SIGUSR2 and hands the socket over, trying again at the next
+    /// signal if the successor fails to start. Returns once a successor took
+    /// over; the old process should then stop accepting and drain.
+    pub async fn on_signal(self) {
+        loop {
+            upgrade_signal().await;
+            #[cfg(unix)]
+            match self.spawn_successor().await {
+                Ok(pid) => {
+                    tracing::info!("Handed the listening socket over to process {}, draining", pid);
+                    return;
+                }
+                Err(e) => warn!("Upgrade failed, still serving: {}", e),
+            }
+        }
+    }
+
+    /// Starts this binary again with the same arguments and the socket, and
+    /// returns once the successor survived its first seconds. The binary is
+    /// looked up by the name it was started with, so a binary replaced on
+    /// disk is the one that takes over.
+    #[cfg(unix)]
+    async fn spawn_successor(&self) -> io::Result<u32> {
+        use std::os::fd::AsRawFd;
+
+        let mut args: Vec<_> = std::env::args_os().collect();
+        if args.is_empty() {
+            return Err(io::Error::other("unknown program name"));
+        }
+        let program = args.remove(0);
+        self.socket.set_cloexec(false)?;
+        let spawned = tokio::process::Command::new(program)
+            .args(args)
+            .env(LISTEN_FD_ENV, self.socket.as_raw_fd().to_string())
+            .env_remove("LISTEN_FDS")
+            .env_remove("LISTEN_PID")
+            .spawn();
+        self.socket.set_cloexec(true)?;
+        let mut child = spawned?;
+        let pid = child.id().unwrap_or_default();
+        tracing::info!("Started successor process {} on the listening socket", pid);
+        tokio::select! {
+            status = child.wait() => Err(io::Error::other(format!("successor {} exited during startup ({})", pid, status?))),
+            _ = tokio::time::sleep(STARTUP_GRACE) => Ok(pid),
+        }
+    }
+}
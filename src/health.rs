@@ -0,0 +1,34 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use std::sync::Arc;
+
+use crate::dispatcher::AppState;
+
+/// Why the dispatcher should not receive more traffic, if it is saturated.
+pub fn not_ready_reason(state: &AppState) -> Option<String> {
+    if let Some(max) = state.config.ready_max_queued {
+        let queued = state.total_queued();
+        if queued > max {
+            return Some(format!("{} requests queued (limit {})", queued, max));
+        }
+    }
+    if let Some(max_secs) = state.config.ready_max_wait_secs
+        && let Some(wait) = state.estimated_wait()
+        && wait.as_secs() > max_secs
+    {
+        return Some(if wait == std::time::Duration::MAX {
+            "no backend online".to_string()
+        } else {
+            format!("estimated wait {}s (limit {}s)", wait.as_secs(), max_secs)
+        });
+    }
+    None
+}
+
+/// Readiness probe for load balancers: `200 OK`, or `503` once the configured
+/// queue depth or estimated wait threshold is exceeded.
+pub async fn health_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match not_ready_reason(&state) {
+        None => (StatusCode::OK, "OK".to_string()),
+        Some(reason) => (StatusCode::SERVICE_UNAVAILABLE, format!("NOT READY: {}", reason)),
+    }
+}
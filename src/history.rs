@@ -0,0 +1,146 @@
+//! Ring buffer of recently finished requests, browsed in the TUI history view.
+
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Local};
+use serde_json::Value;
+use std::{collections::VecDeque, time::Duration};
+
+use crate::dispatcher::FailureKind;
+use crate::hooks::usage_counts;
+
+/// Bytes kept from the end of a response to find its token counts.
+const USAGE_TAIL: usize = 8 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    Failed(FailureKind),
+    /// The client went away while the response was streaming.
+    Disconnected,
+    /// Dropped before reaching a backend (client gone, blocked, no backend online).
+    Dropped,
+    /// Cancelled through the queue API or by an admin.
+    Cancelled,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Completed => "completed",
+            Outcome::Failed(FailureKind::Client) => "failed (client)",
+            Outcome::Failed(FailureKind::Backend) => "failed (backend)",
+            Outcome::Disconnected => "disconnected",
+            Outcome::Dropped => "dropped",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// One finished request.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub task_id: String,
+    pub user_id: String,
+    pub path: String,
+    pub model: Option<String>,
+    pub backend: Option<String>,
+    pub outcome: Outcome,
+    /// HTTP status returned by the backend, if it answered.
+    pub status: Option<u16>,
+    pub queue_wait: Duration,
+    /// Time spent on the backend (zero when never dispatched).
+    pub duration: Duration,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    pub finished_at: DateTime<Local>,
+    /// Why the request failed or was dropped.
+    pub detail: Option<String>,
+}
+
+impl HistoryEntry {
+    /// An entry for a request that never reached a backend.
+    pub fn unsent(task_id: &str, user_id: &str, path: &str, model: Option<String>, queue_wait: Duration, outcome: Outcome, detail: &str) -> Self {
+        Self {
+            task_id: task_id.to_string(),
+            user_id: user_id.to_string(),
+            path: path.to_string(),
+            model,
+            backend: None,
+            outcome,
+            status: None,
+            queue_wait,
+            duration: Duration::ZERO,
+            prompt_tokens: None,
+            completion_tokens: None,
+            finished_at: Local::now(),
+            detail: Some(detail.to_string()),
+        }
+    }
+}
+
+/// The last `capacity` finished requests, oldest first.
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity.min(1024)),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries, newest first.
+    pub fn newest_first(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev()
+    }
+}
+
+/// Keeps the end of a response body so the token counts of its final event
+/// (Ollama `eval_count`, OpenAI `usage`) can be read once it has finished.
+#[derive(Default)]
+pub struct UsageTail {
+    tail: BytesMut,
+}
+
+impl UsageTail {
+    pub fn push(&mut self, chunk: &Bytes) {
+        self.tail.extend_from_slice(chunk);
+        if self.tail.len() > USAGE_TAIL {
+            let _ = self.tail.split_to(self.tail.len() - USAGE_TAIL);
+        }
+    }
+
+    /// (prompt, completion) tokens from the last JSON line or SSE event that reports them.
+    pub fn counts(&self) -> (Option<u64>, Option<u64>) {
+        self.tail[..].split(|&b| b == b'\n')
+            .rev()
+            .filter_map(|line| {
+                let line = line.strip_prefix(b"data:").unwrap_or(line);
+                serde_json::from_slice::<Value>(line.trim_ascii()).ok()
+            })
+            .map(|value| usage_counts(&value))
+            .find(|(prompt, completion)| prompt.is_some() || completion.is_some())
+            .unwrap_or((None, None))
+    }
+}
@@ -0,0 +1,483 @@
+use bytes::{Bytes, BytesMut};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Value, json};
+use std::{collections::HashMap, time::Instant};
+
+/// Default number of trailing characters `redact` holds back per text field, so a
+/// match split across streamed chunks is still caught.
+const REDACT_HOLDBACK: usize = 64;
+
+/// A post-processing step applied to backend responses.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookKind {
+    /// Remove `<think>...</think>` blocks and `thinking` fields from generated text.
+    StripThink,
+    /// Report the model name the client asked for instead of the backend's name.
+    RewriteModel,
+    /// Append a `usage_summary` event with token counts and duration.
+    UsageSummary,
+    /// Replace text matching `patterns` in generated text, across chunk boundaries.
+    Redact,
+}
+
+/// A regular expression compiled when the config file is loaded.
+#[derive(Clone, Debug)]
+pub struct Pattern(pub Regex);
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Regex::new(&source)
+            .map(Pattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid pattern '{}': {}", source, e)))
+    }
+}
+
+/// One hook entry under `response_hooks` in the config file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseHookConfig {
+    pub hook: HookKind,
+    /// Routes the hook applies to; empty means all.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Requested models the hook applies to; empty means all.
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Regular expressions masked by `redact`.
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+    /// Text substituted for each `redact` match (default `[REDACTED]`).
+    #[serde(default)]
+    pub replacement: Option<String>,
+    /// Trailing characters held back per text field while streaming; must be at
+    /// least the longest expected match (default 64).
+    #[serde(default)]
+    pub holdback: Option<usize>,
+}
+
+impl ResponseHookConfig {
+    /// Checks that hook-specific settings are only given where they apply.
+    pub fn validate(&self) -> Result<(), String> {
+        let redact_fields = !self.patterns.is_empty() || self.replacement.is_some() || self.holdback.is_some();
+        match self.hook {
+            HookKind::Redact if self.patterns.is_empty() => Err("redact hook needs at least one pattern".to_string()),
+            HookKind::Redact => Ok(()),
+            _ if redact_fields => Err("patterns, replacement and holdback only apply to the redact hook".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn applies(&self, path: &str, model: Option<&str>) -> bool {
+        (self.routes.is_empty() || self.routes.iter().any(|r| r == path))
+            && (self.models.is_empty() || model.is_some_and(|m| self.models.iter().any(|x| crate::dispatcher::model_name_matches(m, x))))
+    }
+}
+
+/// How the response body is framed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Framing {
+    Ndjson,
+    Sse,
+    Json,
+}
+
+/// Request details hooks may need.
+pub struct HookContext {
+    pub requested_model: Option<String>,
+    pub backend_url: String,
+    pub started: Instant,
+}
+
+/// Applies the configured hooks to one response, event by event.
+pub struct ResponseTransformer {
+    hooks: Vec<HookKind>,
+    /// One per `Redact` entry in `hooks`, in the same order.
+    redactors: Vec<Redactor>,
+    framing: Framing,
+    ctx: HookContext,
+    pending: BytesMut,
+    in_think: bool,
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+}
+
+impl ResponseTransformer {
+    /// Builds a transformer when at least one hook applies to this response.
+    pub fn new(configs: &[ResponseHookConfig], path: &str, content_type: &str, ctx: HookContext) -> Option<Self> {
+        let applicable: Vec<&ResponseHookConfig> = configs.iter()
+            .filter(|c| c.applies(path, ctx.requested_model.as_deref()))
+            .collect();
+        let hooks: Vec<HookKind> = applicable.iter().map(|c| c.hook).collect();
+        let redactors = applicable.iter()
+            .filter(|c| c.hook == HookKind::Redact)
+            .map(|c| Redactor::new(c))
+            .collect();
+        if hooks.is_empty() {
+            return None;
+        }
+        let framing = if content_type.contains("ndjson") {
+            Framing::Ndjson
+        } else if content_type.contains("event-stream") {
+            Framing::Sse
+        } else if content_type.contains("json") {
+            Framing::Json
+        } else {
+            return None;
+        };
+        Some(Self {
+            hooks,
+            redactors,
+            framing,
+            ctx,
+            pending: BytesMut::new(),
+            in_think: false,
+            prompt_tokens: None,
+            completion_tokens: None,
+        })
+    }
+
+    /// Feeds one backend chunk and returns the bytes to forward.
+    pub fn push(&mut self, chunk: Bytes) -> Bytes {
+        self.pending.extend_from_slice(&chunk);
+        if self.framing == Framing::Json {
+            return Bytes::new(); // transformed as a whole in `finish`
+        }
+        match self.pending.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => {
+                let complete = self.pending.split_to(pos + 1);
+                self.transform_lines(&complete)
+            }
+            None => Bytes::new(),
+        }
+    }
+
+    /// Flushes buffered data plus any summary event.
+    pub fn finish(&mut self) -> Bytes {
+        let rest = self.pending.split();
+        match self.framing {
+            Framing::Json => {
+                let Ok(mut value) = serde_json::from_slice::<Value>(&rest) else {
+                    return rest.freeze();
+                };
+                self.apply(&mut value);
+                if let (Some(summary), Some(obj)) = (self.summary(), value.as_object_mut()) {
+                    obj.insert("usage_summary".to_string(), summary);
+                }
+                Bytes::from(value.to_string())
+            }
+            Framing::Ndjson => {
+                let mut out = BytesMut::new();
+                if !rest.is_empty() {
+                    let mut line = rest.to_vec();
+                    line.push(b'\n');
+                    out.extend_from_slice(&self.transform_lines(&line));
+                }
+                if let Some(summary) = self.summary() {
+                    out.extend_from_slice(json!({ "usage_summary": summary }).to_string().as_bytes());
+                    out.extend_from_slice(b"\n");
+                }
+                out.freeze()
+            }
+            Framing::Sse => {
+                let mut out = BytesMut::new();
+                if !rest.is_empty() {
+                    out.extend_from_slice(&self.transform_lines(&rest));
+                }
+                // A stream without `[DONE]` still gets its summary at the end.
+                if let Some(summary) = self.summary() {
+                    out.extend_from_slice(format!("data: {}\n\n", json!({ "usage_summary": summary })).as_bytes());
+                }
+                out.freeze()
+            }
+        }
+    }
+
+    fn transform_lines(&mut self, data: &[u8]) -> Bytes {
+        let mut out = BytesMut::with_capacity(data.len());
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            let text = line.trim_ascii_end();
+            let payload = match self.framing {
+                Framing::Sse => text.strip_prefix(b"data: "),
+                _ => Some(text),
+            };
+            let Some(payload) = payload.filter(|p| !p.is_empty()) else {
+                out.extend_from_slice(line);
+                continue;
+            };
+
+            if self.framing == Framing::Sse && payload == b"[DONE]" {
+                if let Some(summary) = self.summary() {
+                    out.extend_from_slice(format!("data: {}\n\n", json!({ "usage_summary": summary })).as_bytes());
+                }
+                out.extend_from_slice(line);
+                continue;
+            }
+
+            match serde_json::from_slice::<Value>(payload) {
+                Ok(mut value) => {
+                    self.apply(&mut value);
+                    if self.framing == Framing::Sse {
+                        out.extend_from_slice(b"data: ");
+                    }
+                    out.extend_from_slice(value.to_string().as_bytes());
+                    out.extend_from_slice(b"\n");
+                }
+                Err(_) => out.extend_from_slice(line),
+            }
+        }
+        out.freeze()
+    }
+
+    fn apply(&mut self, value: &mut Value) {
+        let mut redactor = 0;
+        // A whole JSON body has no later chunk to wait for
+        let last = self.framing == Framing::Json || is_last_event(value);
+        for hook in self.hooks.clone() {
+            match hook {
+                HookKind::Redact => {
+                    if let Some(r) = self.redactors.get_mut(redactor) {
+                        r.apply(value, last);
+                    }
+                    redactor += 1;
+                }
+                HookKind::StripThink => self.strip_think(value),
+                HookKind::RewriteModel => {
+                    if let (Some(model), Some(field)) = (&self.ctx.requested_model, value.get_mut("model")) {
+                        *field = Value::String(model.clone());
+                    }
+                }
+                HookKind::UsageSummary => self.collect_usage(value),
+            }
+        }
+    }
+
+    fn strip_think(&mut self, value: &mut Value) {
+        if let Some(message) = value.get_mut("message").and_then(Value::as_object_mut) {
+            message.remove("thinking");
+        }
+        let mut texts: Vec<&mut Value> = Vec::new();
+        let in_think = &mut self.in_think;
+        if let Some(obj) = value.as_object_mut() {
+            for (key, v) in obj.iter_mut() {
+                match key.as_str() {
+                    "response" => texts.push(v),
+                    "message" => {
+                        if let Some(c) = v.get_mut("content") {
+                            texts.push(c);
+                        }
+                    }
+                    "choices" => {
+                        for choice in v.as_array_mut().into_iter().flatten() {
+                            if let Some(choice) = choice.as_object_mut() {
+                                for (ck, cv) in choice.iter_mut() {
+                                    match ck.as_str() {
+                                        "text" => texts.push(cv),
+                                        "delta" | "message" => {
+                                            if let Some(c) = cv.get_mut("content") {
+                                                texts.push(c);
+                                            }
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for text in texts {
+            if let Some(s) = text.as_str() {
+                *text = Value::String(strip_think_text(s, in_think));
+            }
+        }
+    }
+
+    fn collect_usage(&mut self, value: &Value) {
+        let (prompt, completion) = usage_counts(value);
+        self.prompt_tokens = prompt.or(self.prompt_tokens);
+        self.completion_tokens = completion.or(self.completion_tokens);
+    }
+
+    /// Summary event for `usage_summary`; taken once so it is only emitted once.
+    fn summary(&mut self) -> Option<Value> {
+        let idx = self.hooks.iter().position(|h| *h == HookKind::UsageSummary)?;
+        self.hooks.remove(idx);
+        Some(json!({
+            "model": self.ctx.requested_model,
+            "backend": self.ctx.backend_url,
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "duration_ms": self.ctx.started.elapsed().as_millis() as u64,
+        }))
+    }
+}
+
+/// Masks pattern matches in the text fields of a response, one stream per field.
+struct Redactor {
+    regex: Regex,
+    replacement: String,
+    holdback: usize,
+    /// Text held back per field (JSON pointer) until the next event.
+    tails: HashMap<String, String>,
+}
+
+impl Redactor {
+    fn new(config: &ResponseHookConfig) -> Self {
+        let combined = config.patterns.iter()
+            .map(|p| format!("(?:{})", p.0.as_str()))
+            .collect::<Vec<_>>()
+            .join("|");
+        Self {
+            regex: Regex::new(&combined).expect("patterns compiled individually"),
+            replacement: config.replacement.clone().unwrap_or_else(|| "[REDACTED]".to_string()),
+            holdback: config.holdback.unwrap_or(REDACT_HOLDBACK),
+            tails: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, value: &mut Value, last: bool) {
+        for pointer in text_pointers(value) {
+            if let Some(field) = value.pointer_mut(&pointer)
+                && let Some(text) = field.as_str()
+            {
+                let mut buffer = self.tails.remove(&pointer).unwrap_or_default();
+                buffer.push_str(text);
+                *field = Value::String(self.filter(&pointer, buffer, last));
+            }
+        }
+        if last {
+            // Held-back text of a field missing from the final event (e.g. an empty OpenAI delta)
+            for (pointer, tail) in std::mem::take(&mut self.tails) {
+                let text = self.filter(&pointer, tail, true);
+                insert_text(value, &pointer, text);
+            }
+        }
+    }
+
+    /// Replaces the matches in `buffer` and returns the text that is safe to
+    /// send; the rest is kept for the field's next fragment unless `last`.
+    fn filter(&mut self, pointer: &str, buffer: String, last: bool) -> String {
+        let mut cut = if last { buffer.len() } else { floor_char_boundary(&buffer, buffer.len().saturating_sub(self.holdback)) };
+        let mut out = String::with_capacity(buffer.len());
+        let mut pos = 0;
+        for m in self.regex.find_iter(&buffer) {
+            if m.start() >= cut {
+                break;
+            }
+            // The match may continue in the next fragment
+            if m.end() == buffer.len() && !last {
+                cut = m.start();
+                break;
+            }
+            out.push_str(&buffer[pos..m.start()]);
+            out.push_str(&self.replacement);
+            pos = m.end();
+        }
+        let end = cut.max(pos);
+        out.push_str(&buffer[pos..end]);
+        if end < buffer.len() {
+            self.tails.insert(pointer.to_string(), buffer[end..].to_string());
+        }
+        out
+    }
+}
+
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// JSON pointers of the generated-text fields of one Ollama or OpenAI event.
+fn text_pointers(value: &Value) -> Vec<String> {
+    let mut pointers = Vec::new();
+    for key in ["response", "thinking"] {
+        if value.get(key).is_some_and(Value::is_string) {
+            pointers.push(format!("/{}", key));
+        }
+    }
+    for key in ["content", "thinking"] {
+        if value.get("message").and_then(|m| m.get(key)).is_some_and(Value::is_string) {
+            pointers.push(format!("/message/{}", key));
+        }
+    }
+    for (i, choice) in value.get("choices").and_then(Value::as_array).into_iter().flatten().enumerate() {
+        if choice.get("text").is_some_and(Value::is_string) {
+            pointers.push(format!("/choices/{}/text", i));
+        }
+        for key in ["delta", "message"] {
+            if choice.get(key).and_then(|m| m.get("content")).is_some_and(Value::is_string) {
+                pointers.push(format!("/choices/{}/{}/content", i, key));
+            }
+        }
+    }
+    pointers
+}
+
+/// Sets the string at `pointer`, creating the last key if its parent object exists.
+fn insert_text(value: &mut Value, pointer: &str, text: String) {
+    let Some((parent, key)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    if let Some(obj) = value.pointer_mut(parent).and_then(Value::as_object_mut) {
+        let field = obj.entry(key).or_insert_with(|| Value::String(String::new()));
+        if let Some(existing) = field.as_str() {
+            *field = Value::String(format!("{}{}", existing, text));
+        }
+    }
+}
+
+/// True for the event that ends a streamed response: Ollama `done`, or an OpenAI
+/// chunk with a `finish_reason`.
+fn is_last_event(value: &Value) -> bool {
+    value.get("done").and_then(Value::as_bool) == Some(true)
+        || value.get("choices").and_then(Value::as_array)
+            .is_some_and(|choices| choices.iter().any(|c| c.get("finish_reason").is_some_and(|r| !r.is_null())))
+}
+
+/// Removes text between `<think>` and `</think>`, carrying the open state across
+/// streamed fragments.
+fn strip_think_text(s: &str, in_think: &mut bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    loop {
+        if *in_think {
+            match rest.find("</think>") {
+                Some(end) => {
+                    rest = rest[end + "</think>".len()..].trim_start_matches('\n');
+                    *in_think = false;
+                }
+                None => return out,
+            }
+        } else {
+            match rest.find("<think>") {
+                Some(start) => {
+                    out.push_str(&rest[..start]);
+                    rest = &rest[start + "<think>".len()..];
+                    *in_think = true;
+                }
+                None => {
+                    out.push_str(rest);
+                    return out;
+                }
+            }
+        }
+    }
+}
+
+/// (prompt, completion) token counts reported in one response event, in either
+/// Ollama (`prompt_eval_count`/`eval_count`) or OpenAI (`usage`) form.
+pub fn usage_counts(value: &Value) -> (Option<u64>, Option<u64>) {
+    let usage = value.get("usage");
+    let prompt = value.get("prompt_eval_count").and_then(Value::as_u64)
+        .or_else(|| usage?.get("prompt_tokens")?.as_u64());
+    let completion = value.get("eval_count").and_then(Value::as_u64)
+        .or_else(|| usage?.get("completion_tokens")?.as_u64());
+    (prompt, completion)
+}
@@ -0,0 +1,182 @@
+//! External identity resolution: an API key or JWT is exchanged for a user id,
+//! tier and quota by calling an HTTP endpoint of the organization's own user
+//! management, with results cached so the endpoint isn't hit per request.
+
+use axum::http::{HeaderMap, HeaderName};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Cached results kept at most; expired entries are pruned first when full.
+const CACHE_CAPACITY: usize = 10_000;
+
+/// `identity` section of the config file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityConfig {
+    /// Endpoint called with the client's credential header; answers with an [`Identity`].
+    pub url: String,
+    /// Request header carrying the credential.
+    #[serde(default = "default_header")]
+    pub header: String,
+    /// How long a resolved identity is reused.
+    #[serde(default = "default_cache_secs")]
+    pub cache_secs: u64,
+    /// How long a rejected credential stays rejected without asking again.
+    #[serde(default = "default_negative_cache_secs")]
+    pub negative_cache_secs: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_header() -> String {
+    "Authorization".to_string()
+}
+
+fn default_cache_secs() -> u64 {
+    300
+}
+
+fn default_negative_cache_secs() -> u64 {
+    30
+}
+
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+impl IdentityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("url '{}' must start with http:// or https://", self.url));
+        }
+        HeaderName::try_from(self.header.as_str()).map_err(|_| format!("invalid header name '{}'", self.header))?;
+        Ok(())
+    }
+}
+
+/// What the endpoint says about a credential.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Identity {
+    pub user_id: String,
+    /// Tier from the config file the user belongs to.
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// Overrides the tier's `max_concurrent`.
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// Overrides the tier's `max_queued`.
+    #[serde(default)]
+    pub max_queued: Option<usize>,
+}
+
+/// Why a request couldn't be given an identity.
+#[derive(Debug)]
+pub enum IdentityError {
+    /// No credential, or the endpoint rejected it (answered 401, 403 or 404).
+    Unauthorized,
+    /// The endpoint failed or answered something unusable.
+    Unavailable(String),
+}
+
+struct Cached {
+    identity: Option<Identity>,
+    expires: Instant,
+}
+
+pub struct IdentityResolver {
+    config: IdentityConfig,
+    header: HeaderName,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<String, Cached>>,
+    /// Latest identity per user id, consulted for tier and quota lookups.
+    users: Mutex<HashMap<String, Identity>>,
+}
+
+impl IdentityResolver {
+    pub fn new(config: IdentityConfig) -> Self {
+        let header = HeaderName::try_from(config.header.as_str()).unwrap_or(axum::http::header::AUTHORIZATION);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap_or_default();
+        Self {
+            config,
+            header,
+            client,
+            cache: Mutex::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Header holding the credential, removed before a request is forwarded.
+    pub fn header(&self) -> &HeaderName {
+        &self.header
+    }
+
+    /// Resolves the credential of a request, from the cache when possible.
+    pub async fn resolve(&self, headers: &HeaderMap) -> Result<Identity, IdentityError> {
+        let credential = headers.get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.trim().is_empty())
+            .ok_or(IdentityError::Unauthorized)?
+            .to_string();
+
+        let cached = self.cache.lock().unwrap().get(&credential)
+            .filter(|c| c.expires > Instant::now())
+            .map(|c| c.identity.clone());
+        let identity = match cached {
+            Some(identity) => identity,
+            None => {
+                let identity = self.fetch(&credential).await?;
+                let ttl = if identity.is_some() { self.config.cache_secs } else { self.config.negative_cache_secs };
+                let mut cache = self.cache.lock().unwrap();
+                if cache.len() >= CACHE_CAPACITY {
+                    let now = Instant::now();
+                    cache.retain(|_, c| c.expires > now);
+                    if cache.len() >= CACHE_CAPACITY {
+                        cache.clear();
+                    }
+                }
+                cache.insert(credential, Cached { identity: identity.clone(), expires: Instant::now() + Duration::from_secs(ttl) });
+                identity
+            }
+        };
+        let identity = identity.ok_or(IdentityError::Unauthorized)?;
+        self.users.lock().unwrap().insert(identity.user_id.clone(), identity.clone());
+        Ok(identity)
+    }
+
+    /// Asks the endpoint; `Ok(None)` means the credential was rejected.
+    async fn fetch(&self, credential: &str) -> Result<Option<Identity>, IdentityError> {
+        let res = self.client.get(&self.config.url)
+            .header(&self.header, credential)
+            .send()
+            .await
+            .map_err(|e| IdentityError::Unavailable(e.to_string()))?;
+        let status = res.status();
+        if matches!(status.as_u16(), 401 | 403 | 404) {
+            debug!("Identity endpoint rejected a credential ({})", status);
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(IdentityError::Unavailable(format!("identity endpoint answered {}", status)));
+        }
+        let identity: Identity = res.json().await
+            .map_err(|e| IdentityError::Unavailable(format!("invalid identity response: {}", e)))?;
+        if identity.user_id.trim().is_empty() {
+            warn!("Identity endpoint returned an empty user_id");
+            return Err(IdentityError::Unavailable("identity response has an empty user_id".to_string()));
+        }
+        Ok(Some(identity))
+    }
+
+    /// Latest resolved identity of `user_id`.
+    pub fn identity_of(&self, user_id: &str) -> Option<Identity> {
+        self.users.lock().unwrap().get(user_id).cloned()
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+/// An address block such as `203.0.113.7` or `2001:db8:1:2::/64`.
+///
+/// Addresses are canonicalized, so an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`)
+/// is the same as its IPv4 form. A single address is a full-length prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IpPrefix {
+    network: IpAddr,
+    len: u8,
+}
+
+impl IpPrefix {
+    /// The block of `len` leading bits around `addr` (clamped to the address width).
+    pub fn new(addr: IpAddr, len: u8) -> Self {
+        let addr = addr.to_canonical();
+        let len = len.min(Self::width(&addr));
+        let network = match addr {
+            IpAddr::V4(a) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+                IpAddr::V4((u32::from(a) & mask).into())
+            }
+            IpAddr::V6(a) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+                IpAddr::V6((u128::from(a) & mask).into())
+            }
+        };
+        Self { network, len }
+    }
+
+    pub fn single(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+        Self::new(addr, Self::width(&addr))
+    }
+
+    fn width(addr: &IpAddr) -> u8 {
+        if addr.is_ipv4() { 32 } else { 128 }
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.len == Self::width(&self.network)
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        addr.is_ipv4() == self.network.is_ipv4() && Self::new(addr, self.len).network == self.network
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_single() {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.len)
+        }
+    }
+}
+
+impl FromStr for IpPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("invalid IP address '{}'", addr))?;
+        match len {
+            None => Ok(Self::single(addr)),
+            Some(len) => {
+                let len: u8 = len.trim().parse().map_err(|_| format!("invalid prefix length '{}'", len))?;
+                // `::ffff:a.b.c.d/104` is the IPv4 block `a.b.c.d/8`
+                let len = match addr {
+                    IpAddr::V6(v6) if v6.to_ipv4_mapped().is_some() => len.saturating_sub(96),
+                    _ => len,
+                };
+                if len > Self::width(&addr.to_canonical()) {
+                    return Err(format!("prefix length {} is too long for {}", len, addr));
+                }
+                Ok(Self::new(addr, len))
+            }
+        }
+    }
+}
+
+impl Serialize for IpPrefix {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpPrefix {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,240 @@
+//! Brute-force defense: `401`/`403` answers are counted per client address,
+//! and an address collecting too many of them within a window is blocked for a
+//! while, twice as long each time it comes back (fail2ban-style). Requests
+//! refused because their IP or user is already blocked are counted separately.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::blocked_store::BlockInfo;
+use crate::dispatcher::AppState;
+use crate::ip::IpPrefix;
+
+pub const DEFAULT_WINDOW_SECS: u64 = 600;
+pub const DEFAULT_BAN_SECS: u64 = 600;
+pub const DEFAULT_MAX_BAN_SECS: u64 = 24 * 60 * 60;
+/// Attempts kept per address for the admin API.
+const HISTORY: usize = 20;
+/// How often expired bans are lifted.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// When failed attempts turn into a temporary block.
+#[derive(Clone, Debug)]
+pub struct JailPolicy {
+    /// Failed attempts within `window` that trigger a ban.
+    pub max_failures: u32,
+    pub window: Duration,
+    /// Length of the first ban; each further ban of the same address doubles it.
+    pub ban: Duration,
+    pub max_ban: Duration,
+}
+
+/// Response extension marking a request refused because its IP or user is
+/// blocked, so it counts as a blocked attempt rather than a failed one.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockedAttempt;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptKind {
+    FailedAuth,
+    Blocked,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Attempt {
+    /// RFC 3339 time of the answer.
+    pub at: String,
+    pub kind: AttemptKind,
+    pub status: u16,
+    pub method: String,
+    pub path: String,
+}
+
+#[derive(Default)]
+struct Record {
+    /// Failed attempts within the policy window.
+    failures: VecDeque<Instant>,
+    failed_total: u64,
+    blocked_total: u64,
+    bans: u32,
+    banned_until: Option<Instant>,
+    history: VecDeque<Attempt>,
+}
+
+/// One address in `GET /admin/auth-failures`.
+#[derive(Serialize, Clone, Debug)]
+pub struct JailReport {
+    pub ip: IpPrefix,
+    pub failed_total: u64,
+    pub blocked_total: u64,
+    /// Failed attempts still within the window.
+    pub recent_failures: usize,
+    pub bans: u32,
+    /// Seconds left of the current temporary block, if any.
+    pub banned_for_secs: Option<u64>,
+    pub attempts: Vec<Attempt>,
+}
+
+#[derive(Default)]
+pub struct Jail {
+    records: HashMap<IpPrefix, Record>,
+    pub failed_total: u64,
+    pub blocked_total: u64,
+    pub bans_total: u64,
+}
+
+impl Jail {
+    /// Counts an attempt of `prefix`; returns the ban length when a failed
+    /// attempt crosses the policy threshold, to be started with `start_ban`.
+    pub fn record(&mut self, prefix: IpPrefix, attempt: Attempt, policy: Option<&JailPolicy>) -> Option<Duration> {
+        let record = self.records.entry(prefix).or_default();
+        let kind = attempt.kind;
+        if record.history.len() == HISTORY {
+            record.history.pop_front();
+        }
+        record.history.push_back(attempt);
+        if kind == AttemptKind::Blocked {
+            record.blocked_total += 1;
+            self.blocked_total += 1;
+            return None;
+        }
+        record.failed_total += 1;
+        self.failed_total += 1;
+
+        let policy = policy?;
+        let now = Instant::now();
+        while record.failures.front().is_some_and(|at| now.duration_since(*at) > policy.window) {
+            record.failures.pop_front();
+        }
+        record.failures.push_back(now);
+        if record.banned_until.is_some() || record.failures.len() < policy.max_failures as usize {
+            return None;
+        }
+        let ban = policy.ban.saturating_mul(1 << record.bans.min(16)).min(policy.max_ban);
+        record.bans += 1;
+        record.failures.clear();
+        self.bans_total += 1;
+        Some(ban)
+    }
+
+    pub fn start_ban(&mut self, prefix: IpPrefix, ban: Duration) {
+        self.records.entry(prefix).or_default().banned_until = Some(Instant::now() + ban);
+    }
+
+    /// Turns a temporary block into a regular one, e.g. when an operator
+    /// blocks or unblocks the address by hand.
+    pub fn forget_ban(&mut self, prefix: &IpPrefix) {
+        if let Some(record) = self.records.get_mut(prefix) {
+            record.banned_until = None;
+        }
+    }
+
+    pub fn is_banned(&self, prefix: &IpPrefix) -> bool {
+        self.records.get(prefix).is_some_and(|r| r.banned_until.is_some())
+    }
+
+    /// Addresses under a temporary block.
+    pub fn banned(&self) -> HashSet<IpPrefix> {
+        self.records.iter().filter(|(_, r)| r.banned_until.is_some()).map(|(p, _)| *p).collect()
+    }
+
+    /// Ends the bans that ran out and returns their addresses.
+    fn take_expired(&mut self) -> Vec<IpPrefix> {
+        let now = Instant::now();
+        self.records.iter_mut()
+            .filter(|(_, r)| r.banned_until.is_some_and(|until| until <= now))
+            .map(|(prefix, r)| {
+                r.banned_until = None;
+                *prefix
+            })
+            .collect()
+    }
+
+    /// Every address with recorded attempts, most failures first.
+    pub fn report(&self, window: Duration) -> Vec<JailReport> {
+        let now = Instant::now();
+        let mut report: Vec<JailReport> = self.records.iter()
+            .map(|(prefix, r)| JailReport {
+                ip: *prefix,
+                failed_total: r.failed_total,
+                blocked_total: r.blocked_total,
+                recent_failures: r.failures.iter().filter(|at| now.duration_since(**at) <= window).count(),
+                bans: r.bans,
+                banned_for_secs: r.banned_until.map(|until| until.saturating_duration_since(now).as_secs()),
+                attempts: r.history.iter().cloned().collect(),
+            })
+            .collect();
+        report.sort_by(|a, b| b.failed_total.cmp(&a.failed_total).then(a.ip.to_string().cmp(&b.ip.to_string())));
+        report
+    }
+}
+
+/// Counts the `401`/`403` answers of every route and bans addresses that
+/// collect too many.
+pub async fn track_auth_failures(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let response = next.run(req).await;
+    record(&state, addr.ip(), &method, &path, &response);
+    response
+}
+
+/// Records `response` if it is a `401` or `403`.
+pub fn record(state: &AppState, ip: IpAddr, method: &str, path: &str, response: &Response) {
+    let status = response.status();
+    if status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN {
+        return;
+    }
+    let kind = if response.extensions().get::<BlockedAttempt>().is_some() { AttemptKind::Blocked } else { AttemptKind::FailedAuth };
+    let attempt = Attempt {
+        at: chrono::Local::now().to_rfc3339(),
+        kind,
+        status: status.as_u16(),
+        method: method.to_string(),
+        path: path.to_string(),
+    };
+    let prefix = state.block_target(ip);
+    let ban = state.jail.lock().unwrap().record(prefix, attempt, state.config.jail.as_ref());
+    if let Some(ban) = ban {
+        warn!("Banning {} for {}s after repeated failed authentication", state.describe_ip(&ip), ban.as_secs());
+        // After `block_ip`, which turns bans into regular blocks
+        let info = BlockInfo::new("jail")
+            .with_reason(Some("repeated failed authentication".to_string()))
+            .expiring_in(Some(ban));
+        state.block_ip(prefix, info);
+        state.jail.lock().unwrap().start_ban(prefix, ban);
+    }
+}
+
+/// Lifts temporary blocks once they run out.
+pub async fn run_jail(state: Arc<AppState>) {
+    if state.config.jail.is_none() {
+        return;
+    }
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        let expired = state.jail.lock().unwrap().take_expired();
+        for prefix in expired {
+            info!("Ban of {} expired", prefix);
+            state.unblock_ip(prefix);
+        }
+    }
+}
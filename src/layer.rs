@@ -0,0 +1,519 @@
+//! Tower middleware for embedding the dispatcher in another axum application.
+//!
+//! ```no_run
+//! use axum::{Router, routing::get};
+//! use ollamamq::OllamaMqLayer;
+//!
+//! # async fn run() {
+//! let app: Router = Router::new()
+//!     .route("/status", get(|| async { "ok" }))
+//!     .layer(OllamaMqLayer::builder().backend_url("http://localhost:11434").build());
+//! # }
+//! ```
+//!
+//! Requests for Ollama (`/api/*`) and OpenAI (`/v1/*`) paths are queued per user
+//! and proxied to the configured backends, `/api/queue/self` and `/api/jobs` are
+//! answered by the dispatcher itself; everything else reaches the inner service.
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower::{Layer, Service, ServiceExt};
+use tracing::warn;
+
+use crate::autoscale::{AutoscaleConfig, run_autoscaler};
+use crate::config::FileConfig;
+use crate::dispatcher::{
+    ApiFamily, AppState, CanaryConfig, DispatcherConfig, SchedulerStart, TieBreak, detect_api_family,
+    handle_request, normalize_backend_url, run_detached_expiry, run_worker,
+};
+use crate::blocked_store::{run_block_expiry, run_blocked_store};
+use crate::docker::run_docker_names;
+use crate::jail::{JailPolicy, run_jail};
+use crate::rollup::run_rollup;
+use crate::forecast;
+use crate::maintenance::run_maintenance;
+use crate::shutdown::DrainPolicy;
+use crate::slow_client::SlowClientPolicy;
+use crate::slots::{SlotPolicy, SlotRelease};
+use crate::statsd::{StatsdConfig, run_statsd};
+use crate::structured::StructuredOutputMode;
+
+const DEFAULT_BODY_LIMIT: usize = 1024 * 1024 * 1024;
+
+pub struct OllamaMqLayerBuilder {
+    config: DispatcherConfig,
+    body_limit: usize,
+    intercept_all: bool,
+}
+
+impl Default for OllamaMqLayerBuilder {
+    fn default() -> Self {
+        Self {
+            config: DispatcherConfig {
+                backend_urls: Vec::new(),
+                ..DispatcherConfig::default()
+            },
+            body_limit: DEFAULT_BODY_LIMIT,
+            intercept_all: false,
+        }
+    }
+}
+
+impl OllamaMqLayerBuilder {
+    /// Adds a backend server (Ollama, LM Studio, ...).
+    pub fn backend_url(mut self, url: impl AsRef<str>) -> Self {
+        self.config.backend_urls.push(normalize_backend_url(url.as_ref()));
+        self
+    }
+
+    pub fn backend_urls<I, U>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = U>,
+        U: AsRef<str>,
+    {
+        self.config.backend_urls.extend(urls.into_iter().map(|u| normalize_backend_url(u.as_ref())));
+        self
+    }
+
+    /// Backend request timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Separate backend timeouts for embedding and generation requests
+    /// (`None` keeps the general timeout).
+    pub fn route_timeouts(mut self, embed: Option<Duration>, generate: Option<Duration>) -> Self {
+        self.config.embed_timeout = embed;
+        self.config.generate_timeout = generate;
+        self
+    }
+
+    /// Learns generation and embedding timeouts per backend and model as
+    /// p99 × `factor` of recent durations, up to `max`.
+    pub fn learned_timeouts(mut self, factor: f64, max: Duration) -> Self {
+        self.config.learned_timeout_factor = Some(factor);
+        self.config.learned_timeout_max = max;
+        self
+    }
+
+    /// Reaches backends through an HTTP or SOCKS proxy (e.g. `socks5h://bastion:1080`);
+    /// a backend's own `proxy` in the config file takes precedence.
+    pub fn backend_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.config.backend_proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sends the resolved user id to backends in `name` (e.g. `X-Forwarded-User`),
+    /// replacing whatever the client put there.
+    pub fn forward_user_header(mut self, name: axum::http::HeaderName) -> Self {
+        self.config.forward_user_header = Some(name);
+        self
+    }
+
+    /// Lets browser requests from `origin` (`*` for any) reach the proxy routes.
+    pub fn cors_origin(mut self, origin: impl Into<String>) -> Self {
+        self.config.cors_origins.push(origin.into());
+        self
+    }
+
+    /// File keeping blocked users and IPs, notes and the watchlist
+    /// (default `blocked_items.json` in the working directory).
+    pub fn blocked_file(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config.blocked_file = path.into();
+        self
+    }
+
+    /// Names clients after their Docker containers, read from the Docker socket
+    /// at `socket` (usually [`crate::docker::DEFAULT_SOCKET`]).
+    pub fn docker_names(mut self, socket: impl Into<std::path::PathBuf>) -> Self {
+        self.config.docker_socket = Some(socket.into());
+        self
+    }
+
+    pub fn canary(mut self, canary: CanaryConfig) -> Self {
+        self.config.canary = Some(canary);
+        self
+    }
+
+    /// Puts users on cooldown for `duration` after `failures` consecutive failed requests.
+    pub fn cooldown(mut self, failures: u32, duration: Duration) -> Self {
+        self.config.cooldown_failures = failures;
+        self.config.cooldown_secs = duration.as_secs();
+        self
+    }
+
+    /// Per-user caps on running and queued requests (0 = unlimited).
+    pub fn user_limits(mut self, max_concurrent: usize, max_queued: usize) -> Self {
+        self.config.user_max_concurrent = max_concurrent;
+        self.config.user_max_queued = max_queued;
+        self
+    }
+
+    /// Per-user cap on estimated prompt tokens waiting in the queue (0 = unlimited).
+    pub fn user_max_queued_tokens(mut self, max_tokens: u64) -> Self {
+        self.config.user_max_queued_tokens = max_tokens;
+        self
+    }
+
+    /// Runs each user's smaller prompts first instead of in arrival order.
+    pub fn shortest_job_first(mut self, enabled: bool) -> Self {
+        self.config.shortest_job_first = enabled;
+        self
+    }
+
+    /// Logs every dispatch decision and sends it as a `schedule` event.
+    pub fn scheduler_trace(mut self, enabled: bool) -> Self {
+        self.config.scheduler_trace = enabled;
+        self
+    }
+
+    /// Where round-robin starts when the layer is built.
+    pub fn scheduler_start(mut self, start: SchedulerStart) -> Self {
+        self.config.scheduler_start = start;
+        self
+    }
+
+    /// Order of users with the same number of processed requests.
+    pub fn tie_break(mut self, order: TieBreak) -> Self {
+        self.config.tie_break = order;
+        self
+    }
+
+    /// Loads a waiting request's model on the backend it will likely run on
+    /// when no backend has it loaded, so the load overlaps the queue wait.
+    pub fn warmup_on_miss(mut self, enabled: bool) -> Self {
+        self.config.warmup_on_miss = enabled;
+        self
+    }
+
+    /// File keeping the last served user for `SchedulerStart::Resume`.
+    pub fn scheduler_state_file(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config.scheduler_state_file = path.into();
+        self
+    }
+
+    /// Holds non-streaming requests for `window` so requests for the same
+    /// model are dispatched back-to-back.
+    pub fn batch_window(mut self, window: Duration) -> Self {
+        self.config.batch_window_ms = window.as_millis() as u64;
+        self
+    }
+
+    /// Raised caps for a user while nobody else has work queued or running.
+    pub fn idle_boost(mut self, max_concurrent: usize, max_queued: usize) -> Self {
+        self.config.idle_boost_concurrent = Some(max_concurrent);
+        self.config.idle_boost_queued = Some(max_queued);
+        self
+    }
+
+    /// Sheds batch-lane requests with 503 while the estimated queue wait exceeds `wait`.
+    pub fn shed_batch_wait(mut self, wait: Duration) -> Self {
+        self.config.shed_batch_wait_secs = Some(wait.as_secs());
+        self
+    }
+
+    /// Rejects new requests with 503 while queued bodies hold more than `bytes`.
+    pub fn max_queued_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_queued_bytes = bytes;
+        self
+    }
+
+    /// Moves the oldest queued bodies to `dir` instead of rejecting new requests
+    /// once `max_queued_bytes` is reached, up to `max_bytes` on disk (0 = unlimited).
+    pub fn spool_dir(mut self, dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        self.config.spool_dir = Some(dir.into());
+        self.config.max_spooled_bytes = max_bytes;
+        self
+    }
+
+    /// Keeps resumable requests (`X-Resumable`) whose client went away for
+    /// `grace`, so a reconnecting client can re-attach via `/api/jobs/{id}/attach`.
+    pub fn disconnect_grace(mut self, grace: Duration) -> Self {
+        self.config.disconnect_grace_secs = Some(grace.as_secs());
+        self
+    }
+
+    /// Fails queued requests once no backend has been online for `grace`.
+    pub fn offline_grace(mut self, grace: Duration) -> Self {
+        self.config.offline_grace_secs = Some(grace.as_secs());
+        self
+    }
+
+    /// Success target in percent and rolling window of each backend's error budget.
+    pub fn error_budget(mut self, target: f64, window: Duration) -> Self {
+        self.config.error_budget_target = target;
+        self.config.error_budget_window = window;
+        self
+    }
+
+    /// Time span of the per-user share timeline (default 10 minutes).
+    pub fn fairness_window(mut self, window: Duration) -> Self {
+        self.config.fairness_window = window;
+        self
+    }
+
+    /// Scale-up/down signals for an external autoscaler.
+    pub fn autoscale(mut self, autoscale: AutoscaleConfig) -> Self {
+        self.config.autoscale = autoscale;
+        self
+    }
+
+    /// Push metrics to a StatsD/DogStatsD agent.
+    pub fn statsd(mut self, statsd: StatsdConfig) -> Self {
+        self.config.statsd = statsd;
+        self
+    }
+
+    /// Reject requests a model can't serve (context length, images) before queuing them.
+    pub fn model_checks(mut self, enabled: bool) -> Self {
+        self.config.model_checks = enabled;
+        self
+    }
+
+    /// Temporarily blocks IPs that collect too many `401`/`403` answers.
+    pub fn auth_jail(mut self, policy: JailPolicy) -> Self {
+        self.config.jail = Some(policy);
+        self
+    }
+
+    /// How long cached `/api/tags` and `/api/show` answers are served;
+    /// `Duration::ZERO` proxies them like any other request.
+    pub fn model_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.config.model_cache_ttl = ttl;
+        self
+    }
+
+    /// What to do with clients that leave the response buffer full for `timeout`.
+    pub fn slow_client(mut self, policy: SlowClientPolicy, timeout: Duration) -> Self {
+        self.config.slow_client_policy = policy;
+        self.config.slow_client_timeout_secs = timeout.as_secs().max(1);
+        self
+    }
+
+    /// When streamed and non-streamed responses give their backend slot to the
+    /// next request; by default both hold it until fully relayed.
+    pub fn slot_release(mut self, streaming: SlotRelease, non_streaming: SlotRelease) -> Self {
+        self.config.slot_policy = SlotPolicy { streaming, non_streaming };
+        self
+    }
+
+    /// Drain policy and timeout of `shutdown::drain`, which the host application
+    /// awaits before stopping its server. Set-aside requests go to `save` if given.
+    pub fn shutdown(mut self, policy: DrainPolicy, timeout: Duration, save: Option<std::path::PathBuf>) -> Self {
+        self.config.shutdown_drain = policy;
+        self.config.shutdown_timeout = timeout;
+        self.config.shutdown_save = save;
+        self
+    }
+
+    /// Open connections one client IP may hold. Only enforced when the host
+    /// application serves through `conn_limit::LimitedListener`.
+    pub fn max_connections_per_ip(mut self, limit: usize) -> Self {
+        self.config.max_connections_per_ip = limit;
+        self
+    }
+
+    /// Passes WebSocket upgrades on any path through to a backend, with at most
+    /// `max_per_user` open per user (0 = unlimited).
+    pub fn websockets(mut self, max_per_user: usize) -> Self {
+        self.config.websockets = true;
+        self.config.websocket_max_per_user = max_per_user;
+        self
+    }
+
+    /// Validates answers to requests asking for JSON or a JSON schema.
+    pub fn structured_output(mut self, mode: StructuredOutputMode) -> Self {
+        self.config.structured_output = mode;
+        self
+    }
+
+    /// Appends every finished request to `path` for `ollamaMQ report`.
+    pub fn usage_log(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config.usage_log = Some(path.into());
+        self
+    }
+
+    /// Rotates the usage log into a zstd-compressed archive once it passes
+    /// `max_bytes`, keeping `max_files` archives.
+    pub fn usage_log_rotation(mut self, max_bytes: u64, max_files: usize) -> Self {
+        self.config.usage_log_max_bytes = max_bytes;
+        self.config.usage_log_max_files = max_files;
+        self
+    }
+
+    /// Keeps up to `capacity` requests failed by a backend for replay, with at
+    /// most `max_bytes` of compressed bodies (0 = unlimited).
+    pub fn dead_letters(mut self, capacity: usize, max_bytes: u64) -> Self {
+        self.config.dead_letter_size = capacity;
+        self.config.dead_letter_max_bytes = max_bytes;
+        self
+    }
+
+    /// Structured settings such as user tiers, as loaded from a config file.
+    pub fn file_config(mut self, file: FileConfig) -> Self {
+        self.config.file = file;
+        self
+    }
+
+    /// Maximum request body size buffered into the queue.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Proxy every request instead of only `/api/*` and `/v1/*` paths.
+    pub fn intercept_all(mut self, intercept_all: bool) -> Self {
+        self.intercept_all = intercept_all;
+        self
+    }
+
+    /// Creates the shared dispatcher state and spawns its worker, so this must be
+    /// called from within a tokio runtime.
+    pub fn build(mut self) -> OllamaMqLayer {
+        if self.config.backend_urls.is_empty() {
+            self.config.backend_urls = DispatcherConfig::default().backend_urls;
+        }
+        if let Some(ref dir) = self.config.spool_dir
+            && let Err(e) = crate::spool::prepare(dir)
+        {
+            warn!("Cannot use spool directory {}: {}", dir.display(), e);
+            self.config.spool_dir = None;
+        }
+        let state = Arc::new(AppState::new(self.config));
+        tokio::spawn(run_worker(state.clone()));
+        tokio::spawn(run_maintenance(state.clone()));
+        tokio::spawn(run_autoscaler(state.clone()));
+        tokio::spawn(run_statsd(state.clone()));
+        tokio::spawn(run_docker_names(state.clone()));
+        tokio::spawn(run_blocked_store(state.clone()));
+        tokio::spawn(run_block_expiry(state.clone()));
+        tokio::spawn(run_detached_expiry(state.clone()));
+        tokio::spawn(run_jail(state.clone()));
+        tokio::spawn(run_rollup(state.clone()));
+        tokio::spawn(forecast::load_history(state.clone()));
+
+        OllamaMqLayer {
+            state,
+            body_limit: self.body_limit,
+            intercept_all: self.intercept_all,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OllamaMqLayer {
+    state: Arc<AppState>,
+    body_limit: usize,
+    intercept_all: bool,
+}
+
+impl OllamaMqLayer {
+    pub fn builder() -> OllamaMqLayerBuilder {
+        OllamaMqLayerBuilder::default()
+    }
+
+    /// Dispatcher state, e.g. for rendering stats or blocking users.
+    pub fn state(&self) -> Arc<AppState> {
+        self.state.clone()
+    }
+}
+
+impl<S> Layer<S> for OllamaMqLayer {
+    type Service = OllamaMqService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OllamaMqService {
+            inner,
+            state: self.state.clone(),
+            body_limit: self.body_limit,
+            intercept_all: self.intercept_all,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OllamaMqService<S> {
+    inner: S,
+    state: Arc<AppState>,
+    body_limit: usize,
+    intercept_all: bool,
+}
+
+impl<S> Service<Request<Body>> for OllamaMqService<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The inner service is driven with `oneshot`, which polls it for readiness itself.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if req.uri().path() == "/api/me" || req.uri().path().starts_with("/api/queue/self") || req.uri().path().starts_with("/api/jobs/") {
+            let queue_api = crate::queue_api::router().with_state(self.state.clone());
+            return Box::pin(queue_api.oneshot(req));
+        }
+        if req.uri().path().starts_with("/api/batch") {
+            let batch_api = crate::batch::router().with_state(self.state.clone());
+            return Box::pin(batch_api.oneshot(req));
+        }
+
+        if self.state.config.websockets && crate::websocket::is_upgrade(req.headers()) {
+            let state = self.state.clone();
+            return Box::pin(async move {
+                let ip = req
+                    .extensions()
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| addr.ip())
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                let (method, path) = (req.method().to_string(), req.uri().path().to_string());
+                let response = crate::websocket::proxy(state.clone(), ip, req).await;
+                crate::jail::record(&state, ip, &method, &path, &response);
+                Ok(response)
+            });
+        }
+
+        let intercept = self.intercept_all || detect_api_family(req.uri().path()) != ApiFamily::Unknown;
+        if !intercept {
+            return Box::pin(self.inner.clone().oneshot(req));
+        }
+
+        let state = self.state.clone();
+        let body_limit = self.body_limit;
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let ip = parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            let body = match axum::body::to_bytes(body, body_limit).await {
+                Ok(b) => b,
+                Err(_) => return Ok((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response()),
+            };
+            let (method, path) = (parts.method.to_string(), parts.uri.path().to_string());
+            let response = handle_request(state.clone(), ip, parts.method, parts.headers, path.clone(), body).await;
+            crate::jail::record(&state, ip, &method, &path, &response);
+            Ok(response)
+        })
+    }
+}
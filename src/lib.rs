@@ -0,0 +1,66 @@
+pub mod admin;
+pub mod autoscale;
+pub mod batch;
+pub mod blocked_store;
+pub mod capabilities;
+pub mod catalogue;
+pub mod columns;
+pub mod compat;
+pub mod compress;
+pub mod config;
+pub mod config_view;
+pub mod conn_limit;
+pub mod dead_letter;
+pub mod demo;
+pub mod dispatcher;
+pub mod docker;
+pub mod error_budget;
+pub mod errors;
+pub mod events;
+pub mod experiments;
+pub mod fallback;
+pub mod handover;
+pub mod fairness;
+pub mod forecast;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod identity;
+pub mod ip;
+pub mod jail;
+pub mod layer;
+pub mod logging;
+pub mod maintenance;
+pub mod methods;
+pub mod metrics;
+pub mod namespace;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod ndjson;
+pub mod policy;
+pub mod probe;
+pub mod queue_api;
+pub mod rollup;
+pub mod scheduler_trace;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod shutdown;
+pub mod slo;
+pub mod slots;
+pub mod slow_client;
+pub mod spool;
+pub mod statsd;
+pub mod structured;
+pub mod snapshot;
+pub mod templates;
+pub mod timeouts;
+pub mod timings;
+pub mod tools;
+pub mod translate;
+pub mod tui;
+pub mod ulid;
+pub mod usage;
+pub mod user_ids;
+pub mod websocket;
+
+pub use layer::{OllamaMqLayer, OllamaMqLayerBuilder, OllamaMqService};
@@ -0,0 +1,165 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+    Size,
+}
+
+pub struct LogOptions {
+    pub path: PathBuf,
+    pub rotation: LogRotation,
+    pub max_size_mb: u64,
+    pub max_files: usize,
+    pub json: bool,
+    /// Where JSON logs go while the TUI is active, rotated like `path`; `-` for
+    /// stderr, which then must not be the terminal the dashboard draws on.
+    pub json_path: PathBuf,
+}
+
+/// Appends to a single file and shifts it to `<name>.1`, `<name>.2`, ... once it
+/// grows past `max_bytes`, deleting anything beyond `max_files` archives.
+struct SizeRotatingWriter {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl SizeRotatingWriter {
+    fn new(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self { path, file, written, max_bytes, max_files })
+    }
+
+    fn archive_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.archive_path(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let _ = fs::rename(self.archive_path(n), self.archive_path(n + 1));
+            }
+            fs::rename(&self.path, self.archive_path(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn file_writer(path: &Path, opts: &LogOptions) -> io::Result<Box<dyn Write + Send>> {
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("ollamamq.log");
+    fs::create_dir_all(dir)?;
+
+    let rotation = match opts.rotation {
+        LogRotation::Size => {
+            let max_bytes = opts.max_size_mb.max(1) * 1024 * 1024;
+            return Ok(Box::new(SizeRotatingWriter::new(path.to_path_buf(), max_bytes, opts.max_files)?));
+        }
+        LogRotation::Never => return Ok(Box::new(tracing_appender::rolling::never(dir, file_name))),
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    };
+
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name)
+        .max_log_files(opts.max_files.max(1))
+        .build(dir)
+        .map_err(io::Error::other)?;
+    Ok(Box::new(appender))
+}
+
+fn env_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+}
+
+/// Where JSON logs go while the TUI is active. Stderr is only accepted when it is
+/// redirected, since writing to the terminal would garble the dashboard.
+fn json_writer(opts: &LogOptions) -> io::Result<Box<dyn Write + Send>> {
+    if opts.json_path.as_os_str() != "-" {
+        return file_writer(&opts.json_path, opts).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", opts.json_path.display(), e)));
+    }
+    if io::stderr().is_terminal() {
+        return Err(io::Error::other("--log-json-file - needs stderr redirected away from the terminal while the TUI runs"));
+    }
+    Ok(Box::new(io::stderr()))
+}
+
+/// Installs the global subscriber. With the TUI active, human-readable logs go to
+/// the (rotated) log file and the optional JSON stream to its own sink, since the
+/// terminal belongs to the dashboard. The returned guards must be kept alive.
+pub fn init(opts: &LogOptions, use_tui: bool) -> io::Result<Vec<WorkerGuard>> {
+    let mut guards = Vec::new();
+
+    if use_tui {
+        let file = file_writer(&opts.path, opts).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", opts.path.display(), e)))?;
+        let (file, g) = tracing_appender::non_blocking(file);
+        guards.push(g);
+        let file_layer = tracing_subscriber::fmt::layer().with_writer(file).with_ansi(false);
+
+        let json_layer = if opts.json {
+            let (json, g) = tracing_appender::non_blocking(json_writer(opts)?);
+            guards.push(g);
+            Some(tracing_subscriber::fmt::layer().json().with_ansi(false).with_writer(json))
+        } else {
+            None
+        };
+
+        tracing_subscriber::registry()
+            .with(env_filter())
+            .with(file_layer)
+            .with(json_layer)
+            .init();
+    } else {
+        let stdout_layer = if opts.json {
+            tracing_subscriber::fmt::layer().json().boxed()
+        } else {
+            tracing_subscriber::fmt::layer().boxed()
+        };
+
+        tracing_subscriber::registry()
+            .with(env_filter())
+            .with(stdout_layer)
+            .init();
+    }
+
+    Ok(guards)
+}
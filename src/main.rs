@@ -1,18 +1,48 @@
 use axum::{
     Router,
     routing::{any, get},
+    serve::ListenerExt,
 };
 use clap::Parser;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tokio::sync::Notify;
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use tokio::sync::{Notify, oneshot};
+use tracing::{info, warn};
 
-mod dispatcher;
-mod tui;
+use ollamamq::admin;
+use ollamamq::autoscale::{AutoscaleConfig, run_autoscaler};
+use ollamamq::conn_limit::LimitedListener;
+use ollamamq::config::{FileConfig, apply_proxy};
+use ollamamq::dispatcher::{
+    AppState, CanaryConfig, DispatcherConfig, MultiIpPolicy, SchedulerStart, SessionAffinity, TieBreak, normalize_backend_url,
+    proxy_handler, run_detached_expiry, run_worker,
+};
+use ollamamq::blocked_store::{self, run_block_expiry, run_blocked_store};
+use ollamamq::docker::run_docker_names;
+use ollamamq::handover::{self, Handover, ListenerSource};
+use ollamamq::health;
+use ollamamq::jail::{JailPolicy, run_jail, track_auth_failures};
+use ollamamq::rollup::run_rollup;
+use ollamamq::forecast;
+use ollamamq::logging::{self, LogOptions, LogRotation};
+use ollamamq::maintenance::{MaintenanceWindow, run_maintenance};
+use ollamamq::metrics;
+use ollamamq::ndjson::StreamValidation;
+use ollamamq::queue_api;
+use ollamamq::batch;
+use ollamamq::shutdown::{self, DrainPolicy};
+use ollamamq::slow_client::SlowClientPolicy;
+use ollamamq::slots::{SlotPolicy, SlotRelease};
+use ollamamq::statsd::{StatsdConfig, StatsdFormat, run_statsd};
+use ollamamq::structured::StructuredOutputMode;
+use ollamamq::snapshot;
+use ollamamq::tui;
+use ollamamq::websocket;
 
-use crate::dispatcher::{AppState, proxy_handler, run_worker};
+mod cli;
+mod service;
+use service::ServiceAction;
 
 use std::io::IsTerminal;
 
@@ -31,6 +61,46 @@ struct Args {
     #[arg(short, long, default_value_t = 300)]
     timeout: u64,
 
+    /// Timeout in seconds for embedding requests (default: --timeout)
+    #[arg(long)]
+    embed_timeout: Option<u64>,
+
+    /// Timeout in seconds for generate, chat and completion requests (default: --timeout)
+    #[arg(long)]
+    generate_timeout: Option<u64>,
+
+    /// Learn generation and embedding timeouts per backend and model as p99 × this factor of recent durations (e.g. 3)
+    #[arg(long)]
+    learned_timeout_factor: Option<f64>,
+
+    /// Longest learned timeout in seconds
+    #[arg(long, default_value_t = 3600)]
+    learned_timeout_max_secs: u64,
+
+    /// HTTP or SOCKS proxy for reaching backends (e.g. socks5h://bastion:1080)
+    #[arg(long)]
+    backend_proxy: Option<String>,
+
+    /// Send the resolved user id to backends in this header (e.g. X-Forwarded-User); clients can't set it themselves
+    #[arg(long)]
+    forward_user_header: Option<axum::http::HeaderName>,
+
+    /// Origins whose browser requests may reach the proxy routes, comma-separated (`*` for any)
+    #[arg(long, value_delimiter = ',')]
+    cors_origins: Vec<String>,
+
+    /// Show Docker clients by their Compose service or container name, read from the Docker socket
+    #[arg(long)]
+    docker_names: bool,
+
+    /// Docker socket used by --docker-names
+    #[arg(long, default_value = ollamamq::docker::DEFAULT_SOCKET)]
+    docker_socket: PathBuf,
+
+    /// File keeping blocked users and IPs, user notes and the watchlist across restarts
+    #[arg(long, default_value = ollamamq::blocked_store::DEFAULT_PATH)]
+    blocked_file: PathBuf,
+
     /// Disable TUI dashboard
     #[arg(long)]
     no_tui: bool,
@@ -38,63 +108,721 @@ struct Args {
     /// Allow all routes (enable fallback proxy)
     #[arg(long, default_value_t = false)]
     allow_all_routes: bool,
+
+    /// Backend URL that receives a share of traffic as a canary
+    #[arg(long)]
+    canary_backend: Option<String>,
+
+    /// Model whose traffic is split to the canary backend (all models if omitted)
+    #[arg(long, requires = "canary_backend")]
+    canary_model: Option<String>,
+
+    /// Percentage of matching traffic routed to the canary backend
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(0..=100))]
+    canary_percent: u32,
+
+    /// Number of busiest users exported individually on /metrics (others are aggregated)
+    #[arg(long, default_value_t = 20)]
+    metrics_top_users: usize,
+
+    /// Validate NDJSON response streams: flag malformed lines, repair framing, or drop bad lines
+    #[arg(long, value_enum, default_value_t = StreamValidation::Off)]
+    stream_validation: StreamValidation,
+
+    /// Validate answers to requests asking for JSON or a JSON schema: count invalid ones
+    /// per model ('flag'), and also send non-streamed requests once more ('retry')
+    #[arg(long, value_enum, default_value_t = StructuredOutputMode::Off)]
+    structured_output: StructuredOutputMode,
+
+    /// Recurring maintenance window as '<cron>;<duration>;<action>' where action is
+    /// 'pause-batch', 'drain=<backend-url>' or 'limit-batch=<jobs>/<duration>',
+    /// e.g. '0 2 * * *;90m;pause-batch' or '0 9 * * 1-5;9h;limit-batch=1/m' (repeatable)
+    #[arg(long)]
+    maintenance_window: Vec<MaintenanceWindow>,
+
+    /// Keep follow-up requests of a session on the same backend, keyed by user id or X-Session-ID header
+    #[arg(long, value_enum, default_value_t = SessionAffinity::Off)]
+    session_affinity: SessionAffinity,
+
+    /// Seconds of inactivity after which a session is no longer tied to its backend
+    #[arg(long, default_value_t = 600)]
+    affinity_ttl_secs: u64,
+
+    /// Report /health as not ready (503) when more requests than this are queued
+    #[arg(long)]
+    ready_max_queued: Option<usize>,
+
+    /// Report /health as not ready (503) when the estimated queue wait exceeds this many seconds
+    #[arg(long)]
+    ready_max_wait_secs: Option<u64>,
+
+    /// At startup, wait up to this many seconds for a backend to answer before listening; exits if none does
+    #[arg(long, value_name = "SECS")]
+    wait_for_backends: Option<u64>,
+
+    /// Reject batch-lane requests with 503 while the estimated queue wait exceeds this many seconds
+    #[arg(long)]
+    shed_batch_wait_secs: Option<u64>,
+
+    /// Log file used while the TUI is active
+    #[arg(long, default_value = "ollamamq.log")]
+    log_file: PathBuf,
+
+    /// Log file rotation policy
+    #[arg(long, value_enum, default_value_t = LogRotation::Never)]
+    log_rotation: LogRotation,
+
+    /// Rotate the log file once it exceeds this size in MB (with --log-rotation size)
+    #[arg(long, default_value_t = 50)]
+    log_max_size_mb: u64,
+
+    /// Number of rotated log files to keep
+    #[arg(long, default_value_t = 5)]
+    log_max_files: usize,
+
+    /// Emit JSON logs to stdout (to --log-json-file while the TUI is active)
+    #[arg(long)]
+    log_json: bool,
+
+    /// File receiving JSON logs while the TUI is active, rotated like --log-file; `-` for stderr, which must then be redirected
+    #[arg(long, default_value = "ollamamq.json.log")]
+    log_json_file: PathBuf,
+
+    /// Bearer token required by the /admin API (the API is disabled when unset)
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// JSON config file with structured settings such as user tiers
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Consecutive failed requests before a user gets a cooldown (0 disables)
+    #[arg(long, default_value_t = 0)]
+    cooldown_failures: u32,
+
+    /// Cooldown length in seconds
+    #[arg(long, default_value_t = 30)]
+    cooldown_secs: u64,
+
+    /// Prefix length blocked when blocking an IPv6 client (128 blocks the single address)
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u8).range(1..=128))]
+    ipv6_block_prefix: u8,
+
+    /// Requests one user may have running at once (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    user_max_concurrent: usize,
+
+    /// Requests one user may have queued; further requests get 429 (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    user_max_queued: usize,
+
+    /// Estimated prompt tokens one user may have queued; further requests get 429 (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    user_max_queued_tokens: u64,
+
+    /// Megabytes the bodies of all queued requests may hold; further requests get 503 (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    max_queued_mb: u64,
+
+    /// Directory to move the oldest queued bodies to when --max-queued-mb is reached, instead of rejecting new requests
+    #[arg(long)]
+    spool_dir: Option<PathBuf>,
+
+    /// Megabytes the spool may hold; past it requests get 503 again (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    max_spooled_mb: u64,
+
+    /// Open connections one client IP may hold; further ones are closed on accept (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    max_connections_per_ip: usize,
+
+    /// Pass WebSocket upgrades on any path through to a backend, after the usual identity and block checks
+    #[arg(long)]
+    websockets: bool,
+
+    /// Open WebSocket connections one user may hold, with --websockets (0 = unlimited)
+    #[arg(long, default_value_t = 4)]
+    websocket_max_per_user: usize,
+
+    /// Run each user's smaller prompts first instead of in arrival order
+    #[arg(long)]
+    shortest_job_first: bool,
+
+    /// Log every dispatch decision (competing users, their counts, why a user and backend were picked) and send it to event scripts
+    #[arg(long)]
+    scheduler_trace: bool,
+
+    /// Where round-robin starts after a restart: the first user, a random one, or after the user served last
+    #[arg(long, value_enum, default_value_t = SchedulerStart::First)]
+    scheduler_start: SchedulerStart,
+
+    /// Order of users with the same number of processed requests
+    #[arg(long, value_enum, default_value_t = TieBreak::UserId)]
+    tie_break: TieBreak,
+
+    /// File keeping the last served user for --scheduler-start resume
+    #[arg(long, default_value = ollamamq::dispatcher::SCHEDULER_STATE_FILE)]
+    scheduler_state_file: PathBuf,
+
+    /// Load a waiting request's model on the backend it will likely run on if no backend has it loaded
+    #[arg(long)]
+    warmup_on_miss: bool,
+
+    /// Hold non-streaming requests this many milliseconds so requests for the same model run back-to-back (0 disables)
+    #[arg(long, default_value_t = 0)]
+    batch_window_ms: u64,
+
+    /// Concurrency cap for a user while they are the only one with work
+    #[arg(long)]
+    idle_boost_concurrent: Option<usize>,
+
+    /// Queue cap for a user while they are the only one with work
+    #[arg(long)]
+    idle_boost_queued: Option<usize>,
+
+    /// Fail queued requests with 503 once no backend has been online for this many seconds
+    #[arg(long)]
+    offline_grace_secs: Option<u64>,
+
+    /// Seconds a resumable request (X-Resumable) whose client went away waits for it to re-attach before being dropped
+    #[arg(long)]
+    disconnect_grace_secs: Option<u64>,
+
+    /// Percentage of requests each backend should serve without a backend failure, for its error budget
+    #[arg(long, default_value_t = ollamamq::error_budget::DEFAULT_TARGET)]
+    error_budget_target: f64,
+
+    /// Rolling window the error budget is measured over, in minutes
+    #[arg(long, default_value_t = ollamamq::error_budget::DEFAULT_WINDOW_MINS)]
+    error_budget_window_mins: u64,
+
+    /// Minutes of dispatches kept for the fairness timeline (TUI `F`, /admin/fairness)
+    #[arg(long, default_value_t = ollamamq::fairness::DEFAULT_WINDOW_MINS, value_parser = clap::value_parser!(u64).range(1..))]
+    fairness_window_mins: u64,
+
+    /// Block an IP for a while after this many 401/403 answers within --auth-fail-window-secs (unset: only count them)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    auth_fail_limit: Option<u32>,
+
+    /// Window in seconds in which --auth-fail-limit failed attempts lead to a block
+    #[arg(long, default_value_t = ollamamq::jail::DEFAULT_WINDOW_SECS)]
+    auth_fail_window_secs: u64,
+
+    /// Length of the first temporary block in seconds; each further block of the same IP doubles it
+    #[arg(long, default_value_t = ollamamq::jail::DEFAULT_BAN_SECS)]
+    auth_ban_secs: u64,
+
+    /// Longest temporary block in seconds
+    #[arg(long, default_value_t = ollamamq::jail::DEFAULT_MAX_BAN_SECS)]
+    auth_ban_max_secs: u64,
+
+    /// What to do when a user id sends requests from several IPs at once
+    #[arg(long, value_enum, default_value_t = MultiIpPolicy::Allow)]
+    multi_ip_policy: MultiIpPolicy,
+
+    /// Finished requests kept for the TUI history view (0 disables it)
+    #[arg(long, default_value_t = 500)]
+    history_size: usize,
+
+    /// Requests failed by a backend kept with their body for replay from the TUI history view (0 disables it)
+    #[arg(long, default_value_t = 100)]
+    dead_letter_size: usize,
+
+    /// Megabytes of compressed dead letter bodies kept; the oldest make way past it (0 = unlimited)
+    #[arg(long, default_value_t = 256)]
+    dead_letter_max_mb: u64,
+
+    /// Append every finished request to this file, for `ollamaMQ report`
+    #[arg(long, env = "OLLAMAMQ_USAGE_LOG")]
+    usage_log: Option<PathBuf>,
+
+    /// Rotate the usage log into a zstd-compressed archive once it exceeds this size in MB (0 = never)
+    #[arg(long, default_value_t = 0)]
+    usage_log_max_mb: u64,
+
+    /// Number of compressed usage log archives to keep
+    #[arg(long, default_value_t = 5)]
+    usage_log_max_files: usize,
+
+    /// Don't reject requests that exceed a model's context length or send images to text-only models
+    #[arg(long)]
+    no_model_checks: bool,
+
+    /// Answer /api/tags and /api/show from a per-backend cache refreshed by the health check, for this many seconds (0 = always proxy)
+    #[arg(long, default_value_t = ollamamq::catalogue::DEFAULT_TTL_SECS)]
+    model_cache_ttl_secs: u64,
+
+    /// What to do when a client reads a response slower than the backend produces it
+    #[arg(long, value_enum, default_value_t = SlowClientPolicy::Wait)]
+    slow_client_policy: SlowClientPolicy,
+
+    /// Seconds a client may leave the response buffer full before --slow-client-policy applies
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    slow_client_timeout_secs: u64,
+
+    /// When a streamed (NDJSON/SSE) response frees its backend for the next request
+    #[arg(long, value_enum, default_value_t = SlotRelease::Completion)]
+    stream_slot_release: SlotRelease,
+
+    /// When a non-streamed response frees its backend for the next request
+    #[arg(long, value_enum, default_value_t = SlotRelease::Completion)]
+    non_stream_slot_release: SlotRelease,
+
+    /// Signal scale-up when the estimated queue wait stays above this many seconds
+    #[arg(long)]
+    scale_up_wait_secs: Option<u64>,
+
+    /// How long the queue wait must stay above --scale-up-wait-secs before signalling
+    #[arg(long, default_value_t = 60)]
+    scale_up_sustain_secs: u64,
+
+    /// Hint scale-down for backends idle this many seconds while nothing is queued
+    #[arg(long)]
+    scale_down_idle_secs: Option<u64>,
+
+    /// URL that receives scale signals as JSON POSTs
+    #[arg(long)]
+    scale_webhook: Option<String>,
+
+    /// File kept up to date with the current scale signal as JSON
+    #[arg(long)]
+    scale_signal_file: Option<PathBuf>,
+
+    /// Resend an unchanged up/down signal to the webhook after this many seconds
+    #[arg(long, default_value_t = 300)]
+    scale_repeat_secs: u64,
+
+    /// Push metrics to a StatsD/DogStatsD agent at this address (e.g. 127.0.0.1:8125)
+    #[arg(long)]
+    statsd_addr: Option<String>,
+
+    /// Prefix of every StatsD metric name
+    #[arg(long, default_value = "ollamamq")]
+    statsd_prefix: String,
+
+    /// Tags added to every StatsD metric, comma-separated key:value pairs (DogStatsD only)
+    #[arg(long, value_delimiter = ',')]
+    statsd_tags: Vec<String>,
+
+    /// StatsD line format: plain statsd drops all tags
+    #[arg(long, value_enum, default_value_t = StatsdFormat::Dogstatsd)]
+    statsd_format: StatsdFormat,
+
+    /// How often queue and backend gauges are sent to StatsD, in seconds
+    #[arg(long, default_value_t = 10)]
+    statsd_interval_secs: u64,
+
+    /// Share one work queue with other instances through this NATS server (e.g. nats://10.0.0.5:4222)
+    #[cfg(feature = "nats")]
+    #[arg(long)]
+    nats_url: Option<String>,
+
+    /// JetStream stream holding the shared queue
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value = "OLLAMAMQ")]
+    nats_stream: String,
+
+    /// Subject tasks are published on
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value = "ollamamq.tasks")]
+    nats_subject: String,
+
+    /// Durable consumer shared by all instances
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value = "ollamamq")]
+    nats_consumer: String,
+
+    /// Tasks this instance pulls from NATS at once (0 = only publish, never run shared tasks)
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value_t = 4)]
+    nats_max_pending: usize,
+
+    /// Seconds without progress before NATS redelivers a task to another instance
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(2..))]
+    nats_ack_wait_secs: u64,
+
+    /// Delivery attempts of a task before its client gets an error
+    #[cfg(feature = "nats")]
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(i64).range(1..))]
+    nats_max_deliver: i64,
+
+    /// Rhai script with handlers for dispatcher events (on_completed, on_blocked, ...)
+    #[cfg(feature = "scripting")]
+    #[arg(long, env = "OLLAMAMQ_SCRIPT")]
+    script: Option<PathBuf>,
+
+    /// Restore queues, counters and blocks from a snapshot taken with POST /admin/snapshot
+    #[arg(long)]
+    restore: Option<PathBuf>,
+
+    /// Requests still served on graceful shutdown (Ctrl+C, SIGTERM, service stop):
+    /// 'all' queued ones, only the 'in-flight' ones, or in-flight plus queued 'interactive' ones
+    #[arg(long, value_enum, default_value_t = DrainPolicy::All)]
+    shutdown_drain: DrainPolicy,
+
+    /// Longest graceful shutdown; requests still queued then are set aside
+    #[arg(long, default_value_t = ollamamq::shutdown::DEFAULT_TIMEOUT_SECS)]
+    shutdown_timeout_secs: u64,
+
+    /// Save requests set aside on shutdown to this snapshot file, for --restore (default: drop them)
+    #[arg(long)]
+    shutdown_save: Option<PathBuf>,
+
+    /// Bind the port with SO_REUSEPORT, so a new ollamaMQ can start on it while this one drains (Unix)
+    #[arg(long)]
+    reuse_port: bool,
+
+    /// Draw the TUI with plain ASCII symbols (for terminals without Unicode/braille glyphs)
+    #[arg(long)]
+    ascii: bool,
+
+    /// Draw the TUI in the main screen buffer instead of the alternate screen
+    #[arg(long)]
+    no_alt_screen: bool,
+
+    /// Notify on alerts while the TUI is open: terminal bell, OSC 9 desktop notification, or both
+    #[arg(long, value_enum, default_value_t = tui::AlertNotify::Off)]
+    alert_notify: tui::AlertNotify,
+
+    /// Alert when more than this many requests are queued in total
+    #[arg(long)]
+    alert_queue_over: Option<usize>,
+
+    /// Alert when a watched user (toggled with `w` in the TUI) submits a request
+    #[arg(long)]
+    alert_watched: bool,
+
+    /// File format of dashboard snapshots saved with `s` in the TUI
+    #[arg(long, value_enum, default_value_t = tui::ExportFormat::Markdown)]
+    export_format: tui::ExportFormat,
+
+    /// How often the TUI re-reads dispatcher state, in milliseconds (key presses are handled immediately)
+    #[arg(long, default_value_t = 250, value_parser = clap::value_parser!(u64).range(50..))]
+    tui_refresh_ms: u64,
+
+    /// Windows service management: install, uninstall, or run under the service manager
+    #[arg(long, value_enum)]
+    service: Option<ServiceAction>,
+
+    /// Manage a running instance instead of starting one
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
+
 struct TuiState {
     visible: bool,
     toggle_notify: Arc<Notify>,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+fn main() {
+    let mut args = Args::parse();
+    match args.command.take() {
+        Some(cli::Command::Demo(demo)) => {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(run_demo(args, demo));
+            return;
+        }
+        Some(command) => {
+            let code = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(cli::run(command));
+            std::process::exit(code);
+        }
+        None => {}
+    }
+    if let Some(action) = args.service {
+        service::main(action);
+        return;
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(run(args, None));
+}
+
+/// `ollamaMQ demo`: the dispatcher in front of fake backends, with synthetic
+/// users sending it traffic.
+async fn run_demo(mut args: Args, demo: cli::DemoArgs) {
+    args.backend_urls = match ollamamq::demo::start_backends(demo.backends.into()).await {
+        Ok(urls) => urls,
+        Err(e) => {
+            eprintln!("Failed to start demo backends: {}", e);
+            std::process::exit(1);
+        }
+    };
+    ollamamq::demo::spawn_traffic(format!("http://127.0.0.1:{}", args.port), demo.users.into());
+    run(args, None).await;
+}
+
+/// Runs the dispatcher until the process exits, or until `stop` fires when
+/// running under a service manager.
+async fn run(args: Args, stop: Option<oneshot::Receiver<()>>) {
+    let as_service = stop.is_some();
     let backend_urls: Vec<String> = args.backend_urls.iter()
-        .map(|url| {
-            let trimmed = url.trim_end_matches('/').to_string();
-            if !trimmed.starts_with("http://") && !trimmed.starts_with("https://") {
-                format!("http://{}", trimmed)
-            } else {
-                trimmed
-            }
-        })
+        .map(|url| normalize_backend_url(url))
         .collect();
 
+    let canary = args.canary_backend.as_ref().map(|url| CanaryConfig {
+        backend_url: normalize_backend_url(url),
+        model: args.canary_model.clone(),
+        percent: args.canary_percent,
+    });
+
+    let mut file_config = match args.config {
+        Some(ref path) => match FileConfig::load(path) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Failed to load config: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => FileConfig::default(),
+    };
+
+    if let Some(ref proxy) = args.backend_proxy
+        && let Err(e) = apply_proxy(reqwest::Client::builder(), Some(proxy))
+    {
+        eprintln!("Invalid --backend-proxy: {}", e);
+        std::process::exit(1);
+    }
+
+    if !(args.error_budget_target > 0.0 && args.error_budget_target < 100.0) || args.error_budget_window_mins == 0 {
+        eprintln!("--error-budget-target must be between 0 and 100 (exclusive) and --error-budget-window-mins at least 1");
+        std::process::exit(1);
+    }
+    if args.learned_timeout_factor.is_some_and(|f| !(f >= 1.0 && f.is_finite())) {
+        eprintln!("--learned-timeout-factor must be at least 1");
+        std::process::exit(1);
+    }
+    if let Some(ref dir) = args.spool_dir
+        && let Err(e) = ollamamq::spool::prepare(dir)
+    {
+        eprintln!("Cannot use --spool-dir {}: {}", dir.display(), e);
+        std::process::exit(1);
+    }
+
+    for key in file_config.backends.keys() {
+        if !backend_urls.contains(&normalize_backend_url(key)) {
+            eprintln!("Warning: config backend '{}' does not match any --backend-urls entry", key);
+        }
+    }
+
+    let restore = args.restore.as_ref().map(|path| match snapshot::read(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to restore snapshot: {}", e);
+            std::process::exit(1);
+        }
+    });
+    // Tiers from the snapshot apply only when the new config defines none
+    if let Some(ref s) = restore
+        && file_config.tiers.is_empty()
+    {
+        file_config.tiers = s.header.tiers.clone();
+        file_config.default_tier = s.header.default_tier.clone();
+    }
+
     // Determine if we should run TUI
-    let use_tui = !args.no_tui && std::io::stdout().is_terminal();
+    let use_tui = !as_service && !args.no_tui && std::io::stdout().is_terminal();
 
-    // Keep the guard alive for the duration of main
-    let _guard: Option<tracing_appender::non_blocking::WorkerGuard>;
+    // Keep the guards alive for the duration of main
+    let log_options = LogOptions {
+        path: args.log_file.clone(),
+        rotation: args.log_rotation,
+        max_size_mb: args.log_max_size_mb,
+        max_files: args.log_max_files,
+        json: args.log_json,
+        json_path: args.log_json_file.clone(),
+    };
+    // Services have no console, so they log to the file just like the TUI does
+    let _guards = match logging::init(&log_options, use_tui || as_service) {
+        Ok(guards) => guards,
+        Err(e) => {
+            eprintln!("Failed to set up logging: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    if use_tui {
-        let file_appender = tracing_appender::rolling::never(".", "ollamamq.log");
-        let (non_blocking, g) = tracing_appender::non_blocking(file_appender);
-        _guard = Some(g);
-
-        tracing_subscriber::fmt()
-            .with_writer(non_blocking)
-            .with_ansi(false)
-            .with_env_filter(
-                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-            )
-            .init();
-    } else {
-        _guard = None;
-        tracing_subscriber::fmt()
-            .with_env_filter(
-                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-            )
-            .init();
+    if let Some(ref c) = canary {
+        info!(
+            "Canary routing {}% of {} traffic to {}",
+            c.percent,
+            c.model.as_deref().unwrap_or("all"),
+            c.backend_url
+        );
     }
 
-    let state = Arc::new(AppState::new(backend_urls, args.timeout));
+    let state = Arc::new(AppState::new(DispatcherConfig {
+        backend_urls,
+        timeout: std::time::Duration::from_secs(args.timeout),
+        embed_timeout: args.embed_timeout.map(std::time::Duration::from_secs),
+        generate_timeout: args.generate_timeout.map(std::time::Duration::from_secs),
+        learned_timeout_factor: args.learned_timeout_factor,
+        learned_timeout_max: std::time::Duration::from_secs(args.learned_timeout_max_secs),
+        backend_proxy: args.backend_proxy.clone(),
+        forward_user_header: args.forward_user_header.clone(),
+        cors_origins: args.cors_origins.clone(),
+        docker_socket: args.docker_names.then(|| args.docker_socket.clone()),
+        blocked_file: args.blocked_file.clone(),
+        canary,
+        metrics_top_users: args.metrics_top_users,
+        stream_validation: args.stream_validation,
+        structured_output: args.structured_output,
+        maintenance_windows: args.maintenance_window.clone(),
+        cooldown_failures: args.cooldown_failures,
+        cooldown_secs: args.cooldown_secs,
+        session_affinity: args.session_affinity,
+        affinity_ttl_secs: args.affinity_ttl_secs,
+        ready_max_queued: args.ready_max_queued,
+        ready_max_wait_secs: args.ready_max_wait_secs,
+        wait_for_backends_secs: args.wait_for_backends,
+        shed_batch_wait_secs: args.shed_batch_wait_secs,
+        admin_token: args.admin_token.clone(),
+        ipv6_block_prefix: args.ipv6_block_prefix,
+        user_max_concurrent: args.user_max_concurrent,
+        user_max_queued: args.user_max_queued,
+        user_max_queued_tokens: args.user_max_queued_tokens,
+        shortest_job_first: args.shortest_job_first,
+        scheduler_trace: args.scheduler_trace,
+        scheduler_start: args.scheduler_start,
+        tie_break: args.tie_break,
+        scheduler_state_file: args.scheduler_state_file.clone(),
+        warmup_on_miss: args.warmup_on_miss,
+        batch_window_ms: args.batch_window_ms,
+        idle_boost_concurrent: args.idle_boost_concurrent,
+        idle_boost_queued: args.idle_boost_queued,
+        offline_grace_secs: args.offline_grace_secs,
+        disconnect_grace_secs: args.disconnect_grace_secs,
+        max_queued_bytes: args.max_queued_mb * 1024 * 1024,
+        spool_dir: args.spool_dir.clone(),
+        max_spooled_bytes: args.max_spooled_mb * 1024 * 1024,
+        max_connections_per_ip: args.max_connections_per_ip,
+        websockets: args.websockets,
+        websocket_max_per_user: args.websocket_max_per_user,
+        error_budget_target: args.error_budget_target,
+        error_budget_window: std::time::Duration::from_secs(args.error_budget_window_mins * 60),
+        fairness_window: std::time::Duration::from_secs(args.fairness_window_mins * 60),
+        history_size: args.history_size,
+        dead_letter_size: args.dead_letter_size,
+        dead_letter_max_bytes: args.dead_letter_max_mb * 1024 * 1024,
+        usage_log: args.usage_log.clone(),
+        usage_log_max_bytes: args.usage_log_max_mb * 1024 * 1024,
+        usage_log_max_files: args.usage_log_max_files,
+        multi_ip_policy: args.multi_ip_policy,
+        jail: args.auth_fail_limit.map(|max_failures| JailPolicy {
+            max_failures,
+            window: std::time::Duration::from_secs(args.auth_fail_window_secs),
+            ban: std::time::Duration::from_secs(args.auth_ban_secs),
+            max_ban: std::time::Duration::from_secs(args.auth_ban_max_secs.max(args.auth_ban_secs)),
+        }),
+        model_checks: !args.no_model_checks,
+        model_cache_ttl: std::time::Duration::from_secs(args.model_cache_ttl_secs),
+        slow_client_policy: args.slow_client_policy,
+        slow_client_timeout_secs: args.slow_client_timeout_secs,
+        slot_policy: SlotPolicy { streaming: args.stream_slot_release, non_streaming: args.non_stream_slot_release },
+        shutdown_drain: args.shutdown_drain,
+        shutdown_timeout: std::time::Duration::from_secs(args.shutdown_timeout_secs),
+        shutdown_save: args.shutdown_save.clone(),
+        autoscale: AutoscaleConfig {
+            up_wait_secs: args.scale_up_wait_secs,
+            up_sustain_secs: args.scale_up_sustain_secs,
+            down_idle_secs: args.scale_down_idle_secs,
+            webhook: args.scale_webhook.clone(),
+            signal_file: args.scale_signal_file.clone(),
+            repeat_secs: args.scale_repeat_secs,
+        },
+        statsd: StatsdConfig {
+            addr: args.statsd_addr.clone(),
+            prefix: args.statsd_prefix.clone(),
+            tags: args.statsd_tags.clone(),
+            format: args.statsd_format,
+            interval: std::time::Duration::from_secs(args.statsd_interval_secs.max(1)),
+        },
+        file: file_config,
+    }));
+
+    if let Some(snapshot) = restore {
+        snapshot::restore(&state, snapshot);
+    }
+
+    // Not listening before a backend answers, so clients started alongside don't queue up doomed requests
+    if let Some(secs) = args.wait_for_backends {
+        if use_tui {
+            eprintln!("Waiting up to {}s for a backend to answer (progress in {})...", secs, args.log_file.display());
+        }
+        if !ollamamq::probe::wait_for_backends(&state, std::time::Duration::from_secs(secs)).await {
+            warn!("No backend answered within {}s, exiting", secs);
+            if use_tui {
+                eprintln!("Error: no backend answered within {}s (--wait-for-backends)", secs);
+            }
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(feature = "nats")]
+    if let Some(ref url) = args.nats_url {
+        let config = ollamamq::nats::NatsConfig {
+            url: url.clone(),
+            stream: args.nats_stream.clone(),
+            subject: args.nats_subject.clone(),
+            consumer: args.nats_consumer.clone(),
+            max_pending: args.nats_max_pending,
+            ack_wait: std::time::Duration::from_secs(args.nats_ack_wait_secs),
+            max_deliver: args.nats_max_deliver,
+        };
+        match ollamamq::nats::NatsQueue::connect(config).await {
+            Ok(queue) => {
+                let _ = state.nats.set(queue);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        tokio::spawn(ollamamq::nats::run_consumer(state.clone()));
+    }
+
+    #[cfg(feature = "scripting")]
+    if let Some(ref path) = args.script {
+        match ollamamq::scripting::load(path, state.clone()) {
+            Ok(script) => ollamamq::scripting::run(&state, script),
+            Err(e) => {
+                eprintln!("Error: script {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
     let worker_state = state.clone();
     tokio::spawn(async move {
         run_worker(worker_state).await;
     });
+    tokio::spawn(run_maintenance(state.clone()));
+    tokio::spawn(run_autoscaler(state.clone()));
+    tokio::spawn(run_statsd(state.clone()));
+    tokio::spawn(run_docker_names(state.clone()));
+    tokio::spawn(run_blocked_store(state.clone()));
+    tokio::spawn(run_block_expiry(state.clone()));
+    tokio::spawn(run_detached_expiry(state.clone()));
+    tokio::spawn(run_jail(state.clone()));
+    tokio::spawn(run_rollup(state.clone()));
+    tokio::spawn(forecast::load_history(state.clone()));
 
     let mut app = Router::new()
-        .route("/health", get(|| async { "OK" }))
+        .route("/health", get(health::health_handler))
+        .route("/metrics", get(metrics::metrics_handler))
+        .merge(admin::router(state.clone()))
+        .merge(queue_api::router())
+        .merge(batch::router())
         // Ollama API Endpoints (Explicitly listed)
         .route("/", any(proxy_handler))
         .route("/api/generate", any(proxy_handler))
@@ -124,12 +852,21 @@ async fn main() {
     }
 
     let app = app
+        .layer(axum::middleware::from_fn_with_state(state.clone(), websocket::intercept))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), track_auth_failures))
         .layer(axum::extract::DefaultBodyLimit::max(1024 * 1024 * 1024)) // 1GB limit
         .with_state(state.clone());
 
-    let addr = format!("0.0.0.0:{}", args.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    info!("Dispatcher running on http://{}", addr);
+    let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
+    let (listener, source) = handover::listen(addr, args.reuse_port).unwrap();
+    match source {
+        ListenerSource::Bound => info!("Dispatcher running on http://{}", addr),
+        ListenerSource::Handover => info!("Dispatcher running on http://{}, socket taken over from the previous process", listener.local_addr().unwrap()),
+        ListenerSource::Systemd => info!("Dispatcher running on http://{}, socket passed by systemd", listener.local_addr().unwrap()),
+    }
+    let handover = Handover::new(&listener).unwrap();
+    // The no-op tap gives the custom listener `ConnectInfo<SocketAddr>`
+    let listener = LimitedListener::new(listener, state.clone()).tap_io(|_| {});
 
     if use_tui {
         let tui_state = Arc::new(Mutex::new(TuiState {
@@ -146,21 +883,80 @@ async fn main() {
             .unwrap();
         });
 
+        // The classic Windows console host lacks braille glyphs; Windows Terminal sets WT_SESSION
+        let legacy_console = cfg!(windows) && std::env::var_os("WT_SESSION").is_none();
+        let tui_options = tui::TuiOptions {
+            ascii: args.ascii || legacy_console,
+            alternate_screen: !args.no_alt_screen,
+            notify: args.alert_notify,
+            alert_queue_over: args.alert_queue_over,
+            alert_watched: args.alert_watched,
+            refresh: std::time::Duration::from_millis(args.tui_refresh_ms),
+            export_format: args.export_format,
+        };
+
+        // SIGTERM drains like `q` in the dashboard, which exits once the drain is done
+        let signal_state = state.clone();
+        tokio::spawn(async move {
+            shutdown::signal().await;
+            shutdown::drain(signal_state).await;
+        });
+        // A successor couldn't draw the dashboard in this terminal
+        drop(handover);
+        tokio::spawn(async {
+            loop {
+                handover::upgrade_signal().await;
+                warn!("SIGUSR2 upgrades need --no-tui, ignored");
+            }
+        });
+
         // Run TUI on the main thread
-        tui_loop(tui_state, state).await;
+        tui_loop(tui_state, state.clone(), tui_options).await;
     } else {
         // Just run the server on the main thread
-        axum::serve(
+        let drain_state = state.clone();
+        let reuse_port = args.reuse_port;
+        let server = axum::serve(
             listener,
             app.into_make_service_with_connect_info::<SocketAddr>(),
         )
-        .await
-        .unwrap();
+        .with_graceful_shutdown(async move {
+            let stopped = async {
+                match stop {
+                    Some(rx) => {
+                        let _ = rx.await;
+                        info!("Stop requested by the service manager");
+                    }
+                    None => shutdown::signal().await,
+                }
+            };
+            let handed_over = tokio::select! {
+                _ = stopped => false,
+                _ = handover.on_signal() => true,
+            };
+            if handed_over || reuse_port {
+                // Another process accepts on the port, so stop accepting now and drain what's left
+                tokio::spawn(shutdown::drain(drain_state));
+            } else {
+                shutdown::drain(drain_state).await;
+            }
+        });
+        // Requests still running after the shutdown timeout are not waited for
+        tokio::select! {
+            res = server => {
+                res.unwrap();
+                shutdown::drained(&state).await;
+            }
+            _ = shutdown::abandoned(&state) => {}
+        }
     }
+    // Don't lose a block made just before exiting to the debounce
+    blocked_store::save_now(&state);
+    state.save_scheduler_state();
 }
 
-async fn tui_loop(tui_state: Arc<Mutex<TuiState>>, state: Arc<AppState>) {
-    let mut dashboard = tui::TuiDashboard::new();
+async fn tui_loop(tui_state: Arc<Mutex<TuiState>>, state: Arc<AppState>, options: tui::TuiOptions) {
+    let mut dashboard = tui::TuiDashboard::with_options(options);
     let toggle_notify = Arc::new(tui_state.lock().unwrap().toggle_notify.clone());
 
     loop {
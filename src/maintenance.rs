@@ -0,0 +1,292 @@
+use chrono::{DateTime, Datelike, Duration, Local, Timelike};
+use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+use tracing::info;
+
+use crate::dispatcher::AppState;
+
+/// One cron field as the set of values it matches.
+#[derive(Clone, Debug)]
+struct CronField(Vec<bool>);
+
+impl CronField {
+    fn parse(spec: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut allowed = vec![false; (max + 1) as usize];
+        for part in spec.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((r, s)) => (r, s.parse::<u32>().map_err(|_| format!("invalid step '{}'", s))?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err("step must be positive".to_string());
+            }
+            let (lo, hi) = if range == "*" {
+                (min, max)
+            } else if let Some((a, b)) = range.split_once('-') {
+                let a = a.parse::<u32>().map_err(|_| format!("invalid value '{}'", a))?;
+                let b = b.parse::<u32>().map_err(|_| format!("invalid value '{}'", b))?;
+                (a, b)
+            } else {
+                let v = range.parse::<u32>().map_err(|_| format!("invalid value '{}'", range))?;
+                (v, if part.contains('/') { max } else { v })
+            };
+            if lo < min || hi > max || lo > hi {
+                return Err(format!("'{}' out of range {}-{}", part, min, max));
+            }
+            for v in (lo..=hi).step_by(step as usize) {
+                allowed[v as usize] = true;
+            }
+        }
+        Ok(Self(allowed))
+    }
+
+    fn matches(&self, v: u32) -> bool {
+        self.0.get(v as usize).copied().unwrap_or(false)
+    }
+}
+
+/// Standard five-field cron expression: minute hour day-of-month month day-of-week.
+#[derive(Clone, Debug)]
+pub struct CronExpr {
+    source: String,
+    minute: CronField,
+    hour: CronField,
+    day_of_month: CronField,
+    month: CronField,
+    day_of_week: CronField,
+}
+
+impl CronExpr {
+    pub fn matches(&self, t: &DateTime<Local>) -> bool {
+        self.minute.matches(t.minute())
+            && self.hour.matches(t.hour())
+            && self.day_of_month.matches(t.day())
+            && self.month.matches(t.month())
+            && self.day_of_week.matches(t.weekday().num_days_from_sunday())
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("cron expression '{}' must have 5 fields", s));
+        }
+        // Day-of-week accepts 7 as an alias for Sunday.
+        let mut day_of_week = CronField::parse(fields[4], 0, 7)?;
+        if day_of_week.matches(7) {
+            day_of_week.0[0] = true;
+        }
+        Ok(Self {
+            source: s.to_string(),
+            minute: CronField::parse(fields[0], 0, 59)?,
+            hour: CronField::parse(fields[1], 0, 23)?,
+            day_of_month: CronField::parse(fields[2], 1, 31)?,
+            month: CronField::parse(fields[3], 1, 12)?,
+            day_of_week,
+        })
+    }
+}
+
+impl fmt::Display for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MaintenanceAction {
+    /// Hold batch-lane tasks in their queues.
+    PauseBatch,
+    /// Stop dispatching new tasks to this backend URL.
+    Drain(String),
+    /// Dispatch at most `jobs` batch-lane tasks per `per`.
+    LimitBatch { jobs: u32, per: Duration },
+}
+
+/// `1m`, `90m` or `2h` for a whole number of minutes.
+fn format_span(d: Duration) -> String {
+    let mins = d.num_minutes();
+    if mins % (24 * 60) == 0 {
+        format!("{}d", mins / (24 * 60))
+    } else if mins % 60 == 0 {
+        format!("{}h", mins / 60)
+    } else {
+        format!("{}m", mins)
+    }
+}
+
+/// A batch rate such as `1/m`, `10/h` or `3/15m`.
+fn parse_rate(s: &str) -> Result<MaintenanceAction, String> {
+    let (jobs, per) = s.split_once('/').ok_or_else(|| format!("invalid batch rate '{}' (use <jobs>/<duration>, e.g. 1/m)", s))?;
+    let jobs: u32 = jobs.trim().parse().map_err(|_| format!("invalid job count in '{}'", s))?;
+    let per = per.trim();
+    // `1/m` reads better than `1/1m`
+    let per = if per.starts_with(|c: char| c.is_ascii_digit()) { parse_duration(per)? } else { parse_duration(&format!("1{}", per))? };
+    if jobs == 0 || per <= Duration::zero() {
+        return Err(format!("batch rate '{}' must allow at least one job per positive duration", s));
+    }
+    Ok(MaintenanceAction::LimitBatch { jobs, per })
+}
+
+impl fmt::Display for MaintenanceAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceAction::PauseBatch => f.write_str("pause-batch"),
+            MaintenanceAction::Drain(url) => write!(f, "drain {}", url.replace("http://", "").replace("https://", "")),
+            MaintenanceAction::LimitBatch { jobs, per } => write!(f, "batch {}/{}", jobs, format_span(*per)),
+        }
+    }
+}
+
+/// A recurring window, written as `<cron>;<duration>;<action>`, e.g.
+/// `0 2 * * *;90m;pause-batch`, `0 3 * * 6;2h;drain=http://gpu1:11434` or
+/// `0 9 * * 1-5;9h;limit-batch=1/m`.
+#[derive(Clone, Debug)]
+pub struct MaintenanceWindow {
+    pub start: CronExpr,
+    pub duration: Duration,
+    pub action: MaintenanceAction,
+}
+
+/// A duration such as `90m`, `2h` or `7d`; a bare number is minutes.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: i64 = num.parse().map_err(|_| format!("invalid duration '{}'", s))?;
+    match unit {
+        "m" | "" => Ok(Duration::minutes(n)),
+        "h" => Ok(Duration::hours(n)),
+        "d" => Ok(Duration::days(n)),
+        _ => Err(format!("invalid duration unit in '{}' (use m, h or d)", s)),
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(';').collect();
+        if parts.len() != 3 {
+            return Err("expected '<cron>;<duration>;<action>'".to_string());
+        }
+        let action = match parts[2].trim() {
+            "pause-batch" => MaintenanceAction::PauseBatch,
+            a => match (a.strip_prefix("drain="), a.strip_prefix("limit-batch=")) {
+                (Some(url), _) => MaintenanceAction::Drain(crate::dispatcher::normalize_backend_url(url)),
+                (_, Some(rate)) => parse_rate(rate)?,
+                _ => return Err(format!("unknown action '{}' (use pause-batch, drain=<url> or limit-batch=<jobs>/<duration>)", a)),
+            },
+        };
+        let duration = parse_duration(parts[1])?;
+        if duration <= Duration::zero() {
+            return Err("duration must be positive".to_string());
+        }
+        Ok(Self {
+            start: parts[0].parse()?,
+            duration,
+            action,
+        })
+    }
+}
+
+impl MaintenanceWindow {
+    /// Start of the occurrence covering `now`, if the window is currently open.
+    fn active_since(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let now = now.with_second(0)?.with_nanosecond(0)?;
+        (0..self.duration.num_minutes())
+            .map(|m| now - Duration::minutes(m))
+            .find(|t| self.start.matches(t))
+    }
+
+    /// Next start strictly after `now`, searching up to a week ahead.
+    fn next_start(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let base = now.with_second(0)?.with_nanosecond(0)?;
+        (1..=7 * 24 * 60)
+            .map(|m| base + Duration::minutes(m))
+            .find(|t| self.start.matches(t))
+    }
+}
+
+/// What the maintenance scheduler currently enforces, refreshed periodically.
+#[derive(Clone, Debug, Default)]
+pub struct MaintenanceStatus {
+    pub batch_paused: bool,
+    /// Most batch-lane tasks dispatched per period, the strictest of the active windows.
+    pub batch_rate: Option<(u32, Duration)>,
+    pub drained_backends: HashSet<String>,
+    /// Active windows with their end time.
+    pub active: Vec<(MaintenanceAction, DateTime<Local>)>,
+    /// Soonest upcoming window, if no window is active.
+    pub next: Option<(MaintenanceAction, DateTime<Local>)>,
+}
+
+pub fn evaluate(windows: &[MaintenanceWindow], now: DateTime<Local>) -> MaintenanceStatus {
+    let mut status = MaintenanceStatus::default();
+    for w in windows {
+        if let Some(since) = w.active_since(now) {
+            match w.action {
+                MaintenanceAction::PauseBatch => status.batch_paused = true,
+                MaintenanceAction::Drain(ref url) => {
+                    status.drained_backends.insert(url.clone());
+                }
+                MaintenanceAction::LimitBatch { jobs, per } => {
+                    // Fewer jobs per minute wins: a/p < b/q is a*q < b*p
+                    let stricter = status.batch_rate.is_none_or(|(j, p)| {
+                        i64::from(jobs) * p.num_seconds() < i64::from(j) * per.num_seconds()
+                    });
+                    if stricter {
+                        status.batch_rate = Some((jobs, per));
+                    }
+                }
+            }
+            status.active.push((w.action.clone(), since + w.duration));
+        } else if let Some(next) = w.next_start(now)
+            && status.next.as_ref().is_none_or(|(_, t)| next < *t)
+        {
+            status.next = Some((w.action.clone(), next));
+        }
+    }
+    status
+}
+
+/// Re-evaluates the configured windows every few seconds and logs transitions.
+pub async fn run_maintenance(state: Arc<AppState>) {
+    if state.config.maintenance_windows.is_empty() {
+        return;
+    }
+    loop {
+        let status = evaluate(&state.config.maintenance_windows, Local::now());
+        let changed = {
+            let mut current = state.maintenance.lock().unwrap();
+            let changed = current.batch_paused != status.batch_paused
+                || current.batch_rate != status.batch_rate
+                || current.drained_backends != status.drained_backends;
+            *current = status.clone();
+            changed
+        };
+        if changed {
+            if status.active.is_empty() {
+                info!("Maintenance window ended; normal dispatching resumed");
+            } else {
+                for (action, until) in &status.active {
+                    info!("Maintenance active: {} until {}", action, until.format("%H:%M"));
+                }
+            }
+            // Paused work may now be dispatchable again.
+            state.notify.notify_one();
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    }
+}
+
+/// Compact "1h05m" style countdown.
+pub fn format_countdown(d: Duration) -> String {
+    let mins = d.num_minutes().max(0);
+    if mins >= 60 {
+        format!("{}h{:02}m", mins / 60, mins % 60)
+    } else {
+        format!("{}m", mins.max(1))
+    }
+}
@@ -0,0 +1,111 @@
+//! HTTP methods on proxy routes: `OPTIONS` (plain or CORS preflight) and
+//! `HEAD` are answered by the dispatcher, and a method a route doesn't take
+//! gets a `405` with an accurate `Allow` header, all without queuing.
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+/// How long browsers may cache a preflight answer, in seconds.
+const PREFLIGHT_MAX_AGE: &str = "600";
+/// What `GET /` answers on Ollama, used for `HEAD /` without a backend round trip.
+const ROOT_BODY: &str = "Ollama is running";
+/// Largest `GET` answer read to tell a `HEAD` its length; model lists are far smaller.
+const HEAD_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Methods the backend takes on a known proxy route; `None` for other paths,
+/// which are forwarded as they come (`--allow-all-routes`).
+pub fn allowed(path: &str) -> Option<&'static [Method]> {
+    const GET: &[Method] = &[Method::GET];
+    const POST: &[Method] = &[Method::POST];
+    match path {
+        "/" | "/api/tags" | "/api/ps" | "/api/version" | "/v1/models" => Some(GET),
+        "/api/generate" | "/api/chat" | "/api/embed" | "/api/embeddings" | "/api/show" | "/api/create" | "/api/copy"
+        | "/api/pull" | "/api/push" | "/v1/chat/completions" | "/v1/completions" | "/v1/embeddings" => Some(POST),
+        "/api/delete" => Some(&[Method::DELETE]),
+        _ if path.starts_with("/api/blobs/") => Some(&[Method::HEAD, Method::POST]),
+        _ if path.starts_with("/v1/models/") => Some(GET),
+        _ => None,
+    }
+}
+
+/// Value of the `Allow` header for `methods`: `HEAD` goes with `GET`, and
+/// `OPTIONS` is always answered.
+pub fn allow_header(methods: &[Method]) -> String {
+    let mut names: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+        names.push("HEAD");
+    }
+    names.push("OPTIONS");
+    names.join(", ")
+}
+
+/// The `Origin` of a cross-origin request if `origins` (`--cors-origins`) lets it in.
+pub fn allowed_origin<'a>(origins: &[String], headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+    let origin = headers.get(header::ORIGIN)?;
+    let text = origin.to_str().ok()?;
+    origins.iter().any(|o| o == "*" || o.eq_ignore_ascii_case(text)).then_some(origin)
+}
+
+/// Answers `OPTIONS`, `HEAD /` and methods a known route doesn't take; `None`
+/// for everything that goes on to the queue.
+pub fn answer(method: &Method, path: &str, headers: &HeaderMap, origins: &[String]) -> Option<Response> {
+    let methods = allowed(path)?;
+    if method == Method::OPTIONS {
+        let allow = allow_header(methods);
+        let mut response = (StatusCode::NO_CONTENT, [(header::ALLOW, allow.clone())]).into_response();
+        let preflight = headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if let (true, Some(origin)) = (preflight, allowed_origin(origins, headers)) {
+            let out = response.headers_mut();
+            out.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            out.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_str(&allow).unwrap_or(HeaderValue::from_static("*")));
+            if let Some(requested) = headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+                out.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+            }
+            out.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static(PREFLIGHT_MAX_AGE));
+            out.insert(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        return Some(response);
+    }
+    if method == Method::HEAD && path == "/" {
+        return Some((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+                (header::CONTENT_LENGTH, ROOT_BODY.len().to_string()),
+            ],
+        )
+            .into_response());
+    }
+    let taken = methods.contains(method) || (method == Method::HEAD && methods.contains(&Method::GET));
+    if taken {
+        return None;
+    }
+    let allow = allow_header(methods);
+    let body = serde_json::json!({ "error": format!("method {} not allowed on {} (allowed: {})", method, path, allow) });
+    Some((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, allow)], axum::Json(body)).into_response())
+}
+
+/// A `GET` answer turned into the answer to a `HEAD`: same status and headers,
+/// with the `Content-Length` of the body left out.
+pub async fn head_of(response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    if !parts.headers.contains_key(header::CONTENT_LENGTH)
+        && let Ok(body) = axum::body::to_bytes(body, HEAD_BODY_LIMIT).await
+    {
+        parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Adds `Access-Control-Allow-Origin` for `origin`, taken from `allowed_origin`,
+/// unless the backend set it already.
+pub fn allow_origin(response: &mut Response, origin: HeaderValue) {
+    let out = response.headers_mut();
+    if !out.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN) {
+        out.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        out.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+}
@@ -0,0 +1,398 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    sync::Arc,
+};
+
+use crate::dispatcher::{AppState, BackendStatus, CancelStage, UserStats};
+use crate::error_budget::BudgetStatus;
+use crate::errors::ErrorClass;
+use crate::rollup::{WINDOWS, WindowCounts};
+use crate::slots::ResponseKind;
+use crate::timings::Stage;
+
+/// Metric name, type, help text and value accessor.
+type Family<T> = (&'static str, &'static str, &'static str, fn(&T) -> u64);
+/// Gauge name, help text and value accessor; no sample is written for `None`.
+type OptionalGauge<T> = (&'static str, &'static str, fn(&T) -> Option<f64>);
+
+/// Label value for users folded out of the top-N.
+pub const OTHER_USERS_LABEL: &str = "_other";
+
+/// Keeps the `top_n` first entries of `stats` (already sorted busiest-first) and
+/// folds the remainder into a single `_other` entry, so a scan with thousands of
+/// user ids can't explode label cardinality.
+pub fn cap_user_stats(mut stats: Vec<UserStats>, top_n: usize) -> Vec<UserStats> {
+    if stats.len() <= top_n {
+        return stats;
+    }
+    let rest = stats.split_off(top_n);
+    let mut other = UserStats {
+        user_id: OTHER_USERS_LABEL.to_string(),
+        ..UserStats::default()
+    };
+    for s in &rest {
+        other.absorb(s);
+    }
+    stats.push(other);
+    stats
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Renders dispatcher state in the Prometheus text exposition format.
+pub fn render(state: &AppState) -> String {
+    let mut out = String::new();
+
+    let all_users = state.user_stats();
+    let tracked_users = all_users.len();
+    let users = cap_user_stats(all_users, state.config.metrics_top_users);
+
+    write_family(&mut out, "ollamamq_users_tracked", "gauge", "Distinct user ids seen since startup.");
+    let _ = writeln!(out, "ollamamq_users_tracked {}", tracked_users);
+
+    let user_families: [Family<UserStats>; 9] = [
+        ("ollamamq_user_queued", "gauge", "Requests waiting in the user's queue.", |s| s.queued as u64),
+        ("ollamamq_user_processing", "gauge", "Requests currently being served for the user.", |s| s.processing as u64),
+        ("ollamamq_user_processed_total", "counter", "Requests completed for the user.", |s| s.processed as u64),
+        ("ollamamq_user_dropped_total", "counter", "Requests dropped for the user.", |s| s.dropped as u64),
+        ("ollamamq_user_multi_ip_flagged_total", "counter", "Requests sent while the user was connected from another IP.", |s| s.multi_ip_flagged as u64),
+        ("ollamamq_user_bytes_in_total", "counter", "Request body bytes received from the user.", |s| s.bytes_in),
+        ("ollamamq_user_bytes_out_total", "counter", "Response bytes streamed to the user.", |s| s.bytes_out),
+        ("ollamamq_user_tool_requests_total", "counter", "Completed requests of the user that offered the model tools.", |s| s.tool_requests),
+        ("ollamamq_user_tool_calls_total", "counter", "Tool calls returned in answers to the user.", |s| s.tool_calls),
+    ];
+    for (name, kind, help, value) in user_families {
+        write_family(&mut out, name, kind, help);
+        for s in &users {
+            let _ = writeln!(out, "{}{{user=\"{}\"}} {}", name, escape_label(&s.user_id), value(s));
+        }
+    }
+
+    write_family(&mut out, "ollamamq_user_errors_total", "counter", "Failed and rejected requests of the user, by class.");
+    for s in &users {
+        for class in ErrorClass::ALL {
+            let _ = writeln!(out, "ollamamq_user_errors_total{{user=\"{}\",class=\"{}\"}} {}", escape_label(&s.user_id), class.as_str(), s.errors.get(class));
+        }
+    }
+
+    let backends = state.backends.lock().unwrap().clone();
+    // Failures per (user, backend, cause), users outside the top-N folded like above
+    let named: HashSet<&str> = users.iter().map(|s| s.user_id.as_str()).filter(|u| *u != OTHER_USERS_LABEL).collect();
+    let mut failures: BTreeMap<(String, String, &str), usize> = BTreeMap::new();
+    for ((user, backend, kind), n) in state.failures.lock().unwrap().iter() {
+        let user = if named.contains(user.as_str()) { user.clone() } else { OTHER_USERS_LABEL.to_string() };
+        *failures.entry((user, backend.clone(), kind.as_str())).or_insert(0) += n;
+    }
+    write_family(
+        &mut out,
+        "ollamamq_user_backend_failures_total",
+        "counter",
+        "Failed requests per user and backend, by cause (client or backend).",
+    );
+    for ((user, backend, kind), n) in &failures {
+        let _ = writeln!(
+            out,
+            "ollamamq_user_backend_failures_total{{user=\"{}\",backend=\"{}\",kind=\"{}\"}} {}",
+            escape_label(user),
+            escape_label(backend),
+            kind,
+            n
+        );
+    }
+
+    // Completions over the rollup windows, users outside the top-N folded like above
+    {
+        let rollup = state.rollup.lock().unwrap();
+        let mut recent: BTreeMap<String, WindowCounts> = users.iter().map(|s| (s.user_id.clone(), WindowCounts::default())).collect();
+        for (user, counts) in &rollup.users {
+            let user = if named.contains(user.as_str()) { user.clone() } else { OTHER_USERS_LABEL.to_string() };
+            let entry = recent.entry(user).or_default();
+            for (total, n) in entry.iter_mut().zip(counts) {
+                *total += n;
+            }
+        }
+        write_family(&mut out, "ollamamq_user_completed_recent", "gauge", "Requests completed for the user over the last 5m, 1h and 24h.");
+        for (user, counts) in &recent {
+            for ((window, _), n) in WINDOWS.iter().zip(counts) {
+                let _ = writeln!(out, "ollamamq_user_completed_recent{{user=\"{}\",window=\"{}\"}} {}", escape_label(user), window, n);
+            }
+        }
+        write_family(&mut out, "ollamamq_model_completed_recent", "gauge", "Requests completed for the model over the last 5m, 1h and 24h.");
+        let models: BTreeMap<_, _> = rollup.models.iter().collect();
+        for (model, counts) in models {
+            for ((window, _), n) in WINDOWS.iter().zip(counts) {
+                let _ = writeln!(out, "ollamamq_model_completed_recent{{model=\"{}\",window=\"{}\"}} {}", escape_label(model), window, n);
+            }
+        }
+    }
+
+    let backend_families: [Family<BackendStatus>; 6] = [
+        ("ollamamq_backend_up", "gauge", "Whether the backend passed its last health check.", |b| b.is_online as u64),
+        ("ollamamq_backend_active_requests", "gauge", "Requests in flight on the backend.", |b| b.active_requests as u64),
+        ("ollamamq_backend_processed_total", "counter", "Requests handled by the backend.", |b| b.processed_count as u64),
+        ("ollamamq_backend_failed_total", "counter", "Requests that failed because of the backend (5xx, timeouts, connection errors).", |b| b.failed_count as u64),
+        ("ollamamq_backend_client_errors_total", "counter", "Requests the backend rejected as invalid (4xx).", |b| b.client_errors as u64),
+        ("ollamamq_backend_malformed_responses_total", "counter", "NDJSON responses containing malformed lines.", |b| b.malformed_responses as u64),
+    ];
+    for (name, kind, help, value) in backend_families {
+        write_family(&mut out, name, kind, help);
+        for b in &backends {
+            let _ = writeln!(
+                out,
+                "{}{{backend=\"{}\",canary=\"{}\"}} {}",
+                name,
+                escape_label(&b.url),
+                b.is_canary,
+                value(b)
+            );
+        }
+    }
+
+    write_family(&mut out, "ollamamq_backend_slot_seconds_total", "counter", "Time requests held the backend's slot, by kind of response.");
+    for b in &backends {
+        for kind in ResponseKind::ALL {
+            let _ = writeln!(out, "ollamamq_backend_slot_seconds_total{{backend=\"{}\",kind=\"{}\"}} {:.3}", escape_label(&b.url), kind.as_str(), b.slots.busy(kind).as_secs_f64());
+        }
+    }
+    write_family(&mut out, "ollamamq_backend_slot_requests_total", "counter", "Requests that held the backend's slot, by kind of response.");
+    for b in &backends {
+        for kind in ResponseKind::ALL {
+            let _ = writeln!(out, "ollamamq_backend_slot_requests_total{{backend=\"{}\",kind=\"{}\"}} {}", escape_label(&b.url), kind.as_str(), b.slots.requests(kind));
+        }
+    }
+    write_family(&mut out, "ollamamq_backend_slot_occupancy", "gauge", "Share of the time since the backend was added that its slot was held by finished requests.");
+    for b in &backends {
+        let _ = writeln!(out, "ollamamq_backend_slot_occupancy{{backend=\"{}\"}} {:.4}", escape_label(&b.url), b.slots.share());
+    }
+    write_family(&mut out, "ollamamq_backend_relaying_requests", "gauge", "Responses still being relayed after giving the backend's slot back at their first chunk.");
+    for b in &backends {
+        let _ = writeln!(out, "ollamamq_backend_relaying_requests{{backend=\"{}\"}} {}", escape_label(&b.url), b.slots.relaying);
+    }
+    write_family(&mut out, "ollamamq_backend_relay_seconds_total", "counter", "Time spent relaying responses after their slot was released.");
+    for b in &backends {
+        let _ = writeln!(out, "ollamamq_backend_relay_seconds_total{{backend=\"{}\"}} {:.3}", escape_label(&b.url), b.slots.relay_time.as_secs_f64());
+    }
+    write_family(&mut out, "ollamamq_backend_early_slot_releases_total", "counter", "Slots given back at the first chunk (--stream-slot-release/--non-stream-slot-release first-chunk).");
+    for b in &backends {
+        let _ = writeln!(out, "ollamamq_backend_early_slot_releases_total{{backend=\"{}\"}} {}", escape_label(&b.url), b.slots.early_releases);
+    }
+
+    // Error budget gauges are left out for backends without requests in the window
+    let budgets: Vec<_> = backends.iter().map(|b| (b, state.error_budget(b))).collect();
+    let budget_families: [OptionalGauge<BudgetStatus>; 4] = [
+        ("ollamamq_backend_success_ratio", "Share of requests over the error budget window that didn't fail because of the backend.", |s| s.success_rate),
+        ("ollamamq_backend_error_budget_remaining", "Share of the backend's error budget left over the window; negative once overspent.", |s| s.remaining),
+        ("ollamamq_backend_error_budget_burn_rate", "How fast the backend uses up its error budget over the window (1 = exactly the budget).", |s| s.burn_rate),
+        ("ollamamq_backend_error_budget_short_burn_rate", "Error budget burn rate over the last five minutes.", |s| s.short_burn_rate),
+    ];
+    for (name, help, value) in budget_families {
+        write_family(&mut out, name, "gauge", help);
+        for (b, status) in &budgets {
+            if let Some(v) = value(status) {
+                let _ = writeln!(out, "{}{{backend=\"{}\"}} {:.4}", name, escape_label(&b.url), v);
+            }
+        }
+    }
+    write_family(&mut out, "ollamamq_backend_error_budget_target", "gauge", "Success target of each backend's error budget, 0 to 1.");
+    let _ = writeln!(out, "ollamamq_backend_error_budget_target {}", state.config.error_budget_target / 100.0);
+
+    let slos = state.slo_status();
+    if !slos.is_empty() {
+        let labels = |s: &crate::slo::SloStatus| {
+            format!("tier=\"{}\",stage=\"{}\",percentile=\"{}\"", escape_label(&s.tier), s.stage.name(), s.percentile)
+        };
+        write_family(&mut out, "ollamamq_slo_latency_seconds", "gauge", "Observed latency at the objective's percentile over the tier's recent requests.");
+        for s in &slos {
+            if let Some(ms) = s.observed_ms {
+                let _ = writeln!(out, "ollamamq_slo_latency_seconds{{{}}} {:.3}", labels(s), ms as f64 / 1000.0);
+            }
+        }
+        write_family(&mut out, "ollamamq_slo_target_seconds", "gauge", "Latency target of each tier objective.");
+        for s in &slos {
+            let _ = writeln!(out, "ollamamq_slo_target_seconds{{{}}} {:.3}", labels(s), s.target_ms as f64 / 1000.0);
+        }
+        write_family(&mut out, "ollamamq_slo_violated", "gauge", "Whether the tier objective is currently violated.");
+        for s in &slos {
+            let _ = writeln!(out, "ollamamq_slo_violated{{{}}} {}", labels(s), s.violated as u8);
+        }
+        write_family(&mut out, "ollamamq_slo_violations_total", "counter", "Times the tier objective went from met to violated.");
+        for s in &slos {
+            let _ = writeln!(out, "ollamamq_slo_violations_total{{{}}} {}", labels(s), s.violations);
+        }
+    }
+
+    let mut shed: Vec<_> = state.shed_counts.lock().unwrap().iter().map(|((tier, lane), n)| (tier.clone(), *lane, *n)).collect();
+    shed.sort_by(|a, b| (&a.0, a.1.as_str()).cmp(&(&b.0, b.1.as_str())));
+    write_family(&mut out, "ollamamq_shed_requests_total", "counter", "Requests rejected by load shedding, per tier and lane.");
+    for (tier, lane, n) in &shed {
+        let _ = writeln!(out, "ollamamq_shed_requests_total{{tier=\"{}\",lane=\"{}\"}} {}", escape_label(tier), lane.as_str(), n);
+    }
+
+    let mut structured: Vec<_> = state.structured.lock().unwrap().iter().map(|(m, s)| (m.clone(), s.clone())).collect();
+    structured.sort_by(|a, b| a.0.cmp(&b.0));
+    write_family(&mut out, "ollamamq_structured_output_checked_total", "counter", "Answers to JSON/schema requests validated with --structured-output, per model.");
+    for (model, s) in &structured {
+        let _ = writeln!(out, "ollamamq_structured_output_checked_total{{model=\"{}\"}} {}", escape_label(model), s.checked);
+    }
+    write_family(&mut out, "ollamamq_structured_output_invalid_total", "counter", "Answers that did not match the requested format, per model.");
+    for (model, s) in &structured {
+        let _ = writeln!(out, "ollamamq_structured_output_invalid_total{{model=\"{}\"}} {}", escape_label(model), s.invalid);
+    }
+    write_family(&mut out, "ollamamq_structured_output_retries_total", "counter", "Requests sent again after an invalid answer, per model.");
+    for (model, s) in &structured {
+        let _ = writeln!(out, "ollamamq_structured_output_retries_total{{model=\"{}\"}} {}", escape_label(model), s.retries);
+    }
+
+    {
+        let experiments = state.experiments.lock().unwrap();
+        let label = |(experiment, arm): &(String, String)| format!("experiment=\"{}\",arm=\"{}\"", escape_label(experiment), escape_label(arm));
+        write_family(&mut out, "ollamamq_experiment_requests_total", "counter", "Dispatched requests per experiment arm, by outcome.");
+        for (key, s) in &experiments.arms {
+            let other = s.requests - s.completed - s.failed - s.rejected;
+            for (outcome, n) in [("completed", s.completed), ("failed", s.failed), ("rejected", s.rejected), ("other", other)] {
+                let _ = writeln!(out, "ollamamq_experiment_requests_total{{{},outcome=\"{}\"}} {}", label(key), outcome, n);
+            }
+        }
+        write_family(&mut out, "ollamamq_experiment_tokens_total", "counter", "Prompt and completion tokens per experiment arm.");
+        for (key, s) in &experiments.arms {
+            let _ = writeln!(out, "ollamamq_experiment_tokens_total{{{},kind=\"prompt\"}} {}", label(key), s.prompt_tokens);
+            let _ = writeln!(out, "ollamamq_experiment_tokens_total{{{},kind=\"completion\"}} {}", label(key), s.completion_tokens);
+        }
+        write_family(&mut out, "ollamamq_experiment_backend_seconds_total", "counter", "Backend time of completed requests per experiment arm.");
+        for (key, s) in &experiments.arms {
+            let _ = writeln!(out, "ollamamq_experiment_backend_seconds_total{{{}}} {:.3}", label(key), s.backend_time.as_secs_f64());
+        }
+        write_family(&mut out, "ollamamq_experiment_stage_seconds", "gauge", "Per-stage latency percentiles of recently completed requests per experiment arm.");
+        for (key, s) in &experiments.arms {
+            for stage in [Stage::FirstChunk, Stage::Streaming] {
+                if let Some(values) = s.timings.percentiles(stage, &[0.5, 0.95]) {
+                    for (q, v) in [0.5, 0.95].iter().zip(values) {
+                        let _ = writeln!(out, "ollamamq_experiment_stage_seconds{{{},stage=\"{}\",quantile=\"{}\"}} {:.3}", label(key), stage.name(), q, v.as_secs_f64());
+                    }
+                }
+            }
+        }
+    }
+
+    let (hits, misses) = {
+        let catalogue = state.catalogue.lock().unwrap();
+        (catalogue.hits, catalogue.misses)
+    };
+    write_family(&mut out, "ollamamq_model_cache_hits_total", "counter", "/api/tags and /api/show requests answered from the model catalogue.");
+    let _ = writeln!(out, "ollamamq_model_cache_hits_total {}", hits);
+    write_family(&mut out, "ollamamq_model_cache_misses_total", "counter", "/api/tags and /api/show requests the model catalogue had no fresh answer for.");
+    let _ = writeln!(out, "ollamamq_model_cache_misses_total {}", misses);
+
+    {
+        let jail = state.jail.lock().unwrap();
+        write_family(&mut out, "ollamamq_auth_failures_total", "counter", "401/403 answers to clients that weren't already blocked.");
+        let _ = writeln!(out, "ollamamq_auth_failures_total {}", jail.failed_total);
+        write_family(&mut out, "ollamamq_blocked_attempts_total", "counter", "Requests refused because their IP or user is blocked.");
+        let _ = writeln!(out, "ollamamq_blocked_attempts_total {}", jail.blocked_total);
+        write_family(&mut out, "ollamamq_auth_bans_total", "counter", "Temporary IP blocks after repeated failed authentication.");
+        let _ = writeln!(out, "ollamamq_auth_bans_total {}", jail.bans_total);
+        write_family(&mut out, "ollamamq_auth_banned_ips", "gauge", "IPs currently under a temporary block.");
+        let _ = writeln!(out, "ollamamq_auth_banned_ips {}", jail.banned().len());
+    }
+
+    write_family(&mut out, "ollamamq_open_connections", "gauge", "Client connections currently open.");
+    let _ = writeln!(out, "ollamamq_open_connections {}", state.sockets.total());
+    write_family(&mut out, "ollamamq_connections_rejected_total", "counter", "Connections closed on accept because their IP reached --max-connections-per-ip.");
+    let _ = writeln!(out, "ollamamq_connections_rejected_total {}", state.sockets.rejected.load(std::sync::atomic::Ordering::Relaxed));
+
+    if state.config.websockets {
+        let sockets = state.websockets.lock().unwrap();
+        write_family(&mut out, "ollamamq_websocket_connections", "gauge", "Proxied WebSocket connections currently open, per user.");
+        for (user, open) in sockets.by_user() {
+            let _ = writeln!(out, "ollamamq_websocket_connections{{user=\"{}\"}} {}", escape_label(&user), open);
+        }
+        write_family(&mut out, "ollamamq_websocket_opened_total", "counter", "WebSocket connections opened through the dispatcher.");
+        let _ = writeln!(out, "ollamamq_websocket_opened_total {}", sockets.opened_total);
+        write_family(&mut out, "ollamamq_websocket_rejected_total", "counter", "WebSocket upgrades refused because the user reached --websocket-max-per-user.");
+        let _ = writeln!(out, "ollamamq_websocket_rejected_total {}", sockets.rejected_total);
+    }
+
+    write_family(&mut out, "ollamamq_queued_body_bytes", "gauge", "Bytes held by the bodies of queued requests.");
+    let _ = writeln!(out, "ollamamq_queued_body_bytes {}", state.queued_bytes());
+    write_family(&mut out, "ollamamq_queued_body_bytes_limit", "gauge", "Limit on queued body bytes set with --max-queued-mb (0 = unlimited).");
+    let _ = writeln!(out, "ollamamq_queued_body_bytes_limit {}", state.config.max_queued_bytes);
+    write_family(&mut out, "ollamamq_detached_requests", "gauge", "Resumable requests whose client went away, waiting for it to re-attach.");
+    let _ = writeln!(out, "ollamamq_detached_requests {}", state.detached.lock().unwrap().len());
+    write_family(&mut out, "ollamamq_reattached_total", "counter", "Resumable requests a reconnecting client re-attached to.");
+    let _ = writeln!(out, "ollamamq_reattached_total {}", state.reattached.load(std::sync::atomic::Ordering::Relaxed));
+    write_family(&mut out, "ollamamq_model_fallbacks_total", "counter", "Requests switched to a fallback model from model_fallbacks.");
+    let _ = writeln!(out, "ollamamq_model_fallbacks_total {}", state.model_fallbacks.load(std::sync::atomic::Ordering::Relaxed));
+
+    write_family(&mut out, "ollamamq_spooled_body_bytes", "gauge", "Bytes of queued bodies moved to --spool-dir.");
+    let _ = writeln!(out, "ollamamq_spooled_body_bytes {}", state.spooled_bytes());
+    write_family(&mut out, "ollamamq_spooled_requests_total", "counter", "Queued requests whose body was moved to --spool-dir.");
+    let _ = writeln!(out, "ollamamq_spooled_requests_total {}", state.spooled_tasks.load(std::sync::atomic::Ordering::Relaxed));
+    write_family(&mut out, "ollamamq_memory_rejected_total", "counter", "Requests rejected because queued bodies reached --max-queued-mb.");
+    let _ = writeln!(out, "ollamamq_memory_rejected_total {}", state.memory_rejected.load(std::sync::atomic::Ordering::Relaxed));
+
+    write_family(&mut out, "ollamamq_slow_client_responses_total", "counter", "Responses the slow-client policy disconnected, spilled to disk or skipped chunks of.");
+    let _ = writeln!(
+        out,
+        "ollamamq_slow_client_responses_total{{policy=\"{}\"}} {}",
+        state.config.slow_client_policy.as_str(),
+        state.slow_client_responses.load(std::sync::atomic::Ordering::Relaxed)
+    );
+
+    write_family(&mut out, "ollamamq_cancelled_requests_total", "counter", "Backend requests dropped because their client disconnected, by stage (waiting for the answer, or streaming it).");
+    for stage in CancelStage::ALL {
+        let _ = writeln!(
+            out,
+            "ollamamq_cancelled_requests_total{{stage=\"{}\"}} {}",
+            stage.as_str(),
+            state.cancellations[stage as usize].load(std::sync::atomic::Ordering::Relaxed)
+        );
+    }
+    write_family(&mut out, "ollamamq_cancelled_backend_seconds_saved_total", "counter", "Estimated backend (GPU) time saved by those cancellations: the recent median request duration beyond the time already spent.");
+    let _ = writeln!(
+        out,
+        "ollamamq_cancelled_backend_seconds_saved_total {:.3}",
+        state.cancel_saved_ms.load(std::sync::atomic::Ordering::Relaxed) as f64 / 1000.0
+    );
+
+    let quantiles = [0.5, 0.9, 0.99];
+    let stages = state.stage_stats.lock().unwrap();
+    write_family(
+        &mut out,
+        "ollamamq_request_stage_seconds",
+        "gauge",
+        "Per-stage latency percentiles over recently completed requests.",
+    );
+    for stage in Stage::ALL {
+        if let Some(values) = stages.percentiles(stage, &quantiles) {
+            for (q, v) in quantiles.iter().zip(values) {
+                let _ = writeln!(
+                    out,
+                    "ollamamq_request_stage_seconds{{stage=\"{}\",quantile=\"{}\"}} {:.3}",
+                    stage.name(),
+                    q,
+                    v.as_secs_f64()
+                );
+            }
+        }
+    }
+    write_family(&mut out, "ollamamq_request_stage_samples", "gauge", "Completed requests the stage percentiles are based on.");
+    let _ = writeln!(out, "ollamamq_request_stage_samples {}", stages.len());
+
+    out
+}
+
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&state),
+    )
+}
@@ -0,0 +1,96 @@
+//! Namespaces let separate teams share one dispatcher. A request's namespace
+//! comes from its API key or the `X-Namespace` header, and its user id is
+//! qualified as `namespace/user`, so queues, quotas, blocks and statistics of
+//! one team never mix with another's.
+
+use axum::http::{HeaderMap, header};
+use serde::Deserialize;
+
+use crate::config::FileConfig;
+
+pub const HEADER: &str = "X-Namespace";
+/// Separates the namespace from the user id in qualified ids.
+pub const SEPARATOR: char = '/';
+
+const MAX_NAME_LEN: usize = 64;
+
+/// One entry of `namespaces` in the config file.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamespaceConfig {
+    /// Keys sent as `Authorization: Bearer <key>` that place a request in this
+    /// namespace. When set, the `X-Namespace` header alone no longer gets in.
+    pub api_keys: Vec<String>,
+    /// Requests the whole namespace may have waiting (0 = unlimited).
+    pub max_queued: Option<usize>,
+}
+
+/// Why a request couldn't be given a namespace.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NamespaceError {
+    /// The header names something that isn't a valid namespace.
+    Invalid(String),
+    /// The namespace requires one of its API keys.
+    KeyRequired(String),
+}
+
+/// Namespace names: letters, digits, `-`, `_` and `.`, at most 64 characters.
+pub fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Namespace whose API key the request carries.
+pub fn key_namespace<'a>(config: &'a FileConfig, headers: &HeaderMap) -> Option<&'a str> {
+    let key = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))?;
+    config.namespaces.iter()
+        .find(|(_, ns)| ns.api_keys.iter().any(|k| k == key))
+        .map(|(name, _)| name.as_str())
+}
+
+/// Namespace of a request: its API key wins over the header, and no namespace
+/// at all leaves the user id as it is.
+pub fn resolve(config: &FileConfig, headers: &HeaderMap) -> Result<Option<String>, NamespaceError> {
+    if let Some(name) = key_namespace(config, headers) {
+        return Ok(Some(name.to_string()));
+    }
+    let Some(name) = headers.get(HEADER).and_then(|v| v.to_str().ok()).map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    if !is_valid(name) {
+        return Err(NamespaceError::Invalid(name.to_string()));
+    }
+    if config.namespaces.get(name).is_some_and(|ns| !ns.api_keys.is_empty()) {
+        return Err(NamespaceError::KeyRequired(name.to_string()));
+    }
+    Ok(Some(name.to_string()))
+}
+
+/// `namespace/user`, or the plain user id outside any namespace.
+pub fn qualify(namespace: Option<&str>, user_id: &str) -> String {
+    match namespace {
+        Some(ns) => format!("{}{}{}", ns, SEPARATOR, user_id),
+        None => user_id.to_string(),
+    }
+}
+
+/// Namespace part of a (qualified) user id.
+pub fn namespace_of(user_id: &str) -> Option<&str> {
+    user_id.split_once(SEPARATOR).map(|(ns, _)| ns)
+}
+
+/// Whether `user_id` belongs to `namespace`; no filter matches everyone.
+pub fn in_namespace(user_id: &str, namespace: Option<&str>) -> bool {
+    namespace.is_none_or(|ns| namespace_of(user_id) == Some(ns))
+}
+
+/// Namespace-wide queue cap for `user_id`, with the namespace name.
+pub fn max_queued<'a>(config: &'a FileConfig, user_id: &str) -> Option<(&'a str, usize)> {
+    let ns = namespace_of(user_id)?;
+    let (name, ns) = config.namespaces.get_key_value(ns)?;
+    ns.max_queued.filter(|n| *n > 0).map(|n| (name.as_str(), n))
+}
@@ -0,0 +1,380 @@
+//! Optional NATS JetStream work queue shared by several ollamaMQ instances.
+//!
+//! Instances accepting a request publish it to the stream instead of queuing it
+//! locally and relay the response from a per-request reply subject. Every
+//! instance with capacity pulls tasks into its own user queues, where the
+//! regular worker runs them on the local backends. Response parts are sent back
+//! as core NATS messages, and the JetStream message is acked once the response
+//! has been streamed completely.
+
+use async_nats::jetstream::{
+    self, AckKind,
+    consumer::{AckPolicy, PullConsumer, pull},
+    stream::{self, RetentionPolicy},
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::sync::{Semaphore, mpsc};
+use tracing::{debug, info, warn};
+
+use crate::dispatcher::{AppState, Lane, ResponsePart, Task};
+
+const PREFIX: &str = "Ollamamq-";
+const H_USER: &str = "Ollamamq-User";
+const H_METHOD: &str = "Ollamamq-Method";
+const H_PATH: &str = "Ollamamq-Path";
+const H_REPLY: &str = "Ollamamq-Reply";
+const H_EXPERIMENT: &str = "Ollamamq-Experiment";
+const H_ARM: &str = "Ollamamq-Arm";
+/// Kind of a response part: `status`, `chunk`, `error` or `end`.
+const H_PART: &str = "Ollamamq-Part";
+const H_STATUS: &str = "Ollamamq-Status";
+/// Delivery attempt that produced a response part.
+const H_DELIVERY: &str = "Ollamamq-Delivery";
+
+/// How long one pull request waits for a task before asking again.
+const PULL_EXPIRES: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct NatsConfig {
+    pub url: String,
+    pub stream: String,
+    pub subject: String,
+    /// Durable consumer shared by all instances.
+    pub consumer: String,
+    /// Tasks this instance pulls and holds at once (0 = publish only).
+    pub max_pending: usize,
+    /// Redelivery timeout of an unacked task; extended while the task is running.
+    pub ack_wait: Duration,
+    /// Delivery attempts before a task is answered with an error.
+    pub max_deliver: i64,
+}
+
+/// Requests one user published through this instance that are still waiting
+/// for their response to finish.
+#[derive(Clone, Copy, Default)]
+pub struct Pending {
+    pub requests: usize,
+    pub prompt_tokens: u64,
+}
+
+pub struct NatsQueue {
+    client: async_nats::Client,
+    jetstream: jetstream::Context,
+    consumer: PullConsumer,
+    config: NatsConfig,
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+impl NatsQueue {
+    /// Connects and creates the work-queue stream and its durable consumer if needed.
+    pub async fn connect(config: NatsConfig) -> Result<Self, String> {
+        let client = async_nats::connect(&config.url).await
+            .map_err(|e| format!("cannot connect to NATS at {}: {}", config.url, e))?;
+        let jetstream = jetstream::new(client.clone());
+        let stream = jetstream
+            .get_or_create_stream(stream::Config {
+                name: config.stream.clone(),
+                subjects: vec![config.subject.clone()],
+                retention: RetentionPolicy::WorkQueue,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| format!("cannot create stream {}: {}", config.stream, e))?;
+        let consumer = stream
+            .get_or_create_consumer(
+                &config.consumer,
+                pull::Config {
+                    durable_name: Some(config.consumer.clone()),
+                    ack_policy: AckPolicy::Explicit,
+                    ack_wait: config.ack_wait,
+                    max_deliver: config.max_deliver,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| format!("cannot create consumer {}: {}", config.consumer, e))?;
+        info!("Sharing the queue through NATS stream {} ({})", config.stream, config.url);
+        Ok(Self { client, jetstream, consumer, config, pending: Arc::default() })
+    }
+
+    /// Requests published through this instance per user. The stream can't
+    /// tell how many of a user's requests are waiting, so admission limits
+    /// count these; add a request before `submit`, which takes it off again.
+    pub fn pending(&self) -> MutexGuard<'_, HashMap<String, Pending>> {
+        self.pending.lock().unwrap()
+    }
+
+    /// Publishes a task for any instance to run and relays its response parts to
+    /// `task.responder`. Fails if the stream didn't accept the task.
+    pub async fn submit(&self, user_id: &str, task: Task) -> Result<(), String> {
+        let pending = PendingGuard {
+            pending: self.pending.clone(),
+            user_id: user_id.to_string(),
+            prompt_tokens: task.prompt_tokens,
+        };
+        let reply = self.client.new_inbox();
+        let mut replies = self.client.subscribe(reply.clone()).await.map_err(|e| e.to_string())?;
+
+        let mut headers = async_nats::HeaderMap::new();
+        for (name, value) in task.headers.iter() {
+            if let Ok(value) = value.to_str() {
+                headers.append(name.as_str(), value);
+            }
+        }
+        headers.insert(H_USER, user_id);
+        headers.insert(H_METHOD, task.method.as_str());
+        headers.insert(H_PATH, task.path.as_str());
+        headers.insert(H_REPLY, reply.as_str());
+        if let Some(ref a) = task.experiment {
+            headers.insert(H_EXPERIMENT, a.experiment.as_str());
+            headers.insert(H_ARM, a.arm.as_str());
+        }
+        self.jetstream
+            .publish_with_headers(self.config.subject.clone(), headers, task.body)
+            .await
+            .map_err(|e| e.to_string())?
+            .await
+            .map_err(|e| e.to_string())?;
+        debug!("Published task {} of user {} to NATS", task.id, user_id);
+
+        let responder = task.responder;
+        tokio::spawn(async move {
+            let _pending = pending;
+            let mut delivery: Option<String> = None;
+            loop {
+                let msg = tokio::select! {
+                    msg = replies.next() => msg,
+                    // The client went away; a late response is simply not received
+                    _ = responder.closed() => break,
+                };
+                let Some(msg) = msg else {
+                    break;
+                };
+                let headers = msg.headers.unwrap_or_default();
+                let get = |name: &str| headers.get(name).map(|v| v.as_str().to_string());
+
+                // A worker failed mid-response and the task was redelivered; the
+                // client already has part of the first attempt
+                let attempt = get(H_DELIVERY);
+                if delivery.is_none() {
+                    delivery = attempt;
+                } else if attempt != delivery {
+                    let _ = responder.send(ResponsePart::Failed("worker failed while streaming the response".to_string())).await;
+                    break;
+                }
+
+                let part = match get(H_PART).as_deref() {
+                    Some("status") => {
+                        let status = get(H_STATUS).and_then(|s| s.parse::<u16>().ok())
+                            .and_then(|s| StatusCode::from_u16(s).ok())
+                            .unwrap_or(StatusCode::BAD_GATEWAY);
+                        ResponsePart::Status(status, http_headers(&headers))
+                    }
+                    Some("chunk") => ResponsePart::Chunk(msg.payload),
+                    Some("error") => ResponsePart::Failed(String::from_utf8_lossy(&msg.payload).into_owned()),
+                    _ => break,
+                };
+                let last = matches!(part, ResponsePart::Failed(_));
+                if responder.send(part).await.is_err() || last {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn reply(&self, subject: &str, delivery: &str, part: &str, mut headers: async_nats::HeaderMap, payload: Bytes) {
+        headers.insert(H_PART, part);
+        headers.insert(H_DELIVERY, delivery);
+        if let Err(e) = self.client.publish_with_headers(subject.to_string(), headers, payload).await {
+            warn!("Failed to send NATS reply to {}: {}", subject, e);
+        }
+    }
+}
+
+/// Takes a submitted request off `NatsQueue::pending` once its response is over.
+struct PendingGuard {
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+    user_id: String,
+    prompt_tokens: u64,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(p) = pending.get_mut(&self.user_id) {
+            p.requests = p.requests.saturating_sub(1);
+            p.prompt_tokens = p.prompt_tokens.saturating_sub(self.prompt_tokens);
+            if p.requests == 0 {
+                pending.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+/// HTTP headers carried in a NATS message, without ollamaMQ's own.
+fn http_headers(headers: &async_nats::HeaderMap) -> HeaderMap {
+    let mut out = HeaderMap::new();
+    for (name, values) in headers.iter() {
+        let name: &str = name.as_ref();
+        if name.starts_with(PREFIX) {
+            continue;
+        }
+        let Ok(name) = HeaderName::try_from(name) else {
+            continue;
+        };
+        for value in values {
+            if let Ok(value) = HeaderValue::try_from(value.as_str()) {
+                out.append(name.clone(), value);
+            }
+        }
+    }
+    out
+}
+
+/// Pulls tasks from the shared stream while this instance has capacity for them.
+pub async fn run_consumer(state: Arc<AppState>) {
+    let Some(queue) = state.nats.get() else {
+        return;
+    };
+    if queue.config.max_pending == 0 {
+        return;
+    }
+    let capacity = Arc::new(Semaphore::new(queue.config.max_pending));
+    loop {
+        let permit = capacity.clone().acquire_owned().await.expect("semaphore is never closed");
+        let batch = queue.consumer.batch().max_messages(1).expires(PULL_EXPIRES).messages().await;
+        let mut batch = match batch {
+            Ok(batch) => batch,
+            Err(e) => {
+                warn!("NATS pull failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        match batch.next().await {
+            Some(Ok(msg)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    run_task(&state, msg).await;
+                    drop(permit);
+                });
+            }
+            Some(Err(e)) => {
+                warn!("NATS pull failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            // Nothing arrived before the pull expired
+            None => {}
+        }
+    }
+}
+
+/// Queues one pulled task locally, streams its response back and acks it.
+async fn run_task(state: &Arc<AppState>, msg: jetstream::Message) {
+    let queue = state.nats.get().expect("consumer runs only with NATS");
+    let headers = msg.headers.clone().unwrap_or_default();
+    let get = |name: &str| headers.get(name).map(|v| v.as_str().to_string());
+    let (Some(user_id), Some(reply), Some(method), Some(path)) = (get(H_USER), get(H_REPLY), get(H_METHOD), get(H_PATH)) else {
+        warn!("Dropping NATS message without task headers on {}", msg.subject);
+        let _ = msg.ack_with(AckKind::Term).await;
+        return;
+    };
+    let delivered = msg.info().map(|i| i.delivered).unwrap_or(1);
+    let delivery = delivered.to_string();
+
+    let task_headers = http_headers(&headers);
+    let json = serde_json::from_slice::<serde_json::Value>(&msg.payload).ok();
+    let requested_model = json.as_ref()
+        .and_then(|json| json.get("model").and_then(|m| m.as_str()).map(|s| s.to_string()));
+    let batchable = crate::dispatcher::is_batchable(&path, json.as_ref());
+    let (tx, mut rx) = mpsc::channel(32);
+    let task = Task {
+        id: state.next_task_id(),
+        method: Method::from_bytes(method.as_bytes()).unwrap_or(Method::POST),
+        canary: state.roll_canary(requested_model.as_deref()),
+        lane: Lane::from_headers(&task_headers),
+        affinity_key: state.config.session_affinity.key(&user_id, &task_headers),
+        path,
+        headers: task_headers,
+        body: msg.payload.clone(),
+        responder: tx,
+        requested_model,
+        prompt_tokens: json.as_ref().map_or(0, crate::capabilities::estimate_prompt_tokens),
+        debug_trace: false,
+        batchable,
+        tools: json.as_ref().is_some_and(crate::tools::offers_tools),
+        experiment: get(H_EXPERIMENT).zip(get(H_ARM)).and_then(|(e, a)| crate::experiments::lookup(&state.config.file, &e, &a)),
+        enqueued_at: Instant::now(),
+        queued_bytes: state.hold_queued_bytes(msg.payload.len()),
+        spooled: None,
+        resumable: false,
+        fallback_from: None,
+    };
+    debug!("Pulled task {} of user {} from NATS (delivery {})", task.id, user_id, delivered);
+    state.queues.lock().unwrap().entry(user_id).or_default().push_back(task);
+    state.notify.notify_one();
+
+    let mut progress = tokio::time::interval(queue.config.ack_wait / 2);
+    progress.tick().await;
+    let mut started = false;
+    let mut failure: Option<String> = None;
+    loop {
+        let part = tokio::select! {
+            part = rx.recv() => part,
+            _ = progress.tick() => {
+                let _ = msg.ack_with(AckKind::Progress).await;
+                continue;
+            }
+        };
+        match part {
+            Some(ResponsePart::Status(status, http)) => {
+                started = true;
+                let mut headers = async_nats::HeaderMap::new();
+                for (name, value) in http.iter() {
+                    if let Ok(value) = value.to_str() {
+                        headers.append(name.as_str(), value);
+                    }
+                }
+                headers.insert(H_STATUS, status.as_u16().to_string());
+                queue.reply(&reply, &delivery, "status", headers, Bytes::new()).await;
+            }
+            Some(ResponsePart::Chunk(chunk)) => {
+                queue.reply(&reply, &delivery, "chunk", async_nats::HeaderMap::new(), chunk).await;
+            }
+            Some(ResponsePart::Error(e)) => failure = Some(format!("Backend error: {}", e)),
+            Some(ResponsePart::Failed(e)) => failure = Some(e),
+            None => break,
+        }
+    }
+
+    // Nothing reached the client yet: let another attempt (maybe on another instance) take it
+    let retry = !started && delivered < queue.config.max_deliver;
+    if retry {
+        debug!("Returning NATS task to the stream (delivery {})", delivered);
+        let _ = msg.ack_with(AckKind::Nak(None)).await;
+        return;
+    }
+    match failure {
+        Some(e) => queue.reply(&reply, &delivery, "error", async_nats::HeaderMap::new(), Bytes::from(e)).await,
+        None if !started => {
+            let e = Bytes::from_static(b"request dropped before reaching a backend");
+            queue.reply(&reply, &delivery, "error", async_nats::HeaderMap::new(), e).await;
+        }
+        None => queue.reply(&reply, &delivery, "end", async_nats::HeaderMap::new(), Bytes::new()).await,
+    }
+    if let Err(e) = queue.client.flush().await {
+        warn!("Failed to flush NATS replies: {}", e);
+    }
+    let acked = if started { msg.double_ack().await } else { msg.ack_with(AckKind::Term).await };
+    if let Err(e) = acked {
+        warn!("Failed to ack NATS task: {}", e);
+    }
+}
@@ -0,0 +1,88 @@
+use bytes::{Bytes, BytesMut};
+
+/// What to do with NDJSON streams coming back from backends.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StreamValidation {
+    /// Forward chunks untouched.
+    #[default]
+    Off,
+    /// Forward chunks untouched but count malformed lines in stats.
+    Flag,
+    /// Re-frame the stream so every forwarded chunk holds whole lines.
+    Repair,
+    /// Re-frame the stream and drop lines that are not valid JSON.
+    Drop,
+}
+
+/// Line-buffering checker for `application/x-ndjson` responses. Lines split across
+/// chunks are reassembled before being parsed, so only real garbage is flagged.
+pub struct NdjsonValidator {
+    mode: StreamValidation,
+    pending: BytesMut,
+    malformed: usize,
+}
+
+impl NdjsonValidator {
+    pub fn new(mode: StreamValidation) -> Self {
+        Self {
+            mode,
+            pending: BytesMut::new(),
+            malformed: 0,
+        }
+    }
+
+    /// Number of malformed lines seen so far.
+    pub fn malformed(&self) -> usize {
+        self.malformed
+    }
+
+    /// Feeds one backend chunk and returns the bytes to forward to the client.
+    pub fn push(&mut self, chunk: Bytes) -> Bytes {
+        self.pending.extend_from_slice(&chunk);
+        let complete = match self.pending.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => self.pending.split_to(pos + 1).freeze(),
+            None => Bytes::new(),
+        };
+
+        let checked = self.check_lines(&complete);
+        if self.mode == StreamValidation::Flag {
+            chunk
+        } else {
+            checked
+        }
+    }
+
+    /// Flushes a trailing line that was not newline-terminated.
+    pub fn finish(&mut self) -> Bytes {
+        if self.pending.is_empty() {
+            return Bytes::new();
+        }
+        let mut rest = self.pending.split().to_vec();
+        rest.push(b'\n');
+        let checked = self.check_lines(&rest);
+        if self.mode == StreamValidation::Flag {
+            Bytes::new() // already forwarded verbatim by `push`
+        } else {
+            checked
+        }
+    }
+
+    fn check_lines(&mut self, data: &[u8]) -> Bytes {
+        let mut out = BytesMut::with_capacity(data.len());
+        for line in data.split(|&b| b == b'\n') {
+            let trimmed = line.trim_ascii();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if serde_json::from_slice::<serde::de::IgnoredAny>(trimmed).is_err() {
+                self.malformed += 1;
+                if self.mode == StreamValidation::Drop {
+                    continue;
+                }
+            }
+            out.extend_from_slice(line);
+            out.extend_from_slice(b"\n");
+        }
+        out.freeze()
+    }
+}
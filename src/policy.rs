@@ -0,0 +1,83 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Request-body rules for one route, configured under `policies` in the config file.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutePolicy {
+    /// Fields that must be present; nested fields use dots (`options.num_ctx`).
+    pub required: Vec<String>,
+    /// Keys permitted inside `options`; any other key is rejected.
+    pub allowed_options: Option<Vec<String>>,
+    /// Upper bound on the length of the `messages` array.
+    pub max_messages: Option<usize>,
+}
+
+/// A rule violation, naming the offending field (`None` when the body as a whole is wrong).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl PolicyViolation {
+    pub(crate) fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: Some(field.into()),
+            message: message.into(),
+        }
+    }
+
+    fn body(message: impl Into<String>) -> Self {
+        Self {
+            field: None,
+            message: message.into(),
+        }
+    }
+}
+
+fn lookup<'a>(body: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(body, |v, key| v.get(key))
+}
+
+impl RoutePolicy {
+    pub fn check(&self, body: &[u8]) -> Result<(), PolicyViolation> {
+        let json: Value = serde_json::from_slice(body)
+            .map_err(|e| PolicyViolation::body(format!("request body is not valid JSON: {}", e)))?;
+        if !json.is_object() {
+            return Err(PolicyViolation::body("request body must be a JSON object"));
+        }
+
+        for field in &self.required {
+            if lookup(&json, field).is_none_or(Value::is_null) {
+                return Err(PolicyViolation::new(field.as_str(), format!("missing required field '{}'", field)));
+            }
+        }
+
+        if let Some(ref allowed) = self.allowed_options
+            && let Some(options) = json.get("options")
+        {
+            let Some(options) = options.as_object() else {
+                return Err(PolicyViolation::new("options", "'options' must be an object"));
+            };
+            if let Some(key) = options.keys().find(|k| !allowed.contains(k)) {
+                return Err(PolicyViolation::new(
+                    format!("options.{}", key),
+                    format!("option '{}' is not allowed (allowed: {})", key, allowed.join(", ")),
+                ));
+            }
+        }
+
+        if let Some(max) = self.max_messages
+            && let Some(messages) = json.get("messages").and_then(Value::as_array)
+            && messages.len() > max
+        {
+            return Err(PolicyViolation::new(
+                "messages",
+                format!("'messages' has {} entries (max {})", messages.len(), max),
+            ));
+        }
+
+        Ok(())
+    }
+}
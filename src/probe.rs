@@ -0,0 +1,337 @@
+//! Backend checks: the probes the health check runs every round, and a tiny
+//! generation on top of them for `ollamaMQ probe`, so the question "why is this
+//! backend offline" is answered by the same code that decided it.
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use crate::compat::BackendFeatures;
+use crate::dispatcher::{AppState, BackendApiType};
+
+/// How often `--wait-for-backends` probes the backends.
+const WAIT_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest a single probe may take while waiting, so one unreachable host
+/// doesn't hold up the others.
+const WAIT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the wait logs its progress.
+const WAIT_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How one endpoint of the backend answered.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckLevel {
+    /// Answered as expected.
+    Ok,
+    /// Not there (error status or no connection); normal for endpoints of the
+    /// other API flavour.
+    Missing,
+    /// Answered, but with something the dispatcher can't use.
+    Problem,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Finding {
+    /// Endpoint path, e.g. `/api/tags`.
+    pub endpoint: &'static str,
+    pub level: CheckLevel,
+    pub detail: String,
+}
+
+/// What one health check round found out about a backend.
+#[derive(Serialize, Default, Debug)]
+pub struct BackendCheck {
+    pub online: bool,
+    #[serde(serialize_with = "api_type_name")]
+    pub api_type: BackendApiType,
+    pub models: HashSet<String>,
+    pub loaded: HashSet<String>,
+    /// Ollama models with their digests, from `/api/tags`.
+    #[serde(skip)]
+    pub digests: Vec<(String, Option<String>)>,
+    /// Raw `/api/tags` entries, for the model catalogue.
+    #[serde(skip)]
+    pub tag_entries: Option<Vec<Value>>,
+    #[serde(skip)]
+    pub features: Option<BackendFeatures>,
+    pub findings: Vec<Finding>,
+}
+
+fn api_type_name<S: serde::Serializer>(t: &BackendApiType, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(t.as_str())
+}
+
+impl BackendCheck {
+    fn note(&mut self, endpoint: &'static str, level: CheckLevel, detail: impl Into<String>) {
+        self.findings.push(Finding { endpoint, level, detail: detail.into() });
+    }
+}
+
+/// An error with its causes; reqwest's own message leaves out why the
+/// connection failed (refused, DNS, TLS).
+fn error_chain(e: &reqwest::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+/// A body that should be JSON, or why it isn't.
+async fn json_body(res: reqwest::Response) -> Result<Value, String> {
+    let body = res.text().await.map_err(|e| format!("body could not be read: {}", e))?;
+    serde_json::from_str::<Value>(&body).map_err(|_| {
+        let shown: String = body.chars().take(200).collect();
+        format!("responded 200 but body is not valid JSON: {}", shown.trim())
+    })
+}
+
+/// Probes the Ollama endpoints (`/api/tags`, `/api/ps`, `/api/version`), the
+/// OpenAI `/v1/models`, and `/` when neither answers.
+pub async fn check_backend(client: &reqwest::Client, url: &str) -> BackendCheck {
+    let mut check = BackendCheck::default();
+
+    // Probe Ollama API: /api/tags → expects {"models": [...]}
+    match client.get(format!("{}/api/tags", url)).send().await {
+        Ok(res) if res.status().is_success() => {
+            check.online = true;
+            match json_body(res).await {
+                Ok(json) => match json.get("models").and_then(|m| m.as_array()) {
+                    Some(models_json) => {
+                        check.api_type = check.api_type.merge(BackendApiType::Ollama);
+                        check.note("/api/tags", CheckLevel::Ok, format!("Ollama API, {} model(s)", models_json.len()));
+                        for m in models_json {
+                            if let Some(name) = m.get("name").and_then(|n| n.as_str()) {
+                                check.models.insert(name.to_string());
+                                let digest = m.get("digest").and_then(|d| d.as_str()).map(|d| d.to_string());
+                                check.digests.push((name.to_string(), digest));
+                            }
+                        }
+                        check.tag_entries = Some(models_json.clone());
+                    }
+                    None => check.note("/api/tags", CheckLevel::Problem,
+                        format!("responded 200 but 'models' array not found or invalid. Body: {}", json)),
+                },
+                Err(e) => check.note("/api/tags", CheckLevel::Problem, e),
+            }
+        }
+        Ok(res) if res.status().is_redirection() => {
+            let location = res.headers().get(axum::http::header::LOCATION).and_then(|v| v.to_str().ok()).unwrap_or("");
+            check.note("/api/tags", CheckLevel::Problem, format!("redirects ({}) to '{}'; update its URL", res.status(), location));
+        }
+        Ok(res) => check.note("/api/tags", CheckLevel::Missing, format!("returned status: {}", res.status())),
+        Err(e) => check.note("/api/tags", CheckLevel::Missing, format!("error: {}", error_chain(&e))),
+    }
+
+    // Also check for loaded models via /api/ps if it was an Ollama-like response
+    if check.online
+        && let Ok(res) = client.get(format!("{}/api/ps", url)).send().await
+        && res.status().is_success()
+        && let Ok(json) = json_body(res).await
+        && let Some(models_json) = json.get("models").and_then(|m| m.as_array())
+    {
+        for m in models_json {
+            if let Some(name) = m.get("name").and_then(|n| n.as_str()) {
+                check.loaded.insert(name.to_string());
+            }
+        }
+        check.note("/api/ps", CheckLevel::Ok, format!("{} model(s) loaded", check.loaded.len()));
+    }
+
+    // Older Ollama versions lack some endpoints and fields; requests get adapted to what it supports
+    if check.api_type == BackendApiType::Ollama {
+        match client.get(format!("{}/api/version", url)).send().await {
+            Ok(res) if res.status().is_success() => {
+                match json_body(res).await.map(|json| json.get("version").and_then(|v| v.as_str()).map(BackendFeatures::from_version)) {
+                    Ok(Some(features)) => {
+                        let missing = features.missing();
+                        let detail = if missing.is_empty() {
+                            format!("Ollama {}", features.version)
+                        } else {
+                            format!("Ollama {} without {}; requests will be adapted", features.version, missing.join(", "))
+                        };
+                        check.note("/api/version", CheckLevel::Ok, detail);
+                        check.features = Some(features);
+                    }
+                    Ok(None) => check.note("/api/version", CheckLevel::Problem, "no 'version' in the answer"),
+                    Err(e) => check.note("/api/version", CheckLevel::Problem, e),
+                }
+            }
+            Ok(res) => check.note("/api/version", CheckLevel::Missing, format!("returned status: {}", res.status())),
+            Err(e) => check.note("/api/version", CheckLevel::Missing, format!("error: {}", error_chain(&e))),
+        }
+    }
+
+    // Probe OpenAI API: /v1/models → expects {"data": [...]}
+    match client.get(format!("{}/v1/models", url)).send().await {
+        Ok(res) if res.status().is_success() => {
+            check.online = true;
+            match json_body(res).await {
+                Ok(json) => match json.get("data").and_then(|d| d.as_array()) {
+                    Some(data_json) => {
+                        check.api_type = check.api_type.merge(BackendApiType::OpenAi);
+                        check.note("/v1/models", CheckLevel::Ok, format!("OpenAI API, {} model(s)", data_json.len()));
+                        for m in data_json {
+                            if let Some(id) = m.get("id").and_then(|i| i.as_str()) {
+                                check.models.insert(id.to_string());
+                            }
+                        }
+                    }
+                    None => check.note("/v1/models", CheckLevel::Problem,
+                        format!("responded 200 but 'data' array not found or invalid. Body: {}", json)),
+                },
+                Err(e) => check.note("/v1/models", CheckLevel::Problem, e),
+            }
+        }
+        Ok(res) => check.note("/v1/models", CheckLevel::Missing, format!("returned status: {}", res.status())),
+        Err(e) => check.note("/v1/models", CheckLevel::Missing, format!("error: {}", error_chain(&e))),
+    }
+
+    // Fallback: just check root if both specific probes failed
+    if !check.online {
+        match client.get(format!("{}/", url)).send().await {
+            Ok(res) if res.status().is_success() => {
+                check.online = true;
+                check.note("/", CheckLevel::Ok, "answers, but speaks neither API");
+            }
+            Ok(res) => check.note("/", CheckLevel::Missing, format!("returned status: {}", res.status())),
+            Err(e) => check.note("/", CheckLevel::Missing, format!("error: {}", error_chain(&e))),
+        }
+    }
+    check
+}
+
+/// Result of a one-token generation.
+#[derive(Serialize, Debug)]
+pub struct Generation {
+    pub endpoint: &'static str,
+    pub model: String,
+    pub ok: bool,
+    pub status: Option<u16>,
+    pub millis: u64,
+    pub detail: String,
+}
+
+/// Generates a single token with `model`, through `/api/generate` on Ollama
+/// backends and `/v1/chat/completions` on OpenAI ones. Answers are judged the
+/// way the worker judges them: redirects and HTML pages are failures.
+pub async fn try_generation(client: &reqwest::Client, url: &str, api_type: BackendApiType, model: &str) -> Generation {
+    let (endpoint, body) = if api_type == BackendApiType::OpenAi {
+        ("/v1/chat/completions", json!({ "model": model, "messages": [{ "role": "user", "content": "Hi" }], "max_tokens": 1, "stream": false }))
+    } else {
+        ("/api/generate", json!({ "model": model, "prompt": "Hi", "stream": false, "options": { "num_predict": 1 } }))
+    };
+    let started = Instant::now();
+    let mut generation = Generation { endpoint, model: model.to_string(), ok: false, status: None, millis: 0, detail: String::new() };
+    match client.post(format!("{}{}", url, endpoint)).json(&body).send().await {
+        Ok(res) => {
+            generation.status = Some(res.status().as_u16());
+            if crate::dispatcher::is_unusable_response(endpoint, &res) {
+                generation.detail = crate::dispatcher::unusable_response_error(url, res).await.2;
+            } else if !res.status().is_success() {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                let error = serde_json::from_str::<Value>(&text).ok()
+                    .and_then(|v| v.get("error").map(|e| e.get("message").unwrap_or(e).as_str().map(str::to_string).unwrap_or_else(|| e.to_string())))
+                    .unwrap_or_else(|| text.trim().chars().take(200).collect());
+                generation.detail = format!("{}: {}", status, error);
+            } else {
+                match json_body(res).await {
+                    Ok(json) => {
+                        let text = json.get("response").and_then(Value::as_str)
+                            .or_else(|| json.pointer("/choices/0/message/content").and_then(Value::as_str));
+                        generation.ok = text.is_some();
+                        generation.detail = match text {
+                            Some(text) => format!("answered {:?}", text),
+                            None => "answer has no generated text".to_string(),
+                        };
+                    }
+                    Err(e) => generation.detail = e,
+                }
+            }
+        }
+        Err(e) => generation.detail = format!("error: {}", error_chain(&e)),
+    }
+    generation.millis = started.elapsed().as_millis() as u64;
+    generation
+}
+
+/// Model for the generation check when none is given: a loaded one, so the
+/// check doesn't load a model, else the first by name.
+pub fn pick_model(check: &BackendCheck) -> Option<String> {
+    let mut loaded: Vec<&String> = check.loaded.iter().filter(|m| check.models.contains(*m)).collect();
+    loaded.sort();
+    let mut models: Vec<&String> = check.models.iter().collect();
+    models.sort();
+    loaded.first().or(models.first()).map(|m| m.to_string())
+}
+
+/// Backend HTTP client with the timeout, TLS and proxy settings the dispatcher
+/// would use.
+pub fn client_for(backend: Option<&crate::config::BackendConfig>, proxy: Option<&str>, timeout: Duration) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(crate::dispatcher::redirect_policy());
+    let proxy = backend.and_then(|b| b.proxy.as_deref()).or(proxy);
+    let built = match backend {
+        Some(backend) => backend.apply_tls(builder),
+        None => Ok(builder),
+    };
+    built.and_then(|b| crate::config::apply_proxy(b, proxy)).and_then(|b| b.build().map_err(|e| e.to_string()))
+}
+
+/// `--wait-for-backends`: probes every backend until at least one answers or
+/// `timeout` passes, so the dispatcher doesn't accept (and queue) requests for
+/// backends that aren't up yet. Backends are marked online or offline by what
+/// the last round found; `false` if none answered in time.
+pub async fn wait_for_backends(state: &AppState, timeout: Duration) -> bool {
+    let started = Instant::now();
+    let mut logged: Option<Instant> = None;
+    loop {
+        let urls: Vec<String> = state.backends.lock().unwrap().iter().map(|b| b.url.clone()).collect();
+        let mut online = HashSet::new();
+        for url in &urls {
+            let client = match client_for(state.config.file.backend_config(url), state.config.backend_proxy.as_deref(), WAIT_PROBE_TIMEOUT) {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!("Invalid TLS or proxy settings for backend {}: {}", url, e);
+                    continue;
+                }
+            };
+            if check_backend(&client, url).await.online {
+                online.insert(url.clone());
+            }
+        }
+        if !online.is_empty() {
+            for backend in state.backends.lock().unwrap().iter_mut() {
+                backend.is_online = online.contains(&backend.url);
+            }
+            let mut names: Vec<&String> = online.iter().collect();
+            names.sort();
+            info!(
+                "{}/{} backend(s) reachable after {}s: {}",
+                online.len(),
+                urls.len(),
+                started.elapsed().as_secs(),
+                names.iter().map(|u| u.as_str()).collect::<Vec<_>>().join(", ")
+            );
+            return true;
+        }
+        let left = timeout.saturating_sub(started.elapsed());
+        if left.is_zero() {
+            return false;
+        }
+        if logged.is_none_or(|at| at.elapsed() >= WAIT_LOG_INTERVAL) {
+            info!("Waiting for a backend: 0/{} reachable, {}s left", urls.len(), left.as_secs());
+            logged = Some(Instant::now());
+        }
+        tokio::time::sleep(WAIT_INTERVAL.min(left)).await;
+    }
+}
@@ -0,0 +1,398 @@
+//! Self-service queue management: a user can inspect, reorder and cancel their
+//! own queued requests, wait for one to finish, or look up their own usage;
+//! admins can do the same for any user.
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::Duration};
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+
+use crate::admin::path_user;
+use crate::dispatcher::{AppState, Task, caller_id};
+use crate::events::Event;
+use crate::history::HistoryEntry;
+
+/// Longest prompt excerpt shown in queue listings.
+const PREVIEW_CHARS: usize = 60;
+/// Wait of `/api/jobs/{id}/wait` without a `timeout` parameter...
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+/// ...and the longest one allowed.
+const MAX_WAIT: Duration = Duration::from_secs(300);
+/// Finished requests listed by `/api/me`.
+const RECENT_REQUESTS: usize = 20;
+
+#[derive(Serialize)]
+pub struct QueuedTaskInfo {
+    pub task_id: String,
+    /// 0 = next to be dispatched for this user.
+    pub position: usize,
+    pub path: String,
+    pub model: Option<String>,
+    pub lane: &'static str,
+    pub queued_secs: u64,
+    /// Prompt size estimated at admission.
+    pub prompt_tokens_estimate: u64,
+    /// Start of the prompt or last chat message, to tell requests apart.
+    pub preview: Option<String>,
+}
+
+/// `None` for bodies in the spool, which aren't read back for a listing.
+fn preview(task: &Task) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_slice(&task.body).ok()?;
+    let text = json.get("prompt")
+        .or_else(|| json.get("messages")?.as_array()?.last()?.get("content"))?
+        .as_str()?;
+    let mut out: String = text.chars().take(PREVIEW_CHARS).collect();
+    if text.chars().count() > PREVIEW_CHARS {
+        out.push('…');
+    }
+    Some(out)
+}
+
+pub fn queued_tasks(state: &AppState, user_id: &str) -> Vec<QueuedTaskInfo> {
+    let queues = state.queues.lock().unwrap();
+    queues.get(user_id)
+        .map(|q| q.iter().enumerate().map(|(position, t)| QueuedTaskInfo {
+            task_id: t.id.clone(),
+            position,
+            path: t.path.clone(),
+            model: t.requested_model.clone(),
+            lane: t.lane.as_str(),
+            queued_secs: t.enqueued_at.elapsed().as_secs(),
+            prompt_tokens_estimate: t.prompt_tokens,
+            preview: preview(t),
+        }).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct MoveBody {
+    position: usize,
+}
+
+
+fn not_queued(task_id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "task is not queued (unknown, cancelled, or already dispatched)", "task_id": task_id })),
+    )
+        .into_response()
+}
+
+fn list(state: &AppState, user_id: &str) -> Response {
+    Json(queued_tasks(state, user_id)).into_response()
+}
+
+fn get_one(state: &AppState, user_id: &str, task_id: &str) -> Response {
+    match queued_tasks(state, user_id).into_iter().find(|t| t.task_id == task_id) {
+        Some(info) => Json(info).into_response(),
+        None => not_queued(task_id),
+    }
+}
+
+fn cancel(state: &AppState, user_id: &str, task_id: &str) -> Response {
+    if state.cancel_task(user_id, task_id) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        not_queued(task_id)
+    }
+}
+
+fn move_to(state: &AppState, user_id: &str, task_id: &str, position: usize) -> Response {
+    match state.move_task(user_id, task_id, position) {
+        Some(position) => Json(serde_json::json!({ "task_id": task_id, "position": position })).into_response(),
+        None => not_queued(task_id),
+    }
+}
+
+#[derive(Deserialize)]
+struct WaitQuery {
+    timeout: Option<String>,
+}
+
+/// Parses a wait timeout such as `30s`, `500ms`, `2m` or plain seconds.
+fn parse_timeout(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = num.parse().ok()?;
+    match unit {
+        "" | "s" => Some(Duration::from_secs(n)),
+        "ms" => Some(Duration::from_millis(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        _ => None,
+    }
+}
+
+/// Where a task of `user_id` is right now.
+enum JobState {
+    Queued(usize),
+    /// Client gone, waiting for it to re-attach.
+    Detached,
+    Running,
+    Finished(Box<HistoryEntry>),
+}
+
+fn job_state(state: &AppState, user_id: &str, task_id: &str) -> Option<JobState> {
+    // Checked in the order a task moves through, so one in transit is found in the next place
+    if let Some(position) = state.queues.lock().unwrap().get(user_id).and_then(|q| q.iter().position(|t| t.id == task_id)) {
+        return Some(JobState::Queued(position));
+    }
+    if state.detached.lock().unwrap().get(task_id).is_some_and(|d| d.user_id == user_id) {
+        return Some(JobState::Detached);
+    }
+    if state.running_tasks.lock().unwrap().get(task_id).is_some_and(|owner| owner == user_id) {
+        return Some(JobState::Running);
+    }
+    state.history.lock().unwrap().newest_first()
+        .find(|e| e.task_id == task_id && e.user_id == user_id)
+        .map(|e| JobState::Finished(Box::new(e.clone())))
+}
+
+fn job_json(task_id: &str, job: &JobState) -> Value {
+    match job {
+        JobState::Queued(position) => json!({ "task_id": task_id, "state": "queued", "position": position }),
+        JobState::Detached => json!({ "task_id": task_id, "state": "detached" }),
+        JobState::Running => json!({ "task_id": task_id, "state": "running" }),
+        JobState::Finished(e) => json!({
+            "task_id": task_id,
+            "state": "finished",
+            "outcome": e.outcome.as_str(),
+            "status": e.status,
+            "backend": e.backend,
+            "queue_ms": e.queue_wait.as_millis() as u64,
+            "duration_ms": e.duration.as_millis() as u64,
+            "prompt_tokens": e.prompt_tokens,
+            "completion_tokens": e.completion_tokens,
+            "finished_at": e.finished_at.to_rfc3339(),
+            "detail": e.detail,
+        }),
+    }
+}
+
+/// Blocks until the task finishes or `timeout` passes, then reports its state.
+async fn wait(state: &AppState, user_id: &str, task_id: &str, timeout: Option<&str>) -> Response {
+    let timeout = match timeout {
+        None => DEFAULT_WAIT,
+        Some(t) => match parse_timeout(t) {
+            Some(d) => d.min(MAX_WAIT),
+            None => {
+                let body = json!({ "error": format!("invalid timeout '{}' (use e.g. 30s, 500ms or 2m)", t) });
+                return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+            }
+        },
+    };
+    let deadline = Instant::now() + timeout;
+    // Subscribed before looking, so a finish in between isn't missed
+    let mut events = state.events.subscribe();
+    let mut job = job_state(state, user_id, task_id);
+    loop {
+        match job {
+            None => {
+                let body = json!({ "error": "unknown task (or finished longer ago than the history keeps)", "task_id": task_id });
+                return (StatusCode::NOT_FOUND, Json(body)).into_response();
+            }
+            Some(ref finished @ JobState::Finished(_)) => return Json(job_json(task_id, finished)).into_response(),
+            Some(_) => match tokio::time::timeout_at(deadline, events.recv()).await {
+                Ok(Ok(Event::Finished(entry))) if entry.task_id == task_id => job = Some(JobState::Finished(entry)),
+                Ok(Ok(_)) => {}
+                Ok(Err(RecvError::Lagged(_))) => job = job_state(state, user_id, task_id),
+                Ok(Err(RecvError::Closed)) | Err(_) => {
+                    // Looked up again, the position may have moved while waiting
+                    let job = job_state(state, user_id, task_id).unwrap_or(JobState::Running);
+                    return Json(job_json(task_id, &job)).into_response();
+                }
+            },
+        }
+    }
+}
+
+/// The answer to an operator's replay of the caller's failed request `task_id`.
+fn replay(state: &AppState, user_id: &str, task_id: &str) -> Response {
+    match state.dead_letters.lock().unwrap().replay(user_id, task_id) {
+        Some(replay) => {
+            let mut body = serde_json::to_value(replay).unwrap_or_default();
+            body["replay_of"] = json!(task_id);
+            Json(body).into_response()
+        }
+        None => {
+            let body = json!({ "error": "no replay of this task (not replayed, or finished longer than an hour ago)", "task_id": task_id });
+            (StatusCode::NOT_FOUND, Json(body)).into_response()
+        }
+    }
+}
+
+/// Requests of other users dispatched before the one at `position` of
+/// `user_id`'s queue, assuming everyone takes turns one request at a time.
+fn ahead_of(queues: &HashMap<String, VecDeque<Task>>, user_id: &str, position: usize) -> usize {
+    let others: usize = queues.iter()
+        .filter(|(u, _)| u.as_str() != user_id)
+        .map(|(_, q)| q.len().min(position + 1))
+        .sum();
+    others + position
+}
+
+/// The caller's own queue, limits and recent requests, for client
+/// applications without access to the admin API.
+fn me(state: &AppState, user_id: &str) -> Response {
+    let ahead: Vec<usize> = {
+        let queues = state.queues.lock().unwrap();
+        let len = queues.get(user_id).map_or(0, |q| q.len());
+        (0..len).map(|position| ahead_of(&queues, user_id, position)).collect()
+    };
+    let queued: Vec<Value> = queued_tasks(state, user_id).into_iter()
+        .map(|info| {
+            let ahead = ahead.get(info.position).copied().unwrap_or(0);
+            let mut task = serde_json::to_value(info).unwrap_or_default();
+            task["ahead"] = json!(ahead);
+            task["estimated_start_secs"] = json!(state.estimated_wait_after(ahead).map(|d| d.as_secs()));
+            task
+        })
+        .collect();
+    let grace = state.config.disconnect_grace_secs.unwrap_or(0);
+    let detached: Vec<Value> = state.detached.lock().unwrap().values()
+        .filter(|d| d.user_id == user_id)
+        .map(|d| json!({
+            "task_id": d.task.id,
+            "path": d.task.path,
+            "model": d.task.requested_model,
+            "attach_within_secs": grace.saturating_sub(d.since.elapsed().as_secs()),
+        }))
+        .collect();
+    let stats = state.user_stats().into_iter().find(|u| u.user_id == user_id);
+    let (max_concurrent, max_queued) = state.user_caps(user_id, false);
+    let queued_tokens: u64 = state.queues.lock().unwrap().get(user_id).map_or(0, |q| q.iter().map(|t| t.prompt_tokens).sum());
+    let recent: Vec<Value> = state.history.lock().unwrap().newest_first()
+        .filter(|e| e.user_id == user_id)
+        .take(RECENT_REQUESTS)
+        .map(|e| json!({
+            "task_id": e.task_id,
+            "path": e.path,
+            "model": e.model,
+            "outcome": e.outcome.as_str(),
+            "status": e.status,
+            "queue_ms": e.queue_wait.as_millis() as u64,
+            "duration_ms": e.duration.as_millis() as u64,
+            "prompt_tokens": e.prompt_tokens,
+            "completion_tokens": e.completion_tokens,
+            "finished_at": e.finished_at.to_rfc3339(),
+            "detail": e.detail,
+        }))
+        .collect();
+    Json(json!({
+        "user_id": user_id,
+        "tier": state.tier_of(user_id),
+        "queued": queued.len(),
+        "running": stats.as_ref().map_or(0, |s| s.processing),
+        "processed": stats.as_ref().map_or(0, |s| s.processed),
+        "dropped": stats.as_ref().map_or(0, |s| s.dropped),
+        "bytes_in": stats.as_ref().map_or(0, |s| s.bytes_in),
+        "bytes_out": stats.as_ref().map_or(0, |s| s.bytes_out),
+        "tool_requests": stats.as_ref().map_or(0, |s| s.tool_requests),
+        "tool_calls": stats.as_ref().map_or(0, |s| s.tool_calls),
+        "errors": stats.as_ref().map(|s| s.errors).unwrap_or_default(),
+        "quota": {
+            "max_concurrent": max_concurrent,
+            "max_queued": max_queued,
+            "max_queued_tokens": state.max_queued_tokens(user_id),
+            "queued_tokens": queued_tokens,
+            "cooldown_secs": state.cooldown_remaining(user_id).map(|d| d.as_secs_f64().ceil() as u64),
+        },
+        "tasks": queued,
+        "detached": detached,
+        "recent": recent,
+    }))
+    .into_response()
+}
+
+/// `/api/me` and `/api/queue/self` routes, scoped to the caller's `X-User-ID` (or resolved identity).
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/me", get(|State(state): State<Arc<AppState>>, headers: HeaderMap| async move {
+            match caller_id(&state, &headers).await {
+                Ok(user_id) => me(&state, &user_id),
+                Err(response) => response,
+            }
+        }))
+        .route("/api/queue/self", get(|State(state): State<Arc<AppState>>, headers: HeaderMap| async move {
+            match caller_id(&state, &headers).await {
+                Ok(user_id) => list(&state, &user_id),
+                Err(response) => response,
+            }
+        }))
+        .route(
+            "/api/queue/self/{task_id}",
+            get(|State(state): State<Arc<AppState>>, headers: HeaderMap, Path(task_id): Path<String>| async move {
+                match caller_id(&state, &headers).await {
+                    Ok(user_id) => get_one(&state, &user_id, &task_id),
+                    Err(response) => response,
+                }
+            })
+            .delete(|State(state): State<Arc<AppState>>, headers: HeaderMap, Path(task_id): Path<String>| async move {
+                match caller_id(&state, &headers).await {
+                    Ok(user_id) => cancel(&state, &user_id, &task_id),
+                    Err(response) => response,
+                }
+            })
+            .put(|State(state): State<Arc<AppState>>, headers: HeaderMap, Path(task_id): Path<String>, Json(body): Json<MoveBody>| async move {
+                match caller_id(&state, &headers).await {
+                    Ok(user_id) => move_to(&state, &user_id, &task_id, body.position),
+                    Err(response) => response,
+                }
+            }),
+        )
+        .route("/api/jobs/{task_id}/attach", get(crate::dispatcher::attach_handler))
+        .route("/api/jobs/{task_id}/replay", get(|State(state): State<Arc<AppState>>, headers: HeaderMap, Path(task_id): Path<String>| async move {
+            match caller_id(&state, &headers).await {
+                Ok(user_id) => replay(&state, &user_id, &task_id),
+                Err(response) => response,
+            }
+        }))
+        .route(
+            "/api/jobs/{task_id}/wait",
+            get(|State(state): State<Arc<AppState>>, headers: HeaderMap, Path(task_id): Path<String>, Query(query): Query<WaitQuery>| async move {
+                match caller_id(&state, &headers).await {
+                    Ok(user_id) => wait(&state, &user_id, &task_id, query.timeout.as_deref()).await,
+                    Err(response) => response,
+                }
+            }),
+        )
+}
+
+/// Admin equivalents under `/admin/queue/{user_id}`; mounted behind the admin token.
+pub fn admin_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/queue/{user_id}", get(|State(state): State<Arc<AppState>>, Path(user_id): Path<String>| async move {
+            match path_user(&state, &user_id) {
+                Ok(user_id) => list(&state, &user_id),
+                Err(rejection) => rejection.into_response(),
+            }
+        }))
+        .route(
+            "/admin/queue/{user_id}/{task_id}",
+            get(|State(state): State<Arc<AppState>>, Path((user_id, task_id)): Path<(String, String)>| async move {
+                match path_user(&state, &user_id) {
+                    Ok(user_id) => get_one(&state, &user_id, &task_id),
+                    Err(rejection) => rejection.into_response(),
+                }
+            })
+            .delete(|State(state): State<Arc<AppState>>, Path((user_id, task_id)): Path<(String, String)>| async move {
+                match path_user(&state, &user_id) {
+                    Ok(user_id) => cancel(&state, &user_id, &task_id),
+                    Err(rejection) => rejection.into_response(),
+                }
+            })
+            .put(|State(state): State<Arc<AppState>>, Path((user_id, task_id)): Path<(String, String)>, Json(body): Json<MoveBody>| async move {
+                match path_user(&state, &user_id) {
+                    Ok(user_id) => move_to(&state, &user_id, &task_id, body.position),
+                    Err(rejection) => rejection.into_response(),
+                }
+            }),
+        )
+}
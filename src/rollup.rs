@@ -0,0 +1,99 @@
+//! Recent activity: completed requests per user and per model over the last
+//! 5 minutes, hour and day. Completions go into one-minute buckets, and a
+//! rollup task sums them up and retires buckets older than a day, so the
+//! TUI and metrics can show current load next to the lifetime counters.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::dispatcher::AppState;
+
+/// Window labels and lengths in minutes.
+pub const WINDOWS: [(&str, u64); 3] = [("5m", 5), ("1h", 60), ("24h", 24 * 60)];
+const BUCKET: Duration = Duration::from_secs(60);
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Completed requests in each of `WINDOWS`.
+pub type WindowCounts = [u64; 3];
+
+#[derive(Default)]
+struct Bucket {
+    users: HashMap<String, u64>,
+    models: HashMap<String, u64>,
+}
+
+pub struct Rollup {
+    started: Instant,
+    /// Buckets by minute since `started`, oldest first.
+    buckets: VecDeque<(u64, Bucket)>,
+    /// Totals as of the last `roll`; users and models without a completion
+    /// in the longest window are left out.
+    pub users: HashMap<String, WindowCounts>,
+    pub models: HashMap<String, WindowCounts>,
+}
+
+impl Default for Rollup {
+    fn default() -> Self {
+        Self { started: Instant::now(), buckets: VecDeque::new(), users: HashMap::new(), models: HashMap::new() }
+    }
+}
+
+impl Rollup {
+    fn minute(&self) -> u64 {
+        self.started.elapsed().as_secs() / BUCKET.as_secs()
+    }
+
+    pub fn record(&mut self, user_id: &str, model: Option<&str>) {
+        let minute = self.minute();
+        if self.buckets.back().is_none_or(|(m, _)| *m != minute) {
+            self.buckets.push_back((minute, Bucket::default()));
+        }
+        let bucket = &mut self.buckets.back_mut().unwrap().1;
+        *bucket.users.entry(user_id.to_string()).or_insert(0) += 1;
+        if let Some(model) = model {
+            *bucket.models.entry(model.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Retires buckets older than the longest window and recomputes the totals.
+    pub fn roll(&mut self) {
+        let now = self.minute();
+        let longest = WINDOWS[WINDOWS.len() - 1].1;
+        while self.buckets.front().is_some_and(|(m, _)| now - m >= longest) {
+            self.buckets.pop_front();
+        }
+        let mut users: HashMap<String, WindowCounts> = HashMap::new();
+        let mut models: HashMap<String, WindowCounts> = HashMap::new();
+        for (minute, bucket) in &self.buckets {
+            let age = now - minute;
+            for (i, (_, len)) in WINDOWS.iter().enumerate() {
+                if age >= *len {
+                    continue;
+                }
+                for (user, n) in &bucket.users {
+                    users.entry(user.clone()).or_default()[i] += n;
+                }
+                for (model, n) in &bucket.models {
+                    models.entry(model.clone()).or_default()[i] += n;
+                }
+            }
+        }
+        self.users = users;
+        self.models = models;
+    }
+
+    pub fn user(&self, user_id: &str) -> WindowCounts {
+        self.users.get(user_id).copied().unwrap_or_default()
+    }
+}
+
+/// Keeps the windowed totals current.
+pub async fn run_rollup(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(ROLLUP_INTERVAL).await;
+        state.rollup.lock().unwrap().roll();
+    }
+}
@@ -0,0 +1,62 @@
+//! `--scheduler-trace`: a record of every dispatch decision with the users
+//! that competed for it, what the scheduler weighed for each, and why the
+//! chosen user and backend won; logged and sent as `schedule` events.
+
+use serde::Serialize;
+use tracing::info;
+
+/// A user with work ready when the decision was made.
+#[derive(Serialize, Clone, Debug)]
+pub struct Candidate {
+    pub user: String,
+    /// Requests served so far; the fewest go first.
+    pub processed: usize,
+    pub running: usize,
+    pub queued: usize,
+}
+
+/// A user with queued work that was not considered, and why.
+#[derive(Serialize, Clone, Debug)]
+pub struct Skipped {
+    pub user: String,
+    pub reason: &'static str,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Decision {
+    pub task_id: String,
+    pub user: String,
+    /// Why this user went next, e.g. `round robin` or `vip`.
+    pub user_reason: &'static str,
+    /// Position of the round-robin cursor among `candidates` after the pick.
+    pub cursor: usize,
+    /// In scheduling order.
+    pub candidates: Vec<Candidate>,
+    pub skipped: Vec<Skipped>,
+    pub backend: String,
+    /// Why this backend was picked among `eligible`, e.g. `session affinity`.
+    pub backend_reason: &'static str,
+    pub eligible: Vec<String>,
+}
+
+impl Decision {
+    pub fn log(&self) {
+        let candidates: Vec<String> = self.candidates.iter()
+            .map(|c| format!("{} processed={} running={} queued={}", c.user, c.processed, c.running, c.queued))
+            .collect();
+        let skipped: Vec<String> = self.skipped.iter().map(|s| format!("{}: {}", s.user, s.reason)).collect();
+        info!(
+            target: "ollamamq::scheduler",
+            "Task {}: user {} ({}) on {} ({}); candidates [{}], cursor {}; skipped [{}]; eligible backends [{}]",
+            self.task_id,
+            self.user,
+            self.user_reason,
+            self.backend,
+            self.backend_reason,
+            candidates.join(", "),
+            self.cursor,
+            skipped.join(", "),
+            self.eligible.join(", ")
+        );
+    }
+}
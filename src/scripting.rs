@@ -0,0 +1,135 @@
+//! Optional Rhai scripts reacting to dispatcher events.
+//!
+//! A script defines handlers named after the events it cares about
+//! (`on_completed`, `on_failed`, `on_dropped`, `on_blocked`, `on_unblocked`,
+//! `on_backend`, `on_slo`, `on_schedule`), each taking the event as an object map. Handlers run one at
+//! a time on a dedicated thread and may call a small action API: blocking and
+//! unblocking users or IPs, setting the VIP or Boost user, and sending webhooks.
+
+use rhai::{AST, Dynamic, Engine, EvalAltResult, Map, Scope};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{runtime::Handle, sync::broadcast::error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::blocked_store::BlockInfo;
+use crate::dispatcher::AppState;
+use crate::ip::IpPrefix;
+
+/// Operations one handler call may perform before it is aborted, so a
+/// runaway loop can't stall event processing.
+const MAX_OPERATIONS: u64 = 1_000_000;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event names a script can handle, without the `on_` prefix.
+const EVENTS: [&str; 8] = ["completed", "failed", "dropped", "blocked", "unblocked", "backend", "slo", "schedule"];
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    /// Events the script has a handler for.
+    handled: Vec<&'static str>,
+}
+
+type ActionResult = Result<(), Box<EvalAltResult>>;
+
+/// Compiles the script at `path` with the action API bound to `state`.
+pub fn load(path: &Path, state: Arc<AppState>) -> Result<Script, String> {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_print(|text| info!(target: "ollamamq::script", "{}", text));
+    engine.on_debug(|text, _, pos| debug!(target: "ollamamq::script", "{} {}", pos, text));
+
+    let s = state.clone();
+    engine.register_fn("block_user", move |user: &str| s.block_user(user.to_string(), BlockInfo::new("script")));
+    let s = state.clone();
+    engine.register_fn("unblock_user", move |user: &str| s.unblock_user(user));
+    let s = state.clone();
+    engine.register_fn("block_ip", move |ip: &str| -> ActionResult {
+        let prefix: IpPrefix = ip.parse().map_err(|e| format!("invalid IP '{}': {}", ip, e))?;
+        s.block_ip(prefix, BlockInfo::new("script"));
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("unblock_ip", move |ip: &str| -> ActionResult {
+        let prefix: IpPrefix = ip.parse().map_err(|e| format!("invalid IP '{}': {}", ip, e))?;
+        s.unblock_ip(prefix);
+        Ok(())
+    });
+    // An empty user id clears the selection
+    let s = state.clone();
+    engine.register_fn("set_vip", move |user: &str| {
+        *s.vip_user.lock().unwrap() = Some(s.config.file.user_ids.key(user)).filter(|u| !u.is_empty());
+    });
+    let s = state;
+    engine.register_fn("set_boost", move |user: &str| {
+        *s.boost_user.lock().unwrap() = Some(s.config.file.user_ids.key(user)).filter(|u| !u.is_empty());
+    });
+    let client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let runtime = Handle::current();
+    engine.register_fn("webhook", move |url: &str, payload: Map| -> ActionResult {
+        let body: serde_json::Value = rhai::serde::from_dynamic(&payload.into())?;
+        let (client, url) = (client.clone(), url.to_string());
+        // Sent in the background so a slow receiver doesn't hold up the next event
+        runtime.spawn(async move {
+            match client.post(&url).json(&body).send().await {
+                Ok(res) if !res.status().is_success() => warn!("Script webhook {} answered {}", url, res.status()),
+                Ok(_) => {}
+                Err(e) => warn!("Script webhook {} failed: {}", url, e),
+            }
+        });
+        Ok(())
+    });
+
+    let source = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let ast = engine.compile(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let handled: Vec<&'static str> = EVENTS.into_iter()
+        .filter(|event| {
+            let name = format!("on_{}", event);
+            ast.iter_functions().any(|f| f.name == name && f.params.len() == 1)
+        })
+        .collect();
+    if handled.is_empty() {
+        return Err(format!("{}: no event handlers (e.g. fn on_completed(event) {{ ... }})", path.display()));
+    }
+    Ok(Script { engine, ast, handled })
+}
+
+/// Feeds dispatcher events to the script until the dispatcher shuts down.
+pub fn run(state: &AppState, script: Script) {
+    let mut events = state.events.subscribe();
+    info!("Script handles events: {}", script.handled.join(", "));
+    std::thread::spawn(move || {
+        let mut scope = Scope::new();
+        loop {
+            let event = match events.blocking_recv() {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Script fell behind and missed {} event(s)", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let name = event.name();
+            if !script.handled.contains(&name) {
+                continue;
+            }
+            let arg: Dynamic = match rhai::serde::to_dynamic(event.to_json()) {
+                Ok(arg) => arg,
+                Err(e) => {
+                    warn!("Cannot pass {} event to script: {}", name, e);
+                    continue;
+                }
+            };
+            if let Err(e) = script.engine.call_fn::<Dynamic>(&mut scope, &script.ast, format!("on_{}", name), (arg,)) {
+                warn!("Script handler on_{} failed: {}", name, e);
+            }
+        }
+    });
+}
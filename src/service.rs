@@ -0,0 +1,153 @@
+//! Running ollamaMQ as a Windows service (`--service install|uninstall|run`).
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceAction {
+    /// Register the service with the current arguments and start it at boot.
+    Install,
+    /// Stop and remove the service.
+    Uninstall,
+    /// Entry point used by the service control manager.
+    Run,
+}
+
+#[cfg(not(windows))]
+pub fn main(_action: ServiceAction) {
+    eprintln!("--service is only supported on Windows");
+    std::process::exit(1);
+}
+
+#[cfg(windows)]
+pub use windows_impl::main;
+
+#[cfg(windows)]
+mod windows_impl {
+    use clap::Parser;
+    use std::{ffi::OsString, sync::Mutex, time::Duration};
+    use tokio::sync::oneshot;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+            ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult},
+        service_dispatcher,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    use super::ServiceAction;
+    use crate::{Args, run};
+
+    const SERVICE_NAME: &str = "ollamaMQ";
+    const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+    pub fn main(action: ServiceAction) {
+        let result = match action {
+            ServiceAction::Install => install(),
+            ServiceAction::Uninstall => uninstall(),
+            ServiceAction::Run => service_dispatcher::start(SERVICE_NAME, ffi_service_main),
+        };
+        if let Err(e) = result {
+            eprintln!("Service {:?} failed: {}", action, e);
+            std::process::exit(1);
+        }
+    }
+
+    /// The process arguments minus `--service <action>`, so the installed service
+    /// starts with the same settings it was installed with.
+    fn launch_arguments() -> Vec<OsString> {
+        let mut out = Vec::new();
+        let mut args = std::env::args_os().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--service" {
+                args.next();
+                continue;
+            }
+            if arg.to_string_lossy().starts_with("--service=") {
+                continue;
+            }
+            out.push(arg);
+        }
+        out.extend(["--service", "run", "--no-tui"].map(OsString::from));
+        out
+    }
+
+    fn install() -> windows_service::Result<()> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+        let info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from("ollamaMQ dispatcher"),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: std::env::current_exe().map_err(windows_service::Error::Winapi)?,
+            launch_arguments: launch_arguments(),
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+        let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+        service.set_description(env!("CARGO_PKG_DESCRIPTION"))?;
+        println!("Service '{}' installed; start it with: sc start {}", SERVICE_NAME, SERVICE_NAME);
+        Ok(())
+    }
+
+    fn uninstall() -> windows_service::Result<()> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+        let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+        service.delete()?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop()?;
+        }
+        println!("Service '{}' removed", SERVICE_NAME);
+        Ok(())
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        // Services start in System32; keep logs and blocked_items.json next to the binary.
+        if let Some(dir) = std::env::current_exe().ok().and_then(|p| p.parent().map(|d| d.to_path_buf())) {
+            let _ = std::env::set_current_dir(dir);
+        }
+        let _ = run_service();
+    }
+
+    fn status(state: ServiceState, accept: ServiceControlAccept) -> ServiceStatus {
+        ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted: accept,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+
+    fn run_service() -> windows_service::Result<()> {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let stop_tx = Mutex::new(Some(stop_tx));
+        let handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(tx) = stop_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+        handle.set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN))?;
+
+        let args = Args::parse();
+        match tokio::runtime::Builder::new_multi_thread().enable_all().build() {
+            Ok(runtime) => runtime.block_on(run(args, Some(stop_rx))),
+            Err(e) => eprintln!("Failed to start runtime: {}", e),
+        }
+
+        handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty()))?;
+        Ok(())
+    }
+}
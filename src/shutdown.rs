@@ -0,0 +1,217 @@
+//! Graceful shutdown. Once stopping, the dispatcher refuses new requests and
+//! drains its queues according to a policy: finish everything, finish only
+//! what is running and save the queued rest, or keep serving the interactive
+//! lane while batch requests are set aside as dead letters.
+
+use axum::{body::Bytes, http::{HeaderMap, StatusCode}};
+use serde::Serialize;
+use std::{
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::dispatcher::{AppState, Lane, ResponsePart, Task};
+use crate::history::{HistoryEntry, Outcome};
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 120;
+/// Seconds clients refused during shutdown are asked to wait before retrying.
+pub const RETRY_AFTER_SECS: u64 = 5;
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+const ABANDON_GRACE: Duration = Duration::from_secs(2);
+
+/// Which queued requests are still served while shutting down.
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DrainPolicy {
+    /// Finish every queued and running request.
+    #[default]
+    All,
+    /// Finish running requests; queued ones are saved and answered with 503.
+    InFlight,
+    /// Finish running and queued interactive requests; queued batch requests
+    /// are saved as dead letters and answered with 503.
+    Interactive,
+}
+
+impl DrainPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DrainPolicy::All => "all",
+            DrainPolicy::InFlight => "in-flight",
+            DrainPolicy::Interactive => "interactive",
+        }
+    }
+
+    /// Whether `task` is set aside instead of dispatched.
+    fn sets_aside(&self, task: &Task) -> bool {
+        match self {
+            DrainPolicy::All => false,
+            DrainPolicy::InFlight => true,
+            DrainPolicy::Interactive => task.lane == Lane::Batch,
+        }
+    }
+}
+
+/// Progress of a running shutdown, for the logs and the TUI.
+#[derive(Clone, Debug)]
+pub struct ShutdownStatus {
+    pub policy: DrainPolicy,
+    pub started: Instant,
+    pub deadline: Instant,
+    /// Requests set aside so far, saved to `--shutdown-save` if given.
+    pub set_aside: usize,
+    pub finished: bool,
+}
+
+/// Drains the dispatcher and returns once nothing is left to wait for or the
+/// timeout ran out. New requests are refused from the first call on.
+pub async fn drain(state: Arc<AppState>) {
+    let policy = state.config.shutdown_drain;
+    let started = Instant::now();
+    {
+        let mut status = state.shutdown.lock().unwrap();
+        if status.is_some() {
+            return;
+        }
+        *status = Some(ShutdownStatus {
+            policy,
+            started,
+            deadline: started + state.config.shutdown_timeout,
+            set_aside: 0,
+            finished: false,
+        });
+    }
+    info!(
+        "Shutting down (drain policy: {}): {} queued, {} in flight",
+        policy.as_str(), state.total_queued(), state.in_flight.load(Ordering::Relaxed)
+    );
+
+    let mut saved: Vec<(String, Vec<Task>)> = Vec::new();
+    let mut last_log = Instant::now();
+    loop {
+        // Requests admitted just before the refusal started may still arrive
+        set_aside(&state, &mut saved, |t| policy.sets_aside(t));
+        let queued = state.total_queued();
+        let in_flight = state.in_flight.load(Ordering::Relaxed);
+        if queued == 0 && in_flight == 0 {
+            break;
+        }
+        if started.elapsed() >= state.config.shutdown_timeout {
+            set_aside(&state, &mut saved, |_| true);
+            warn!(
+                "Shutdown timeout of {}s reached: {} queued request(s) set aside, {} in flight abandoned",
+                state.config.shutdown_timeout.as_secs(), queued, in_flight
+            );
+            break;
+        }
+        if last_log.elapsed() >= LOG_INTERVAL {
+            info!("Draining: {} queued, {} in flight, {} set aside", queued, in_flight, set_aside_count(&saved));
+            last_log = Instant::now();
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    let count = set_aside_count(&saved);
+    if count > 0 {
+        match state.config.shutdown_save {
+            Some(ref path) => match std::fs::write(path, crate::snapshot::capture_tasks(&state, &saved)) {
+                Ok(()) => info!("Saved {} request(s) to {}, restore them with --restore", count, path.display()),
+                Err(e) => warn!("Failed to save {} request(s) to {}: {}", count, path.display(), e),
+            },
+            None => warn!("Dropped {} queued request(s) on shutdown, use --shutdown-save to keep them", count),
+        }
+    }
+    info!("Shutdown drained in {:.1}s", started.elapsed().as_secs_f64());
+    if let Some(ref mut status) = *state.shutdown.lock().unwrap() {
+        status.finished = true;
+    }
+}
+
+fn set_aside_count(saved: &[(String, Vec<Task>)]) -> usize {
+    saved.iter().map(|(_, tasks)| tasks.len()).sum()
+}
+
+/// Takes the queued tasks matching `pred` out of the queues and answers
+/// their clients with 503.
+fn set_aside(state: &AppState, saved: &mut Vec<(String, Vec<Task>)>, pred: impl Fn(&Task) -> bool) {
+    let taken: Vec<(String, Vec<Task>)> = {
+        let mut queues = state.queues.lock().unwrap();
+        queues.iter_mut()
+            .filter_map(|(user, q)| {
+                let (matching, rest): (Vec<Task>, Vec<Task>) = q.drain(..).partition(|t| pred(t));
+                q.extend(rest);
+                (!matching.is_empty()).then(|| (user.clone(), matching))
+            })
+            .collect()
+    };
+    let detail = if state.config.shutdown_save.is_some() { "saved on shutdown" } else { "dropped on shutdown" };
+    for (user, tasks) in taken {
+        for task in &tasks {
+            let mut headers = HeaderMap::new();
+            headers.insert(axum::http::header::CONTENT_TYPE, "application/json".parse().unwrap());
+            headers.insert(axum::http::header::RETRY_AFTER, RETRY_AFTER_SECS.into());
+            let body = serde_json::json!({ "error": "dispatcher shutting down", "retry_after_secs": RETRY_AFTER_SECS });
+            let _ = task.responder.try_send(ResponsePart::Status(StatusCode::SERVICE_UNAVAILABLE, headers));
+            let _ = task.responder.try_send(ResponsePart::Chunk(Bytes::from(body.to_string())));
+            state.record_history(HistoryEntry::unsent(
+                &task.id, &user, &task.path, task.requested_model.clone(), task.enqueued_at.elapsed(),
+                Outcome::Dropped, detail,
+            ));
+        }
+        info!("Set aside {} queued request(s) of user {} ({})", tasks.len(), user, detail);
+        state.record_dropped(&user, tasks.len());
+        if let Some(ref mut status) = *state.shutdown.lock().unwrap() {
+            status.set_aside += tasks.len();
+        }
+        match saved.iter_mut().find(|(u, _)| *u == user) {
+            Some((_, existing)) => existing.extend(tasks),
+            None => saved.push((user, tasks)),
+        }
+    }
+}
+
+/// Resolves once the shutdown timeout ran out, with a moment for the last
+/// responses to reach their clients.
+pub async fn abandoned(state: &AppState) {
+    loop {
+        let deadline = state.shutdown.lock().unwrap().as_ref().map(|s| s.deadline);
+        if deadline.is_some_and(|d| Instant::now() >= d + ABANDON_GRACE) {
+            return;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Resolves once the drain is done.
+pub async fn drained(state: &AppState) {
+    while !state.shutdown.lock().unwrap().as_ref().is_some_and(|s| s.finished) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Resolves at the first Ctrl+C (or SIGTERM on Unix); a second one exits
+/// right away without waiting for the drain.
+pub async fn signal() {
+    wait_for_signal().await;
+    tokio::spawn(async {
+        wait_for_signal().await;
+        warn!("Second stop signal, exiting without draining");
+        std::process::exit(130);
+    });
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
@@ -0,0 +1,117 @@
+//! Latency objectives per tier, such as "premium: p95 of the first chunk under
+//! 2s". Stage timings of completed requests are kept per tier and the
+//! objectives are re-evaluated whenever one of the tier's requests completes.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+use crate::config::FileConfig;
+use crate::timings::{Stage, StageStats, StageTiming, format_duration};
+
+/// Completed requests needed before an objective can count as violated.
+pub const MIN_SAMPLES: usize = 20;
+
+/// One entry under a tier's `slos` in the config file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SloConfig {
+    pub stage: Stage,
+    /// Percentile the target applies to, e.g. 95.
+    pub percentile: f64,
+    pub target_ms: u64,
+}
+
+impl SloConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.percentile > 0.0 && self.percentile <= 100.0) {
+            return Err(format!("percentile {} must be above 0 and at most 100", self.percentile));
+        }
+        if self.target_ms == 0 {
+            return Err("target_ms must be above 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Where one objective stands.
+#[derive(Clone, Debug, Serialize)]
+pub struct SloStatus {
+    pub tier: String,
+    pub stage: Stage,
+    pub percentile: f64,
+    pub target_ms: u64,
+    /// Latency at the percentile over the tier's recent requests.
+    pub observed_ms: Option<u64>,
+    pub samples: usize,
+    pub violated: bool,
+    /// Times the objective went from met to violated since startup.
+    pub violations: u64,
+}
+
+impl SloStatus {
+    /// e.g. `premium p95 first_chunk < 2.0s`.
+    pub fn label(&self) -> String {
+        format!(
+            "{} p{} {} < {}",
+            self.tier, self.percentile, self.stage.name(), format_duration(Duration::from_millis(self.target_ms))
+        )
+    }
+}
+
+/// Stage timings per tier and the last verdict of each objective.
+#[derive(Default)]
+pub struct SloTracker {
+    samples: HashMap<String, StageStats>,
+    /// Violated flag and violation count per (tier, index into its `slos`).
+    verdicts: HashMap<(String, usize), (bool, u64)>,
+}
+
+impl SloTracker {
+    /// Adds a completed request of `tier` and returns the objectives whose
+    /// verdict changed with it.
+    pub fn record(&mut self, tier: &str, slos: &[SloConfig], timing: StageTiming) -> Vec<SloStatus> {
+        self.samples.entry(tier.to_string()).or_default().record(timing);
+        let mut changed = Vec::new();
+        for (i, slo) in slos.iter().enumerate() {
+            let mut status = self.evaluate(tier, i, slo);
+            let (violated, violations) = self.verdicts.entry((tier.to_string(), i)).or_default();
+            if status.violated != *violated {
+                *violated = status.violated;
+                if status.violated {
+                    *violations += 1;
+                }
+                status.violations = *violations;
+                changed.push(status);
+            }
+        }
+        changed
+    }
+
+    fn evaluate(&self, tier: &str, index: usize, slo: &SloConfig) -> SloStatus {
+        let stats = self.samples.get(tier);
+        let samples = stats.map_or(0, StageStats::len);
+        let observed = stats
+            .and_then(|s| s.percentiles(slo.stage, &[slo.percentile / 100.0]))
+            .and_then(|v| v.first().copied());
+        let violated = samples >= MIN_SAMPLES && observed.is_some_and(|o| o > Duration::from_millis(slo.target_ms));
+        SloStatus {
+            tier: tier.to_string(),
+            stage: slo.stage,
+            percentile: slo.percentile,
+            target_ms: slo.target_ms,
+            observed_ms: observed.map(|o| o.as_millis() as u64),
+            samples,
+            violated,
+            violations: self.verdicts.get(&(tier.to_string(), index)).map_or(0, |(_, n)| *n),
+        }
+    }
+
+    /// Every configured objective, sorted by tier.
+    pub fn status(&self, config: &FileConfig) -> Vec<SloStatus> {
+        let mut tiers: Vec<(&String, &Vec<SloConfig>)> = config.tiers.iter().map(|(name, t)| (name, &t.slos)).collect();
+        tiers.sort_by_key(|(name, _)| *name);
+        tiers.into_iter()
+            .flat_map(|(name, slos)| slos.iter().enumerate().map(move |(i, slo)| self.evaluate(name, i, slo)))
+            .collect()
+    }
+}
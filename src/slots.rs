@@ -0,0 +1,158 @@
+//! When a request gives its backend slot back. By default a backend serves one
+//! request at a time and the slot is held until the response is fully relayed,
+//! so a long streamed answer keeps the backend from everyone else until its
+//! client has read the last token, while a non-streamed one is mostly waiting
+//! for the backend anyway. Releasing at the first chunk lets the next request start while the stream is
+//! still being relayed (the backend then runs both, as `OLLAMA_NUM_PARALLEL`
+//! allows). The occupancy counters here show what each policy costs.
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// When a response frees its backend slot for the next request.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SlotRelease {
+    /// Once the whole response has been relayed to the client.
+    #[default]
+    Completion,
+    /// Once the backend sent the first chunk; relaying goes on without the slot.
+    FirstChunk,
+}
+
+/// Release policy per kind of response.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SlotPolicy {
+    /// NDJSON and server-sent event responses.
+    pub streaming: SlotRelease,
+    /// Single JSON (or other) bodies.
+    pub non_streaming: SlotRelease,
+}
+
+impl SlotPolicy {
+    pub fn for_kind(&self, kind: ResponseKind) -> SlotRelease {
+        match kind {
+            ResponseKind::Streaming => self.streaming,
+            ResponseKind::NonStreaming => self.non_streaming,
+            ResponseKind::Unanswered => SlotRelease::Completion,
+        }
+    }
+}
+
+/// What a request's slot was held for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseKind {
+    Streaming,
+    NonStreaming,
+    /// No usable backend answer: connection errors, timeouts, redirects,
+    /// HTML error pages, or clients gone before the answer.
+    Unanswered,
+}
+
+impl ResponseKind {
+    pub const ALL: [ResponseKind; 3] = [ResponseKind::Streaming, ResponseKind::NonStreaming, ResponseKind::Unanswered];
+
+    /// Kind of a backend response by its content type.
+    pub fn of(content_type: &str) -> Self {
+        if content_type.contains("ndjson") || content_type.contains("event-stream") {
+            ResponseKind::Streaming
+        } else {
+            ResponseKind::NonStreaming
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseKind::Streaming => "streaming",
+            ResponseKind::NonStreaming => "non_streaming",
+            ResponseKind::Unanswered => "unanswered",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// How one backend's slot has been used since it was added.
+#[derive(Clone, Debug)]
+pub struct SlotOccupancy {
+    since: Instant,
+    /// Time the slot was held, per `ResponseKind`.
+    busy: [Duration; 3],
+    /// Requests that held the slot, per `ResponseKind`.
+    requests: [u64; 3],
+    /// Responses still being relayed after giving their slot back.
+    pub relaying: usize,
+    /// Time spent relaying responses after their slot was released.
+    pub relay_time: Duration,
+    /// Slots given back at the first chunk.
+    pub early_releases: u64,
+}
+
+impl Default for SlotOccupancy {
+    fn default() -> Self {
+        Self {
+            since: Instant::now(),
+            busy: [Duration::ZERO; 3],
+            requests: [0; 3],
+            relaying: 0,
+            relay_time: Duration::ZERO,
+            early_releases: 0,
+        }
+    }
+}
+
+impl SlotOccupancy {
+    /// A request let go of the slot after holding it for `held`.
+    pub fn record(&mut self, kind: ResponseKind, held: Duration) {
+        self.busy[kind.index()] += held;
+        self.requests[kind.index()] += 1;
+    }
+
+    pub fn busy(&self, kind: ResponseKind) -> Duration {
+        self.busy[kind.index()]
+    }
+
+    pub fn requests(&self, kind: ResponseKind) -> u64 {
+        self.requests[kind.index()]
+    }
+
+    /// Share of the time since the backend was added that its slot was held,
+    /// counting finished requests only.
+    pub fn share(&self) -> f64 {
+        let total: Duration = self.busy.iter().sum();
+        let elapsed = self.since.elapsed().as_secs_f64();
+        if elapsed > 0.0 { (total.as_secs_f64() / elapsed).min(1.0) } else { 0.0 }
+    }
+
+    pub fn summary(&self) -> OccupancySummary {
+        let kind = |k: ResponseKind| KindSummary { requests: self.requests(k), busy_secs: self.busy(k).as_secs_f64() };
+        OccupancySummary {
+            share: self.share(),
+            streaming: kind(ResponseKind::Streaming),
+            non_streaming: kind(ResponseKind::NonStreaming),
+            unanswered: kind(ResponseKind::Unanswered),
+            relaying: self.relaying,
+            relay_secs: self.relay_time.as_secs_f64(),
+            early_releases: self.early_releases,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct KindSummary {
+    pub requests: u64,
+    pub busy_secs: f64,
+}
+
+/// Slot occupancy of a backend as shown by `/admin/status`.
+#[derive(Serialize, Clone, Debug)]
+pub struct OccupancySummary {
+    pub share: f64,
+    pub streaming: KindSummary,
+    pub non_streaming: KindSummary,
+    pub unanswered: KindSummary,
+    pub relaying: usize,
+    pub relay_secs: f64,
+    pub early_releases: u64,
+}
@@ -0,0 +1,226 @@
+//! Handling of clients that read responses slower than the backend produces
+//! them. Each response goes through a bounded channel; once it is full, a
+//! plain send would hold the backend slot until the client catches up.
+
+use bytes::Bytes;
+use std::{
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+use tracing::{debug, warn};
+
+use crate::dispatcher::ResponsePart;
+
+/// Largest response kept in one spill file; a client further behind is disconnected.
+const SPILL_MAX_BYTES: u64 = 256 * 1024 * 1024;
+/// Size of the pieces a spill file is read back in.
+const SPILL_READ_CHUNK: usize = 64 * 1024;
+
+/// What to do once a client hasn't taken a chunk for `--slow-client-timeout-secs`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SlowClientPolicy {
+    /// Wait for the client however long it takes.
+    #[default]
+    Wait,
+    /// Close the response; the client gets a truncated stream.
+    Disconnect,
+    /// Keep reading from the backend into a temporary file and hand it to the
+    /// client at its own pace after the backend is released.
+    Spill,
+    /// Skip chunks while the client is behind and note the loss in the history.
+    Lossy,
+}
+
+impl SlowClientPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlowClientPolicy::Wait => "wait",
+            SlowClientPolicy::Disconnect => "disconnect",
+            SlowClientPolicy::Spill => "spill",
+            SlowClientPolicy::Lossy => "lossy",
+        }
+    }
+}
+
+/// Why a chunk couldn't be handed to the client.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SinkError {
+    /// The client went away.
+    Disconnected,
+    /// The client was too slow and got disconnected by policy.
+    TooSlow,
+}
+
+struct Spill {
+    path: PathBuf,
+    file: File,
+    bytes: u64,
+}
+
+/// Sends response chunks to a client according to the slow-client policy.
+pub struct ResponseSink {
+    tx: mpsc::Sender<ResponsePart>,
+    policy: SlowClientPolicy,
+    timeout: Duration,
+    task_id: String,
+    spill: Option<Spill>,
+    /// Lossy mode: the client is behind, so chunks are skipped until it has room again.
+    behind: bool,
+    skipped_chunks: usize,
+    skipped_bytes: usize,
+}
+
+impl ResponseSink {
+    pub fn new(tx: mpsc::Sender<ResponsePart>, policy: SlowClientPolicy, timeout: Duration, task_id: &str) -> Self {
+        Self {
+            tx,
+            policy,
+            timeout,
+            task_id: task_id.to_string(),
+            spill: None,
+            behind: false,
+            skipped_chunks: 0,
+            skipped_bytes: 0,
+        }
+    }
+
+    pub async fn send(&mut self, chunk: Bytes) -> Result<(), SinkError> {
+        if self.spill.is_some() {
+            // Everything after the first spilled chunk goes to the file to keep the order
+            return self.write_spill(chunk).await;
+        }
+        if self.behind {
+            return match self.tx.try_send(ResponsePart::Chunk(chunk)) {
+                Ok(()) => {
+                    self.behind = false;
+                    Ok(())
+                }
+                Err(mpsc::error::TrySendError::Full(ResponsePart::Chunk(chunk))) => {
+                    self.skip(&chunk);
+                    Ok(())
+                }
+                Err(_) => Err(SinkError::Disconnected),
+            };
+        }
+        if self.policy == SlowClientPolicy::Wait {
+            return self.tx.send(ResponsePart::Chunk(chunk)).await.map_err(|_| SinkError::Disconnected);
+        }
+
+        let tx = self.tx.clone();
+        let reserved = tokio::time::timeout(self.timeout, tx.reserve()).await;
+        match reserved {
+            Ok(Ok(permit)) => {
+                permit.send(ResponsePart::Chunk(chunk));
+                Ok(())
+            }
+            Ok(Err(_)) => Err(SinkError::Disconnected),
+            Err(_) => self.fell_behind(chunk).await,
+        }
+    }
+
+    async fn fell_behind(&mut self, chunk: Bytes) -> Result<(), SinkError> {
+        match self.policy {
+            SlowClientPolicy::Wait => unreachable!("waiting sends have no timeout"),
+            SlowClientPolicy::Disconnect => {
+                warn!("Task {}: client read nothing for {}s, disconnecting it", self.task_id, self.timeout.as_secs());
+                Err(SinkError::TooSlow)
+            }
+            SlowClientPolicy::Lossy => {
+                debug!("Task {}: client is behind, skipping chunks", self.task_id);
+                self.behind = true;
+                self.skip(&chunk);
+                Ok(())
+            }
+            SlowClientPolicy::Spill => {
+                let path = std::env::temp_dir().join(format!("ollamamq-spill-{}", self.task_id));
+                let file = File::create(&path).await.map_err(|e| {
+                    warn!("Task {}: cannot create spill file {}: {}", self.task_id, path.display(), e);
+                    SinkError::TooSlow
+                })?;
+                debug!("Task {}: client is behind, spilling the response to {}", self.task_id, path.display());
+                self.spill = Some(Spill { path, file, bytes: 0 });
+                self.write_spill(chunk).await
+            }
+        }
+    }
+
+    fn skip(&mut self, chunk: &Bytes) {
+        self.skipped_chunks += 1;
+        self.skipped_bytes += chunk.len();
+    }
+
+    async fn write_spill(&mut self, chunk: Bytes) -> Result<(), SinkError> {
+        let Some(spill) = self.spill.as_mut() else {
+            return Ok(());
+        };
+        spill.bytes += chunk.len() as u64;
+        if spill.bytes > SPILL_MAX_BYTES {
+            warn!("Task {}: client is more than {} MB behind, disconnecting it", self.task_id, SPILL_MAX_BYTES / (1024 * 1024));
+            self.discard_spill().await;
+            return Err(SinkError::TooSlow);
+        }
+        if let Err(e) = spill.file.write_all(&chunk).await {
+            warn!("Task {}: cannot write spill file {}: {}", self.task_id, spill.path.display(), e);
+            self.discard_spill().await;
+            return Err(SinkError::TooSlow);
+        }
+        Ok(())
+    }
+
+    async fn discard_spill(&mut self) {
+        if let Some(spill) = self.spill.take() {
+            drop(spill.file);
+            let _ = tokio::fs::remove_file(&spill.path).await;
+        }
+    }
+
+    /// What the policy did to this response, for the request history.
+    pub fn detail(&self) -> Option<String> {
+        if self.skipped_chunks > 0 {
+            return Some(format!("slow client: skipped {} chunk(s), {} bytes", self.skipped_chunks, self.skipped_bytes));
+        }
+        self.spill.as_ref().map(|s| format!("slow client: spilled {} bytes to disk", s.bytes))
+    }
+
+    /// Ends the response. A spilled response is handed to the client by a
+    /// background task, so the caller can release the backend right away.
+    pub async fn finish(mut self) {
+        let Some(mut spill) = self.spill.take() else {
+            return;
+        };
+        if let Err(e) = spill.file.flush().await {
+            warn!("Task {}: cannot write spill file {}: {}", self.task_id, spill.path.display(), e);
+        }
+        drop(spill.file);
+        let (tx, task_id, path) = (self.tx, self.task_id, spill.path);
+        tokio::spawn(async move {
+            match File::open(&path).await {
+                Ok(mut file) => {
+                    let mut buf = vec![0; SPILL_READ_CHUNK];
+                    loop {
+                        match file.read(&mut buf).await {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                if tx.send(ResponsePart::Chunk(Bytes::copy_from_slice(&buf[..n]))).await.is_err() {
+                                    debug!("Task {}: client left before the spilled response was delivered", task_id);
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                warn!("Task {}: cannot read spill file {}: {}", task_id, path.display(), e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => warn!("Task {}: cannot open spill file {}: {}", task_id, path.display(), e),
+            }
+            let _ = tokio::fs::remove_file(&path).await;
+        });
+    }
+}
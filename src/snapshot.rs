@@ -0,0 +1,274 @@
+//! Versioned state archive for upgrading the dispatcher without losing queued work.
+//!
+//! Layout: `OMQS` magic, format version (u32 LE), JSON header length (u32 LE),
+//! the JSON header, then the bodies of all queued requests back to back,
+//! zstd-compressed as one frame. Version 1 archives kept the bodies raw.
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::blocked_store::BlockInfo;
+use crate::compress;
+use crate::config::TierConfig;
+use crate::dispatcher::{AppState, Bandwidth, Lane, Task};
+use crate::errors::ErrorCounts;
+use crate::ip::IpPrefix;
+
+const MAGIC: &[u8; 4] = b"OMQS";
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotTask {
+    id: String,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    requested_model: Option<String>,
+    batch: bool,
+    canary: bool,
+    affinity_key: Option<String>,
+    body_len: usize,
+    #[serde(default)]
+    resumable: bool,
+    #[serde(default)]
+    fallback_from: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub created_at: String,
+    pub ollamamq_version: String,
+    queues: Vec<(String, Vec<SnapshotTask>)>,
+    processed_counts: HashMap<String, usize>,
+    dropped_counts: HashMap<String, usize>,
+    #[serde(default)]
+    bandwidth: HashMap<String, Bandwidth>,
+    #[serde(default)]
+    error_counts: HashMap<String, ErrorCounts>,
+    user_ips: HashMap<String, IpAddr>,
+    blocked_ips: HashSet<IpPrefix>,
+    blocked_users: HashSet<String>,
+    #[serde(default)]
+    user_blocks: HashMap<String, BlockInfo>,
+    #[serde(default)]
+    ip_blocks: HashMap<IpPrefix, BlockInfo>,
+    user_notes: HashMap<String, String>,
+    #[serde(default)]
+    watched_users: HashSet<String>,
+    vip_user: Option<String>,
+    boost_user: Option<String>,
+    pub tiers: HashMap<String, TierConfig>,
+    pub default_tier: Option<String>,
+}
+
+pub struct Snapshot {
+    pub header: SnapshotHeader,
+    bodies: Bytes,
+}
+
+fn snapshot_task(t: &Task, bodies: &mut Vec<u8>) -> SnapshotTask {
+    let body = t.stored_body();
+    bodies.extend_from_slice(&body);
+    SnapshotTask {
+        id: t.id.clone(),
+        method: t.method.to_string(),
+        path: t.path.clone(),
+        headers: t.headers.iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect(),
+        requested_model: t.requested_model.clone(),
+        batch: t.lane == Lane::Batch,
+        canary: t.canary,
+        affinity_key: t.affinity_key.clone(),
+        body_len: body.len(),
+        resumable: t.resumable,
+        fallback_from: t.fallback_from.clone(),
+    }
+}
+
+/// Serializes the current dispatcher state. In-flight requests are not included.
+pub fn capture(state: &AppState) -> Vec<u8> {
+    let mut bodies = Vec::new();
+    let queues = {
+        let queues = state.queues.lock().unwrap();
+        queues.iter()
+            .filter(|(_, q)| !q.is_empty())
+            .map(|(user, q)| (user.clone(), q.iter().map(|t| snapshot_task(t, &mut bodies)).collect()))
+            .collect()
+    };
+    encode(state, queues, bodies)
+}
+
+/// Like `capture`, but with `tasks` in place of the queues: requests taken
+/// out of them on shutdown.
+pub fn capture_tasks(state: &AppState, tasks: &[(String, Vec<Task>)]) -> Vec<u8> {
+    let mut bodies = Vec::new();
+    let queues = tasks.iter()
+        .map(|(user, q)| (user.clone(), q.iter().map(|t| snapshot_task(t, &mut bodies)).collect()))
+        .collect();
+    encode(state, queues, bodies)
+}
+
+fn encode(state: &AppState, queues: Vec<(String, Vec<SnapshotTask>)>, bodies: Vec<u8>) -> Vec<u8> {
+    let header = SnapshotHeader {
+        created_at: chrono::Local::now().to_rfc3339(),
+        ollamamq_version: env!("CARGO_PKG_VERSION").to_string(),
+        queues,
+        processed_counts: state.processed_counts.lock().unwrap().clone(),
+        dropped_counts: state.dropped_counts.lock().unwrap().clone(),
+        bandwidth: state.bandwidth.lock().unwrap().clone(),
+        error_counts: state.error_counts.lock().unwrap().clone(),
+        user_ips: state.user_ips.lock().unwrap().clone(),
+        blocked_ips: state.blocked_ips.lock().unwrap().clone(),
+        blocked_users: state.blocked_users.lock().unwrap().clone(),
+        user_blocks: state.user_blocks.lock().unwrap().clone(),
+        ip_blocks: state.ip_blocks.lock().unwrap().clone(),
+        user_notes: state.user_notes.lock().unwrap().clone(),
+        watched_users: state.watched_users.lock().unwrap().clone(),
+        vip_user: state.vip_user.lock().unwrap().clone(),
+        boost_user: state.boost_user.lock().unwrap().clone(),
+        tiers: state.config.file.tiers.clone(),
+        default_tier: state.config.file.default_tier.clone(),
+    };
+
+    let json = serde_json::to_vec(&header).expect("snapshot header serializes");
+    let bodies = compress::encode(&bodies).expect("compressing into memory");
+    let mut out = Vec::with_capacity(12 + json.len() + bodies.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(&json);
+    out.extend_from_slice(&bodies);
+    out
+}
+
+pub fn read(path: &Path) -> Result<Snapshot, String> {
+    let data = std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    if data.len() < 12 || &data[..4] != MAGIC {
+        return Err(format!("{} is not an ollamaMQ snapshot", path.display()));
+    }
+    let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    if !(1..=FORMAT_VERSION).contains(&version) {
+        return Err(format!("unsupported snapshot format version {} (supported: 1 to {})", version, FORMAT_VERSION));
+    }
+    let header_len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+    let header_end = 12usize.checked_add(header_len).filter(|&end| end <= data.len())
+        .ok_or_else(|| "truncated snapshot header".to_string())?;
+    let header: SnapshotHeader = serde_json::from_slice(&data[12..header_end])
+        .map_err(|e| format!("invalid snapshot header: {}", e))?;
+    let bodies = match version {
+        1 => Bytes::from(data).slice(header_end..),
+        _ => compress::decode(&data[header_end..])
+            .map(Bytes::from)
+            .map_err(|e| format!("invalid snapshot bodies: {}", e))?,
+    };
+    let expected: usize = header.queues.iter().flat_map(|(_, q)| q).map(|t| t.body_len).sum();
+    if bodies.len() != expected {
+        return Err(format!("snapshot bodies are {} bytes, expected {}", bodies.len(), expected));
+    }
+    Ok(Snapshot { header, bodies })
+}
+
+/// Loads a snapshot into a fresh dispatcher. Clients of the old process are gone,
+/// so restored requests run detached and their responses are discarded.
+/// Returns the number of restored requests.
+pub fn restore(state: &AppState, snapshot: Snapshot) -> usize {
+    let Snapshot { header, bodies } = snapshot;
+    let mut offset = 0;
+    let mut restored = 0;
+    {
+        let mut queues = state.queues.lock().unwrap();
+        for (user, tasks) in header.queues {
+            let queue = queues.entry(user).or_default();
+            for t in tasks {
+                let body = bodies.slice(offset..offset + t.body_len);
+                offset += t.body_len;
+                let mut headers = HeaderMap::new();
+                for (k, v) in &t.headers {
+                    if let (Ok(k), Ok(v)) = (HeaderName::try_from(k.as_str()), HeaderValue::try_from(v.as_str())) {
+                        headers.append(k, v);
+                    }
+                }
+                // Keep the channel open so the worker doesn't treat the task as abandoned
+                let (tx, mut rx) = mpsc::channel(32);
+                tokio::spawn(async move { while rx.recv().await.is_some() {} });
+
+                let json = serde_json::from_slice(&body).ok();
+                let prompt_tokens = json.as_ref().map_or(0, crate::capabilities::estimate_prompt_tokens);
+                let batchable = crate::dispatcher::is_batchable(&t.path, json.as_ref());
+                let tools = json.as_ref().is_some_and(crate::tools::offers_tools);
+                let queued_bytes = state.hold_queued_bytes(body.len());
+                queue.push_back(Task {
+                    id: t.id,
+                    method: Method::from_bytes(t.method.as_bytes()).unwrap_or(Method::POST),
+                    path: t.path,
+                    headers,
+                    body,
+                    responder: tx,
+                    requested_model: t.requested_model,
+                    canary: t.canary,
+                    lane: if t.batch { Lane::Batch } else { Lane::Interactive },
+                    affinity_key: t.affinity_key,
+                    prompt_tokens,
+                    debug_trace: false,
+                    batchable,
+                    tools,
+                    experiment: None,
+                    enqueued_at: Instant::now(),
+                    queued_bytes,
+                    spooled: None,
+                    resumable: t.resumable,
+                    fallback_from: t.fallback_from,
+                });
+                restored += 1;
+            }
+        }
+    }
+
+    state.processed_counts.lock().unwrap().extend(header.processed_counts);
+    state.dropped_counts.lock().unwrap().extend(header.dropped_counts);
+    state.bandwidth.lock().unwrap().extend(header.bandwidth);
+    state.error_counts.lock().unwrap().extend(header.error_counts);
+    state.user_ips.lock().unwrap().extend(header.user_ips);
+    state.blocked_ips.lock().unwrap().extend(header.blocked_ips);
+    state.blocked_users.lock().unwrap().extend(header.blocked_users);
+    state.user_blocks.lock().unwrap().extend(header.user_blocks);
+    state.ip_blocks.lock().unwrap().extend(header.ip_blocks);
+    state.user_notes.lock().unwrap().extend(header.user_notes);
+    state.watched_users.lock().unwrap().extend(header.watched_users);
+    *state.vip_user.lock().unwrap() = header.vip_user;
+    *state.boost_user.lock().unwrap() = header.boost_user;
+    state.save_blocked_items();
+
+    info!(
+        "Restored snapshot from {} (v{}): {} queued request(s), responses of restored requests are discarded",
+        header.created_at, header.ollamamq_version, restored
+    );
+    state.notify.notify_one();
+    restored
+}
+
+pub async fn snapshot_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let filename = format!("attachment; filename=\"ollamamq-{}.bin\"", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, filename),
+        ],
+        capture(&state),
+    )
+}
@@ -0,0 +1,154 @@
+//! Disk spool for queued request bodies. When `--max-queued-mb` is reached and
+//! `--spool-dir` is set, the bodies of the oldest queued requests are written
+//! to the spool instead of rejecting new ones, and read back when their request
+//! is dispatched: a burst costs some latency instead of 503s. Bodies are
+//! zstd-compressed on disk, and `--max-spooled-mb` counts the compressed size.
+
+use bytes::Bytes;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tracing::{debug, info, warn};
+
+use crate::compress::{self, SUFFIX};
+use crate::dispatcher::{AppState, Task};
+
+/// Keeps file names unique when two admissions spool the same body at once.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// A queued body kept in the spool; the file goes away with it.
+pub struct SpooledBody {
+    path: PathBuf,
+    len: u64,
+    total: Arc<AtomicU64>,
+}
+
+impl SpooledBody {
+    /// A body taking `len` bytes in `path`, already added to `total`.
+    pub fn new(path: PathBuf, len: u64, total: Arc<AtomicU64>) -> Self {
+        Self { path, len, total }
+    }
+
+    /// Reads the body without giving up the file, e.g. for a snapshot.
+    pub fn read(&self) -> io::Result<Bytes> {
+        read(&self.path)
+    }
+}
+
+impl Drop for SpooledBody {
+    fn drop(&mut self) {
+        self.total.fetch_sub(self.len, Ordering::Relaxed);
+        if let Err(e) = fs::remove_file(&self.path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Cannot remove spooled body {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn read(path: &Path) -> io::Result<Bytes> {
+    compress::decode(&fs::read(path)?).map(Bytes::from)
+}
+
+/// Creates the spool directory and removes bodies left by an earlier run,
+/// whose requests are gone.
+pub fn prepare(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut stale = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == SUFFIX) && fs::remove_file(&path).is_ok() {
+            stale += 1;
+        }
+    }
+    if stale > 0 {
+        info!("Removed {} stale spooled bodies from {}", stale, dir.display());
+    }
+    Ok(())
+}
+
+/// Moves the bodies of the oldest queued requests to the spool until `needed`
+/// more bytes fit under `--max-queued-mb`. Returns whether they fit now.
+pub async fn make_room(state: &AppState, needed: u64) -> bool {
+    let (Some(dir), max) = (state.config.spool_dir.clone(), state.config.max_queued_bytes) else {
+        return false;
+    };
+    let fits = || state.queued_bytes() + needed <= max;
+    let max_spooled = state.config.max_spooled_bytes;
+    let excess = (state.queued_bytes() + needed).saturating_sub(max);
+    // Picked under the lock, written without it
+    let picked: Vec<(String, Bytes)> = {
+        let queues = state.queues.lock().unwrap();
+        let mut candidates: Vec<&Task> = queues.values().flatten()
+            .filter(|t| t.spooled.is_none() && !t.body.is_empty() && !t.debug_trace)
+            .collect();
+        candidates.sort_by_key(|t| t.enqueued_at);
+        let mut freed = 0;
+        let mut picked = Vec::new();
+        for task in candidates {
+            if freed >= excess {
+                break;
+            }
+            let len = task.body.len() as u64;
+            if max_spooled > 0 && state.spooled_bytes() + freed + len > max_spooled {
+                break;
+            }
+            freed += len;
+            picked.push((task.id.clone(), task.body.clone()));
+        }
+        if freed < excess {
+            return false;
+        }
+        picked
+    };
+
+    let written = tokio::task::spawn_blocking(move || {
+        picked.into_iter()
+            .filter_map(|(id, body)| {
+                let path = dir.join(format!("{}-{}.{}", id, SEQUENCE.fetch_add(1, Ordering::Relaxed), SUFFIX));
+                match compress::encode(&body).and_then(|packed| fs::write(&path, &packed).map(|()| packed.len())) {
+                    Ok(len) => Some((id, path, body.len(), len as u64)),
+                    Err(e) => {
+                        warn!("Cannot spool body of task {} to {}: {}", id, path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    let mut queues = state.queues.lock().unwrap();
+    for (id, path, raw, len) in written {
+        let spooled = state.hold_spooled_bytes(path, len);
+        // Dispatched or cancelled while being written: the file goes with `spooled`
+        let Some(task) = queues.values_mut().flatten().find(|t| t.id == id && t.spooled.is_none()) else {
+            continue;
+        };
+        task.body = Bytes::new();
+        task.queued_bytes.release();
+        task.spooled = Some(spooled);
+        state.spooled_tasks.fetch_add(1, Ordering::Relaxed);
+        debug!("Task {}: body of {} bytes moved to the spool ({} compressed)", id, raw, len);
+    }
+    fits()
+}
+
+/// Reads a spooled body back into the task before it is sent.
+pub async fn rehydrate(task: &mut Task) -> io::Result<()> {
+    let Some(spooled) = task.spooled.take() else {
+        return Ok(());
+    };
+    let path = spooled.path.clone();
+    task.body = tokio::task::spawn_blocking(move || read(&path)).await.map_err(io::Error::other)??;
+    // The file goes only now, after it was read
+    drop(spooled);
+    Ok(())
+}
+
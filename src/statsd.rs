@@ -0,0 +1,228 @@
+//! Optional StatsD/DogStatsD push for environments that collect metrics through
+//! a UDP agent rather than scraping `/metrics`: queue and backend gauges every
+//! `--statsd-interval-secs`, and request counters and timers as requests finish.
+
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::UdpSocket, sync::broadcast::error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::dispatcher::AppState;
+use crate::events::Event;
+
+/// Finished-request metrics are sent at least this often.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Datagram size that fits an Ethernet MTU without fragmenting.
+const MAX_PACKET: usize = 1432;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StatsdFormat {
+    /// Plain StatsD: no tags.
+    Statsd,
+    /// DogStatsD: tags appended as `|#key:value,...`.
+    #[default]
+    Dogstatsd,
+}
+
+#[derive(Clone, Debug)]
+pub struct StatsdConfig {
+    /// Agent address, e.g. `127.0.0.1:8125`; nothing is sent without it.
+    pub addr: Option<String>,
+    /// Prepended to every metric name with a dot; empty for none.
+    pub prefix: String,
+    /// `key:value` tags added to every metric (DogStatsD only).
+    pub tags: Vec<String>,
+    pub format: StatsdFormat,
+    /// How often gauges are sent.
+    pub interval: Duration,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            prefix: "ollamamq".to_string(),
+            tags: Vec::new(),
+            format: StatsdFormat::default(),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Characters that would end a name or tag early.
+fn sanitize(value: &str) -> String {
+    value.chars()
+        .map(|c| if matches!(c, '|' | ',' | '#' | '@') || c.is_whitespace() { '_' } else { c })
+        .collect()
+}
+
+/// Metric lines waiting to be sent, packed into datagrams.
+struct Batch<'a> {
+    config: &'a StatsdConfig,
+    packets: Vec<String>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(config: &'a StatsdConfig) -> Self {
+        Self { config, packets: Vec::new() }
+    }
+
+    fn add(&mut self, name: &str, value: impl std::fmt::Display, kind: &str, tags: &[(&str, &str)]) {
+        let mut line = String::new();
+        if !self.config.prefix.is_empty() {
+            let _ = write!(line, "{}.", sanitize(&self.config.prefix).replace(':', "_"));
+        }
+        let _ = write!(line, "{}:{}|{}", name, value, kind);
+        if self.config.format == StatsdFormat::Dogstatsd {
+            let all: Vec<String> = self.config.tags.iter()
+                .map(|t| sanitize(t))
+                .chain(tags.iter().map(|(k, v)| format!("{}:{}", k, sanitize(v))))
+                .collect();
+            if !all.is_empty() {
+                let _ = write!(line, "|#{}", all.join(","));
+            }
+        }
+        match self.packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= MAX_PACKET => {
+                packet.push('\n');
+                packet.push_str(&line);
+            }
+            _ => self.packets.push(line),
+        }
+    }
+
+    fn gauge(&mut self, name: &str, value: impl std::fmt::Display, tags: &[(&str, &str)]) {
+        self.add(name, value, "g", tags);
+    }
+
+    fn count(&mut self, name: &str, value: u64, tags: &[(&str, &str)]) {
+        self.add(name, value, "c", tags);
+    }
+
+    fn timing(&mut self, name: &str, value: Duration, tags: &[(&str, &str)]) {
+        self.add(name, value.as_millis(), "ms", tags);
+    }
+
+    async fn send(&mut self, socket: &UdpSocket) {
+        for packet in self.packets.drain(..) {
+            // UDP: a missing agent only shows up as occasional send errors
+            if let Err(e) = socket.send(packet.as_bytes()).await {
+                debug!("StatsD send failed: {}", e);
+            }
+        }
+    }
+}
+
+fn add_gauges(batch: &mut Batch, state: &AppState) {
+    let (queued, users) = {
+        let queues = state.queues.lock().unwrap();
+        (queues.values().map(|q| q.len()).sum::<usize>(), queues.values().filter(|q| !q.is_empty()).count())
+    };
+    batch.gauge("queue.depth", queued, &[]);
+    batch.gauge("queue.users", users, &[]);
+    batch.gauge("queue.body_bytes", state.queued_bytes(), &[]);
+    if let Some(wait) = state.estimated_wait().filter(|w| *w != Duration::MAX) {
+        batch.gauge("queue.estimated_wait_ms", wait.as_millis(), &[]);
+    }
+
+    let backends = state.backends.lock().unwrap().clone();
+    batch.gauge("backends.online", backends.iter().filter(|b| b.is_online).count(), &[]);
+    batch.gauge("backends.total", backends.len(), &[]);
+    let mut processing = 0;
+    for b in &backends {
+        processing += b.active_requests;
+        batch.gauge("backend.active_requests", b.active_requests, &[("backend", &b.url)]);
+        batch.gauge("backend.up", b.is_online as u8, &[("backend", &b.url)]);
+    }
+    batch.gauge("requests.processing", processing, &[]);
+}
+
+fn add_event(batch: &mut Batch, event: &Event) {
+    match event {
+        Event::Finished(entry) => {
+            let model = entry.model.as_deref().unwrap_or("none");
+            let backend = entry.backend.as_deref().unwrap_or("none");
+            let tags = [("model", model), ("backend", backend)];
+            batch.count(&format!("requests.{}", event.name()), 1, &tags);
+            batch.timing("request.queue_wait", entry.queue_wait, &tags);
+            if entry.backend.is_some() {
+                batch.timing("request.duration", entry.duration, &tags);
+            }
+            if let Some(n) = entry.prompt_tokens {
+                batch.count("tokens.prompt", n, &tags);
+            }
+            if let Some(n) = entry.completion_tokens {
+                batch.count("tokens.completion", n, &tags);
+            }
+        }
+        Event::Blocked { .. } => batch.count("blocks", 1, &[]),
+        Event::Backend { url, online } => {
+            batch.count(if *online { "backend.online_transitions" } else { "backend.offline_transitions" }, 1, &[("backend", url)]);
+        }
+        Event::Unblocked { .. } | Event::Slo(_) | Event::Schedule(_) => {}
+    }
+}
+
+async fn connect(addr: &str) -> std::io::Result<UdpSocket> {
+    let target: SocketAddr = tokio::net::lookup_host(addr).await?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))?;
+    let local: SocketAddr = if target.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// Sends metrics to the StatsD agent until the dispatcher shuts down.
+pub async fn run_statsd(state: Arc<AppState>) {
+    let config = state.config.statsd.clone();
+    let Some(ref addr) = config.addr else {
+        return;
+    };
+    let mut events = state.events.subscribe();
+    let mut gauges = tokio::time::interval(config.interval);
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut batch = Batch::new(&config);
+    let mut socket: Option<UdpSocket> = None;
+    let mut warned = false;
+
+    loop {
+        tokio::select! {
+            _ = gauges.tick() => add_gauges(&mut batch, &state),
+            _ = flush.tick() => {}
+            event = events.recv() => {
+                match event {
+                    Ok(event) => add_event(&mut batch, &event),
+                    Err(RecvError::Lagged(n)) => debug!("StatsD emitter missed {} events", n),
+                    Err(RecvError::Closed) => return,
+                }
+                continue;
+            }
+        }
+
+        // Resolved again until it works, e.g. while the agent's DNS name appears
+        if socket.is_none() {
+            match connect(addr).await {
+                Ok(s) => {
+                    info!("Sending StatsD metrics to {}", addr);
+                    socket = Some(s);
+                }
+                Err(e) => {
+                    if !warned {
+                        warn!("Cannot reach StatsD agent {}: {}", addr, e);
+                        warned = true;
+                    }
+                    batch.packets.clear();
+                    continue;
+                }
+            }
+        }
+        if let Some(ref socket) = socket {
+            batch.send(socket).await;
+        }
+    }
+}
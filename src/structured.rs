@@ -0,0 +1,312 @@
+//! Checks of structured output: when a request asks for JSON (`format: "json"`,
+//! a JSON schema in `format`, or OpenAI's `response_format`), the generated text
+//! of the completed response is parsed and validated against it. Results are
+//! counted per model, and answers that aren't streamed can be retried once.
+
+use axum::http::{HeaderMap, Method, StatusCode};
+use bytes::Bytes;
+use futures_util::{StreamExt, stream::BoxStream};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::dispatcher::AppState;
+
+/// Responses larger than this are forwarded without a check.
+const MAX_CHECKED_BYTES: usize = 8 * 1024 * 1024;
+
+/// What to do with requests asking for structured output.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StructuredOutputMode {
+    /// Forward responses unchecked.
+    #[default]
+    Off,
+    /// Validate completed responses and count failures per model.
+    Flag,
+    /// Like flag, and send a non-streamed request once more when its answer is invalid.
+    Retry,
+}
+
+impl StructuredOutputMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StructuredOutputMode::Off => "off",
+            StructuredOutputMode::Flag => "flag",
+            StructuredOutputMode::Retry => "retry",
+        }
+    }
+}
+
+/// The output a request asked for.
+#[derive(Clone, Debug)]
+pub enum OutputFormat {
+    Json,
+    Schema(Value),
+}
+
+/// Requested format of a request body, and whether the answer is streamed.
+pub struct Requested {
+    pub format: OutputFormat,
+    pub stream: bool,
+}
+
+pub fn requested_format(path: &str, body: &[u8]) -> Option<Requested> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let openai = path.starts_with("/v1/");
+    let format = if openai {
+        let rf = json.get("response_format")?;
+        match rf.get("type").and_then(Value::as_str)? {
+            "json_object" => OutputFormat::Json,
+            "json_schema" => match rf.pointer("/json_schema/schema") {
+                Some(schema) => OutputFormat::Schema(schema.clone()),
+                None => OutputFormat::Json,
+            },
+            _ => return None,
+        }
+    } else {
+        match json.get("format")? {
+            Value::String(s) if s == "json" => OutputFormat::Json,
+            schema @ Value::Object(_) => OutputFormat::Schema(schema.clone()),
+            _ => return None,
+        }
+    };
+    // Ollama streams unless told otherwise, OpenAI-compatible endpoints don't
+    let stream = json.get("stream").and_then(Value::as_bool).unwrap_or(!openai);
+    Some(Requested { format, stream })
+}
+
+/// Collects a response while it is forwarded, for `finish`.
+pub struct StructuredCheck {
+    format: OutputFormat,
+    body: Vec<u8>,
+    oversized: bool,
+}
+
+impl StructuredCheck {
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format, body: Vec::new(), oversized: false }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.body.len() + chunk.len() > MAX_CHECKED_BYTES {
+            self.oversized = true;
+            self.body = Vec::new();
+        }
+        if !self.oversized {
+            self.body.extend_from_slice(chunk);
+        }
+    }
+
+    /// `None` when the response was too large to check, otherwise whether the
+    /// generated text matches the requested format.
+    pub fn finish(&self) -> Option<Result<(), String>> {
+        (!self.oversized).then(|| check(&self.format, &self.body))
+    }
+}
+
+/// Validates the text generated in `body`: a single JSON answer, NDJSON
+/// chunks, or server-sent events.
+pub fn check(format: &OutputFormat, body: &[u8]) -> Result<(), String> {
+    let text = generated_text(body);
+    // The format applies to text; an answer calling a tool has none
+    if text.trim().is_empty() && crate::tools::calls_in_body(body) > 0 {
+        return Ok(());
+    }
+    let value: Value = serde_json::from_str(text.trim()).map_err(|e| format!("not JSON: {}", e))?;
+    match format {
+        OutputFormat::Json => Ok(()),
+        OutputFormat::Schema(schema) => validate(schema, &value, "$"),
+    }
+}
+
+fn generated_text(body: &[u8]) -> String {
+    if let Ok(json) = serde_json::from_slice::<Value>(body) {
+        return content_of(&json).unwrap_or_default().to_string();
+    }
+    let mut text = String::new();
+    for line in body.split(|&b| b == b'\n') {
+        let line = line.strip_prefix(b"data:").unwrap_or(line);
+        if let Some(piece) = serde_json::from_slice::<Value>(line.trim_ascii()).ok().as_ref().and_then(content_of) {
+            text.push_str(piece);
+        }
+    }
+    text
+}
+
+/// Generated text of one Ollama or OpenAI response object or chunk.
+fn content_of(json: &Value) -> Option<&str> {
+    json.pointer("/message/content")
+        .or_else(|| json.get("response"))
+        .or_else(|| json.pointer("/choices/0/message/content"))
+        .or_else(|| json.pointer("/choices/0/delta/content"))
+        .or_else(|| json.pointer("/choices/0/text"))
+        .and_then(Value::as_str)
+}
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// Validates `value` against the commonly used part of JSON Schema: `type`,
+/// `enum`, `const`, `properties`, `required`, `additionalProperties`,
+/// `items`, `minItems`/`maxItems` and `anyOf`/`oneOf`. Other keywords pass.
+fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+    match schema.get("type") {
+        Some(Value::String(t)) if !type_matches(t, value) => return Err(format!("{}: expected {}", path, t)),
+        Some(Value::Array(types)) if !types.iter().filter_map(Value::as_str).any(|t| type_matches(t, value)) => {
+            return Err(format!("{}: expected one of {}", path, Value::Array(types.clone())));
+        }
+        _ => {}
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array)
+        && !options.contains(value)
+    {
+        return Err(format!("{}: {} is not one of {}", path, value, Value::Array(options.clone())));
+    }
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        return Err(format!("{}: expected {}", path, expected));
+    }
+    if let Some(alternatives) = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(Value::as_array)
+        && !alternatives.iter().any(|s| validate(s, value, path).is_ok())
+    {
+        return Err(format!("{}: matches none of the alternatives", path));
+    }
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                return Err(format!("{}: missing required property '{}'", path, name));
+            }
+        }
+        for (name, field) in object {
+            let field_path = format!("{}.{}", path, name);
+            match properties.and_then(|p| p.get(name)) {
+                Some(field_schema) => validate(field_schema, field, &field_path)?,
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => return Err(format!("{}: unexpected property", field_path)),
+                    Some(extra @ Value::Object(_)) => validate(extra, field, &field_path)?,
+                    _ => {}
+                },
+            }
+        }
+    }
+    if let Some(items) = value.as_array() {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+            && (items.len() as u64) < min
+        {
+            return Err(format!("{}: fewer than {} items", path, min));
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+            && items.len() as u64 > max
+        {
+            return Err(format!("{}: more than {} items", path, max));
+        }
+        if let Some(item_schema) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                validate(item_schema, item, &format!("{}[{}]", path, i))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Structured-output checks of one model.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct StructuredStats {
+    /// Completed answers that were validated.
+    pub checked: u64,
+    /// Answers that did not match the requested format.
+    pub invalid: u64,
+    /// Requests sent again after an invalid answer.
+    pub retries: u64,
+}
+
+/// A backend answer about to be forwarded: either the live response or one
+/// that was read in full for a check.
+pub struct BackendAnswer {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BoxStream<'static, reqwest::Result<Bytes>>,
+}
+
+impl From<reqwest::Response> for BackendAnswer {
+    fn from(response: reqwest::Response) -> Self {
+        Self {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes_stream().boxed(),
+        }
+    }
+}
+
+/// The request to send again for `--structured-output retry`.
+pub struct RetryRequest {
+    pub method: Method,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub timeout: Duration,
+}
+
+/// Reads a successful non-streamed answer in full and, when it doesn't match
+/// `format`, sends the request once more and returns the second answer. The
+/// first answer counts as checked and invalid.
+pub async fn retry_invalid(
+    client: &reqwest::Client,
+    url: &str,
+    request: RetryRequest,
+    first: reqwest::Response,
+    format: &OutputFormat,
+    state: &AppState,
+    model: &str,
+) -> BackendAnswer {
+    if !first.status().is_success() {
+        return first.into();
+    }
+    let status = first.status();
+    let headers = first.headers().clone();
+    let buffered = |body: reqwest::Result<Bytes>| BackendAnswer {
+        status,
+        headers: headers.clone(),
+        body: futures_util::stream::iter([body]).boxed(),
+    };
+    let body = match first.bytes().await {
+        Ok(body) => body,
+        Err(e) => return buffered(Err(e)),
+    };
+    if body.len() > MAX_CHECKED_BYTES {
+        return buffered(Ok(body));
+    }
+    let Err(reason) = check(format, &body) else {
+        return buffered(Ok(body));
+    };
+    state.record_structured(model, false, true);
+    warn!("Invalid structured output from {} for model {} ({}), retrying once", url, model, reason);
+    let res = client.request(request.method, url)
+        .timeout(request.timeout)
+        .headers(request.headers)
+        .body(request.body)
+        .send()
+        .await;
+    match res {
+        Ok(second) => second.into(),
+        Err(e) => {
+            debug!("Structured output retry to {} failed: {}", url, e);
+            buffered(Ok(body))
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::config::FileConfig;
+
+/// A named prompt template, configured under `templates` in the config file.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptTemplate {
+    /// System prompt placed before the client's messages.
+    pub system: Option<String>,
+    /// Few-shot messages (`{"role": ..., "content": ...}`) placed after the system
+    /// prompts and before the client's first message.
+    pub messages: Vec<Value>,
+    /// Users the template applies to.
+    pub users: Vec<String>,
+    /// Tiers the template applies to.
+    pub tiers: Vec<String>,
+    /// Requested model names the template applies to.
+    pub models: Vec<String>,
+}
+
+impl PromptTemplate {
+    /// A template without any selector applies to every request; otherwise the
+    /// user or tier must match, and the model must match when models are listed.
+    fn applies(&self, user_id: &str, tier: Option<&str>, model: Option<&str>) -> bool {
+        let who = (self.users.is_empty() && self.tiers.is_empty())
+            || self.users.iter().any(|u| u == user_id)
+            || tier.is_some_and(|t| self.tiers.iter().any(|x| x == t));
+        let what = self.models.is_empty()
+            || model.is_some_and(|m| self.models.iter().any(|x| crate::dispatcher::model_name_matches(m, x)));
+        who && what
+    }
+}
+
+/// Injects all matching templates (in name order) into a `/api/chat` body.
+/// Returns the rewritten body and the applied template names, or `None` when
+/// nothing applies or the body has no `messages` array.
+pub fn apply(config: &FileConfig, user_id: &str, tier: Option<&str>, body: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    if config.templates.is_empty() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let model = json.get("model").and_then(Value::as_str).map(str::to_string);
+
+    let mut names: Vec<&String> = config.templates.iter()
+        .filter(|(_, t)| t.applies(user_id, tier, model.as_deref()))
+        .map(|(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort();
+
+    let mut system = Vec::new();
+    let mut shots = Vec::new();
+    for name in &names {
+        let t = &config.templates[*name];
+        if let Some(ref s) = t.system {
+            system.push(json!({ "role": "system", "content": s }));
+        }
+        shots.extend(t.messages.iter().cloned());
+    }
+
+    let messages = json.get_mut("messages")?.as_array_mut()?;
+    // The client's own system prompts stay first after ours, ahead of the few-shot examples
+    let client_system = messages.iter()
+        .take_while(|m| m.get("role").and_then(Value::as_str) == Some("system"))
+        .count();
+    let rest = messages.split_off(client_system);
+    let client_system = std::mem::take(messages);
+    messages.extend(system);
+    messages.extend(client_system);
+    messages.extend(shots);
+    messages.extend(rest);
+
+    Some((serde_json::to_vec(&json).ok()?, names.into_iter().cloned().collect()))
+}
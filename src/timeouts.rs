@@ -0,0 +1,137 @@
+//! Backend timeouts per (backend, model): a manual override from the config
+//! file, or, with `--learned-timeout-factor`, a baseline learned from how long
+//! the model's recent requests took on that backend (p99 × factor), so large
+//! models get the time they need and small ones fail fast. Requests fall back
+//! to the static `--timeout` of their route until enough have completed.
+
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use crate::config::FileConfig;
+use crate::dispatcher::{DispatcherConfig, RouteClass, model_pattern_matches};
+
+/// Completed requests kept per (backend, model).
+const SAMPLE_WINDOW: usize = 200;
+/// Completed requests needed before a learned timeout applies.
+pub const MIN_SAMPLES: usize = 20;
+/// Shortest learned timeout, so a run of instant answers can't starve a slow one.
+const MIN_LEARNED: Duration = Duration::from_secs(10);
+/// Percentile of recent durations the factor applies to.
+const PERCENTILE: f64 = 0.99;
+
+/// Where a request's timeout came from.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutSource {
+    /// `timeouts` of the backend or `model_timeouts` in the config file.
+    Config,
+    Learned,
+    /// `--timeout`, `--generate-timeout` or `--embed-timeout`.
+    Static,
+}
+
+impl TimeoutSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeoutSource::Config => "config",
+            TimeoutSource::Learned => "learned",
+            TimeoutSource::Static => "static",
+        }
+    }
+}
+
+/// Recent durations of completed requests per (backend, model).
+#[derive(Default)]
+pub struct LearnedTimeouts {
+    samples: HashMap<(String, String), VecDeque<Duration>>,
+}
+
+/// What `GET /admin/timeouts` reports for one (backend, model).
+#[derive(Serialize, Clone, Debug)]
+pub struct TimeoutBaseline {
+    pub backend: String,
+    pub model: String,
+    pub samples: usize,
+    pub p99_ms: u64,
+    /// `None` until `MIN_SAMPLES` requests completed.
+    pub learned_secs: Option<u64>,
+}
+
+impl LearnedTimeouts {
+    pub fn record(&mut self, backend: &str, model: &str, duration: Duration) {
+        let samples = self.samples.entry((backend.to_string(), model.to_string())).or_default();
+        if samples.len() == SAMPLE_WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(duration);
+    }
+
+    fn p99(samples: &VecDeque<Duration>) -> Duration {
+        let mut values: Vec<Duration> = samples.iter().copied().collect();
+        values.sort_unstable();
+        let rank = ((PERCENTILE * values.len() as f64).ceil() as usize).clamp(1, values.len());
+        values[rank - 1]
+    }
+
+    /// p99 × `factor` of the recent requests for `model` on `backend`, within
+    /// `MIN_LEARNED` and `max`; `None` with too few of them.
+    pub fn learned(&self, backend: &str, model: &str, factor: f64, max: Duration) -> Option<Duration> {
+        let samples = self.samples.get(&(backend.to_string(), model.to_string()))?;
+        (samples.len() >= MIN_SAMPLES).then(|| Self::p99(samples).mul_f64(factor).clamp(MIN_LEARNED, max.max(MIN_LEARNED)))
+    }
+
+    /// Every (backend, model) seen, sorted, with its current baseline.
+    pub fn baselines(&self, factor: Option<f64>, max: Duration) -> Vec<TimeoutBaseline> {
+        let mut list: Vec<TimeoutBaseline> = self.samples.iter()
+            .map(|((backend, model), samples)| TimeoutBaseline {
+                backend: backend.clone(),
+                model: model.clone(),
+                samples: samples.len(),
+                p99_ms: Self::p99(samples).as_millis() as u64,
+                learned_secs: factor.and_then(|f| self.learned(backend, model, f, max)).map(|d| d.as_secs()),
+            })
+            .collect();
+        list.sort_by(|a, b| (&a.backend, &a.model).cmp(&(&b.backend, &b.model)));
+        list
+    }
+}
+
+/// The config file override for `model` on `backend`: the backend's own
+/// `timeouts` first, then `model_timeouts`; the first matching pattern wins.
+pub fn configured(file: &FileConfig, backend: &str, model: &str) -> Option<Duration> {
+    let matching = |patterns: &HashMap<String, u64>| {
+        let mut matches: Vec<(&String, &u64)> = patterns.iter().filter(|(p, _)| model_pattern_matches(model, p)).collect();
+        // Exact names before wildcards, then longer (more specific) patterns first
+        matches.sort_by_key(|(p, _)| (p.contains('*'), std::cmp::Reverse(p.len())));
+        matches.first().map(|(_, secs)| Duration::from_secs(**secs))
+    };
+    file.backend_config(backend)
+        .and_then(|b| matching(&b.timeouts))
+        .or_else(|| matching(&file.model_timeouts))
+}
+
+/// Timeout for a request for `model` on `backend` to `path`, and where it came from.
+pub fn resolve(config: &DispatcherConfig, learned: &LearnedTimeouts, backend: &str, model: Option<&str>, path: &str) -> (Duration, TimeoutSource) {
+    let fixed = (config.timeout_for(path), TimeoutSource::Static);
+    let Some(model) = model else {
+        return fixed;
+    };
+    if let Some(timeout) = configured(&config.file, backend, model) {
+        return (timeout, TimeoutSource::Config);
+    }
+    // Model management (pull, create, ...) has nothing to learn from
+    if RouteClass::from_path(path) == RouteClass::Other {
+        return fixed;
+    }
+    config.learned_timeout_factor
+        .and_then(|factor| learned.learned(backend, model, factor, config.learned_timeout_max))
+        .map_or(fixed, |timeout| (timeout, TimeoutSource::Learned))
+}
+
+/// Error detail of a request that ran into its timeout.
+pub fn timed_out(timeout: Duration, source: TimeoutSource) -> String {
+    format!("timed out after {}s ({} timeout)", timeout.as_secs(), source.as_str())
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Completed requests kept for percentile calculation.
+const SAMPLE_WINDOW: usize = 1000;
+
+/// Time one request spent in each stage.
+#[derive(Clone, Copy, Debug)]
+pub struct StageTiming {
+    /// Enqueue until a backend was picked.
+    pub queue_wait: Duration,
+    /// Dispatch until the first body chunk arrived.
+    pub first_chunk: Duration,
+    /// First chunk until the response finished streaming.
+    pub streaming: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    QueueWait,
+    FirstChunk,
+    Streaming,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 3] = [Stage::QueueWait, Stage::FirstChunk, Stage::Streaming];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::QueueWait => "queue_wait",
+            Stage::FirstChunk => "first_chunk",
+            Stage::Streaming => "streaming",
+        }
+    }
+
+    fn of(&self, t: &StageTiming) -> Duration {
+        match self {
+            Stage::QueueWait => t.queue_wait,
+            Stage::FirstChunk => t.first_chunk,
+            Stage::Streaming => t.streaming,
+        }
+    }
+}
+
+/// Sliding window of recent stage timings.
+#[derive(Default)]
+pub struct StageStats {
+    samples: VecDeque<StageTiming>,
+}
+
+impl StageStats {
+    pub fn record(&mut self, timing: StageTiming) {
+        if self.samples.len() == SAMPLE_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(timing);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Nearest-rank percentiles (`qs` in 0.0..=1.0) of one stage, `None` without samples.
+    pub fn percentiles(&self, stage: Stage, qs: &[f64]) -> Option<Vec<Duration>> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut values: Vec<Duration> = self.samples.iter().map(|t| stage.of(t)).collect();
+        values.sort_unstable();
+        Some(qs.iter()
+            .map(|q| {
+                let rank = ((q * values.len() as f64).ceil() as usize).clamp(1, values.len());
+                values[rank - 1]
+            })
+            .collect())
+    }
+}
+
+/// Event rate over a sliding window, counted in one-second buckets.
+pub struct RateMeter {
+    origin: Instant,
+    window_secs: u64,
+    /// (second since `origin`, events in that second), oldest first.
+    buckets: VecDeque<(u64, u64)>,
+}
+
+impl RateMeter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            origin: Instant::now(),
+            window_secs: window.as_secs().max(1),
+            buckets: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self) {
+        let now = self.origin.elapsed().as_secs();
+        match self.buckets.back_mut() {
+            Some((sec, count)) if *sec == now => *count += 1,
+            _ => self.buckets.push_back((now, 1)),
+        }
+        self.prune(now);
+    }
+
+    /// Average events per second over the window.
+    pub fn per_sec(&mut self) -> f64 {
+        let now = self.origin.elapsed().as_secs();
+        self.prune(now);
+        let total: u64 = self.buckets.iter().map(|(_, c)| c).sum();
+        // Early on, average over the time that has actually passed
+        let span = self.window_secs.min(now + 1);
+        total as f64 / span as f64
+    }
+
+    fn prune(&mut self, now: u64) {
+        while self.buckets.front().is_some_and(|(sec, _)| sec + self.window_secs <= now) {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+/// Compact human-readable duration ("850ms", "12.3s").
+pub fn format_duration(d: Duration) -> String {
+    let ms = d.as_millis();
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else if ms < 60_000 {
+        format!("{:.1}s", d.as_secs_f64())
+    } else {
+        format!("{}m{:02}s", ms / 60_000, (ms / 1000) % 60)
+    }
+}
+
+/// Uptime as `45s`, `12m`, `3h07m` or `2d04h`.
+pub fn format_uptime(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h{:02}m", secs / 3600, (secs / 60) % 60),
+        _ => format!("{}d{:02}h", secs / 86400, (secs / 3600) % 24),
+    }
+}
+
+/// Byte count as `850B`, `12.3KB`, `4.1MB` or `2.0GB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
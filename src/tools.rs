@@ -0,0 +1,89 @@
+//! Tool (function) calling. Requests with `tools`, `tool_choice` and `tool`
+//! role messages, and the tool calls in the answers, are forwarded as sent;
+//! this module only recognises them, to count tool calls per user and so that
+//! checks on the generated text don't take a tool call for an empty answer.
+
+use bytes::BytesMut;
+use serde_json::Value;
+
+/// Bytes of a single JSON answer kept to count its tool calls.
+const MAX_COUNTED_BYTES: usize = 1024 * 1024;
+
+/// Whether a request body offers the model tools to call (`tools`, or the
+/// legacy OpenAI `functions`).
+pub fn offers_tools(json: &Value) -> bool {
+    ["tools", "functions"].iter()
+        .any(|key| json.get(key).and_then(Value::as_array).is_some_and(|t| !t.is_empty()))
+}
+
+/// Tool calls started in one Ollama or OpenAI response event. A streamed
+/// OpenAI call is spread over several deltas and only counted at the first,
+/// which carries its `id`.
+pub fn calls_in(value: &Value) -> u64 {
+    let len = |v: Option<&Value>| v.and_then(Value::as_array).map_or(0, |calls| calls.len() as u64);
+    let mut calls = len(value.pointer("/message/tool_calls"));
+    for choice in value.get("choices").and_then(Value::as_array).into_iter().flatten() {
+        calls += len(choice.pointer("/message/tool_calls"));
+        calls += choice.pointer("/delta/tool_calls").and_then(Value::as_array).into_iter().flatten()
+            .filter(|call| call.get("id").is_some_and(|id| !id.is_null()))
+            .count() as u64;
+        if choice.pointer("/message/function_call").is_some() || choice.pointer("/delta/function_call/name").is_some() {
+            calls += 1;
+        }
+    }
+    calls
+}
+
+/// Tool calls in a whole response body: a single JSON answer, NDJSON chunks or
+/// server-sent events.
+pub fn calls_in_body(body: &[u8]) -> u64 {
+    if let Ok(value) = serde_json::from_slice::<Value>(body) {
+        return calls_in(&value);
+    }
+    let mut counter = ToolCallCounter::new(true);
+    counter.push(body);
+    counter.finish()
+}
+
+/// Counts the tool calls of a response while it is forwarded.
+pub struct ToolCallCounter {
+    /// NDJSON or server-sent events, counted line by line; otherwise one JSON
+    /// answer, counted once complete.
+    streamed: bool,
+    pending: BytesMut,
+    calls: u64,
+}
+
+impl ToolCallCounter {
+    pub fn new(streamed: bool) -> Self {
+        Self { streamed, pending: BytesMut::new(), calls: 0 }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        if !self.streamed && self.pending.len() + chunk.len() > MAX_COUNTED_BYTES {
+            return;
+        }
+        self.pending.extend_from_slice(chunk);
+        if self.streamed {
+            while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+                let line = self.pending.split_to(end + 1);
+                self.count(&line);
+            }
+        }
+    }
+
+    /// Total tool calls, once the response has ended.
+    pub fn finish(&mut self) -> u64 {
+        let rest = self.pending.split();
+        self.count(&rest);
+        self.calls
+    }
+
+    fn count(&mut self, event: &[u8]) {
+        let event = event.trim_ascii();
+        let event = event.strip_prefix(b"data:").unwrap_or(event);
+        if let Ok(value) = serde_json::from_slice::<Value>(event.trim_ascii()) {
+            self.calls += calls_in(&value);
+        }
+    }
+}
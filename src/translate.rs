@@ -0,0 +1,419 @@
+//! Translation of Ollama requests for backends that only speak the OpenAI API
+//! (vLLM, LM Studio, the llama.cpp server): `/api/chat` and `/api/generate`
+//! are sent as `/v1/chat/completions` (or `/v1/completions` for raw prompts)
+//! and the answers are turned back into Ollama JSON or NDJSON, so one queue can
+//! feed a mixed fleet.
+
+use axum::http::{HeaderValue, header};
+use bytes::{Bytes, BytesMut};
+use futures_util::{StreamExt, stream::BoxStream};
+use serde_json::{Map, Value, json};
+use std::{collections::VecDeque, time::Instant};
+
+use crate::structured::BackendAnswer;
+
+/// Whether requests to `path` can be translated for an OpenAI-only backend.
+pub fn translatable(path: &str) -> bool {
+    matches!(path, "/api/chat" | "/api/generate")
+}
+
+/// Which Ollama endpoint the answer has to look like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shape {
+    /// `/api/chat`: text in `message.content`.
+    Chat,
+    /// `/api/generate`: text in `response`.
+    Generate,
+}
+
+/// A request rewritten for an OpenAI-only backend.
+pub struct Translated {
+    /// Path sent to the backend.
+    pub path: &'static str,
+    pub body: Bytes,
+    /// Turns the answer back into what the client asked for.
+    pub response: ResponseTranslator,
+}
+
+/// Options that mean the same under both APIs.
+const SHARED_OPTIONS: &[&str] = &["temperature", "top_p", "top_k", "min_p", "seed", "stop", "presence_penalty", "frequency_penalty"];
+
+/// Rewrites an `/api/chat` or `/api/generate` body, or returns `None` for
+/// other paths and bodies that aren't JSON objects.
+pub fn translate(path: &str, body: &[u8]) -> Option<Translated> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let obj = json.as_object()?;
+    let model = obj.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    // Ollama streams unless told otherwise
+    let stream = obj.get("stream").and_then(Value::as_bool).unwrap_or(true);
+
+    let mut out = Map::new();
+    out.insert("model".to_string(), json!(model));
+    let (backend_path, shape) = match path {
+        "/api/chat" => {
+            let messages = obj.get("messages").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+            out.insert("messages".to_string(), Value::Array(chat_messages(messages)));
+            ("/v1/chat/completions", Shape::Chat)
+        }
+        "/api/generate" if obj.get("raw").and_then(Value::as_bool) == Some(true) => {
+            // No chat template: the prompt goes to the model as is
+            out.insert("prompt".to_string(), obj.get("prompt").cloned().unwrap_or(json!("")));
+            if let Some(suffix) = obj.get("suffix") {
+                out.insert("suffix".to_string(), suffix.clone());
+            }
+            ("/v1/completions", Shape::Generate)
+        }
+        "/api/generate" => {
+            let mut messages = Vec::new();
+            if let Some(system) = obj.get("system").and_then(Value::as_str) {
+                messages.push(json!({ "role": "system", "content": system }));
+            }
+            let prompt = obj.get("prompt").and_then(Value::as_str).unwrap_or_default();
+            let images = obj.get("images").and_then(Value::as_array);
+            messages.push(json!({ "role": "user", "content": content_with_images(prompt, images) }));
+            out.insert("messages".to_string(), Value::Array(messages));
+            ("/v1/chat/completions", Shape::Generate)
+        }
+        _ => return None,
+    };
+
+    if let Some(tools) = obj.get("tools") {
+        // Ollama took its tool definitions from OpenAI, so they carry over as they are
+        out.insert("tools".to_string(), tools.clone());
+    }
+    match obj.get("format") {
+        Some(Value::String(f)) if f == "json" => {
+            out.insert("response_format".to_string(), json!({ "type": "json_object" }));
+        }
+        Some(schema @ Value::Object(_)) => {
+            out.insert("response_format".to_string(), json!({ "type": "json_schema", "json_schema": { "name": "response", "schema": schema } }));
+        }
+        _ => {}
+    }
+    if let Some(options) = obj.get("options").and_then(Value::as_object) {
+        for key in SHARED_OPTIONS {
+            if let Some(value) = options.get(*key) {
+                out.insert(key.to_string(), value.clone());
+            }
+        }
+        // -1 and -2 mean "until done" and "fill the context" to Ollama
+        if let Some(n) = options.get("num_predict").and_then(Value::as_i64).filter(|n| *n > 0) {
+            out.insert("max_tokens".to_string(), json!(n));
+        }
+    }
+    out.insert("stream".to_string(), json!(stream));
+    if stream {
+        // Token counts only arrive in a final chunk when asked for
+        out.insert("stream_options".to_string(), json!({ "include_usage": true }));
+    }
+
+    Some(Translated {
+        path: backend_path,
+        body: Bytes::from(serde_json::to_vec(&Value::Object(out)).ok()?),
+        response: ResponseTranslator::new(shape, stream, model),
+    })
+}
+
+/// Text with images attached as data URLs, the way OpenAI takes them.
+fn content_with_images(text: &str, images: Option<&Vec<Value>>) -> Value {
+    let images: Vec<&str> = images.into_iter().flatten().filter_map(Value::as_str).collect();
+    if images.is_empty() {
+        return json!(text);
+    }
+    let mut parts = vec![json!({ "type": "text", "text": text })];
+    for image in images {
+        let url = if image.starts_with("data:") { image.to_string() } else { format!("data:image/png;base64,{}", image) };
+        parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+    }
+    Value::Array(parts)
+}
+
+/// Ollama chat messages as OpenAI ones. Tool calls get ids, which Ollama
+/// doesn't use, and each tool result answers the oldest call still open.
+fn chat_messages(messages: &[Value]) -> Vec<Value> {
+    let mut open_calls: VecDeque<String> = VecDeque::new();
+    messages.iter().enumerate().map(|(i, message)| {
+        let role = message.get("role").and_then(Value::as_str).unwrap_or("user");
+        let text = message.get("content").and_then(Value::as_str).unwrap_or_default();
+        let mut out = json!({ "role": role, "content": content_with_images(text, message.get("images").and_then(Value::as_array)) });
+        if let Some(calls) = message.get("tool_calls").and_then(Value::as_array) {
+            let calls: Vec<Value> = calls.iter().enumerate().map(|(j, call)| {
+                let id = format!("call_{}_{}", i, j);
+                open_calls.push_back(id.clone());
+                let arguments = match call.pointer("/function/arguments") {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(args) => args.to_string(),
+                    None => "{}".to_string(),
+                };
+                json!({
+                    "id": id,
+                    "type": "function",
+                    "function": { "name": call.pointer("/function/name").cloned().unwrap_or(json!("")), "arguments": arguments },
+                })
+            }).collect();
+            out["tool_calls"] = Value::Array(calls);
+        }
+        if role == "tool"
+            && let Some(id) = open_calls.pop_front()
+        {
+            out["tool_call_id"] = json!(id);
+        }
+        out
+    }).collect()
+}
+
+/// A tool call assembled from streamed fragments.
+#[derive(Default)]
+struct PendingCall {
+    name: String,
+    arguments: String,
+}
+
+/// Turns an OpenAI answer (server-sent events or one JSON object) into the
+/// Ollama answer the client expects.
+pub struct ResponseTranslator {
+    shape: Shape,
+    stream: bool,
+    model: String,
+    started: Instant,
+    buffer: BytesMut,
+    done_reason: Option<String>,
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+    calls: Vec<PendingCall>,
+    done: bool,
+}
+
+impl ResponseTranslator {
+    fn new(shape: Shape, stream: bool, model: String) -> Self {
+        Self {
+            shape,
+            stream,
+            model,
+            started: Instant::now(),
+            buffer: BytesMut::new(),
+            done_reason: None,
+            prompt_tokens: None,
+            completion_tokens: None,
+            calls: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Wraps a backend answer: successful ones are translated as they stream,
+    /// errors become Ollama's `{"error": "..."}`.
+    pub fn answer(mut self, mut answer: BackendAnswer) -> BackendAnswer {
+        let content_type = if self.stream && answer.status.is_success() { "application/x-ndjson" } else { "application/json" };
+        answer.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        if !answer.status.is_success() {
+            // Buffered whole, like a non-streamed answer
+            self.stream = false;
+            self.done = true;
+        }
+        answer.body = translate_stream(answer.body, self);
+        answer
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Bytes {
+        self.buffer.extend_from_slice(chunk);
+        if !self.stream {
+            return Bytes::new();
+        }
+        let mut out = BytesMut::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line = self.buffer.split_to(pos + 1);
+            self.event(&line, &mut out);
+        }
+        out.freeze()
+    }
+
+    fn finish(&mut self) -> Bytes {
+        let rest = self.buffer.split().freeze();
+        if !self.stream {
+            return self.translate_whole(rest);
+        }
+        let mut out = BytesMut::new();
+        self.event(&rest, &mut out);
+        if !self.done {
+            self.done_line(&mut out);
+        }
+        out.freeze()
+    }
+
+    /// One line of the event stream.
+    fn event(&mut self, line: &[u8], out: &mut BytesMut) {
+        let Some(data) = line.trim_ascii().strip_prefix(b"data:") else {
+            // Blank separators, comments and `event:` lines
+            return;
+        };
+        let data = data.trim_ascii();
+        if data == b"[DONE]" {
+            if !self.done {
+                self.done_line(out);
+            }
+            return;
+        }
+        let Ok(chunk) = serde_json::from_slice::<Value>(data) else {
+            return;
+        };
+        if let Some(error) = chunk.get("error") {
+            push_line(out, &json!({ "error": error_message(error) }));
+            return;
+        }
+        self.count_usage(&chunk);
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return;
+        };
+        let delta = choice.get("delta").unwrap_or(choice);
+        let text = delta.get("content").or_else(|| choice.get("text")).and_then(Value::as_str).unwrap_or_default();
+        let thinking = delta.get("reasoning_content").or_else(|| delta.get("reasoning")).and_then(Value::as_str).unwrap_or_default();
+        if !text.is_empty() || !thinking.is_empty() {
+            push_line(out, &self.message(text, (!thinking.is_empty()).then_some(thinking), None, false));
+        }
+        for call in delta.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+            let index = call.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+            if self.calls.len() <= index {
+                self.calls.resize_with(index + 1, PendingCall::default);
+            }
+            let pending = &mut self.calls[index];
+            if let Some(name) = call.pointer("/function/name").and_then(Value::as_str) {
+                pending.name.push_str(name);
+            }
+            if let Some(arguments) = call.pointer("/function/arguments").and_then(Value::as_str) {
+                pending.arguments.push_str(arguments);
+            }
+        }
+        if let Some(reason) = choice.get("finish_reason").and_then(Value::as_str) {
+            self.done_reason = Some(reason.to_string());
+        }
+    }
+
+    /// Tool calls collected so far, then the closing `"done": true` line.
+    fn done_line(&mut self, out: &mut BytesMut) {
+        if !self.calls.is_empty() {
+            let calls: Vec<Value> = self.calls.drain(..).map(|c| tool_call(&c.name, &c.arguments)).collect();
+            push_line(out, &self.message("", None, Some(calls), false));
+        }
+        push_line(out, &self.message("", None, None, true));
+        self.done = true;
+    }
+
+    fn translate_whole(&mut self, raw: Bytes) -> Bytes {
+        let Ok(json) = serde_json::from_slice::<Value>(&raw) else {
+            return raw;
+        };
+        if let Some(error) = json.get("error") {
+            return Bytes::from(json!({ "error": error_message(error) }).to_string());
+        }
+        let Some(choice) = json.pointer("/choices/0") else {
+            return raw;
+        };
+        self.count_usage(&json);
+        self.done_reason = choice.get("finish_reason").and_then(Value::as_str).map(str::to_string);
+        let text = choice.pointer("/message/content").or_else(|| choice.get("text")).and_then(Value::as_str).unwrap_or_default();
+        let thinking = choice.pointer("/message/reasoning_content").or_else(|| choice.pointer("/message/reasoning")).and_then(Value::as_str);
+        let calls = choice.pointer("/message/tool_calls").and_then(Value::as_array).map(|calls| {
+            calls.iter().map(|c| {
+                let name = c.pointer("/function/name").and_then(Value::as_str).unwrap_or_default();
+                let arguments = c.pointer("/function/arguments").and_then(Value::as_str).unwrap_or("{}");
+                tool_call(name, arguments)
+            }).collect()
+        });
+        Bytes::from(self.message(text, thinking, calls, true).to_string())
+    }
+
+    fn count_usage(&mut self, json: &Value) {
+        if let Some(usage) = json.get("usage").filter(|u| u.is_object()) {
+            self.prompt_tokens = usage.get("prompt_tokens").and_then(Value::as_u64).or(self.prompt_tokens);
+            self.completion_tokens = usage.get("completion_tokens").and_then(Value::as_u64).or(self.completion_tokens);
+        }
+    }
+
+    /// An Ollama chunk or answer; `done` adds the reason, timing and token counts.
+    fn message(&self, text: &str, thinking: Option<&str>, calls: Option<Vec<Value>>, done: bool) -> Value {
+        let mut out = json!({
+            "model": self.model,
+            "created_at": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        });
+        match self.shape {
+            Shape::Chat => {
+                let mut message = json!({ "role": "assistant", "content": text });
+                if let Some(thinking) = thinking {
+                    message["thinking"] = json!(thinking);
+                }
+                if let Some(calls) = calls {
+                    message["tool_calls"] = Value::Array(calls);
+                }
+                out["message"] = message;
+            }
+            Shape::Generate => {
+                out["response"] = json!(text);
+                if let Some(thinking) = thinking {
+                    out["thinking"] = json!(thinking);
+                }
+            }
+        }
+        out["done"] = json!(done);
+        if done {
+            // Ollama reports a tool call as an ordinary stop
+            let reason = match self.done_reason.as_deref() {
+                None | Some("tool_calls") => "stop",
+                Some(reason) => reason,
+            };
+            out["done_reason"] = json!(reason);
+            out["total_duration"] = json!(self.started.elapsed().as_nanos() as u64);
+            if let Some(n) = self.prompt_tokens {
+                out["prompt_eval_count"] = json!(n);
+            }
+            if let Some(n) = self.completion_tokens {
+                out["eval_count"] = json!(n);
+            }
+        }
+        out
+    }
+}
+
+/// An Ollama tool call: arguments as an object rather than a JSON string.
+fn tool_call(name: &str, arguments: &str) -> Value {
+    let arguments = serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!(arguments));
+    json!({ "function": { "name": name, "arguments": arguments } })
+}
+
+fn error_message(error: &Value) -> String {
+    match error.get("message").unwrap_or(error) {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn push_line(out: &mut BytesMut, value: &Value) {
+    out.extend_from_slice(value.to_string().as_bytes());
+    out.extend_from_slice(b"\n");
+}
+
+/// The translated body; the final chunk is produced when the backend's ends.
+/// An interrupted backend stream ends the translated one without a `done` line.
+fn translate_stream(
+    body: BoxStream<'static, reqwest::Result<Bytes>>,
+    translator: ResponseTranslator,
+) -> BoxStream<'static, reqwest::Result<Bytes>> {
+    futures_util::stream::unfold(Some((body, translator)), |state| async move {
+        let (mut body, mut translator) = state?;
+        loop {
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    let out = translator.push(&chunk);
+                    if !out.is_empty() {
+                        return Some((Ok(out), Some((body, translator))));
+                    }
+                }
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {
+                    let out = translator.finish();
+                    return (!out.is_empty()).then_some((Ok(out), None));
+                }
+            }
+        }
+    })
+    .boxed()
+}
@@ -6,14 +6,319 @@ use crossterm::{
 use ratatui::{
     backend::CrosstermBackend,
     prelude::*,
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    symbols::border,
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState},
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
 use std::net::IpAddr;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::dispatcher::{AppState, BackendApiType, BackendStatus};
+use crate::autoscale::{ScaleSignal, ScaleStatus};
+use crate::blocked_store::BlockInfo;
+use crate::columns::ColumnConfig;
+use crate::config_view::ConfigEntry;
+use crate::dispatcher::{AppState, BackendApiType, BackendStatus, Bandwidth, Task, TokenTotals, ToolUse};
+use crate::error_budget::BudgetStatus;
+use crate::errors::ErrorCounts;
+use crate::fairness::FairnessReport;
+use crate::forecast::Forecast;
+use crate::history::{HistoryEntry, Outcome};
+use crate::ip::IpPrefix;
+use crate::namespace::{in_namespace, namespace_of};
+use crate::rollup::WindowCounts;
+use crate::maintenance::{MaintenanceStatus, format_countdown};
+use crate::shutdown::ShutdownStatus;
+use crate::slo::{MIN_SAMPLES, SloStatus};
+use crate::timings::{Stage, format_bytes, format_duration, format_uptime};
+
+/// How many destructive actions can be undone.
+const UNDO_HISTORY: usize = 10;
+/// Purged tasks are held this long for a possible undo before being dropped.
+const PURGE_UNDO_TTL: Duration = Duration::from_secs(60);
+const STATUS_TTL: Duration = Duration::from_secs(5);
+/// Rows moved by PageUp/PageDown in tables whose visible height isn't tracked.
+const PAGE_ROWS: usize = 10;
+/// Colors of the most served users in the fairness view.
+const SHARE_COLORS: [Color; 8] = [
+    Color::Cyan, Color::Magenta, Color::Yellow, Color::Green, Color::Blue, Color::Red, Color::LightCyan, Color::LightMagenta,
+];
+/// Table rows taken up by borders, the header and its bottom margin.
+const TABLE_CHROME: u16 = 4;
+/// Queued tasks younger than this are drawn green, older ones yellow...
+const TASK_AGE_FRESH: Duration = Duration::from_secs(10);
+/// ...and red from this age on.
+const TASK_AGE_STALE: Duration = Duration::from_secs(60);
+/// Lines of per-task bars shown under an expanded queue row.
+const TASK_BAR_LINES: usize = 3;
+/// Smallest terminal the compact view fits in; anything smaller only gets a
+/// "terminal too small" message.
+const MIN_WIDTH: u16 = 40;
+const MIN_HEIGHT: u16 = 10;
+/// Below this size the three-column dashboard switches to the compact view.
+const COMPACT_WIDTH: u16 = 100;
+const COMPACT_HEIGHT: u16 = 24;
+
+/// Symbols drawn by the dashboard, swappable for terminals without Unicode glyphs.
+struct Glyphs {
+    online: &'static str,
+    offline: &'static str,
+    expanded: &'static str,
+    collapsed: &'static str,
+    active: &'static str,
+    last: &'static str,
+    latency: &'static str,
+    tree: &'static str,
+    blocked: &'static str,
+    vip: &'static str,
+    boost: &'static str,
+    processing: &'static str,
+    queued: &'static str,
+    idle: &'static str,
+    bar: &'static str,
+    /// One queued task in an expanded queue row.
+    task: &'static str,
+    /// One cell of the fairness timeline.
+    share: &'static str,
+    cursor: &'static str,
+    border: border::Set,
+}
+
+const UNICODE_GLYPHS: Glyphs = Glyphs {
+    online: "● ",
+    offline: "○ ",
+    expanded: "▼ ",
+    collapsed: "▶ ",
+    active: "▶",
+    last: "↺",
+    latency: "⏱",
+    tree: "└",
+    blocked: "✖ ",
+    vip: "★ ",
+    boost: "⚡",
+    processing: "▶ ",
+    queued: "● ",
+    idle: "○ ",
+    bar: "⠿",
+    task: "▮",
+    share: "█",
+    cursor: "█",
+    border: border::PLAIN,
+};
+
+const ASCII_GLYPHS: Glyphs = Glyphs {
+    online: "+ ",
+    offline: "- ",
+    expanded: "v ",
+    collapsed: "> ",
+    active: ">",
+    last: "~",
+    latency: "t",
+    tree: "`",
+    blocked: "X ",
+    vip: "* ",
+    boost: "^ ",
+    processing: "> ",
+    queued: "o ",
+    idle: ". ",
+    bar: "#",
+    task: "|",
+    share: "#",
+    cursor: "_",
+    border: border::Set {
+        top_left: "+",
+        top_right: "+",
+        bottom_left: "+",
+        bottom_right: "+",
+        vertical_left: "|",
+        vertical_right: "|",
+        horizontal_top: "-",
+        horizontal_bottom: "-",
+    },
+};
+
+/// How the dashboard draws attention to an alert.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertNotify {
+    Off,
+    /// Ring the terminal bell.
+    Bell,
+    /// Send an OSC 9 desktop notification (iTerm2, WezTerm, kitty, Windows Terminal, ...).
+    Desktop,
+    /// Both bell and desktop notification.
+    Both,
+}
+
+/// File format of dashboard snapshots saved with `s`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Aligned plain-text columns.
+    Text,
+    /// Markdown tables, ready for an incident report or ticket.
+    Markdown,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Markdown => "md",
+        }
+    }
+}
+
+/// Terminal compatibility and alerting settings for the dashboard.
+#[derive(Clone, Copy, Debug)]
+pub struct TuiOptions {
+    /// Draw with plain ASCII instead of braille and other Unicode symbols.
+    pub ascii: bool,
+    /// Render in the terminal's alternate screen buffer.
+    pub alternate_screen: bool,
+    pub notify: AlertNotify,
+    /// Alert when more than this many requests are queued in total.
+    pub alert_queue_over: Option<usize>,
+    /// Alert when a watched user submits a request.
+    pub alert_watched: bool,
+    /// How often dispatcher state is re-read; key presses are handled in between.
+    pub refresh: Duration,
+    pub export_format: ExportFormat,
+}
+
+impl Default for TuiOptions {
+    fn default() -> Self {
+        Self {
+            ascii: false,
+            alternate_screen: true,
+            notify: AlertNotify::Off,
+            alert_queue_over: None,
+            alert_watched: false,
+            refresh: Duration::from_millis(250),
+            export_format: ExportFormat::Markdown,
+        }
+    }
+}
+
+/// Detects alert conditions as they start, so each one notifies only once.
+#[derive(Default)]
+struct AlertWatcher {
+    queue_over: Option<usize>,
+    queue_alerted: bool,
+    offline: HashSet<String>,
+    cooling_down: HashSet<String>,
+    /// Labels of the latency objectives currently violated.
+    slo_violated: HashSet<String>,
+    watched: bool,
+    /// Requests seen so far per watched user; `None` until the first check.
+    watched_seen: Option<HashMap<String, usize>>,
+}
+
+impl AlertWatcher {
+    fn check(&mut self, snapshot: &StateSnapshot) -> Vec<String> {
+        let mut alerts = Vec::new();
+
+        if let Some(limit) = self.queue_over {
+            let queued: usize = snapshot.queues_len.values().sum();
+            if queued > limit && !self.queue_alerted {
+                alerts.push(format!("{} requests queued (limit {})", queued, limit));
+            }
+            self.queue_alerted = queued > limit;
+        }
+
+        let offline: HashSet<String> = snapshot.backends.iter().filter(|b| !b.is_online).map(|b| b.url.clone()).collect();
+        for url in offline.difference(&self.offline) {
+            alerts.push(format!("Backend {} is down", url));
+        }
+        self.offline = offline;
+
+        for user in snapshot.cooling_down.difference(&self.cooling_down) {
+            alerts.push(format!("User {} is on failure cooldown", user));
+        }
+        self.cooling_down = snapshot.cooling_down.clone();
+
+        let violated: HashSet<String> = snapshot.slos.iter().filter(|s| s.violated).map(SloStatus::label).collect();
+        for label in violated.difference(&self.slo_violated) {
+            alerts.push(format!("SLO {} violated", label));
+        }
+        self.slo_violated = violated;
+
+        if self.watched {
+            let seen: HashMap<String, usize> = snapshot.watched.iter()
+                .map(|u| {
+                    let count = [&snapshot.queues_len, &snapshot.processing_counts, &snapshot.processed_counts, &snapshot.dropped_counts]
+                        .iter()
+                        .map(|counts| counts.get(u).copied().unwrap_or(0))
+                        .sum();
+                    (u.clone(), count)
+                })
+                .collect();
+            if let Some(previous) = &self.watched_seen {
+                for (user, count) in &seen {
+                    if *count > previous.get(user).copied().unwrap_or(0) {
+                        alerts.push(format!("Watched user {} submitted a request", user));
+                    }
+                }
+            }
+            self.watched_seen = Some(seen);
+        }
+
+        alerts
+    }
+}
+
+/// Rings the bell and/or sends an OSC 9 notification for one alert.
+fn notify(mode: AlertNotify, message: &str) -> io::Result<()> {
+    use std::io::Write;
+    let mut out = io::stdout();
+    if matches!(mode, AlertNotify::Bell | AlertNotify::Both) {
+        out.write_all(b"\x07")?;
+    }
+    if matches!(mode, AlertNotify::Desktop | AlertNotify::Both) {
+        let text: String = message.chars().filter(|c| !c.is_control()).collect();
+        write!(out, "\x1b]9;ollamaMQ: {}\x07", text)?;
+    }
+    out.flush()
+}
+
+/// A destructive action waiting for the operator to confirm it.
+enum Confirm {
+    BlockUser(String),
+    BlockIp(IpPrefix),
+    PurgeQueue(String),
+}
+
+impl Confirm {
+    fn prompt(&self) -> String {
+        match self {
+            Confirm::BlockUser(user) => format!("Block user '{}'?", user),
+            Confirm::BlockIp(ip) => format!("Block IP {}?", ip),
+            Confirm::PurgeQueue(user) => format!("Purge all queued requests of '{}'?", user),
+        }
+    }
+}
+
+/// Something that was done and can be reverted with `z`.
+enum Undoable {
+    Blocked { users: Vec<String>, ips: Vec<IpPrefix> },
+    /// With the lifted blocks' metadata, restored on undo.
+    Unblocked { users: Vec<(String, BlockInfo)>, ips: Vec<(IpPrefix, BlockInfo)> },
+    Purged { user: String, tasks: Vec<Task>, at: Instant },
+}
+
+/// Full-screen list of the effective configuration, opened with `C`.
+#[derive(Default)]
+struct ConfigView {
+    table_state: TableState,
+}
+
+/// Full-screen list of recently finished requests, opened with `H`.
+#[derive(Default)]
+struct HistoryView {
+    table_state: TableState,
+    /// Show the detail popup of the selected entry.
+    detail: bool,
+}
 
 #[derive(PartialEq)]
 enum Panel {
@@ -22,18 +327,136 @@ enum Panel {
     Blocked,
 }
 
+#[derive(Default)]
 struct StateSnapshot {
     queues_len: HashMap<String, usize>,
     processing_counts: HashMap<String, usize>,
     processed_counts: HashMap<String, usize>,
     dropped_counts: HashMap<String, usize>,
+    /// Completions per user over the rollup windows; users idle for a day are left out.
+    recent: HashMap<String, WindowCounts>,
+    /// Expected requests per model over the next hour.
+    forecast: Forecast,
     user_ips: HashMap<String, IpAddr>,
-    blocked_ips: HashSet<IpAddr>,
+    bandwidth: HashMap<String, Bandwidth>,
+    tool_use: HashMap<String, ToolUse>,
+    tokens: HashMap<String, TokenTotals>,
+    /// Extra users-table columns from the config file.
+    columns: Vec<ColumnConfig>,
+    /// Docker container names of client IPs, with `--docker-names`.
+    ip_names: HashMap<IpAddr, String>,
+    blocked_ips: HashSet<IpPrefix>,
+    /// Blocked IPs whose block is temporary, after failed authentication.
+    banned: HashSet<IpPrefix>,
     blocked_users: HashSet<String>,
+    user_blocks: HashMap<String, BlockInfo>,
+    ip_blocks: HashMap<IpPrefix, BlockInfo>,
     vip_user: Option<String>,
     boost_user: Option<String>,
+    dispatch_next: Option<String>,
     user_ids: Vec<String>,
     backends: Vec<BackendStatus>,
+    /// Error budget standing per backend URL.
+    budgets: HashMap<String, BudgetStatus>,
+    /// Tier latency objectives; empty when none are configured.
+    slos: Vec<SloStatus>,
+    maintenance: MaintenanceStatus,
+    /// Progress of the graceful shutdown, once it started.
+    shutdown: Option<ShutdownStatus>,
+    cooling_down: HashSet<String>,
+    user_notes: HashMap<String, String>,
+    watched: HashSet<String>,
+    /// p50/p90/p99 per stage, empty until a request completes.
+    stage_percentiles: Vec<(Stage, Vec<Duration>)>,
+    stage_samples: usize,
+    uptime: Duration,
+    requests_per_sec: f64,
+    in_flight: usize,
+    /// Bytes held by queued bodies, and the `--max-queued-mb` cap (0 = none).
+    queued_bytes: (u64, u64),
+    spooled_bytes: u64,
+    /// Distinct IPs each user currently has open requests from.
+    connection_ips: HashMap<String, usize>,
+    /// Finished requests, newest first; only captured while the history view is open.
+    history: Vec<HistoryEntry>,
+    /// Replay state of the history entries that failed on a backend, by task id.
+    replay_states: HashMap<String, &'static str>,
+    /// Effective configuration; only captured while the config view is open.
+    config: Vec<ConfigEntry>,
+    /// Share of dispatches per user; only captured while the fairness view is open.
+    fairness: FairnessReport,
+    scale: ScaleStatus,
+    /// Time each queued task of the expanded user has waited, oldest first.
+    task_ages: Vec<Duration>,
+    /// Id of the expanded user's task that runs next.
+    next_task_id: Option<String>,
+    /// Failed and rejected requests of the expanded user, by class.
+    expanded_errors: ErrorCounts,
+    /// Namespaces of all known users, sorted.
+    namespaces: Vec<String>,
+    /// Namespace the user figures are limited to.
+    namespace: Option<String>,
+}
+
+impl StateSnapshot {
+    /// Type column of a blocked address: `BAN` for temporary blocks after
+    /// failed authentication, `NET` for prefixes, otherwise `IP`.
+    fn block_kind(&self, ip: &IpPrefix) -> &'static str {
+        if self.banned.contains(ip) { "BAN" } else if ip.is_single() { "IP" } else { "NET" }
+    }
+
+    /// Rows of the blocked panel, sorted by value: type, value and metadata.
+    fn blocked_rows(&self) -> Vec<(&'static str, String, Option<&BlockInfo>)> {
+        let mut items: Vec<_> = self.blocked_ips.iter()
+            .map(|ip| (self.block_kind(ip), ip.to_string(), self.ip_blocks.get(ip)))
+            .chain(self.blocked_users.iter().map(|u| ("USER", u.clone(), self.user_blocks.get(u))))
+            .collect();
+        items.sort_by(|a, b| a.1.cmp(&b.1));
+        items
+    }
+
+    /// Where `user` last connected from: its container name if known, else the IP.
+    fn source(&self, user: &str) -> String {
+        self.user_ips.get(user)
+            .map(|ip| self.ip_names.get(ip).cloned().unwrap_or_else(|| ip.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Completions of `user` over the rollup windows.
+    fn recent(&self, user: &str) -> WindowCounts {
+        self.recent.get(user).copied().unwrap_or_default()
+    }
+
+    /// A counter of `user` for custom column expressions, by its name in
+    /// `columns::VARIABLES`.
+    fn user_var(&self, user: &str, name: &str) -> f64 {
+        let count = |map: &HashMap<String, usize>| *map.get(user).unwrap_or(&0) as u64;
+        let bandwidth = self.bandwidth.get(user).copied().unwrap_or_default();
+        let tools = self.tool_use.get(user).copied().unwrap_or_default();
+        let tokens = self.tokens.get(user).copied().unwrap_or_default();
+        (match name {
+            "queued" => count(&self.queues_len),
+            "processing" => count(&self.processing_counts),
+            "processed" => count(&self.processed_counts),
+            "dropped" => count(&self.dropped_counts),
+            "recent_5m" => self.recent(user)[0],
+            "recent_1h" => self.recent(user)[1],
+            "recent_24h" => self.recent(user)[2],
+            "bytes_in" => bandwidth.bytes_in,
+            "bytes_out" => bandwidth.bytes_out,
+            "tool_requests" => tools.requests,
+            "tool_calls" => tools.calls,
+            "prompt_tokens" => tokens.prompt,
+            "completion_tokens" => tokens.completion,
+            "tokens" => tokens.prompt + tokens.completion,
+            _ => 0,
+        }) as f64
+    }
+
+    /// Values of the custom columns for `user`.
+    fn custom_columns(&self, user: &str) -> Vec<String> {
+        self.columns.iter().map(|c| c.format(|name| self.user_var(user, name))).collect()
+    }
 }
 
 pub struct TuiDashboard {
@@ -42,261 +465,759 @@ pub struct TuiDashboard {
     blocked_table_state: TableState,
     active_panel: Panel,
     expanded_backends: HashSet<String>,
+    /// User whose queue row shows one bar per queued task.
+    expanded_queue: Option<String>,
     show_help: bool,
+    /// Users table shows bytes in and out per user.
+    show_bandwidth: bool,
+    confirm: Option<Confirm>,
+    /// User whose note is being edited, with the text typed so far.
+    note_input: Option<(String, String)>,
+    /// Failed task being replayed on another model, with the model typed so far.
+    replay_input: Option<(String, String)>,
+    history: Option<HistoryView>,
+    config_view: Option<ConfigView>,
+    /// Full-screen timeline of the share of dispatches per user, opened with `F`.
+    fairness_view: bool,
+    undo_stack: VecDeque<Undoable>,
+    status: Option<(String, Instant)>,
+    glyphs: &'static Glyphs,
+    alternate_screen: bool,
+    notify: AlertNotify,
+    alerts: AlertWatcher,
+    refresh: Duration,
+    export_format: ExportFormat,
+    /// First user row drawn in the users and queue tables.
+    user_offset: usize,
+    queue_offset: usize,
+    /// User rows that fit in the users table, the PageUp/PageDown step.
+    user_page: usize,
+    /// Namespace whose users are shown; `None` shows everyone.
+    namespace: Option<String>,
+}
+
+impl Default for TuiDashboard {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TuiDashboard {
     pub fn new() -> Self {
+        Self::with_options(TuiOptions::default())
+    }
+
+    pub fn with_options(options: TuiOptions) -> Self {
         Self {
             table_state: TableState::default(),
             backend_table_state: TableState::default(),
             blocked_table_state: TableState::default(),
             active_panel: Panel::Users,
             expanded_backends: HashSet::new(),
+            expanded_queue: None,
             show_help: false,
+            show_bandwidth: false,
+            confirm: None,
+            note_input: None,
+            replay_input: None,
+            history: None,
+            config_view: None,
+            fairness_view: false,
+            undo_stack: VecDeque::new(),
+            status: None,
+            glyphs: if options.ascii { &ASCII_GLYPHS } else { &UNICODE_GLYPHS },
+            alternate_screen: options.alternate_screen,
+            notify: options.notify,
+            alerts: AlertWatcher {
+                queue_over: options.alert_queue_over,
+                watched: options.alert_watched,
+                ..AlertWatcher::default()
+            },
+            refresh: options.refresh,
+            export_format: options.export_format,
+            user_offset: 0,
+            queue_offset: 0,
+            user_page: PAGE_ROWS,
+            namespace: None,
         }
     }
 
-    fn capture_snapshot(&self, state: &Arc<AppState>) -> StateSnapshot {
-        let queues_len: HashMap<String, usize> = {
-            let q = state.queues.lock().unwrap();
-            q.iter().map(|(k, v)| (k.clone(), v.len())).collect()
+    fn block(&self) -> Block<'static> {
+        Block::default().border_set(self.glyphs.border)
+    }
+
+    /// Restores the terminal; `run` returns this to quit.
+    fn leave(&self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<bool> {
+        if self.alternate_screen {
+            io::stdout().execute(LeaveAlternateScreen)?;
+        } else {
+            terminal.clear()?;
+        }
+        disable_raw_mode()?;
+        terminal.show_cursor()?;
+        Ok(false)
+    }
+
+    fn set_status(&mut self, msg: impl Into<String>) {
+        self.status = Some((msg.into(), Instant::now()));
+    }
+
+    fn push_undo(&mut self, state: &Arc<AppState>, entry: Undoable) {
+        self.undo_stack.push_front(entry);
+        while self.undo_stack.len() > UNDO_HISTORY {
+            if let Some(Undoable::Purged { user, tasks, .. }) = self.undo_stack.pop_back() {
+                state.record_dropped(&user, tasks.len());
+            }
+        }
+    }
+
+    /// Drops purged tasks whose undo window has passed.
+    fn expire_undo(&mut self, state: &Arc<AppState>) {
+        let mut kept = VecDeque::with_capacity(self.undo_stack.len());
+        for entry in self.undo_stack.drain(..) {
+            match entry {
+                Undoable::Purged { user, tasks, at } if at.elapsed() > PURGE_UNDO_TTL => {
+                    state.record_dropped(&user, tasks.len());
+                }
+                other => kept.push_back(other),
+            }
+        }
+        self.undo_stack = kept;
+    }
+
+    fn apply_confirmed(&mut self, state: &Arc<AppState>, action: Confirm) {
+        match action {
+            Confirm::BlockUser(user) => {
+                state.block_user(user.clone(), BlockInfo::new("tui"));
+                self.set_status(format!("Blocked user {} (z: undo)", user));
+                self.push_undo(state, Undoable::Blocked { users: vec![user], ips: vec![] });
+            }
+            Confirm::BlockIp(ip) => {
+                state.block_ip(ip, BlockInfo::new("tui"));
+                self.set_status(format!("Blocked IP {} (z: undo)", ip));
+                self.push_undo(state, Undoable::Blocked { users: vec![], ips: vec![ip] });
+            }
+            Confirm::PurgeQueue(user) => {
+                let tasks = state.purge_queue(&user);
+                self.set_status(format!("Purged {} request(s) of {} (z: undo)", tasks.len(), user));
+                if !tasks.is_empty() {
+                    self.push_undo(state, Undoable::Purged { user, tasks, at: Instant::now() });
+                }
+            }
+        }
+    }
+
+    fn replay(&mut self, state: &Arc<AppState>, task_id: &str, model: Option<&str>) {
+        match crate::dead_letter::replay(state, task_id, model) {
+            Ok(()) => self.set_status(match model {
+                Some(model) => format!("Replaying task {} on {}", task_id, model),
+                None => format!("Replaying task {}", task_id),
+            }),
+            Err(e) => self.set_status(format!("Cannot replay task {}: {}", task_id, e)),
+        }
+    }
+
+    fn undo_last(&mut self, state: &Arc<AppState>) {
+        let Some(entry) = self.undo_stack.pop_front() else {
+            self.set_status("Nothing to undo");
+            return;
         };
-        let processing_counts = state.processing_counts.lock().unwrap().clone();
-        let processed_counts = state.processed_counts.lock().unwrap().clone();
-        let dropped_counts = state.dropped_counts.lock().unwrap().clone();
-        let user_ips = state.user_ips.lock().unwrap().clone();
-        let blocked_ips = state.blocked_ips.lock().unwrap().clone();
-        let blocked_users = state.blocked_users.lock().unwrap().clone();
-        let vip_user = state.vip_user.lock().unwrap().clone();
-        let boost_user = state.boost_user.lock().unwrap().clone();
-        let backends = state.backends.lock().unwrap().clone();
-
-        let mut user_ids: Vec<String> = queues_len.keys().cloned().collect();
-        user_ids.sort_by(|a, b| {
+        match entry {
+            Undoable::Blocked { users, ips } => {
+                for user in &users {
+                    state.unblock_user(user);
+                }
+                for ip in &ips {
+                    state.unblock_ip(*ip);
+                }
+                self.set_status("Undone: block lifted");
+            }
+            Undoable::Unblocked { users, ips } => {
+                for (user, info) in users {
+                    state.block_user(user, info);
+                }
+                for (ip, info) in ips {
+                    state.block_ip(ip, info);
+                }
+                self.set_status("Undone: block restored");
+            }
+            Undoable::Purged { user, tasks, .. } => {
+                let total = tasks.len();
+                let restored = state.restore_tasks(&user, tasks);
+                self.set_status(format!("Undone: restored {}/{} request(s) of {}", restored, total, user));
+            }
+        }
+    }
+
+    /// Brings `snapshot` up to date. Per-user maps are updated in place, so with
+    /// thousands of users a refresh copies counters instead of every user id, and
+    /// the queues lock is only held while the queue lengths are read.
+    fn refresh_snapshot(&self, state: &Arc<AppState>, snapshot: &mut StateSnapshot) {
+        sync_map(&mut snapshot.queues_len, &state.queues.lock().unwrap(), |q| q.len());
+        sync_map(&mut snapshot.processing_counts, &state.processing_counts.lock().unwrap(), |n| *n);
+        sync_map(&mut snapshot.processed_counts, &state.processed_counts.lock().unwrap(), |n| *n);
+        sync_map(&mut snapshot.dropped_counts, &state.dropped_counts.lock().unwrap(), |n| *n);
+        sync_map(&mut snapshot.recent, &state.rollup.lock().unwrap().users, |n| *n);
+        snapshot.forecast = state.forecast.lock().unwrap().forecast(chrono::Local::now());
+        sync_map(&mut snapshot.user_ips, &state.user_ips.lock().unwrap(), |ip| *ip);
+        sync_map(&mut snapshot.bandwidth, &state.bandwidth.lock().unwrap(), |b| *b);
+        sync_map(&mut snapshot.tool_use, &state.tool_use.lock().unwrap(), |t| *t);
+        sync_map(&mut snapshot.tokens, &state.token_totals.lock().unwrap(), |t| *t);
+        if snapshot.columns.len() != state.config.file.tui_columns.len() {
+            snapshot.columns = state.config.file.tui_columns.clone();
+        }
+        sync_map(&mut snapshot.connection_ips, &state.connections.lock().unwrap(), |ips| ips.len());
+        if let Some(ref docker) = state.docker {
+            snapshot.ip_names = docker.names();
+        }
+        snapshot.blocked_ips = state.blocked_ips.lock().unwrap().clone();
+        snapshot.banned = state.jail.lock().unwrap().banned();
+        snapshot.blocked_users = state.blocked_users.lock().unwrap().clone();
+        snapshot.user_blocks = state.user_blocks.lock().unwrap().clone();
+        snapshot.ip_blocks = state.ip_blocks.lock().unwrap().clone();
+        snapshot.vip_user = state.vip_user.lock().unwrap().clone();
+        snapshot.boost_user = state.boost_user.lock().unwrap().clone();
+        snapshot.dispatch_next = state.dispatch_next.lock().unwrap().clone();
+        snapshot.backends = state.backends.lock().unwrap().clone();
+        snapshot.budgets = snapshot.backends.iter().map(|b| (b.url.clone(), state.error_budget(b))).collect();
+        snapshot.slos = state.slo_status();
+        snapshot.maintenance = state.maintenance.lock().unwrap().clone();
+        snapshot.shutdown = state.shutdown.lock().unwrap().clone();
+        snapshot.scale = state.scale.lock().unwrap().clone();
+        snapshot.cooling_down = {
+            let now = std::time::Instant::now();
+            state.cooldowns.lock().unwrap().iter().filter(|(_, until)| **until > now).map(|(u, _)| u.clone()).collect()
+        };
+        snapshot.namespaces = {
+            let mut names: Vec<String> = snapshot.queues_len.keys().filter_map(|u| namespace_of(u)).map(str::to_string).collect();
+            names.sort();
+            names.dedup();
+            names
+        };
+        snapshot.namespace = self.namespace.clone();
+        if let Some(ref ns) = self.namespace {
+            // Everything per user below only covers the chosen namespace
+            let keep = |user: &String| in_namespace(user, Some(ns));
+            snapshot.queues_len.retain(|u, _| keep(u));
+            snapshot.processing_counts.retain(|u, _| keep(u));
+            snapshot.processed_counts.retain(|u, _| keep(u));
+            snapshot.dropped_counts.retain(|u, _| keep(u));
+            snapshot.recent.retain(|u, _| keep(u));
+            snapshot.user_ips.retain(|u, _| keep(u));
+            snapshot.bandwidth.retain(|u, _| keep(u));
+            snapshot.tool_use.retain(|u, _| keep(u));
+            snapshot.tokens.retain(|u, _| keep(u));
+            snapshot.connection_ips.retain(|u, _| keep(u));
+            snapshot.blocked_users.retain(keep);
+            snapshot.user_blocks.retain(|u, _| keep(u));
+        }
+        snapshot.user_notes = state.user_notes.lock().unwrap().clone();
+        snapshot.watched = state.watched_users.lock().unwrap().clone();
+        (snapshot.stage_percentiles, snapshot.stage_samples) = {
+            let stats = state.stage_stats.lock().unwrap();
+            let p = Stage::ALL.iter()
+                .filter_map(|stage| stats.percentiles(*stage, &[0.5, 0.9, 0.99]).map(|v| (*stage, v)))
+                .collect();
+            (p, stats.len())
+        };
+
+        // Only rebuild the id list when users came or went
+        let queues_len = &snapshot.queues_len;
+        snapshot.user_ids.retain(|u| queues_len.contains_key(u));
+        if snapshot.user_ids.len() != queues_len.len() {
+            snapshot.user_ids = queues_len.keys().cloned().collect();
+        }
+        let (processing_counts, processed_counts, dropped_counts, watched) =
+            (&snapshot.processing_counts, &snapshot.processed_counts, &snapshot.dropped_counts, &snapshot.watched);
+        // Watched users stay pinned to the top
+        snapshot.user_ids.sort_by(|a, b| {
             let a_q = queues_len.get(a).unwrap_or(&0) + processing_counts.get(a).unwrap_or(&0);
             let b_q = queues_len.get(b).unwrap_or(&0) + processing_counts.get(b).unwrap_or(&0);
             let a_total = processed_counts.get(a).unwrap_or(&0) + dropped_counts.get(a).unwrap_or(&0);
             let b_total = processed_counts.get(b).unwrap_or(&0) + dropped_counts.get(b).unwrap_or(&0);
 
-            b_q.cmp(&a_q)
+            watched.contains(b).cmp(&watched.contains(a))
+                .then_with(|| b_q.cmp(&a_q))
                 .then_with(|| b_total.cmp(&a_total))
                 .then_with(|| a.cmp(b))
         });
 
-        StateSnapshot {
-            queues_len,
-            processing_counts,
-            processed_counts,
-            dropped_counts,
-            user_ips,
-            blocked_ips,
-            blocked_users,
-            vip_user,
-            boost_user,
-            user_ids,
-            backends,
-        }
+        snapshot.uptime = state.started_at.elapsed();
+        snapshot.requests_per_sec = state.request_rate.lock().unwrap().per_sec();
+        snapshot.in_flight = state.in_flight.load(std::sync::atomic::Ordering::Relaxed);
+        snapshot.queued_bytes = (state.queued_bytes(), state.config.max_queued_bytes);
+        snapshot.spooled_bytes = state.spooled_bytes();
+        let expanded = self.expanded_queue.as_ref().and_then(|u| {
+            state.queues.lock().unwrap().get(u).map(|q| (q.iter().map(|t| t.enqueued_at).collect::<Vec<_>>(), q.front().map(|t| t.id.clone())))
+        });
+        (snapshot.task_ages, snapshot.next_task_id) = match expanded {
+            Some((mut enqueued, next)) => {
+                enqueued.sort();
+                (enqueued.iter().map(|at| at.elapsed()).collect(), next)
+            }
+            None => (Vec::new(), None),
+        };
+        snapshot.expanded_errors = self.expanded_queue.as_ref()
+            .and_then(|u| state.error_counts.lock().unwrap().get(u).copied())
+            .unwrap_or_default();
+        snapshot.history = if self.history.is_some() {
+            state.history.lock().unwrap().newest_first()
+                .filter(|e| in_namespace(&e.user_id, self.namespace.as_deref()))
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        snapshot.replay_states = {
+            let letters = state.dead_letters.lock().unwrap();
+            snapshot.history.iter()
+                .filter_map(|e| letters.replay_state(&e.task_id).map(|s| (e.task_id.clone(), s)))
+                .collect()
+        };
+        snapshot.config = if self.config_view.is_some() { crate::config_view::effective(state) } else { Vec::new() };
+        snapshot.fairness = if self.fairness_view { state.fairness.lock().unwrap().report() } else { FairnessReport::default() };
     }
 
     pub fn run(&mut self, state: &Arc<AppState>) -> io::Result<bool> {
         enable_raw_mode()?;
-        io::stdout().execute(EnterAlternateScreen)?;
+        if self.alternate_screen {
+            io::stdout().execute(EnterAlternateScreen)?;
+        }
         let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
         terminal.clear()?;
 
+        let mut snapshot = StateSnapshot::default();
+        let mut last_refresh: Option<Instant> = None;
         loop {
-            let snapshot = self.capture_snapshot(state);
+            let mut alerts = Vec::new();
+            if last_refresh.is_none_or(|t| t.elapsed() >= self.refresh) {
+                self.expire_undo(state);
+                // Keep the cursor on the same user when the list is re-sorted
+                let selected_user = self.table_state.selected().and_then(|i| snapshot.user_ids.get(i).cloned());
+                self.refresh_snapshot(state, &mut snapshot);
+                if let Some(i) = selected_user.and_then(|u| snapshot.user_ids.iter().position(|id| *id == u)) {
+                    self.table_state.select(Some(i));
+                }
+                alerts = self.alerts.check(&snapshot);
+                if let Some(last) = alerts.last() {
+                    self.set_status(format!("ALERT: {}", last));
+                }
+                last_refresh = Some(Instant::now());
+                if snapshot.shutdown.as_ref().is_some_and(|s| s.finished) {
+                    return self.leave(&mut terminal);
+                }
+            }
             terminal.draw(|f| self.render(f, &snapshot))?;
+            if self.notify != AlertNotify::Off {
+                for alert in &alerts {
+                    notify(self.notify, alert)?;
+                }
+            }
 
-            if event::poll(std::time::Duration::from_millis(100))? {
-                if let Event::Key(key) = event::read()? {
-                    if key.kind != KeyEventKind::Press {
-                        continue;
+            let until_refresh = self.refresh.saturating_sub(last_refresh.map_or(Duration::ZERO, |t| t.elapsed()));
+            if event::poll(until_refresh)?
+                && let Event::Key(key) = event::read()?
+            {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                // Moving around redraws from the cached snapshot; anything else
+                // may have changed state and re-reads it right away
+                let navigation = matches!(
+                    key.code,
+                    KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown | KeyCode::Home | KeyCode::End | KeyCode::Tab
+                        | KeyCode::Char('j' | 'k' | 'h' | 'l' | 'g' | 'G' | '?')
+                );
+                if !navigation || self.note_input.is_some() || self.replay_input.is_some() {
+                    last_refresh = None;
+                }
+                if let Some((user, mut text)) = self.note_input.take() {
+                    match key.code {
+                        KeyCode::Enter => {
+                            state.set_user_note(&user, &text);
+                            self.set_status(if text.trim().is_empty() { format!("Note of {} removed", user) } else { format!("Note of {} saved", user) });
+                        }
+                        KeyCode::Esc => self.set_status("Cancelled"),
+                        KeyCode::Backspace => {
+                            text.pop();
+                            self.note_input = Some((user, text));
+                        }
+                        KeyCode::Char(c) => {
+                            text.push(c);
+                            self.note_input = Some((user, text));
+                        }
+                        _ => self.note_input = Some((user, text)),
                     }
+                    continue;
+                }
+                if let Some((task_id, mut model)) = self.replay_input.take() {
                     match key.code {
-                        KeyCode::Esc | KeyCode::Char('q') => {
-                            io::stdout().execute(LeaveAlternateScreen)?;
-                            disable_raw_mode()?;
-                            terminal.show_cursor()?;
-                            return Ok(false);
+                        KeyCode::Enter => self.replay(state, &task_id, Some(model.trim()).filter(|m| !m.is_empty())),
+                        KeyCode::Esc => self.set_status("Cancelled"),
+                        KeyCode::Backspace => {
+                            model.pop();
+                            self.replay_input = Some((task_id, model));
                         }
-                        KeyCode::Char('?') => self.show_help = !self.show_help,
-                        KeyCode::Tab | KeyCode::Char('l') => {
-                            self.active_panel = match self.active_panel {
-                                Panel::Backends => Panel::Users,
-                                Panel::Users => Panel::Blocked,
-                                Panel::Blocked => Panel::Backends,
-                            };
+                        KeyCode::Char(c) => {
+                            model.push(c);
+                            self.replay_input = Some((task_id, model));
                         }
-                        KeyCode::Char('h') => {
-                            self.active_panel = match self.active_panel {
-                                Panel::Backends => Panel::Blocked,
-                                Panel::Users => Panel::Backends,
-                                Panel::Blocked => Panel::Users,
+                        _ => self.replay_input = Some((task_id, model)),
+                    }
+                    continue;
+                }
+                if let Some(pending) = self.confirm.take() {
+                    match key.code {
+                        KeyCode::Char('y') | KeyCode::Enter => self.apply_confirmed(state, pending),
+                        KeyCode::Char('n') | KeyCode::Esc => self.set_status("Cancelled"),
+                        _ => self.confirm = Some(pending),
+                    }
+                    continue;
+                }
+                if let Some(ref mut view) = self.history {
+                    let len = snapshot.history.len();
+                    let selected = view.table_state.selected().unwrap_or(0);
+                    let last = len.saturating_sub(1);
+                    match key.code {
+                        KeyCode::Esc | KeyCode::Char('q') if view.detail => view.detail = false,
+                        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('H') => self.history = None,
+                        KeyCode::Enter | KeyCode::Char(' ') => view.detail = !view.detail && len > 0,
+                        KeyCode::Up | KeyCode::Char('k') => view.table_state.select(Some(selected.saturating_sub(1))),
+                        KeyCode::Down | KeyCode::Char('j') => view.table_state.select(Some((selected + 1).min(last))),
+                        KeyCode::PageUp => view.table_state.select(Some(selected.saturating_sub(PAGE_ROWS))),
+                        KeyCode::PageDown => view.table_state.select(Some((selected + PAGE_ROWS).min(last))),
+                        KeyCode::Home | KeyCode::Char('g') => view.table_state.select(Some(0)),
+                        KeyCode::End | KeyCode::Char('G') => view.table_state.select(Some(last)),
+                        KeyCode::Char('r') | KeyCode::Char('m') => {
+                            let Some(entry) = snapshot.history.get(selected) else {
+                                continue;
                             };
+                            if snapshot.replay_states.get(&entry.task_id) != Some(&"ready") {
+                                self.set_status(format!("Task {} can't be replayed (only requests failed by a backend are kept)", entry.task_id));
+                            } else if key.code == KeyCode::Char('m') {
+                                self.replay_input = Some((entry.task_id.clone(), entry.model.clone().unwrap_or_default()));
+                            } else {
+                                let task_id = entry.task_id.clone();
+                                self.replay(state, &task_id, None);
+                            }
                         }
-                        KeyCode::Enter | KeyCode::Char(' ') => {
-                            if self.active_panel == Panel::Backends {
-                                if let Some(i) = self.backend_table_state.selected() {
-                                    if i < snapshot.backends.len() {
-                                        let url = snapshot.backends[i].url.clone();
-                                        if self.expanded_backends.contains(&url) {
-                                            self.expanded_backends.remove(&url);
-                                        } else {
-                                            self.expanded_backends.insert(url);
-                                        }
-                                    }
+                        _ => {}
+                    }
+                    continue;
+                }
+                if let Some(ref mut view) = self.config_view {
+                    let last = snapshot.config.len().saturating_sub(1);
+                    let selected = view.table_state.selected().unwrap_or(0);
+                    match key.code {
+                        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('C') => self.config_view = None,
+                        KeyCode::Up | KeyCode::Char('k') => view.table_state.select(Some(selected.saturating_sub(1))),
+                        KeyCode::Down | KeyCode::Char('j') => view.table_state.select(Some((selected + 1).min(last))),
+                        KeyCode::PageUp => view.table_state.select(Some(selected.saturating_sub(PAGE_ROWS))),
+                        KeyCode::PageDown => view.table_state.select(Some((selected + PAGE_ROWS).min(last))),
+                        KeyCode::Home | KeyCode::Char('g') => view.table_state.select(Some(0)),
+                        KeyCode::End | KeyCode::Char('G') => view.table_state.select(Some(last)),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if self.fairness_view {
+                    if matches!(key.code, KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('F')) {
+                        self.fairness_view = false;
+                    }
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('H') => self.history = Some(HistoryView::default()),
+                    KeyCode::Char('F') => self.fairness_view = true,
+                    KeyCode::Char('C') => self.config_view = Some(ConfigView::default()),
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        // Queued work is drained first; a second `q` quits right away
+                        let idle = state.total_queued() == 0 && state.in_flight.load(std::sync::atomic::Ordering::Relaxed) == 0;
+                        if idle || state.is_shutting_down() {
+                            return self.leave(&mut terminal);
+                        }
+                        tokio::spawn(crate::shutdown::drain(state.clone()));
+                        self.set_status(format!("Shutting down ({}), press q again to quit now", state.config.shutdown_drain.as_str()));
+                    }
+                    KeyCode::Char('?') => self.show_help = !self.show_help,
+                    KeyCode::Char('B') => {
+                        self.show_bandwidth = !self.show_bandwidth;
+                        self.set_status(if self.show_bandwidth { "Showing bytes in/out per user" } else { "Hiding bytes in/out" });
+                    }
+                    KeyCode::Tab | KeyCode::Char('l') => {
+                        self.active_panel = match self.active_panel {
+                            Panel::Backends => Panel::Users,
+                            Panel::Users => Panel::Blocked,
+                            Panel::Blocked => Panel::Backends,
+                        };
+                    }
+                    KeyCode::Char('h') => {
+                        self.active_panel = match self.active_panel {
+                            Panel::Backends => Panel::Blocked,
+                            Panel::Users => Panel::Backends,
+                            Panel::Blocked => Panel::Users,
+                        };
+                    }
+                    KeyCode::Enter | KeyCode::Char(' ') => {
+                        if self.active_panel == Panel::Backends {
+                            if let Some(i) = self.backend_table_state.selected()
+                                && i < snapshot.backends.len()
+                            {
+                                let url = snapshot.backends[i].url.clone();
+                                if self.expanded_backends.contains(&url) {
+                                    self.expanded_backends.remove(&url);
+                                } else {
+                                    self.expanded_backends.insert(url);
                                 }
                             }
+                        } else if self.active_panel == Panel::Users
+                            && let Some(user_id) = self.table_state.selected().and_then(|i| snapshot.user_ids.get(i))
+                        {
+                            self.expanded_queue = if self.expanded_queue.as_ref() == Some(user_id) { None } else { Some(user_id.clone()) };
                         }
-                        KeyCode::Char('p') => {
-                            if self.active_panel == Panel::Users {
-                                if let Some(i) = self.table_state.selected() {
-                                    if i < snapshot.user_ids.len() {
-                                        let user_id = snapshot.user_ids[i].clone();
+                    }
+                    KeyCode::Char('p') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = snapshot.user_ids[i].clone();
                                         
-                                        // 1. Handle VIP
-                                        {
-                                            let mut vip = state.vip_user.lock().unwrap();
-                                            if vip.as_ref() == Some(&user_id) {
-                                                *vip = None;
-                                            } else {
-                                                *vip = Some(user_id.clone());
-                                            }
-                                        }
+                            // 1. Handle VIP
+                            {
+                                let mut vip = state.vip_user.lock().unwrap();
+                                if vip.as_ref() == Some(&user_id) {
+                                    *vip = None;
+                                } else {
+                                    *vip = Some(user_id.clone());
+                                }
+                            }
                                         
-                                        // 2. Clear Boost if we just set VIP
-                                        {
-                                            let mut boost = state.boost_user.lock().unwrap();
-                                            if boost.as_ref() == Some(&user_id) {
-                                                *boost = None;
-                                            }
-                                        }
-                                    }
+                            // 2. Clear Boost if we just set VIP
+                            {
+                                let mut boost = state.boost_user.lock().unwrap();
+                                if boost.as_ref() == Some(&user_id) {
+                                    *boost = None;
                                 }
                             }
                         }
-                        KeyCode::Char('b') => {
-                            if self.active_panel == Panel::Users {
-                                if let Some(i) = self.table_state.selected() {
-                                    if i < snapshot.user_ids.len() {
-                                        let user_id = snapshot.user_ids[i].clone();
+                    }
+                    KeyCode::Char('b') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = snapshot.user_ids[i].clone();
                                         
-                                        // 1. Handle Boost
-                                        {
-                                            let mut boost = state.boost_user.lock().unwrap();
-                                            if boost.as_ref() == Some(&user_id) {
-                                                *boost = None;
-                                            } else {
-                                                *boost = Some(user_id.clone());
-                                            }
-                                        }
+                            // 1. Handle Boost
+                            {
+                                let mut boost = state.boost_user.lock().unwrap();
+                                if boost.as_ref() == Some(&user_id) {
+                                    *boost = None;
+                                } else {
+                                    *boost = Some(user_id.clone());
+                                }
+                            }
                                         
-                                        // 2. Clear VIP if we just set Boost
-                                        {
-                                            let mut vip = state.vip_user.lock().unwrap();
-                                            if vip.as_ref() == Some(&user_id) {
-                                                *vip = None;
-                                            }
-                                        }
-                                    }
+                            // 2. Clear VIP if we just set Boost
+                            {
+                                let mut vip = state.vip_user.lock().unwrap();
+                                if vip.as_ref() == Some(&user_id) {
+                                    *vip = None;
                                 }
                             }
                         }
-                        KeyCode::Char('x') => {
-                            if self.active_panel == Panel::Users {
-                                if let Some(i) = self.table_state.selected() {
-                                    if i < snapshot.user_ids.len() {
-                                        let user_id = snapshot.user_ids[i].clone();
-                                        state.block_user(user_id);
-                                    }
-                                }
+                    }
+                    KeyCode::Char('D') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(user_id) = self.table_state.selected().and_then(|i| snapshot.user_ids.get(i)).cloned()
+                        {
+                            let mut next = state.dispatch_next.lock().unwrap();
+                            if next.as_ref() == Some(&user_id) {
+                                *next = None;
+                                self.set_status(format!("Manual dispatch for {} cancelled", user_id));
+                            } else if snapshot.queues_len.get(&user_id).cloned().unwrap_or(0) == 0 {
+                                self.set_status(format!("Queue of {} is empty", user_id));
+                            } else {
+                                *next = Some(user_id.clone());
+                                drop(next);
+                                state.notify.notify_one();
+                                self.set_status(format!("Next request of {} goes to the next free backend slot", user_id));
                             }
                         }
-                        KeyCode::Char('X') => {
-                            if self.active_panel == Panel::Users {
-                                if let Some(i) = self.table_state.selected() {
-                                    if i < snapshot.user_ids.len() {
-                                        let user_id = &snapshot.user_ids[i];
-                                        if let Some(ip) = snapshot.user_ips.get(user_id) {
-                                            state.block_ip(*ip);
-                                        }
-                                    }
-                                }
+                    }
+                    KeyCode::Char('x') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = snapshot.user_ids[i].clone();
+                            self.confirm = Some(Confirm::BlockUser(user_id));
+                        }
+                    }
+                    KeyCode::Char('X') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = &snapshot.user_ids[i];
+                            if let Some(ip) = snapshot.user_ips.get(user_id) {
+                                self.confirm = Some(Confirm::BlockIp(state.block_target(*ip)));
                             }
                         }
-                        KeyCode::Char('u') => {
-                            if self.active_panel == Panel::Blocked {
-                                let selected = self.blocked_table_state.selected();
-                                if let Some(i) = selected {
-                                    let mut items = Vec::new();
-                                    for ip in snapshot.blocked_ips.iter() {
-                                        items.push(("IP", ip.to_string()));
-                                    }
-                                    for user in snapshot.blocked_users.iter() {
-                                        items.push(("USER", user.clone()));
-                                    }
-                                    items.sort_by(|a, b| a.1.cmp(&b.1));
-
-                                    if i < items.len() {
-                                        let (kind, value) = &items[i];
-                                        if *kind == "IP" {
-                                            if let Ok(ip) = value.parse() {
-                                                state.unblock_ip(ip);
-                                            }
-                                        } else {
-                                            state.unblock_user(value);
+                    }
+                    KeyCode::Char('u') => {
+                        if self.active_panel == Panel::Blocked {
+                            let selected = self.blocked_table_state.selected();
+                            if let Some(i) = selected {
+                                let items = snapshot.blocked_rows();
+                                if i < items.len() {
+                                    let (kind, value, _) = &items[i];
+                                    if *kind != "USER" {
+                                        if let Ok(ip) = value.parse() {
+                                            let info = ip_block_info(state, &ip);
+                                            state.unblock_ip(ip);
+                                            self.set_status(format!("Unblocked IP {} (z: undo)", ip));
+                                            self.push_undo(state, Undoable::Unblocked { users: vec![], ips: vec![(ip, info)] });
                                         }
+                                    } else {
+                                        let info = user_block_info(state, value);
+                                        state.unblock_user(value);
+                                        self.set_status(format!("Unblocked user {} (z: undo)", value));
+                                        self.push_undo(state, Undoable::Unblocked { users: vec![(value.clone(), info)], ips: vec![] });
                                     }
                                 }
-                            } else if self.active_panel == Panel::Users {
-                                if let Some(i) = self.table_state.selected() {
-                                    if i < snapshot.user_ids.len() {
-                                        let user_id = &snapshot.user_ids[i];
-                                        state.unblock_user(user_id);
-                                        if let Some(ip) = snapshot.user_ips.get(user_id) {
-                                            state.unblock_ip(*ip);
-                                        }
-                                    }
+                            }
+                        } else if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = &snapshot.user_ids[i];
+                            let mut users = Vec::new();
+                            let mut ips = Vec::new();
+                            if snapshot.blocked_users.contains(user_id) {
+                                users.push((user_id.clone(), user_block_info(state, user_id)));
+                            }
+                            state.unblock_user(user_id);
+                            if let Some(ip) = snapshot.user_ips.get(user_id) {
+                                for prefix in state.blocks_covering(ip) {
+                                    ips.push((prefix, ip_block_info(state, &prefix)));
+                                    state.unblock_ip(prefix);
                                 }
                             }
+                            if !users.is_empty() || !ips.is_empty() {
+                                self.set_status(format!("Unblocked {} (z: undo)", user_id));
+                                self.push_undo(state, Undoable::Unblocked { users, ips });
+                            }
                         }
-                        KeyCode::Up | KeyCode::Char('k') => {
-                            if self.active_panel == Panel::Backends {
-                                let i = self.backend_table_state.selected().unwrap_or(0).saturating_sub(1);
-                                self.backend_table_state.select(Some(i));
-                            } else if self.active_panel == Panel::Users {
-                                let i = self.table_state.selected().unwrap_or(0).saturating_sub(1);
-                                self.table_state.select(Some(i));
+                    }
+                    KeyCode::Char('d') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = snapshot.user_ids[i].clone();
+                            if snapshot.queues_len.get(&user_id).cloned().unwrap_or(0) > 0 {
+                                self.confirm = Some(Confirm::PurgeQueue(user_id));
                             } else {
-                                let i = self.blocked_table_state.selected().unwrap_or(0).saturating_sub(1);
-                                self.blocked_table_state.select(Some(i));
+                                self.set_status(format!("Queue of {} is empty", user_id));
                             }
                         }
-                        KeyCode::Down | KeyCode::Char('j') => {
-                            if self.active_panel == Panel::Backends {
-                                let len = snapshot.backends.len();
-                                if len > 0 {
-                                    let i = self.backend_table_state.selected().map(|s| (s + 1).min(len.saturating_sub(1))).unwrap_or(0);
-                                    self.backend_table_state.select(Some(i));
-                                }
-                            } else if self.active_panel == Panel::Users {
-                                let len = snapshot.user_ids.len();
-                                if len > 0 {
-                                    let i = self.table_state.selected().map(|s| (s + 1).min(len.saturating_sub(1))).unwrap_or(0);
-                                    self.table_state.select(Some(i));
-                                }
+                    }
+                    KeyCode::Char('n') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = snapshot.user_ids[i].clone();
+                            let current = snapshot.user_notes.get(&user_id).cloned().unwrap_or_default();
+                            self.note_input = Some((user_id, current));
+                        }
+                    }
+                    KeyCode::Char('w') => {
+                        if self.active_panel == Panel::Users
+                            && let Some(i) = self.table_state.selected()
+                            && i < snapshot.user_ids.len()
+                        {
+                            let user_id = snapshot.user_ids[i].clone();
+                            let watch = !snapshot.watched.contains(&user_id);
+                            state.set_watched(&user_id, watch);
+                            self.set_status(if watch {
+                                format!("Watching {}", user_id)
                             } else {
-                                let len = snapshot.blocked_ips.len() + snapshot.blocked_users.len();
-                                if len > 0 {
-                                    let i = self.blocked_table_state.selected().map(|s| (s + 1).min(len.saturating_sub(1))).unwrap_or(0);
-                                    self.blocked_table_state.select(Some(i));
-                                }
+                                format!("Stopped watching {}", user_id)
+                            });
+                        }
+                    }
+                    KeyCode::Char('N') => {
+                        // Cycles through all namespaces, then back to everyone
+                        let next = match self.namespace {
+                            None => snapshot.namespaces.first(),
+                            Some(ref ns) => snapshot.namespaces.iter().find(|n| *n > ns),
+                        };
+                        self.namespace = next.cloned();
+                        self.table_state.select(Some(0));
+                        self.user_offset = 0;
+                        self.queue_offset = 0;
+                        self.set_status(match self.namespace {
+                            Some(ref ns) => format!("Showing namespace {}", ns),
+                            None if snapshot.namespaces.is_empty() => "No namespaces in use".to_string(),
+                            None => "Showing all namespaces".to_string(),
+                        });
+                    }
+                    KeyCode::Char('z') => self.undo_last(state),
+                    KeyCode::Char('s') => match export_dashboard(&snapshot, self.export_format) {
+                        Ok(path) => self.set_status(format!("Dashboard saved to {}", path)),
+                        Err(e) => self.set_status(format!("Saving dashboard failed: {}", e)),
+                    },
+                    KeyCode::PageUp | KeyCode::PageDown | KeyCode::Home | KeyCode::End | KeyCode::Char('g') | KeyCode::Char('G') => {
+                        let (table, len, page) = match self.active_panel {
+                            Panel::Backends => (&mut self.backend_table_state, snapshot.backends.len(), PAGE_ROWS),
+                            Panel::Users => (&mut self.table_state, snapshot.user_ids.len(), self.user_page),
+                            Panel::Blocked => (&mut self.blocked_table_state, snapshot.blocked_ips.len() + snapshot.blocked_users.len(), PAGE_ROWS),
+                        };
+                        let delta = match key.code {
+                            KeyCode::PageUp => -(page as isize),
+                            KeyCode::PageDown => page as isize,
+                            KeyCode::Home | KeyCode::Char('g') => isize::MIN / 2,
+                            _ => isize::MAX / 2,
+                        };
+                        step_selection(table, len, delta);
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        if self.active_panel == Panel::Backends {
+                            let i = self.backend_table_state.selected().unwrap_or(0).saturating_sub(1);
+                            self.backend_table_state.select(Some(i));
+                        } else if self.active_panel == Panel::Users {
+                            let i = self.table_state.selected().unwrap_or(0).saturating_sub(1);
+                            self.table_state.select(Some(i));
+                        } else {
+                            let i = self.blocked_table_state.selected().unwrap_or(0).saturating_sub(1);
+                            self.blocked_table_state.select(Some(i));
+                        }
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        if self.active_panel == Panel::Backends {
+                            let len = snapshot.backends.len();
+                            if len > 0 {
+                                let i = self.backend_table_state.selected().map(|s| (s + 1).min(len.saturating_sub(1))).unwrap_or(0);
+                                self.backend_table_state.select(Some(i));
+                            }
+                        } else if self.active_panel == Panel::Users {
+                            let len = snapshot.user_ids.len();
+                            if len > 0 {
+                                let i = self.table_state.selected().map(|s| (s + 1).min(len.saturating_sub(1))).unwrap_or(0);
+                                self.table_state.select(Some(i));
+                            }
+                        } else {
+                            let len = snapshot.blocked_ips.len() + snapshot.blocked_users.len();
+                            if len > 0 {
+                                let i = self.blocked_table_state.selected().map(|s| (s + 1).min(len.saturating_sub(1))).unwrap_or(0);
+                                self.blocked_table_state.select(Some(i));
                             }
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
             }
         }
@@ -312,8 +1233,9 @@ impl TuiDashboard {
         } else if self.active_panel == Panel::Users {
             if snapshot.user_ids.is_empty() {
                 self.table_state.select(None);
-            } else if self.table_state.selected().is_none() {
-                self.table_state.select(Some(0));
+            } else {
+                let selected = self.table_state.selected().unwrap_or(0).min(snapshot.user_ids.len() - 1);
+                self.table_state.select(Some(selected));
             }
         } else {
             let blocked_total = snapshot.blocked_ips.len() + snapshot.blocked_users.len();
@@ -325,6 +1247,73 @@ impl TuiDashboard {
         }
 
         let area = f.area();
+        if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+            f.render_widget(self.render_too_small(area), area);
+            return;
+        }
+        if area.width < COMPACT_WIDTH || area.height < COMPACT_HEIGHT {
+            self.render_compact(f, snapshot, area);
+        } else {
+            self.render_panels(f, snapshot, area);
+        }
+
+        if let Some(ref pending) = self.confirm {
+            let prompt = pending.prompt();
+            let width = (prompt.len() as u16 + 6).clamp(30, area.width);
+            let popup = centered_rect(width, 5, area);
+            f.render_widget(Clear, popup);
+            f.render_widget(
+                Paragraph::new(vec![
+                    Line::from(Span::styled(prompt, Style::default().fg(Color::White).bold())),
+                    Line::from(vec![
+                        Span::styled("y", Style::default().fg(Color::Green).bold()),
+                        Span::raw("/Enter: confirm   "),
+                        Span::styled("n", Style::default().fg(Color::Red).bold()),
+                        Span::raw("/Esc: cancel"),
+                    ]),
+                ])
+                .alignment(Alignment::Center)
+                .block(self.block().title(" Confirm ").borders(Borders::ALL).border_style(Style::default().fg(Color::Red))),
+                popup,
+            );
+        }
+
+        if let Some((ref task_id, ref model)) = self.replay_input {
+            let popup = centered_rect(60, 5, area);
+            f.render_widget(Clear, popup);
+            f.render_widget(
+                Paragraph::new(vec![
+                    Line::from(vec![
+                        Span::styled(model.clone(), Style::default().fg(Color::Cyan)),
+                        Span::styled(self.glyphs.cursor, Style::default().fg(Color::Yellow)),
+                    ]),
+                    Line::from(Span::styled("Enter: replay (empty keeps the model)   Esc: cancel", Style::default().fg(Color::DarkGray))),
+                ])
+                .block(self.block().title(format!(" Replay {} on model ", task_id)).borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow))),
+                popup,
+            );
+        }
+
+        if let Some((ref user, ref text)) = self.note_input {
+            let popup = centered_rect(60, 5, area);
+            f.render_widget(Clear, popup);
+            f.render_widget(
+                Paragraph::new(vec![
+                    Line::from(vec![
+                        Span::styled(text.clone(), Style::default().fg(Color::White)),
+                        Span::styled(self.glyphs.cursor, Style::default().fg(Color::Yellow)),
+                    ]),
+                    Line::from(Span::styled("Enter: save (empty removes)   Esc: cancel", Style::default().fg(Color::DarkGray))),
+                ])
+                .block(self.block().title(format!(" Note for {} ", user)).borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow))),
+                popup,
+            );
+        }
+    }
+
+    /// The full dashboard: backends, users, and the queue, latency and
+    /// blocked panels.
+    fn render_panels(&mut self, f: &mut Frame, snapshot: &StateSnapshot, area: Rect) {
         let main_chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
@@ -337,6 +1326,22 @@ impl TuiDashboard {
 
         f.render_widget(self.render_stats(snapshot), main_chunks[0]);
 
+        if self.history.is_some() {
+            self.render_history_view(f, snapshot, main_chunks[1]);
+            f.render_widget(self.render_help(), main_chunks[2]);
+            return;
+        }
+        if self.config_view.is_some() {
+            self.render_config_view(f, snapshot, main_chunks[1]);
+            f.render_widget(self.render_help(), main_chunks[2]);
+            return;
+        }
+        if self.fairness_view {
+            self.render_fairness_view(f, snapshot, main_chunks[1]);
+            f.render_widget(self.render_help(), main_chunks[2]);
+            return;
+        }
+
         let content_chunks = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([
@@ -347,15 +1352,33 @@ impl TuiDashboard {
             .split(main_chunks[1]);
 
         f.render_stateful_widget(self.render_backends(snapshot), content_chunks[0], &mut self.backend_table_state);
-        f.render_stateful_widget(self.render_users(snapshot), content_chunks[1], &mut self.table_state);
+        let user_count = snapshot.user_ids.len();
+        let selected = self.table_state.selected().filter(|i| *i < user_count);
+        let user_height = content_chunks[1].height.saturating_sub(TABLE_CHROME) as usize;
+        self.user_page = user_height.max(1);
+        let user_rows = visible_rows(&mut self.user_offset, selected, user_count, user_height);
+        let mut window = TableState::default().with_selected(selected.map(|i| i - user_rows.start));
+        f.render_stateful_widget(self.render_users(snapshot, user_rows), content_chunks[1], &mut window);
 
         let right_chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .constraints([
+                Constraint::Percentage(60),
+                Constraint::Length(7),
+                Constraint::Length(if snapshot.slos.is_empty() { 0 } else { snapshot.slos.len() as u16 + 3 }),
+                Constraint::Min(0),
+            ])
             .split(content_chunks[2]);
 
-        f.render_stateful_widget(self.render_queues(snapshot, right_chunks[0].width), right_chunks[0], &mut self.table_state);
-        f.render_stateful_widget(self.render_blocked(snapshot), right_chunks[1], &mut self.blocked_table_state);
+        let queue_height = right_chunks[0].height.saturating_sub(TABLE_CHROME) as usize;
+        let queue_rows = visible_rows(&mut self.queue_offset, selected, user_count, queue_height);
+        let mut window = TableState::default().with_selected(selected.map(|i| i - queue_rows.start));
+        f.render_stateful_widget(self.render_queues(snapshot, queue_rows, right_chunks[0].width), right_chunks[0], &mut window);
+        f.render_widget(self.render_latency(snapshot), right_chunks[1]);
+        if !snapshot.slos.is_empty() {
+            f.render_widget(self.render_slos(snapshot), right_chunks[2]);
+        }
+        f.render_stateful_widget(self.render_blocked(snapshot), right_chunks[3], &mut self.blocked_table_state);
 
         f.render_widget(self.render_help(), main_chunks[2]);
         if self.show_help {
@@ -363,17 +1386,141 @@ impl TuiDashboard {
         }
     }
 
+    /// Single-column view for small terminals: the headline numbers and one
+    /// row per user.
+    fn render_compact(&mut self, f: &mut Frame, snapshot: &StateSnapshot, area: Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(4), Constraint::Min(0), Constraint::Length(1)])
+            .split(area);
+
+        let total_queued: usize = snapshot.queues_len.values().sum::<usize>() + snapshot.processing_counts.values().sum::<usize>();
+        let online = snapshot.backends.iter().filter(|b| b.is_online).count();
+        let summary = vec![
+            Line::from(vec![
+                Span::styled("Q: ", Style::default().fg(Color::Yellow)),
+                Span::styled(total_queued.to_string(), Style::default().fg(Color::Yellow).bold()),
+                Span::raw(" | "),
+                Span::styled("Done: ", Style::default().fg(Color::Green)),
+                Span::styled(snapshot.processed_counts.values().sum::<usize>().to_string(), Style::default().fg(Color::Green).bold()),
+                Span::raw(" | "),
+                Span::styled("Drop: ", Style::default().fg(Color::Red)),
+                Span::styled(snapshot.dropped_counts.values().sum::<usize>().to_string(), Style::default().fg(Color::Red).bold()),
+                Span::raw(" | "),
+                Span::styled("RPS: ", Style::default().fg(Color::Cyan)),
+                Span::styled(format!("{:.1}", snapshot.requests_per_sec), Style::default().fg(Color::Cyan).bold()),
+            ]),
+            Line::from(vec![
+                Span::styled("Backends: ", Style::default().fg(Color::Green)),
+                Span::styled(
+                    format!("{}/{}", online, snapshot.backends.len()),
+                    Style::default().fg(if online == snapshot.backends.len() { Color::Green } else { Color::Red }).bold(),
+                ),
+                Span::raw(" | "),
+                Span::styled("VIP: ", Style::default().fg(Color::Magenta)),
+                Span::styled(snapshot.vip_user.clone().unwrap_or_else(|| "None".to_string()), Style::default().fg(Color::Magenta).bold()),
+                Span::raw(" | "),
+                Span::styled("Boost: ", Style::default().fg(Color::Yellow)),
+                Span::styled(snapshot.boost_user.clone().unwrap_or_else(|| "None".to_string()), Style::default().fg(Color::Yellow).bold()),
+            ]),
+        ];
+        f.render_widget(
+            Paragraph::new(summary).block(self.block().title(if snapshot.shutdown.is_some() { " ollamaMQ: SHUTTING DOWN " } else { " ollamaMQ " }).borders(Borders::ALL).border_style(Style::default().fg(Color::Cyan))),
+            chunks[0],
+        );
+
+        let footer = match self.status {
+            Some((ref msg, at)) if at.elapsed() < STATUS_TTL => Line::from(Span::styled(format!(" {}", msg), Style::default().fg(Color::Yellow))),
+            _ if self.history.is_some() => Line::from(Span::styled(" j/k: Nav | Enter: Details | r/m: Replay | Esc/H: Close", Style::default().fg(Color::DarkGray))),
+            _ if self.config_view.is_some() => Line::from(Span::styled(" j/k: Nav | Esc/C: Close", Style::default().fg(Color::DarkGray))),
+            _ if self.fairness_view => Line::from(Span::styled(" Esc/F: Close", Style::default().fg(Color::DarkGray))),
+            _ => Line::from(Span::styled(" j/k: Nav | p: VIP | b: Boost | q: Quit", Style::default().fg(Color::DarkGray))),
+        };
+        f.render_widget(Paragraph::new(footer), chunks[2]);
+
+        if self.history.is_some() {
+            self.render_history_view(f, snapshot, chunks[1]);
+            return;
+        }
+        if self.config_view.is_some() {
+            self.render_config_view(f, snapshot, chunks[1]);
+            return;
+        }
+        if self.fairness_view {
+            self.render_fairness_view(f, snapshot, chunks[1]);
+            return;
+        }
+
+        let user_count = snapshot.user_ids.len();
+        let selected = self.table_state.selected().filter(|i| *i < user_count);
+        let user_height = chunks[1].height.saturating_sub(TABLE_CHROME) as usize;
+        self.user_page = user_height.max(1);
+        let user_rows = visible_rows(&mut self.user_offset, selected, user_count, user_height);
+        let title = rows_title("Users", &user_rows, user_count);
+        let rows: Vec<Row> = snapshot.user_ids[user_rows.clone()].iter().map(|user| {
+            let queued = snapshot.queues_len.get(user).unwrap_or(&0) + snapshot.processing_counts.get(user).unwrap_or(&0);
+            let is_blocked = snapshot.blocked_users.contains(user);
+            let (sym, style) = if is_blocked { (self.glyphs.blocked, Style::default().fg(Color::Red)) }
+                              else if snapshot.vip_user.as_ref() == Some(user) { (self.glyphs.vip, Style::default().fg(Color::Magenta)) }
+                              else if snapshot.boost_user.as_ref() == Some(user) { (self.glyphs.boost, Style::default().fg(Color::Yellow)) }
+                              else if *snapshot.processing_counts.get(user).unwrap_or(&0) > 0 { (self.glyphs.processing, Style::default().fg(Color::Cyan)) }
+                              else if queued > 0 { (self.glyphs.queued, Style::default().fg(Color::Green)) }
+                              else { (self.glyphs.idle, Style::default().fg(Color::DarkGray)) };
+            Row::new(vec![
+                Cell::from(Line::from(vec![Span::styled(sym, style), Span::raw(user.clone())])),
+                Cell::from(queued.to_string()),
+                Cell::from(snapshot.processed_counts.get(user).unwrap_or(&0).to_string()),
+                Cell::from(snapshot.recent(user)[1].to_string()),
+                Cell::from(snapshot.dropped_counts.get(user).unwrap_or(&0).to_string()),
+            ])
+        }).collect();
+        let mut window = TableState::default().with_selected(selected.map(|i| i - user_rows.start));
+        f.render_stateful_widget(
+            Table::new(rows, [Constraint::Min(10), Constraint::Length(5), Constraint::Length(6), Constraint::Length(5), Constraint::Length(5)])
+                .header(Row::new(vec!["User ID", "Q", "Done", "1h", "Drop"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+                .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ")
+                .block(self.block().title(title).borders(Borders::ALL).border_style(Style::default().fg(Color::DarkGray))),
+            chunks[1],
+            &mut window,
+        );
+
+    }
+
+    /// Shown instead of tables that would be squeezed beyond reading.
+    fn render_too_small(&self, area: Rect) -> Paragraph<'static> {
+        let mut lines = vec![Line::default(); (area.height / 2).saturating_sub(1) as usize];
+        lines.push(Line::from(Span::styled("Terminal too small", Style::default().fg(Color::Yellow).bold())));
+        lines.push(Line::from(format!("{}x{}, needs at least {}x{}", area.width, area.height, MIN_WIDTH, MIN_HEIGHT)));
+        lines.push(Line::from(Span::styled("q: Quit", Style::default().fg(Color::DarkGray))));
+        Paragraph::new(lines)
+            .alignment(Alignment::Center)
+            .wrap(ratatui::widgets::Wrap { trim: true })
+    }
+
     fn render_stats(&self, snapshot: &StateSnapshot) -> Paragraph<'static> {
         let total_queued: usize = snapshot.queues_len.values().sum();
         let total_processing: usize = snapshot.processing_counts.values().sum();
         let total_processed: usize = snapshot.processed_counts.values().sum();
         let total_dropped: usize = snapshot.dropped_counts.values().sum();
+        let (queued_bytes, max_queued_bytes) = snapshot.queued_bytes;
+        let (mem, mem_color) = if max_queued_bytes > 0 {
+            // Yellow from half the cap, red from three quarters
+            let color = if queued_bytes * 4 >= max_queued_bytes * 3 { Color::Red } else if queued_bytes * 2 >= max_queued_bytes { Color::Yellow } else { Color::Magenta };
+            (format!("{}/{}", format_bytes(queued_bytes), format_bytes(max_queued_bytes)), color)
+        } else {
+            (format_bytes(queued_bytes), Color::Magenta)
+        };
+        let mem = if snapshot.spooled_bytes > 0 { format!("{} +{} spooled", mem, format_bytes(snapshot.spooled_bytes)) } else { mem };
 
-        let stats_line = vec![
+        let mut stats_line = vec![
             Span::styled(" ollamaMQ ", Style::default().fg(Color::Cyan).bold()),
             Span::raw(" | "),
             Span::styled("Panel: ", Style::default().fg(Color::White)),
-            Span::styled(if self.active_panel == Panel::Users { "USERS" } else { "BLOCKED" }, Style::default().fg(Color::Yellow).bold()),
+            Span::styled(
+                if self.history.is_some() { "HISTORY" } else if self.config_view.is_some() { "CONFIG" } else if self.fairness_view { "FAIRNESS" } else if self.active_panel == Panel::Users { "USERS" } else { "BLOCKED" },
+                Style::default().fg(Color::Yellow).bold(),
+            ),
             Span::raw(" | "),
             Span::styled("VIP: ", Style::default().fg(Color::Magenta)),
             Span::styled(snapshot.vip_user.clone().unwrap_or_else(|| "None".to_string()), Style::default().fg(Color::Magenta).bold()),
@@ -386,23 +1533,94 @@ impl TuiDashboard {
             Span::raw(" | "),
             Span::styled("Done: ", Style::default().fg(Color::Green)),
             Span::styled(total_processed.to_string(), Style::default().fg(Color::Green).bold()),
+            Span::styled(format!(" ({} in 1h)", snapshot.recent.values().map(|n| n[1]).sum::<u64>()), Style::default().fg(Color::Green)),
             Span::raw(" | "),
             Span::styled("Drop: ", Style::default().fg(Color::Red)),
             Span::styled(total_dropped.to_string(), Style::default().fg(Color::Red).bold()),
+            Span::raw(" | "),
+            Span::styled("RPS: ", Style::default().fg(Color::Cyan)),
+            Span::styled(format!("{:.1}", snapshot.requests_per_sec), Style::default().fg(Color::Cyan).bold()),
+            Span::raw(" | "),
+            Span::styled("In-flight: ", Style::default().fg(Color::Cyan)),
+            Span::styled(snapshot.in_flight.to_string(), Style::default().fg(Color::Cyan).bold()),
+            Span::raw(" | "),
+            Span::styled("Mem: ", Style::default().fg(mem_color)),
+            Span::styled(mem, Style::default().fg(mem_color).bold()),
+            Span::raw(" | "),
+            Span::styled("Backends: ", Style::default().fg(Color::Green)),
+            Span::styled(
+                format!("{}/{}", snapshot.backends.iter().filter(|b| b.is_online).count(), snapshot.backends.len()),
+                Style::default().fg(if snapshot.backends.iter().all(|b| b.is_online) { Color::Green } else { Color::Red }).bold(),
+            ),
+            Span::raw(" | "),
+            Span::styled("Up: ", Style::default().fg(Color::DarkGray)),
+            Span::styled(format_uptime(snapshot.uptime), Style::default().fg(Color::DarkGray)),
         ];
 
-        Paragraph::new(Line::from(stats_line)).block(Block::default().borders(Borders::ALL))
+        if let Some(ref ns) = snapshot.namespace {
+            stats_line.push(Span::raw(" | "));
+            stats_line.push(Span::styled("NS: ", Style::default().fg(Color::LightBlue)));
+            stats_line.push(Span::styled(ns.clone(), Style::default().fg(Color::LightBlue).bold()));
+        }
+
+        if snapshot.scale.signal != ScaleSignal::Steady {
+            let color = if snapshot.scale.signal == ScaleSignal::Up { Color::Red } else { Color::Blue };
+            stats_line.push(Span::raw(" | "));
+            stats_line.push(Span::styled("Scale: ", Style::default().fg(color)));
+            stats_line.push(Span::styled(snapshot.scale.signal.as_str(), Style::default().fg(color).bold()));
+        }
+
+        if let Some(ref shutdown) = snapshot.shutdown {
+            let left = shutdown.deadline.saturating_duration_since(Instant::now());
+            // Up front, so it isn't cut off on narrow terminals
+            stats_line.splice(1..1, [
+                Span::raw(" | "),
+                Span::styled("SHUTDOWN: ", Style::default().fg(Color::Red).bold()),
+                Span::styled(
+                    format!("drain {}, {} set aside, {}s left", shutdown.policy.as_str(), shutdown.set_aside, left.as_secs()),
+                    Style::default().fg(Color::Red),
+                ),
+            ]);
+        }
+
+        if snapshot.forecast.days > 0 {
+            let busiest = snapshot.forecast.models.first()
+                .filter(|m| m.next_hour >= 0.5)
+                .map(|m| format!(" ({} ~{:.0})", m.model, m.next_hour))
+                .unwrap_or_default();
+            stats_line.push(Span::raw(" | "));
+            stats_line.push(Span::styled("Next 1h: ", Style::default().fg(Color::LightBlue)));
+            stats_line.push(Span::styled(format!("~{:.0}", snapshot.forecast.next_hour), Style::default().fg(Color::LightBlue).bold()));
+            stats_line.push(Span::styled(busiest, Style::default().fg(Color::LightBlue)));
+        }
+
+        let now = chrono::Local::now();
+        if !snapshot.maintenance.active.is_empty() {
+            let desc: Vec<String> = snapshot.maintenance.active.iter()
+                .map(|(action, until)| format!("{} ({} left)", action, format_countdown(*until - now)))
+                .collect();
+            stats_line.push(Span::raw(" | "));
+            stats_line.push(Span::styled("MAINT: ", Style::default().fg(Color::Red).bold()));
+            stats_line.push(Span::styled(desc.join(", "), Style::default().fg(Color::Red)));
+        } else if let Some((ref action, at)) = snapshot.maintenance.next {
+            stats_line.push(Span::raw(" | "));
+            stats_line.push(Span::styled("Maint: ", Style::default().fg(Color::DarkGray)));
+            stats_line.push(Span::styled(format!("{} in {}", action, format_countdown(at - now)), Style::default().fg(Color::DarkGray)));
+        }
+
+        Paragraph::new(Line::from(stats_line)).block(self.block().borders(Borders::ALL))
     }
 
     fn render_backends(&self, snapshot: &StateSnapshot) -> Table<'static> {
+        let has_canary = snapshot.backends.iter().any(|b| b.is_canary);
         let rows: Vec<Row> = snapshot.backends.iter().map(|b| {
             let url = b.url.replace("http://", "").replace("https://", "");
             let is_expanded = self.expanded_backends.contains(&b.url);
             
             let (status_sym, status_style) = if b.is_online {
-                ("● ", Style::default().fg(Color::Green))
+                (self.glyphs.online, Style::default().fg(Color::Green))
             } else {
-                ("○ ", Style::default().fg(Color::Red))
+                (self.glyphs.offline, Style::default().fg(Color::Red))
             };
 
             let type_str = b.api_type.display();
@@ -419,17 +1637,31 @@ impl TuiDashboard {
                 Style::default().fg(Color::Gray)
             };
 
-            let mut name_lines = vec![
-                Line::from(vec![
-                    Span::styled(if is_expanded { "▼ " } else { "▶ " }, Style::default().fg(Color::DarkGray)),
-                    Span::styled(status_sym, status_style),
-                    Span::styled(url, if b.is_online { Style::default().fg(Color::White) } else { Style::default().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT) }),
-                ])
+            let mut title_spans = vec![
+                Span::styled(if is_expanded { self.glyphs.expanded } else { self.glyphs.collapsed }, Style::default().fg(Color::DarkGray)),
+                Span::styled(status_sym, status_style),
+                Span::styled(url, if b.is_online { Style::default().fg(Color::White) } else { Style::default().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT) }),
             ];
+            if b.is_canary {
+                title_spans.push(Span::styled(" [CANARY]", Style::default().fg(Color::Yellow).bold()));
+            }
+            if b.dynamic {
+                title_spans.push(Span::styled(" [DYN]", Style::default().fg(Color::Blue)));
+            }
+            let mut name_lines = vec![Line::from(title_spans)];
+
+            // With a canary configured, show latency on every backend so the two can be compared
+            if has_canary {
+                let avg = b.avg_latency_ms().map(|ms| format!("{}ms", ms)).unwrap_or_else(|| "-".to_string());
+                name_lines.push(Line::from(vec![
+                    Span::styled(format!("  {} Avg:    ", self.glyphs.latency), Style::default().fg(Color::DarkGray)),
+                    Span::styled(avg, Style::default().fg(if b.is_canary { Color::Yellow } else { Color::Gray })),
+                ]));
+            }
 
             // Display Active or Last used model on a new line
             if let Some(model) = b.current_model.clone() {
-                let prefix = if b.active_requests > 0 { "  ▶ Active: " } else { "  ↺ Last:   " };
+                let prefix = if b.active_requests > 0 { format!("  {} Active: ", self.glyphs.active) } else { format!("  {} Last:   ", self.glyphs.last) };
                 let color = if b.active_requests > 0 { Color::Cyan } else { Color::DarkGray };
                 name_lines.push(Line::from(vec![
                     Span::styled(prefix, Style::default().fg(color)),
@@ -437,14 +1669,42 @@ impl TuiDashboard {
                 ]));
             }
 
+            let budget = snapshot.budgets.get(&b.url).copied().unwrap_or_default();
+            let budget_style = if budget.success_rate.is_none() {
+                Style::default().fg(Color::DarkGray)
+            } else if budget.is_exhausted() {
+                Style::default().fg(Color::Red).bold()
+            } else if budget.is_burning() {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default().fg(Color::Green)
+            };
+
             if is_expanded {
+                if let (Some(success), Some(burn), Some(short_burn)) = (budget.success_rate, budget.burn_rate, budget.short_burn_rate) {
+                    name_lines.push(Line::from(vec![
+                        Span::styled("  Budget: ", Style::default().fg(Color::DarkGray)),
+                        Span::styled(
+                            format!("{:.2}% ok of {}, burn {:.1}x (5m {:.1}x)", success * 100.0, budget.requests, burn, short_burn),
+                            budget_style,
+                        ),
+                    ]));
+                }
+                if let Some(ref features) = b.features {
+                    let missing = features.missing();
+                    let mut spans = vec![Span::styled(format!("  Ollama {}", features.version), Style::default().fg(Color::DarkGray))];
+                    if !missing.is_empty() {
+                        spans.push(Span::styled(format!(" (no {})", missing.join(", ")), Style::default().fg(Color::Yellow)));
+                    }
+                    name_lines.push(Line::from(spans));
+                }
                 let mut models: Vec<String> = b.available_models.iter().cloned().collect();
                 models.sort();
                 
                 if models.is_empty() {
                     name_lines.push(Line::from(vec![
                         Span::raw("  "),
-                        Span::styled("└ (No models discovered yet)", Style::default().fg(Color::DarkGray).italic()),
+                        Span::styled(format!("{} (No models discovered yet)", self.glyphs.tree), Style::default().fg(Color::DarkGray).italic()),
                     ]));
                 } else {
                     let total_models = models.len();
@@ -458,7 +1718,7 @@ impl TuiDashboard {
 
                         name_lines.push(Line::from(vec![
                             Span::raw("  "),
-                            Span::styled("└ ", Style::default().fg(Color::DarkGray)),
+                            Span::styled(format!("{} ", self.glyphs.tree), Style::default().fg(Color::DarkGray)),
                             Span::styled(m, m_style),
                             if is_loaded { Span::styled(" (In RAM)", Style::default().fg(Color::Green).italic()) } else { Span::raw("") },
                         ]));
@@ -479,6 +1739,8 @@ impl TuiDashboard {
                 Cell::from(type_str).style(type_style),
                 Cell::from(b.active_requests.to_string()).style(req_style),
                 Cell::from(b.processed_count.to_string()).style(Style::default().fg(Color::DarkGray)),
+                Cell::from(b.failed_count.to_string()).style(if b.failed_count > 0 { Style::default().fg(Color::Red) } else { Style::default().fg(Color::DarkGray) }),
+                Cell::from(format_budget(&budget)).style(budget_style),
             ]).height(height)
         }).collect();
 
@@ -487,86 +1749,696 @@ impl TuiDashboard {
             Constraint::Length(5),
             Constraint::Length(4),
             Constraint::Length(6),
+            Constraint::Length(4),
+            Constraint::Length(6),
         ])
-        .header(Row::new(vec!["Backend", "API", "Act", "Done"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+        .header(Row::new(vec!["Backend", "API", "Act", "Done", "Err", "Budget"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
         .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
         .highlight_symbol(">> ")
-        .block(Block::default().title(" Backend Instances ").borders(Borders::ALL).border_style(if self.active_panel == Panel::Backends { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::DarkGray) }))
+        .block(self.block().title(" Backend Instances ").borders(Borders::ALL).border_style(if self.active_panel == Panel::Backends { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::DarkGray) }))
     }
 
-    fn render_users(&self, snapshot: &StateSnapshot) -> Table<'static> {
-        let rows: Vec<Row> = snapshot.user_ids.iter().map(|user| {
+    fn render_users(&self, snapshot: &StateSnapshot, visible: Range<usize>) -> Table<'static> {
+        let title = rows_title("Active Users", &visible, snapshot.user_ids.len());
+        let rows: Vec<Row> = snapshot.user_ids[visible].iter().map(|user| {
             let queue_len = snapshot.queues_len.get(user).unwrap_or(&0) + snapshot.processing_counts.get(user).unwrap_or(&0);
             let processed = snapshot.processed_counts.get(user).unwrap_or(&0);
+            let recent = snapshot.recent(user);
             let dropped = snapshot.dropped_counts.get(user).unwrap_or(&0);
-            let ip_str = snapshot.user_ips.get(user).map(|i| i.to_string()).unwrap_or_default();
-            let is_blocked = snapshot.blocked_users.contains(user) || snapshot.user_ips.get(user).map_or(false, |ip| snapshot.blocked_ips.contains(ip));
+            let ip_str = snapshot.source(user);
+            let is_blocked = snapshot.blocked_users.contains(user) || snapshot.user_ips.get(user).is_some_and(|ip| snapshot.blocked_ips.iter().any(|p| p.contains(ip)));
             let is_vip = snapshot.vip_user.as_ref() == Some(user);
             let is_boost = snapshot.boost_user.as_ref() == Some(user);
+            let is_watched = snapshot.watched.contains(user);
 
-            let (sym, style) = if is_blocked { ("✖ ", Style::default().fg(Color::Red)) }
-                              else if is_vip { ("★ ", Style::default().fg(Color::Magenta)) }
-                              else if is_boost { ("⚡", Style::default().fg(Color::Yellow)) }
-                              else if *snapshot.processing_counts.get(user).unwrap_or(&0) > 0 { ("▶ ", Style::default().fg(Color::Cyan)) }
-                              else if *snapshot.queues_len.get(user).unwrap_or(&0) > 0 { ("● ", Style::default().fg(Color::Green)) }
-                              else { ("○ ", Style::default().fg(Color::DarkGray)) };
+            let (sym, style) = if is_blocked { (self.glyphs.blocked, Style::default().fg(Color::Red)) }
+                              else if is_vip { (self.glyphs.vip, Style::default().fg(Color::Magenta)) }
+                              else if is_boost { (self.glyphs.boost, Style::default().fg(Color::Yellow)) }
+                              else if *snapshot.processing_counts.get(user).unwrap_or(&0) > 0 { (self.glyphs.processing, Style::default().fg(Color::Cyan)) }
+                              else if *snapshot.queues_len.get(user).unwrap_or(&0) > 0 { (self.glyphs.queued, Style::default().fg(Color::Green)) }
+                              else { (self.glyphs.idle, Style::default().fg(Color::DarkGray)) };
 
-            let mut spans = vec![Span::styled(sym, style), Span::styled(user.clone(), if is_blocked { Style::default().fg(Color::Red).add_modifier(Modifier::CROSSED_OUT) } else if is_vip { Style::default().fg(Color::Magenta).bold() } else if is_boost { Style::default().fg(Color::Yellow).bold() } else { Style::default().fg(Color::White) })];
+            let mut spans = vec![Span::styled(sym, style), Span::styled(user.clone(), if is_blocked { Style::default().fg(Color::Red).add_modifier(Modifier::CROSSED_OUT) } else if is_vip { Style::default().fg(Color::Magenta).bold() } else if is_boost { Style::default().fg(Color::Yellow).bold() } else if is_watched { Style::default().fg(Color::LightCyan).bold() } else { Style::default().fg(Color::White) })];
+            if is_watched { spans.push(Span::styled(" [WATCH]", Style::default().fg(Color::LightCyan).bold())); }
             if is_vip { spans.push(Span::styled(" [VIP]", Style::default().fg(Color::Magenta).bold())); }
             if is_boost { spans.push(Span::styled(" [BST]", Style::default().fg(Color::Yellow).bold())); }
+            if snapshot.dispatch_next.as_ref() == Some(user) { spans.push(Span::styled(" [NEXT]", Style::default().fg(Color::LightMagenta).bold())); }
             if is_blocked { spans.push(Span::styled(" [BLOCKED]", Style::default().fg(Color::Red).bold())); }
+            if snapshot.cooling_down.contains(user) { spans.push(Span::styled(" [COOL]", Style::default().fg(Color::LightBlue).bold())); }
+            if let Some(n) = snapshot.connection_ips.get(user).filter(|n| **n > 1) { spans.push(Span::styled(format!(" [{} IPs]", n), Style::default().fg(Color::LightRed).bold())); }
 
-            Row::new(vec![Cell::from(Line::from(spans)), Cell::from(ip_str).style(Style::default().fg(Color::Cyan)), Cell::from(queue_len.to_string()), Cell::from(processed.to_string()), Cell::from(dropped.to_string())])
+            let note = snapshot.user_notes.get(user).cloned().unwrap_or_default();
+
+            let mut cells = vec![Cell::from(Line::from(spans)), Cell::from(note).style(Style::default().fg(Color::Gray).italic()), Cell::from(ip_str).style(Style::default().fg(Color::Cyan)), Cell::from(queue_len.to_string()), Cell::from(processed.to_string()), Cell::from(recent[0].to_string()), Cell::from(recent[1].to_string()), Cell::from(dropped.to_string())];
+            if self.show_bandwidth {
+                let bandwidth = snapshot.bandwidth.get(user).copied().unwrap_or_default();
+                cells.push(Cell::from(format_bytes(bandwidth.bytes_in)).style(Style::default().fg(Color::LightBlue)));
+                cells.push(Cell::from(format_bytes(bandwidth.bytes_out)).style(Style::default().fg(Color::LightBlue)));
+            }
+            cells.extend(snapshot.custom_columns(user).into_iter().map(|v| Cell::from(v).style(Style::default().fg(Color::LightGreen))));
+            Row::new(cells)
         }).collect();
 
-        Table::new(rows, [Constraint::Percentage(45), Constraint::Percentage(25), Constraint::Percentage(10), Constraint::Percentage(10), Constraint::Percentage(10)])
-            .header(Row::new(vec!["User ID", "Last IP", "Q", "Done", "Drop"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+        let (percentages, header): (&[u16], &[&str]) = if self.show_bandwidth {
+            (&[22, 12, 14, 7, 7, 7, 7, 7, 9, 8], &["User ID", "Note", "Source", "Q", "Done", "5m", "1h", "Drop", "In", "Out"])
+        } else {
+            (&[26, 16, 18, 8, 8, 8, 8, 8], &["User ID", "Note", "Source", "Q", "Done", "5m", "1h", "Drop"])
+        };
+        // Custom columns get 8% each, taken from the built-in ones in proportion
+        let custom = (snapshot.columns.len() as u16 * 8).min(60);
+        let mut widths: Vec<Constraint> = percentages.iter().map(|p| Constraint::Percentage(p * (100 - custom) / 100)).collect();
+        let mut header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+        for column in &snapshot.columns {
+            widths.push(Constraint::Percentage(custom / snapshot.columns.len() as u16));
+            header.push(column.name.clone());
+        }
+        Table::new(rows, widths)
+            .header(Row::new(header).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
             .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
             .highlight_symbol(">> ")
-            .block(Block::default().title(" Active Users ").borders(Borders::ALL).border_style(if self.active_panel == Panel::Users { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::DarkGray) }))
+            .block(self.block().title(title).borders(Borders::ALL).border_style(if self.active_panel == Panel::Users { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::DarkGray) }))
     }
 
-    fn render_queues(&self, snapshot: &StateSnapshot, available_width: u16) -> Table<'static> {
+    fn render_queues(&self, snapshot: &StateSnapshot, visible: Range<usize>, available_width: u16) -> Table<'static> {
+        let title = rows_title("Queue Status", &visible, snapshot.user_ids.len());
         let total_queued = snapshot.queues_len.values().sum::<usize>() + snapshot.processing_counts.values().sum::<usize>();
         let bar_max_width = ((available_width as f32) * 0.45) as usize;
 
-        let rows: Vec<Row> = snapshot.user_ids.iter().map(|user| {
+        let rows: Vec<Row> = snapshot.user_ids[visible].iter().map(|user| {
             let q_len = snapshot.queues_len.get(user).unwrap_or(&0) + snapshot.processing_counts.get(user).unwrap_or(&0);
             let bar_len = if q_len > 0 { ((q_len as f32 / 20.0).min(1.0) * bar_max_width as f32) as usize } else { 0 };
             let color = if snapshot.vip_user.as_ref() == Some(user) { Color::Magenta } else if snapshot.boost_user.as_ref() == Some(user) { Color::Yellow } else if *snapshot.processing_counts.get(user).unwrap_or(&0) > 0 { Color::Cyan } else { Color::Green };
-            let bar = format!("{:<width$}", "⠿".repeat(bar_len), width = bar_max_width);
+            let bar = format!("{:<width$}", self.glyphs.bar.repeat(bar_len), width = bar_max_width);
             let pct = if total_queued > 0 { (q_len as f64 / total_queued as f64) * 100.0 } else { 0.0 };
-            Row::new(vec![Cell::from(user.clone()), Cell::from(bar).style(Style::default().fg(color)), Cell::from(format!("{} ({:.0}%)", q_len, pct)).style(Style::default().fg(color).bold())])
+            let num = format!("{} ({:.0}%)", q_len, pct);
+            if self.expanded_queue.as_ref() != Some(user) {
+                return Row::new(vec![Cell::from(user.clone()), Cell::from(bar).style(Style::default().fg(color)), Cell::from(num).style(Style::default().fg(color).bold())]);
+            }
+
+            // One bar per queued task, colored by how long it has waited
+            let per_line = bar_max_width.max(1);
+            let mut bar_lines = vec![Line::styled(bar, Style::default().fg(color))];
+            let mut num_lines = vec![Line::styled(num, Style::default().fg(color).bold())];
+            let shown = snapshot.task_ages.len().min(per_line * TASK_BAR_LINES);
+            for chunk in snapshot.task_ages[..shown].chunks(per_line) {
+                bar_lines.push(Line::from(chunk.iter().map(|age| {
+                    let color = if *age < TASK_AGE_FRESH { Color::Green } else if *age < TASK_AGE_STALE { Color::Yellow } else { Color::Red };
+                    Span::styled(self.glyphs.task, Style::default().fg(color))
+                }).collect::<Vec<_>>()));
+            }
+            if let (Some(oldest), Some(newest)) = (snapshot.task_ages.first(), snapshot.task_ages.last()) {
+                num_lines.push(Line::styled(format!("old {}", format_duration(*oldest)), Style::default().fg(Color::Gray)));
+                num_lines.push(Line::styled(format!("new {}", format_duration(*newest)), Style::default().fg(Color::DarkGray)));
+            } else {
+                bar_lines.push(Line::styled("(nothing queued)", Style::default().fg(Color::DarkGray).italic()));
+            }
+            if let Some(ref id) = snapshot.next_task_id {
+                bar_lines.push(Line::styled(format!("next: {}", id), Style::default().fg(Color::DarkGray)));
+            }
+            let errors = &snapshot.expanded_errors;
+            bar_lines.push(Line::styled(
+                format!("errors: {}", errors.summary()),
+                Style::default().fg(if errors.total() > 0 { Color::LightRed } else { Color::DarkGray }),
+            ));
+            if shown < snapshot.task_ages.len() {
+                num_lines.push(Line::styled(format!("+{} more", snapshot.task_ages.len() - shown), Style::default().fg(Color::DarkGray)));
+            }
+            let height = bar_lines.len().max(num_lines.len());
+            let name = Line::from(vec![Span::styled(self.glyphs.expanded, Style::default().fg(Color::DarkGray)), Span::raw(user.clone())]);
+            Row::new(vec![Cell::from(name), Cell::from(Text::from(bar_lines)), Cell::from(Text::from(num_lines))]).height(height as u16)
         }).collect();
 
         Table::new(rows, [Constraint::Percentage(30), Constraint::Percentage(45), Constraint::Percentage(25)])
             .header(Row::new(vec!["User ID", "Progress", "Num"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
             .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
             .highlight_symbol(">> ")
-            .block(Block::default().title(" Queue Status ").borders(Borders::ALL))
+            .block(self.block().title(title).borders(Borders::ALL))
     }
 
-    fn render_blocked(&self, snapshot: &StateSnapshot) -> Table<'static> {
-        let mut items = Vec::new();
-        for ip in snapshot.blocked_ips.iter() { items.push(("IP", ip.to_string())); }
-        for user in snapshot.blocked_users.iter() { items.push(("USER", user.clone())); }
-        items.sort_by(|a, b| a.1.cmp(&b.1));
+    fn render_latency(&self, snapshot: &StateSnapshot) -> Table<'static> {
+        let rows: Vec<Row> = snapshot.stage_percentiles.iter().map(|(stage, values)| {
+            let mut cells = vec![Cell::from(stage_label(*stage))];
+            cells.extend(values.iter().map(|v| Cell::from(format_duration(*v)).style(Style::default().fg(Color::Cyan))));
+            Row::new(cells)
+        }).collect();
 
-        let rows: Vec<Row> = items.iter().map(|(kind, val)| Row::new(vec![Cell::from(kind.to_string()).style(if *kind == "IP" { Style::default().fg(Color::Cyan) } else { Style::default().fg(Color::Magenta) }), Cell::from(val.clone())])).collect();
+        Table::new(rows, [Constraint::Percentage(40), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20)])
+            .header(Row::new(vec!["Stage", "p50", "p90", "p99"]).style(Style::default().fg(Color::Yellow).bold()))
+            .block(self.block().title(format!(" Latency (last {}) ", snapshot.stage_samples)).borders(Borders::ALL).border_style(Style::default().fg(Color::DarkGray)))
+    }
 
-        Table::new(rows, [Constraint::Percentage(30), Constraint::Percentage(70)])
-            .header(Row::new(vec!["Type", "Value"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+    fn render_slos(&self, snapshot: &StateSnapshot) -> Table<'static> {
+        let rows: Vec<Row> = snapshot.slos.iter().map(|s| {
+            let (verdict, color) = if s.violated {
+                ("MISS", Color::Red)
+            } else if s.samples < MIN_SAMPLES {
+                ("...", Color::DarkGray)
+            } else {
+                ("OK", Color::Green)
+            };
+            Row::new(vec![
+                Cell::from(s.tier.clone()),
+                Cell::from(format!("p{} {}", s.percentile, s.stage.name())),
+                Cell::from(s.observed_ms.map_or("-".to_string(), |ms| format_duration(Duration::from_millis(ms)))).style(Style::default().fg(color)),
+                Cell::from(format_duration(Duration::from_millis(s.target_ms))),
+                Cell::from(verdict).style(Style::default().fg(color).bold()),
+            ])
+        }).collect();
+
+        Table::new(rows, [Constraint::Percentage(25), Constraint::Percentage(30), Constraint::Percentage(15), Constraint::Percentage(15), Constraint::Percentage(15)])
+            .header(Row::new(vec!["Tier", "Objective", "Now", "Target", ""]).style(Style::default().fg(Color::Yellow).bold()))
+            .block(self.block().title(" SLOs ").borders(Borders::ALL).border_style(Style::default().fg(Color::DarkGray)))
+    }
+
+    fn render_blocked(&self, snapshot: &StateSnapshot) -> Table<'static> {
+        let now = chrono::Local::now();
+        let rows: Vec<Row> = snapshot.blocked_rows().into_iter().map(|(kind, val, info)| Row::new(vec![
+            Cell::from(kind).style(if kind != "USER" { Style::default().fg(Color::Cyan) } else { Style::default().fg(Color::Magenta) }),
+            Cell::from(val),
+            Cell::from(info.map_or("-".to_string(), |i| i.by.clone())).style(Style::default().fg(Color::DarkGray)),
+            Cell::from(info.and_then(|i| i.reason.clone()).unwrap_or_else(|| "-".to_string())),
+            Cell::from(block_expiry(info, now)).style(Style::default().fg(Color::Yellow)),
+        ])).collect();
+
+        Table::new(rows, [Constraint::Percentage(12), Constraint::Percentage(30), Constraint::Percentage(15), Constraint::Percentage(28), Constraint::Percentage(15)])
+            .header(Row::new(vec!["Type", "Value", "By", "Reason", "Expires"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
             .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
             .highlight_symbol(">> ")
-            .block(Block::default().title(" Blocked Items ").borders(Borders::ALL).border_style(if self.active_panel == Panel::Blocked { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::DarkGray) }))
+            .block(self.block().title(" Blocked Items ").borders(Borders::ALL).border_style(if self.active_panel == Panel::Blocked { Style::default().fg(Color::Yellow) } else { Style::default().fg(Color::DarkGray) }))
+    }
+
+    fn render_history_view(&mut self, f: &mut Frame, snapshot: &StateSnapshot, area: Rect) {
+        let Some(ref mut view) = self.history else {
+            return;
+        };
+        if snapshot.history.is_empty() {
+            view.table_state.select(None);
+            view.detail = false;
+        } else {
+            let selected = view.table_state.selected().unwrap_or(0).min(snapshot.history.len() - 1);
+            view.table_state.select(Some(selected));
+        }
+
+        let rows: Vec<Row> = snapshot.history.iter().map(|e| {
+            let outcome_style = Style::default().fg(outcome_color(e.outcome));
+            let tokens = match (e.prompt_tokens, e.completion_tokens) {
+                (None, None) => "-".to_string(),
+                (p, c) => format!("{}/{}", p.map_or("-".to_string(), |n| n.to_string()), c.map_or("-".to_string(), |n| n.to_string())),
+            };
+            Row::new(vec![
+                Cell::from(e.finished_at.format("%H:%M:%S").to_string()).style(Style::default().fg(Color::DarkGray)),
+                Cell::from(e.task_id.clone()).style(Style::default().fg(Color::DarkGray)),
+                Cell::from(e.user_id.clone()),
+                Cell::from(e.model.clone().unwrap_or_else(|| "-".to_string())).style(Style::default().fg(Color::Cyan)),
+                Cell::from(e.outcome.as_str()).style(outcome_style),
+                Cell::from(e.status.map_or("-".to_string(), |s| s.to_string())).style(outcome_style),
+                Cell::from(format_duration(e.queue_wait)),
+                Cell::from(if e.backend.is_some() { format_duration(e.duration) } else { "-".to_string() }),
+                Cell::from(tokens),
+                Cell::from(snapshot.replay_states.get(&e.task_id).copied().unwrap_or("")).style(Style::default().fg(Color::Magenta)),
+            ])
+        }).collect();
+
+        let table = Table::new(rows, [
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Length(16),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(6),
+        ])
+        .header(Row::new(vec!["Time", "Task", "User", "Model", "Outcome", "Status", "Wait", "Duration", "Tokens p/c", "Replay"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+        .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> ")
+        .block(self.block().title(format!(" Request History (last {}) ", snapshot.history.len())).borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow)));
+        let Some(ref mut view) = self.history else {
+            return;
+        };
+        f.render_stateful_widget(table, area, &mut view.table_state);
+
+        let entry = view.table_state.selected().and_then(|i| snapshot.history.get(i));
+        if let (true, Some(e)) = (view.detail, entry) {
+            let popup = centered_rect(70, 16, area);
+            f.render_widget(Clear, popup);
+            f.render_widget(self.render_history_detail(e), popup);
+        }
+    }
+
+    fn render_config_view(&mut self, f: &mut Frame, snapshot: &StateSnapshot, area: Rect) {
+        let rows: Vec<Row> = snapshot.config.iter().enumerate().map(|(i, e)| {
+            let first_of_section = i == 0 || snapshot.config[i - 1].section != e.section;
+            let value_style = if e.changed {
+                Style::default().fg(Color::Magenta).bold()
+            } else if !e.is_default() {
+                Style::default().fg(Color::Yellow).bold()
+            } else {
+                Style::default().fg(Color::White)
+            };
+            Row::new(vec![
+                Cell::from(if first_of_section { e.section } else { "" }).style(Style::default().fg(Color::Cyan).bold()),
+                Cell::from(if e.changed { "*" } else { "" }).style(Style::default().fg(Color::Magenta).bold()),
+                Cell::from(e.key.clone()),
+                Cell::from(e.value.clone()).style(value_style),
+                Cell::from(if e.is_default() { String::new() } else { e.default.clone().unwrap_or_default() }).style(Style::default().fg(Color::DarkGray)),
+            ])
+        }).collect();
+
+        let changed = snapshot.config.iter().filter(|e| e.changed).count();
+        let non_default = snapshot.config.iter().filter(|e| !e.is_default()).count();
+        let table = Table::new(rows, [
+            Constraint::Length(10),
+            Constraint::Length(1),
+            Constraint::Percentage(25),
+            Constraint::Percentage(45),
+            Constraint::Percentage(30),
+        ])
+        .header(Row::new(vec!["Section", "", "Setting", "Value", "Default"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+        .row_highlight_style(Style::default().bg(Color::Rgb(40, 40, 40)).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> ")
+        .block(self.block().title(format!(" Effective Configuration ({} not default, {} changed since startup) ", non_default, changed)).borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow)));
+        let Some(ref mut view) = self.config_view else {
+            return;
+        };
+        f.render_stateful_widget(table, area, &mut view.table_state);
+    }
+
+    fn render_fairness_view(&self, f: &mut Frame, snapshot: &StateSnapshot, area: Rect) {
+        let report = &snapshot.fairness;
+        let legend_rows = report.users.len().min(SHARE_COLORS.len() + 1) as u16;
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(5), Constraint::Length(legend_rows + 4)])
+            .split(area);
+
+        // The most served users get a color each, everyone else shares gray
+        let colored: Vec<&str> = report.users.iter().take(SHARE_COLORS.len()).map(|u| u.user_id.as_str()).collect();
+        let color_of = |user: &str| colored.iter().position(|u| *u == user).map_or(Color::DarkGray, |i| SHARE_COLORS[i]);
+
+        let block = self.block()
+            .title(format!(
+                " Share of Service (last {}, {} per column, {} dispatched) ",
+                format_uptime(Duration::from_secs(report.window_secs)), format_uptime(Duration::from_secs(report.bucket_secs)), report.total
+            ))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow));
+        let inner = block.inner(chunks[0]);
+        f.render_widget(block, chunks[0]);
+        let width = inner.width as usize;
+        let height = inner.height as usize;
+        let per_bucket = (width / report.buckets.len().max(1)).max(1);
+        let shown = report.buckets.len().min(width / per_bucket);
+        let buckets = &report.buckets[report.buckets.len() - shown..];
+        // Users of each column, bottom to top in legend order, with their share
+        let stacks: Vec<Vec<(Color, f64)>> = buckets.iter().map(|b| {
+            let total: u64 = b.dispatched.values().sum();
+            let mut stack: Vec<(usize, Color, f64)> = b.dispatched.iter()
+                .map(|(user, n)| (colored.iter().position(|u| u == user).unwrap_or(usize::MAX), color_of(user), *n as f64 / total as f64))
+                .collect();
+            stack.sort_by_key(|(order, _, _)| *order);
+            stack.into_iter().map(|(_, color, share)| (color, share)).collect()
+        }).collect();
+        let lines: Vec<Line> = (0..height).map(|row| {
+            let level = (height - row) as f64 - 0.5;
+            let spans: Vec<Span> = stacks.iter().map(|stack| {
+                let mut top = 0.0;
+                let cell = stack.iter().find(|(_, share)| {
+                    top += share * height as f64;
+                    level < top
+                });
+                match cell {
+                    Some((color, _)) => Span::styled(self.glyphs.share.repeat(per_bucket), Style::default().fg(*color)),
+                    None => Span::raw(" ".repeat(per_bucket)),
+                }
+            }).collect();
+            Line::from(spans)
+        }).collect();
+        f.render_widget(Paragraph::new(lines), inner);
+
+        let mut rows: Vec<Row> = report.users.iter().take(SHARE_COLORS.len()).map(|u| {
+            let fair_share = if report.total == 0 { 0.0 } else { u.fair_dispatches / report.total as f64 };
+            let ratio = if u.fair_dispatches > 0.0 { format!("{:.2}", u.dispatched as f64 / u.fair_dispatches) } else { "-".to_string() };
+            Row::new(vec![
+                Cell::from(self.glyphs.share.repeat(2)).style(Style::default().fg(color_of(&u.user_id))),
+                Cell::from(u.user_id.clone()),
+                Cell::from(u.dispatched.to_string()),
+                Cell::from(format!("{:.1}%", u.share * 100.0)),
+                Cell::from(format!("{:.1}%", fair_share * 100.0)),
+                Cell::from(ratio),
+            ])
+        }).collect();
+        let others = &report.users[colored.len()..];
+        if !others.is_empty() {
+            let dispatched: u64 = others.iter().map(|u| u.dispatched).sum();
+            rows.push(Row::new(vec![
+                Cell::from(self.glyphs.share.repeat(2)).style(Style::default().fg(Color::DarkGray)),
+                Cell::from(format!("{} other user(s)", others.len())),
+                Cell::from(dispatched.to_string()),
+                Cell::from(format!("{:.1}%", if report.total == 0 { 0.0 } else { dispatched as f64 * 100.0 / report.total as f64 })),
+                Cell::from(""),
+                Cell::from(""),
+            ]));
+        }
+        let table = Table::new(rows, [
+            Constraint::Length(3),
+            Constraint::Percentage(40),
+            Constraint::Length(11),
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(8),
+        ])
+        .header(Row::new(vec!["", "User", "Dispatched", "Share", "Fair share", "Ratio"]).style(Style::default().fg(Color::Yellow).bold()).bottom_margin(1))
+        .block(self.block().title(" Users (fair share: equal split among users waiting at each dispatch) ").borders(Borders::ALL));
+        f.render_widget(table, chunks[1]);
+    }
+
+    fn render_history_detail(&self, e: &HistoryEntry) -> Paragraph<'static> {
+        let field = |name: &str, value: String| Line::from(vec![
+            Span::styled(format!(" {:<13}", name), Style::default().fg(Color::Yellow)),
+            Span::styled(value, Style::default().fg(Color::White)),
+        ]);
+        let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+        let lines = vec![
+            field("Task", e.task_id.clone()),
+            field("Finished", e.finished_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            field("User", e.user_id.clone()),
+            field("Route", e.path.clone()),
+            field("Model", or_dash(e.model.clone())),
+            field("Backend", or_dash(e.backend.clone())),
+            Line::from(vec![
+                Span::styled(format!(" {:<13}", "Outcome"), Style::default().fg(Color::Yellow)),
+                Span::styled(e.outcome.as_str(), Style::default().fg(outcome_color(e.outcome)).bold()),
+            ]),
+            field("Status", or_dash(e.status.map(|s| s.to_string()))),
+            field("Queue wait", format_duration(e.queue_wait)),
+            field("Duration", if e.backend.is_some() { format_duration(e.duration) } else { "-".to_string() }),
+            field("Prompt tok.", or_dash(e.prompt_tokens.map(|n| n.to_string()))),
+            field("Output tok.", or_dash(e.completion_tokens.map(|n| n.to_string()))),
+            field("Detail", or_dash(e.detail.clone())),
+        ];
+        Paragraph::new(lines)
+            .wrap(ratatui::widgets::Wrap { trim: false })
+            .block(self.block().title(" Request Details (Esc: close) ").borders(Borders::ALL).border_style(Style::default().fg(Color::Cyan)))
     }
 
     fn render_help(&self) -> Paragraph<'static> {
-        Paragraph::new(" h/l/Tab: Switch Panel | j/k: Nav | Space/Enter: Expand Models | p: VIP | b: Boost | q: Quit")
-            .block(Block::default().borders(Borders::ALL).title_bottom(Line::from(format!(" v{} ", env!("CARGO_PKG_VERSION"))).alignment(Alignment::Right)))
+        if let Some((ref msg, at)) = self.status
+            && at.elapsed() < STATUS_TTL
+        {
+            return Paragraph::new(format!(" {}", msg))
+                .style(Style::default().fg(Color::Yellow))
+                .block(self.block().borders(Borders::ALL).title_bottom(Line::from(format!(" v{} ", env!("CARGO_PKG_VERSION"))).alignment(Alignment::Right)));
+        }
+        if self.history.is_some() {
+            return Paragraph::new(" j/k: Nav | PgUp/PgDn: Page | g/G: Newest/Oldest | Enter: Details | r: Replay | m: Replay on model | Esc/H: Close")
+                .block(self.block().borders(Borders::ALL).title_bottom(Line::from(format!(" v{} ", env!("CARGO_PKG_VERSION"))).alignment(Alignment::Right)));
+        }
+        if self.fairness_view {
+            return Paragraph::new(" Each column is one time slice, split by the share of dispatches each user got | Esc/F: Close")
+                .block(self.block().borders(Borders::ALL).title_bottom(Line::from(format!(" v{} ", env!("CARGO_PKG_VERSION"))).alignment(Alignment::Right)));
+        }
+        if self.config_view.is_some() {
+            return Paragraph::new(" j/k: Nav | PgUp/PgDn: Page | g/G: First/Last | Esc/C: Close | yellow: not default | * changed since startup")
+                .block(self.block().borders(Borders::ALL).title_bottom(Line::from(format!(" v{} ", env!("CARGO_PKG_VERSION"))).alignment(Alignment::Right)));
+        }
+        Paragraph::new(" h/l/Tab: Switch Panel | j/k: Nav | Space/Enter: Expand | p: VIP | b: Boost | D: Dispatch now | w: Watch | B: Bytes | N: Namespace | H: History | C: Config | F: Fairness | s: Save | z: Undo | q: Quit")
+            .block(self.block().borders(Borders::ALL).title_bottom(Line::from(format!(" v{} ", env!("CARGO_PKG_VERSION"))).alignment(Alignment::Right)))
     }
 
     fn render_detailed_help(&self) -> Paragraph<'static> {
-        Paragraph::new("\n  EXPAND MODELS: 'Space' or 'Enter' (in Backends panel)\n  VIP: 'p' | BOOST: 'b' | BLOCK: 'x' (User) / 'X' (IP) | UNBLOCK: 'u'\n  PANELS: 'Tab' | QUIT: 'q' or 'Esc'\n\n  ★ VIP | ⚡ Boost | ✖ Blocked | ▶ Processing | ● Queued").block(Block::default().title(" Help ").borders(Borders::ALL)).style(Style::default().fg(Color::Gray))
+        let g = self.glyphs;
+        Paragraph::new(format!(
+            "\n  EXPAND MODELS: 'Space' or 'Enter' (in Backends panel) | TASK AGES: 'Space' or 'Enter' (in Users panel)\n  VIP: 'p' | BOOST: 'b' | DISPATCH NEXT NOW: 'D' | BLOCK: 'x' (User) / 'X' (IP) | UNBLOCK: 'u'\n  PURGE QUEUE: 'd' | EDIT NOTE: 'n' | WATCH: 'w' | BYTES IN/OUT: 'B' | NAMESPACE FILTER: 'N' | REQUEST HISTORY: 'H' | CONFIG: 'C' | FAIRNESS: 'F' | SAVE SNAPSHOT: 's' | UNDO LAST ACTION: 'z'\n  PAGE: 'PgUp'/'PgDn' | FIRST/LAST: 'g'/'G' | PANELS: 'Tab' | QUIT: 'q' or 'Esc'\n\n  {}VIP | {}Boost | {}Blocked | {}Processing | {}Queued",
+            g.vip, g.boost, g.blocked, g.processing, g.queued
+        )).block(self.block().title(" Help ").borders(Borders::ALL)).style(Style::default().fg(Color::Gray))
+    }
+}
+
+/// Writes the dashboard tables to `ollamamq-dashboard-<timestamp>` in the
+/// working directory and returns the file name.
+fn export_dashboard(snapshot: &StateSnapshot, format: ExportFormat) -> io::Result<String> {
+    let now = chrono::Local::now();
+    let path = format!("ollamamq-dashboard-{}.{}", now.format("%Y%m%d-%H%M%S"), format.extension());
+    std::fs::write(&path, dashboard_report(snapshot, format, now))?;
+    Ok(path)
+}
+
+fn dashboard_report(snapshot: &StateSnapshot, format: ExportFormat, now: chrono::DateTime<chrono::Local>) -> String {
+    let heading = |title: &str| match format {
+        ExportFormat::Markdown => format!("## {}\n\n", title),
+        ExportFormat::Text => format!("{}\n{}\n\n", title, "=".repeat(title.chars().count())),
+    };
+    let mut out = match format {
+        ExportFormat::Markdown => format!("# ollamaMQ dashboard, {}\n\n", now.format("%Y-%m-%d %H:%M:%S %Z")),
+        ExportFormat::Text => format!("ollamaMQ dashboard, {}\n\n", now.format("%Y-%m-%d %H:%M:%S %Z")),
+    };
+
+    let queued: usize = snapshot.queues_len.values().sum();
+    let online = snapshot.backends.iter().filter(|b| b.is_online).count();
+    out.push_str(&format!(
+        "Version {} | Uptime {} | {:.1} req/s | {} in flight | {} queued | Backends {}/{} online\n\n",
+        env!("CARGO_PKG_VERSION"),
+        format_uptime(snapshot.uptime),
+        snapshot.requests_per_sec,
+        snapshot.in_flight,
+        queued,
+        online,
+        snapshot.backends.len()
+    ));
+    if let Some(ref ns) = snapshot.namespace {
+        out.push_str(&format!("User figures cover namespace {} only.\n\n", ns));
+    }
+
+    out.push_str(&heading("Backends"));
+    let rows = snapshot.backends.iter().map(|b| {
+        let mut flags = Vec::new();
+        if b.is_canary { flags.push("canary"); }
+        if b.dynamic { flags.push("dynamic"); }
+        vec![
+            b.url.clone(),
+            if b.is_online { "online" } else { "offline" }.to_string(),
+            b.api_type.display().to_string(),
+            b.features.as_ref().map(|f| f.version.clone()).unwrap_or_default(),
+            b.active_requests.to_string(),
+            b.processed_count.to_string(),
+            b.failed_count.to_string(),
+            snapshot.budgets.get(&b.url).map(format_budget).unwrap_or_default(),
+            b.avg_latency_ms().map(|ms| format!("{}ms", ms)).unwrap_or_default(),
+            b.current_model.clone().unwrap_or_default(),
+            flags.join(", "),
+        ]
+    }).collect();
+    out.push_str(&export_table(format, &["Backend", "Status", "API", "Version", "Active", "Done", "Err", "Budget", "Avg", "Model", "Flags"], rows));
+
+    out.push_str(&heading("Users"));
+    let rows = snapshot.user_ids.iter().map(|user| {
+        let is_blocked = snapshot.blocked_users.contains(user) || snapshot.user_ips.get(user).is_some_and(|ip| snapshot.blocked_ips.iter().any(|p| p.contains(ip)));
+        let mut flags = Vec::new();
+        if snapshot.watched.contains(user) { flags.push("watch".to_string()); }
+        if snapshot.vip_user.as_ref() == Some(user) { flags.push("vip".to_string()); }
+        if snapshot.boost_user.as_ref() == Some(user) { flags.push("boost".to_string()); }
+        if snapshot.dispatch_next.as_ref() == Some(user) { flags.push("next".to_string()); }
+        if is_blocked { flags.push("blocked".to_string()); }
+        if snapshot.cooling_down.contains(user) { flags.push("cooldown".to_string()); }
+        if let Some(n) = snapshot.connection_ips.get(user).filter(|n| **n > 1) { flags.push(format!("{} IPs", n)); }
+        vec![
+            user.clone(),
+            flags.join(", "),
+            snapshot.user_notes.get(user).cloned().unwrap_or_default(),
+            snapshot.source(user),
+            snapshot.queues_len.get(user).unwrap_or(&0).to_string(),
+            snapshot.processing_counts.get(user).unwrap_or(&0).to_string(),
+            snapshot.processed_counts.get(user).unwrap_or(&0).to_string(),
+            snapshot.recent(user)[0].to_string(),
+            snapshot.recent(user)[1].to_string(),
+            snapshot.recent(user)[2].to_string(),
+            snapshot.dropped_counts.get(user).unwrap_or(&0).to_string(),
+            format_bytes(snapshot.bandwidth.get(user).map_or(0, |b| b.bytes_in)),
+            format_bytes(snapshot.bandwidth.get(user).map_or(0, |b| b.bytes_out)),
+            snapshot.tool_use.get(user).map_or(0, |t| t.requests).to_string(),
+            snapshot.tool_use.get(user).map_or(0, |t| t.calls).to_string(),
+        ].into_iter().chain(snapshot.custom_columns(user)).collect()
+    }).collect();
+    let mut header = vec!["User", "Flags", "Note", "Source", "Queued", "Running", "Done", "5m", "1h", "24h", "Dropped", "In", "Out", "Tool req", "Tool calls"];
+    header.extend(snapshot.columns.iter().map(|c| c.name.as_str()));
+    out.push_str(&export_table(format, &header, rows));
+
+    out.push_str(&heading(&format!("Latency (last {})", snapshot.stage_samples)));
+    let rows = snapshot.stage_percentiles.iter().map(|(stage, values)| {
+        let mut row = vec![stage_label(*stage).to_string()];
+        row.extend(values.iter().map(|v| format_duration(*v)));
+        row
+    }).collect();
+    out.push_str(&export_table(format, &["Stage", "p50", "p90", "p99"], rows));
+
+    if !snapshot.slos.is_empty() {
+        out.push_str(&heading("SLOs"));
+        let rows = snapshot.slos.iter().map(|s| vec![
+            s.tier.clone(),
+            format!("p{} {}", s.percentile, s.stage.name()),
+            s.observed_ms.map_or("-".to_string(), |ms| format_duration(Duration::from_millis(ms))),
+            format_duration(Duration::from_millis(s.target_ms)),
+            s.samples.to_string(),
+            if s.violated { "violated" } else { "met" }.to_string(),
+            s.violations.to_string(),
+        ]).collect();
+        out.push_str(&export_table(format, &["Tier", "Objective", "Now", "Target", "Samples", "State", "Violations"], rows));
+    }
+
+    out.push_str(&heading("Blocked"));
+    let rows: Vec<Vec<String>> = snapshot.blocked_rows().into_iter()
+        .map(|(kind, value, info)| vec![
+            kind.to_string(),
+            value,
+            info.map_or(String::new(), |i| i.by.clone()),
+            info.and_then(|i| i.reason.clone()).unwrap_or_default(),
+            info.map_or(String::new(), |i| i.created_at.clone()),
+            info.and_then(|i| i.expires_at.clone()).unwrap_or_default(),
+        ])
+        .collect();
+    out.push_str(&export_table(format, &["Type", "Value", "By", "Reason", "Blocked At", "Expires At"], rows));
+    out
+}
+
+/// One table of a dashboard report, followed by a blank line.
+fn export_table(format: ExportFormat, headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    if rows.is_empty() {
+        return "(none)\n\n".to_string();
+    }
+    let mut out = String::new();
+    match format {
+        ExportFormat::Markdown => {
+            let cell = |c: &str| c.replace('|', "\\|").replace('\n', " ");
+            out.push_str(&format!("| {} |\n", headers.join(" | ")));
+            out.push_str(&format!("|{}\n", "---|".repeat(headers.len())));
+            for row in &rows {
+                out.push_str(&format!("| {} |\n", row.iter().map(|c| cell(c)).collect::<Vec<_>>().join(" | ")));
+            }
+        }
+        ExportFormat::Text => {
+            let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+            for row in &rows {
+                for (w, c) in widths.iter_mut().zip(row) {
+                    *w = (*w).max(c.chars().count());
+                }
+            }
+            let line = |cells: Vec<&str>| {
+                let padded: Vec<String> = cells.iter().zip(&widths).map(|(c, w)| format!("{:<w$}", c, w = *w)).collect();
+                format!("{}\n", padded.join("  ").trim_end())
+            };
+            out.push_str(&line(headers.to_vec()));
+            let rules: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            out.push_str(&line(rules.iter().map(String::as_str).collect()));
+            for row in &rows {
+                out.push_str(&line(row.iter().map(|c| c.as_str()).collect()));
+            }
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// Metadata of `user`'s block, to restore it on undo.
+fn user_block_info(state: &AppState, user: &str) -> BlockInfo {
+    state.user_blocks.lock().unwrap().get(user).cloned().unwrap_or_else(|| BlockInfo::new("tui"))
+}
+
+fn ip_block_info(state: &AppState, prefix: &IpPrefix) -> BlockInfo {
+    state.ip_blocks.lock().unwrap().get(prefix).cloned().unwrap_or_else(|| BlockInfo::new("tui"))
+}
+
+/// Time left on a block, `never` without an expiry, `-` without metadata.
+fn block_expiry(info: Option<&BlockInfo>, now: chrono::DateTime<chrono::Local>) -> String {
+    match info {
+        None => "-".to_string(),
+        Some(info) => match info.expires() {
+            None => "never".to_string(),
+            Some(at) => format_countdown(at.with_timezone(&chrono::Local) - now),
+        },
+    }
+}
+
+fn stage_label(stage: Stage) -> &'static str {
+    match stage {
+        Stage::QueueWait => "Queue wait",
+        Stage::FirstChunk => "Backend (1st chunk)",
+        Stage::Streaming => "Streaming",
+    }
+}
+
+/// Error budget left as a percentage, `-` without requests in the window.
+fn format_budget(budget: &BudgetStatus) -> String {
+    match budget.remaining {
+        Some(r) if r <= 0.0 => "out".to_string(),
+        Some(r) => format!("{:.0}%", r * 100.0),
+        None => "-".to_string(),
+    }
+}
+
+/// Table title with the visible row range once not every row fits.
+fn rows_title(name: &str, visible: &Range<usize>, len: usize) -> String {
+    if visible.len() < len {
+        format!(" {} ({}-{} of {}) ", name, visible.start + 1, visible.end, len)
+    } else {
+        format!(" {} ", name)
+    }
+}
+
+fn outcome_color(outcome: Outcome) -> Color {
+    match outcome {
+        Outcome::Completed => Color::Green,
+        Outcome::Failed(_) => Color::Red,
+        Outcome::Disconnected | Outcome::Cancelled => Color::Yellow,
+        Outcome::Dropped => Color::DarkGray,
+    }
+}
+
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Mirrors `source` into `target` in place; only keys not seen before are cloned.
+fn sync_map<S, V>(target: &mut HashMap<String, V>, source: &HashMap<String, S>, value: impl Fn(&S) -> V) {
+    target.retain(|k, _| source.contains_key(k));
+    for (k, s) in source {
+        match target.get_mut(k) {
+            Some(v) => *v = value(s),
+            None => {
+                target.insert(k.clone(), value(s));
+            }
+        }
+    }
+}
+
+/// Rows of a `len`-row table that fit in `height`, with `offset` scrolled just
+/// enough to keep `selected` visible. Only these rows are built when drawing.
+fn visible_rows(offset: &mut usize, selected: Option<usize>, len: usize, height: usize) -> Range<usize> {
+    let height = height.max(1);
+    if let Some(s) = selected {
+        if s < *offset {
+            *offset = s;
+        } else if s >= *offset + height {
+            *offset = s + 1 - height;
+        }
+    }
+    *offset = (*offset).min(len.saturating_sub(height));
+    *offset..(*offset + height).min(len)
+}
+
+/// Moves a table selection by `delta` rows, clamped to the table.
+fn step_selection(table: &mut TableState, len: usize, delta: isize) {
+    if len > 0 {
+        let current = table.selected().unwrap_or(0) as isize;
+        table.select(Some((current + delta).clamp(0, len as isize - 1) as usize));
     }
 }
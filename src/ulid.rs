@@ -0,0 +1,72 @@
+//! Task ids as ULIDs: 26 Crockford base32 characters, a millisecond timestamp
+//! followed by 80 random bits. They sort by admission time, stay unique across
+//! restarts and instances, and can be grepped for in every subsystem.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const RANDOM_BITS: u32 = 80;
+const RANDOM_MASK: u128 = (1 << RANDOM_BITS) - 1;
+
+/// Issues monotonic ULIDs: ids from the same millisecond increment the random
+/// part, so they still sort in the order they were issued.
+pub struct UlidGenerator {
+    hasher: RandomState,
+    /// Timestamp and random part of the last id.
+    last: Mutex<(u64, u128)>,
+}
+
+impl Default for UlidGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UlidGenerator {
+    pub fn new() -> Self {
+        Self { hasher: RandomState::new(), last: Mutex::new((0, 0)) }
+    }
+
+    pub fn next(&self) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let mut last = self.last.lock().unwrap();
+        let (ms, random) = if now <= last.0 {
+            // Same millisecond, or the clock went back: count up from the last id
+            match last.1.checked_add(1).filter(|r| *r <= RANDOM_MASK) {
+                Some(r) => (last.0, r),
+                None => (last.0 + 1, self.random()),
+            }
+        } else {
+            (now, self.random())
+        };
+        *last = (ms, random);
+        encode(((ms as u128) << RANDOM_BITS) | random)
+    }
+
+    /// 80 unpredictable bits from the process-random SipHash keys.
+    fn random(&self) -> u128 {
+        let mut value = 0u128;
+        for i in 0..2u64 {
+            let mut h = self.hasher.build_hasher();
+            h.write_u64(i);
+            h.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos()));
+            value = (value << 64) | h.finish() as u128;
+        }
+        // Leave room to count up within the millisecond
+        value & (RANDOM_MASK >> 1)
+    }
+}
+
+fn encode(mut value: u128) -> String {
+    let mut out = [0u8; 26];
+    for c in out.iter_mut().rev() {
+        *c = ALPHABET[(value & 0x1f) as usize];
+        value >>= 5;
+    }
+    String::from_utf8(out.to_vec()).unwrap()
+}
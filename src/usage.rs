@@ -0,0 +1,220 @@
+//! Persistent usage log: one JSON line per finished request, appended to the
+//! `--usage-log` file and summarized by `ollamaMQ report`. With
+//! `--usage-log-max-mb`, the file is rotated into zstd-compressed archives
+//! (`<file>.1.zst`, `<file>.2.zst`, ...) that the readers here include.
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+use crate::compress;
+use crate::history::{HistoryEntry, Outcome};
+
+/// One finished request as stored in the usage log.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UsageRecord {
+    /// RFC 3339 timestamp in the dispatcher's local time zone.
+    pub ts: String,
+    pub user: String,
+    pub model: Option<String>,
+    pub path: String,
+    pub backend: Option<String>,
+    pub outcome: String,
+    pub status: Option<u16>,
+    pub queue_ms: u64,
+    pub duration_ms: u64,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+}
+
+impl UsageRecord {
+    pub fn from_entry(entry: &HistoryEntry) -> Self {
+        Self {
+            ts: entry.finished_at.to_rfc3339(),
+            user: entry.user_id.clone(),
+            model: entry.model.clone(),
+            path: entry.path.clone(),
+            backend: entry.backend.clone(),
+            outcome: entry.outcome.as_str().to_string(),
+            status: entry.status,
+            queue_ms: entry.queue_wait.as_millis() as u64,
+            duration_ms: entry.duration.as_millis() as u64,
+            prompt_tokens: entry.prompt_tokens,
+            completion_tokens: entry.completion_tokens,
+        }
+    }
+
+    fn date(&self) -> Option<NaiveDate> {
+        DateTime::<FixedOffset>::parse_from_rfc3339(&self.ts).ok().map(|t| t.date_naive())
+    }
+}
+
+/// `n`th archive of the usage log at `path`, 1 being the newest.
+fn archive_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}.{}", n, compress::SUFFIX));
+    PathBuf::from(name)
+}
+
+/// The usage log at `path` line by line, oldest first: the archives, then the
+/// current file.
+pub fn lines(path: &Path) -> io::Result<impl Iterator<Item = io::Result<String>>> {
+    let archives = (1..).take_while(|n| archive_path(path, *n).exists()).count();
+    let mut readers: Vec<Box<dyn BufRead>> = Vec::new();
+    for n in (1..=archives).rev() {
+        let archive = BufReader::new(File::open(archive_path(path, n))?);
+        readers.push(Box::new(BufReader::new(compress::reader(archive)?)));
+    }
+    readers.push(Box::new(BufReader::new(File::open(path)?)));
+    Ok(readers.into_iter().flat_map(|r| r.lines()))
+}
+
+struct Current {
+    file: File,
+    written: u64,
+}
+
+/// Append-only writer for the usage log.
+pub struct UsageLog {
+    path: PathBuf,
+    /// Size past which the file is rotated; 0 for never.
+    max_bytes: u64,
+    /// Compressed archives kept.
+    max_files: usize,
+    current: Mutex<Current>,
+}
+
+impl UsageLog {
+    pub fn open(path: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            current: Mutex::new(Current { file, written }),
+        })
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) {
+        let mut line = serde_json::to_string(&UsageRecord::from_entry(entry)).unwrap_or_default();
+        line.push('\n');
+        let mut current = self.current.lock().unwrap();
+        if self.max_bytes > 0
+            && current.written > 0
+            && current.written + line.len() as u64 > self.max_bytes
+            && let Err(e) = self.rotate(&mut current)
+        {
+            warn!("Failed to rotate usage log {}: {}", self.path.display(), e);
+        }
+        // A single write keeps lines whole even if the file is read meanwhile
+        match current.file.write_all(line.as_bytes()) {
+            Ok(()) => current.written += line.len() as u64,
+            Err(e) => warn!("Failed to write usage log: {}", e),
+        }
+    }
+
+    /// Compresses the file into the newest archive, dropping the oldest past
+    /// `max_files`, and starts it over.
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        if self.max_files > 0 {
+            let _ = fs::remove_file(archive_path(&self.path, self.max_files));
+            for n in (1..self.max_files).rev() {
+                let _ = fs::rename(archive_path(&self.path, n), archive_path(&self.path, n + 1));
+            }
+            let mut source = File::open(&self.path)?;
+            compress::copy_compressed(&mut source, File::create(archive_path(&self.path, 1))?)?;
+        }
+        current.file.set_len(0)?;
+        info!("Rotated usage log {} after {} bytes", self.path.display(), current.written);
+        current.written = 0;
+        Ok(())
+    }
+}
+
+/// Grouping of report rows.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportGroup {
+    User,
+    Model,
+    /// One row per user and model.
+    UserModel,
+}
+
+/// Aggregated usage of one user, model, or user/model pair.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct UsageSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub requests: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// Dropped, cancelled, or abandoned by the client.
+    pub other: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Total backend time in seconds.
+    pub backend_secs: f64,
+    /// Total queue wait in seconds.
+    pub queue_secs: f64,
+}
+
+impl UsageSummary {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        match record.outcome.as_str() {
+            o if o == Outcome::Completed.as_str() => self.completed += 1,
+            o if o.starts_with("failed") => self.failed += 1,
+            _ => self.other += 1,
+        }
+        self.prompt_tokens += record.prompt_tokens.unwrap_or(0);
+        self.completion_tokens += record.completion_tokens.unwrap_or(0);
+        self.backend_secs += record.duration_ms as f64 / 1000.0;
+        self.queue_secs += record.queue_ms as f64 / 1000.0;
+    }
+}
+
+/// Reads the usage log and sums up the requests finished between `from` and
+/// `to` (inclusive, local dates). Returns the rows, sorted by key, and the totals.
+pub fn summarize(
+    path: &Path,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    group: ReportGroup,
+) -> std::io::Result<(Vec<UsageSummary>, UsageSummary)> {
+    let mut rows: BTreeMap<(Option<String>, Option<String>), UsageSummary> = BTreeMap::new();
+    let mut total = UsageSummary::default();
+    for line in lines(path)? {
+        let line = line?;
+        // Skips a line cut short by a crash instead of failing the whole report
+        let Ok(record) = serde_json::from_str::<UsageRecord>(&line) else {
+            continue;
+        };
+        let Some(date) = record.date() else {
+            continue;
+        };
+        if from.is_some_and(|f| date < f) || to.is_some_and(|t| date > t) {
+            continue;
+        }
+        let model = || Some(record.model.clone().unwrap_or_else(|| "-".to_string()));
+        let key = match group {
+            ReportGroup::User => (Some(record.user.clone()), None),
+            ReportGroup::Model => (None, model()),
+            ReportGroup::UserModel => (Some(record.user.clone()), model()),
+        };
+        rows.entry(key.clone())
+            .or_insert_with(|| UsageSummary { user: key.0, model: key.1, ..Default::default() })
+            .add(&record);
+        total.add(&record);
+    }
+    Ok((rows.into_values().collect(), total))
+}
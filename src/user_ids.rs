@@ -0,0 +1,152 @@
+//! User id normalization: ids from `X-User-ID` (or an identity endpoint) are
+//! trimmed, lowercased and rewritten by the `user_ids` rules of the config
+//! file before they become queue keys, so `Alice`, `alice ` and `ALICE` share
+//! one queue and one set of counters. Ids that still break the length or
+//! character rules afterwards are rejected.
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+use crate::hooks::Pattern;
+
+/// A regex rewrite of user ids.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rewrite {
+    pub pattern: Pattern,
+    /// Replacement for each match; `$1`, `${name}` refer to capture groups.
+    pub replace: String,
+}
+
+/// Characters a user id may consist of, given as the inside of a regex
+/// character class (`a-z0-9._@-`).
+#[derive(Clone, Debug)]
+pub struct Charset {
+    class: String,
+    regex: Regex,
+}
+
+impl<'de> Deserialize<'de> for Charset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let class = String::deserialize(deserializer)?;
+        Regex::new(&format!("^[{}]*$", class))
+            .map(|regex| Charset { class: class.clone(), regex })
+            .map_err(|e| serde::de::Error::custom(format!("invalid charset '{}': {}", class, e)))
+    }
+}
+
+/// `user_ids` in the config file. Without it, ids are used as sent.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserIdRules {
+    /// Remove leading and trailing whitespace.
+    pub trim: bool,
+    /// Lowercase the id.
+    pub lowercase: bool,
+    /// Rewrites applied in order, after trimming and lowercasing.
+    pub rewrites: Vec<Rewrite>,
+    /// Longest id accepted, in characters, after normalization.
+    pub max_length: Option<usize>,
+    /// Characters allowed in an id after normalization; others are rejected.
+    pub charset: Option<Charset>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UserIdError {
+    Empty,
+    TooLong(usize),
+    Charset(String),
+}
+
+impl fmt::Display for UserIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserIdError::Empty => write!(f, "user id is empty"),
+            UserIdError::TooLong(max) => write!(f, "user id is longer than {} characters", max),
+            UserIdError::Charset(class) => write!(f, "user id may only contain [{}]", class),
+        }
+    }
+}
+
+impl UserIdRules {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_length == Some(0) {
+            return Err("max_length must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether any rule is set; ids pass through untouched otherwise.
+    pub fn is_active(&self) -> bool {
+        self.trim || self.lowercase || !self.rewrites.is_empty() || self.max_length.is_some() || self.charset.is_some()
+    }
+
+    /// The rules in short, e.g. `trim, lowercase, max 64`; `as sent` without any.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.trim {
+            parts.push("trim".to_string());
+        }
+        if self.lowercase {
+            parts.push("lowercase".to_string());
+        }
+        if !self.rewrites.is_empty() {
+            parts.push(format!("{} rewrite(s)", self.rewrites.len()));
+        }
+        if let Some(max) = self.max_length {
+            parts.push(format!("max {}", max));
+        }
+        if let Some(ref charset) = self.charset {
+            parts.push(format!("[{}]", charset.class));
+        }
+        if parts.is_empty() { "as sent".to_string() } else { parts.join(", ") }
+    }
+
+    /// `id` after trimming, lowercasing and the rewrites.
+    pub fn normalize(&self, id: &str) -> String {
+        let mut id = if self.trim { id.trim().to_string() } else { id.to_string() };
+        if self.lowercase {
+            id = id.to_lowercase();
+        }
+        for rewrite in &self.rewrites {
+            id = rewrite.pattern.0.replace_all(&id, rewrite.replace.as_str()).into_owned();
+        }
+        id
+    }
+
+    /// The normalized id, or why it isn't acceptable.
+    pub fn apply(&self, id: &str) -> Result<String, UserIdError> {
+        if !self.is_active() {
+            return Ok(id.to_string());
+        }
+        let id = self.normalize(id);
+        if id.is_empty() {
+            return Err(UserIdError::Empty);
+        }
+        if let Some(max) = self.max_length.filter(|max| id.chars().count() > *max) {
+            return Err(UserIdError::TooLong(max));
+        }
+        if let Some(ref charset) = self.charset
+            && !charset.regex.is_match(&id)
+        {
+            return Err(UserIdError::Charset(charset.class.clone()));
+        }
+        Ok(id)
+    }
+
+    /// Like `apply`, for an id that may be qualified with a namespace
+    /// (`team/alice`): only the user part is normalized.
+    pub fn apply_qualified(&self, id: &str) -> Result<String, UserIdError> {
+        match id.split_once(crate::namespace::SEPARATOR) {
+            Some((namespace, user)) => self.apply(user).map(|user| crate::namespace::qualify(Some(namespace), &user)),
+            None => self.apply(id),
+        }
+    }
+
+    /// `apply_qualified`, keeping ids the rules reject as they are: no request
+    /// can carry them, so they match nothing either way.
+    pub fn key(&self, id: &str) -> String {
+        self.apply_qualified(id).unwrap_or_else(|_| id.to_string())
+    }
+}
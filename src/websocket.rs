@@ -0,0 +1,262 @@
+//! WebSocket proxying: with `--websockets`, `Upgrade: websocket` requests on
+//! any path are passed through to a backend after the same identity and block
+//! checks as queued requests, and with a cap on the connections a user holds
+//! open, so websocket-based clients don't have to bypass the dispatcher.
+//! Frames are relayed as they come; the dispatcher doesn't look into them.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Local;
+use hyper_util::rt::TokioIo;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+use crate::dispatcher::{AppState, backend_client, caller_id, strip_client_headers};
+use crate::errors::ErrorClass;
+use crate::jail::BlockedAttempt;
+
+/// Largest error answer of a backend that refused the upgrade passed on.
+const REFUSAL_BODY_LIMIT: usize = 64 * 1024;
+
+/// Headers that belong to the client's hop and aren't sent on to the backend.
+const HOP_HEADERS: [header::HeaderName; 3] = [header::HOST, header::CONTENT_LENGTH, header::TRANSFER_ENCODING];
+
+/// One proxied WebSocket connection.
+#[derive(Clone, Debug)]
+pub struct Tunnel {
+    pub user_id: String,
+    pub ip: IpAddr,
+    pub backend: String,
+    pub path: String,
+    /// RFC 3339.
+    pub opened_at: String,
+    started: Instant,
+}
+
+impl Tunnel {
+    pub fn open_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+}
+
+/// Open WebSocket connections, and totals since startup.
+#[derive(Default)]
+pub struct WebSockets {
+    open: HashMap<u64, Tunnel>,
+    next_id: u64,
+    pub opened_total: u64,
+    /// Upgrades refused for being over `--websocket-max-per-user`.
+    pub rejected_total: u64,
+    /// Bytes relayed from clients to backends, and back, of closed connections.
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl WebSockets {
+    /// Open connections, oldest first.
+    pub fn open(&self) -> Vec<Tunnel> {
+        let mut open: Vec<(&u64, &Tunnel)> = self.open.iter().collect();
+        open.sort_by_key(|(id, _)| **id);
+        open.into_iter().map(|(_, t)| t.clone()).collect()
+    }
+
+    /// Open connections per user, sorted by user.
+    pub fn by_user(&self) -> Vec<(String, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for tunnel in self.open.values() {
+            *counts.entry(&tunnel.user_id).or_insert(0) += 1;
+        }
+        let mut list: Vec<(String, usize)> = counts.into_iter().map(|(u, n)| (u.to_string(), n)).collect();
+        list.sort();
+        list
+    }
+
+    fn count(&self, f: impl Fn(&Tunnel) -> bool) -> usize {
+        self.open.values().filter(|t| f(t)).count()
+    }
+}
+
+/// An open connection in `AppState::websockets`; removed again on drop.
+struct TunnelGuard {
+    state: Arc<AppState>,
+    id: u64,
+}
+
+impl Drop for TunnelGuard {
+    fn drop(&mut self) {
+        self.state.websockets.lock().unwrap().open.remove(&self.id);
+    }
+}
+
+/// Whether `headers` ask to switch the connection to the WebSocket protocol.
+pub fn is_upgrade(headers: &HeaderMap) -> bool {
+    let upgrade = headers.get(header::UPGRADE).and_then(|v| v.to_str().ok()).is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let connection = headers.get_all(header::CONNECTION).iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("upgrade")));
+    upgrade && connection
+}
+
+/// Middleware taking WebSocket upgrades off the router when `--websockets` is on.
+pub async fn intercept(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !state.config.websockets || !is_upgrade(req.headers()) {
+        return next.run(req).await;
+    }
+    proxy(state, addr.ip(), req).await
+}
+
+fn refuse(status: StatusCode, message: String) -> Response {
+    (status, axum::Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// Checks the caller, upgrades the connection on a backend, and relays it.
+pub async fn proxy(state: Arc<AppState>, ip: IpAddr, mut req: Request) -> Response {
+    let ip = ip.to_canonical();
+    let mut headers = req.headers().clone();
+    let user_id = match caller_id(&state, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    strip_client_headers(&state, &mut headers);
+    if let Some(ref name) = state.config.forward_user_header
+        && let Ok(value) = header::HeaderValue::from_str(&user_id)
+    {
+        headers.insert(name.clone(), value);
+    }
+    for name in HOP_HEADERS {
+        headers.remove(name);
+    }
+
+    if state.is_ip_blocked(&ip) {
+        warn!("Blocked WebSocket from IP: {} for user: {}", state.describe_ip(&ip), user_id);
+        return (StatusCode::FORBIDDEN, axum::Extension(BlockedAttempt), "IP blocked").into_response();
+    }
+    if state.is_user_blocked(&user_id) {
+        warn!("Blocked WebSocket from user: {} ({})", user_id, state.describe_ip(&ip));
+        return (StatusCode::FORBIDDEN, axum::Extension(BlockedAttempt), "User blocked").into_response();
+    }
+    if state.is_shutting_down() {
+        return refuse(StatusCode::SERVICE_UNAVAILABLE, "dispatcher shutting down".to_string());
+    }
+
+    let path = req.uri().path().to_string();
+    // Counted from here on, so concurrent upgrades can't slip past the limit
+    let (backend, guard) = {
+        let backends = state.backends.lock().unwrap();
+        let mut sockets = state.websockets.lock().unwrap();
+        let limit = state.config.websocket_max_per_user;
+        let open = sockets.count(|t| t.user_id == user_id);
+        if limit > 0 && open >= limit {
+            debug!("Rejected WebSocket of user {}: {} already open", user_id, open);
+            sockets.rejected_total += 1;
+            drop(sockets);
+            state.record_error(&user_id, ErrorClass::Rejected);
+            return refuse(StatusCode::TOO_MANY_REQUESTS, format!("user '{}' already has {} WebSocket connection(s) open", user_id, open));
+        }
+        // The online backend with the fewest open connections, then the fewest requests
+        let backend = backends.iter()
+            .filter(|b| b.is_online)
+            .min_by_key(|b| (sockets.count(|t| t.backend == b.url), b.active_requests))
+            .map(|b| b.url.clone());
+        let Some(backend) = backend else {
+            return refuse(StatusCode::SERVICE_UNAVAILABLE, "no healthy backend available".to_string());
+        };
+        let id = sockets.next_id;
+        sockets.next_id += 1;
+        sockets.open.insert(id, Tunnel {
+            user_id: user_id.clone(),
+            ip,
+            backend: backend.clone(),
+            path: path.clone(),
+            opened_at: Local::now().to_rfc3339(),
+            started: Instant::now(),
+        });
+        (backend, TunnelGuard { state: state.clone(), id })
+    };
+
+    let target = match req.uri().query() {
+        Some(query) => format!("{}{}?{}", backend, path, query),
+        None => format!("{}{}", backend, path),
+    };
+    let client = backend_client(&state.config, &backend);
+    let answer = client.get(&target).version(reqwest::Version::HTTP_11).headers(headers).send().await;
+    let answer = match answer {
+        Ok(answer) => answer,
+        Err(e) => {
+            warn!("WebSocket of user {} to {} failed: {}", user_id, backend, e);
+            state.record_error(&user_id, ErrorClass::Connection);
+            return refuse(StatusCode::BAD_GATEWAY, format!("backend {} could not be reached", backend));
+        }
+    };
+    if answer.status() != StatusCode::SWITCHING_PROTOCOLS {
+        // The backend doesn't speak WebSocket on this path; pass its answer on
+        let status = answer.status();
+        debug!("Backend {} refused the WebSocket upgrade of user {} on {} with {}", backend, user_id, path, status);
+        let mut response = Response::builder().status(status);
+        if let Some(content_type) = answer.headers().get(header::CONTENT_TYPE) {
+            response = response.header(header::CONTENT_TYPE, content_type);
+        }
+        let body = answer.bytes().await.map(|b| b.slice(..b.len().min(REFUSAL_BODY_LIMIT))).unwrap_or_default();
+        return response.body(Body::from(body)).unwrap_or_else(|_| status.into_response());
+    }
+
+    let mut response = Response::builder().status(StatusCode::SWITCHING_PROTOCOLS);
+    for (name, value) in answer.headers() {
+        response = response.header(name, value);
+    }
+    let response = response.body(Body::empty()).unwrap();
+    let mut backend_io = match answer.upgrade().await {
+        Ok(io) => io,
+        Err(e) => {
+            warn!("WebSocket of user {} to {} failed: {}", user_id, backend, e);
+            state.record_error(&user_id, ErrorClass::Connection);
+            return refuse(StatusCode::BAD_GATEWAY, format!("backend {} did not switch protocols", backend));
+        }
+    };
+
+    let client_upgrade = hyper::upgrade::on(&mut req);
+    state.websockets.lock().unwrap().opened_total += 1;
+    info!("WebSocket of user {} ({}) on {} opened to {}", user_id, state.describe_ip(&ip), path, backend);
+    tokio::spawn(async move {
+        let client_io = match client_upgrade.await {
+            Ok(io) => io,
+            Err(e) => {
+                debug!("WebSocket of user {} closed before the upgrade: {}", user_id, e);
+                return;
+            }
+        };
+        let mut client_io = TokioIo::new(client_io);
+        let started = Instant::now();
+        let (sent, received) = match tokio::io::copy_bidirectional(&mut client_io, &mut backend_io).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!("WebSocket of user {} to {} broke off: {}", user_id, backend, e);
+                (0, 0)
+            }
+        };
+        state.record_bandwidth(&user_id, sent, received);
+        {
+            let mut sockets = state.websockets.lock().unwrap();
+            sockets.bytes_in += sent;
+            sockets.bytes_out += received;
+        }
+        info!("WebSocket of user {} to {} closed after {}s", user_id, backend, started.elapsed().as_secs());
+        drop(guard);
+    });
+    response
+}
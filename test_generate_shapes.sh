@@ -0,0 +1,95 @@
+#!/bin/bash
+
+# Checks that /api/generate requests using raw mode, fill-in-the-middle
+# (suffix), images and a context array give the same answer through ollamaMQ
+# as from Ollama directly. Requests use temperature 0 and a fixed seed, so
+# both answers must match exactly. Needs curl and jq.
+
+# Configuration
+BASE_URL="${BASE_URL:-http://localhost:11435}"
+OLLAMA_URL="${OLLAMA_URL:-http://localhost:11434}"
+MODEL="${MODEL:-llama3}"
+# A model with a fill-in-the-middle template, e.g. qwen2.5-coder or codellama:code
+FIM_MODEL="${FIM_MODEL:-qwen2.5-coder:1.5b}"
+# A vision model; the image test is skipped when empty
+VISION_MODEL="${VISION_MODEL:-}"
+USER_ID="${USER_ID:-shape-test}"
+
+# 1x1 red PNG
+PIXEL="iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAIAAACQd1PeAAAADElEQVR4nGP4z8AAAAMBAQDJ/pLvAAAAAElFTkSuQmCC"
+OPTIONS='{"temperature": 0, "seed": 42, "num_predict": 24}'
+
+passed=0
+failed=0
+
+# Sends the same body to both endpoints; streamed answers are joined.
+answer() {
+    local url=$1
+    local body=$2
+    curl -s -X POST "$url/api/generate" \
+        -H "X-User-ID: $USER_ID" \
+        -H "Content-Type: application/json" \
+        -d "$body" | jq -rs 'map(.response // "") | join("")'
+}
+
+compare() {
+    local name=$1
+    local body=$2
+    local direct proxied
+    direct=$(answer "$OLLAMA_URL" "$body")
+    proxied=$(answer "$BASE_URL" "$body")
+
+    if [ -z "$direct" ]; then
+        echo "⚠️  [SKIPPED] $name: no answer from Ollama directly"
+    elif [ "$direct" == "$proxied" ]; then
+        echo "✅ [PASS] $name: ${direct:0:60}"
+        passed=$((passed + 1))
+    else
+        echo "❌ [FAIL] $name"
+        echo "   direct:  ${direct:0:120}"
+        echo "   proxied: ${proxied:0:120}"
+        failed=$((failed + 1))
+    fi
+}
+
+echo "🧪 /api/generate shape tests"
+echo "Dispatcher: $BASE_URL"
+echo "Ollama:     $OLLAMA_URL"
+echo "----------------------------------------"
+
+compare "plain" \
+    "{\"model\": \"$MODEL\", \"prompt\": \"Name a color.\", \"stream\": false, \"options\": $OPTIONS}"
+
+compare "raw" \
+    "{\"model\": \"$MODEL\", \"prompt\": \"The capital of France is\", \"raw\": true, \"stream\": false, \"options\": $OPTIONS}"
+
+compare "raw streamed" \
+    "{\"model\": \"$MODEL\", \"prompt\": \"1, 2, 3,\", \"raw\": true, \"options\": $OPTIONS}"
+
+compare "suffix (fill-in-the-middle)" \
+    "{\"model\": \"$FIM_MODEL\", \"prompt\": \"def add(a, b):\\n    \", \"suffix\": \"\\n\\nprint(add(1, 2))\", \"stream\": false, \"options\": $OPTIONS}"
+
+compare "suffix streamed" \
+    "{\"model\": \"$FIM_MODEL\", \"prompt\": \"fn main() {\\n    let x = \", \"suffix\": \";\\n    println!(\\\"{}\\\", x);\\n}\", \"options\": $OPTIONS}"
+
+# Continue a conversation with the context array of a first answer
+context=$(curl -s -X POST "$OLLAMA_URL/api/generate" \
+    -H "Content-Type: application/json" \
+    -d "{\"model\": \"$MODEL\", \"prompt\": \"Remember the number 7.\", \"stream\": false, \"options\": $OPTIONS}" | jq -c '.context // empty')
+if [ -n "$context" ]; then
+    compare "context" \
+        "{\"model\": \"$MODEL\", \"prompt\": \"Which number?\", \"context\": $context, \"stream\": false, \"options\": $OPTIONS}"
+else
+    echo "⚠️  [SKIPPED] context: Ollama returned no context array"
+fi
+
+if [ -n "$VISION_MODEL" ]; then
+    compare "images" \
+        "{\"model\": \"$VISION_MODEL\", \"prompt\": \"What color is this image?\", \"images\": [\"$PIXEL\"], \"stream\": false, \"options\": $OPTIONS}"
+else
+    echo "⚠️  [SKIPPED] images: set VISION_MODEL to run it"
+fi
+
+echo "----------------------------------------"
+echo "Passed: $passed, failed: $failed"
+[ "$failed" -eq 0 ]
@@ -0,0 +1,108 @@
+#!/bin/bash
+
+# Checks that tool (function) calling works the same through ollamaMQ as with
+# Ollama directly: requests with `tools` and `tool_choice` on /api/chat and
+# /v1/chat/completions, streamed or not, and a follow-up carrying the tool
+# result. The tool calls of both answers must match, and /api/me must count
+# them for the user. Needs curl, jq and a model that supports tools.
+
+# Configuration
+BASE_URL="${BASE_URL:-http://localhost:11435}"
+OLLAMA_URL="${OLLAMA_URL:-http://localhost:11434}"
+MODEL="${MODEL:-llama3.1}"
+USER_ID="${USER_ID:-tool-test-$$}"
+
+TOOLS='[{"type": "function", "function": {"name": "get_weather", "description": "Current weather in a city", "parameters": {"type": "object", "properties": {"city": {"type": "string", "description": "City name"}}, "required": ["city"]}}}]'
+QUESTION='{"role": "user", "content": "What is the weather in Paris right now? Use the tool."}'
+OPTIONS='{"temperature": 0, "seed": 42}'
+
+passed=0
+failed=0
+
+# Tool calls of an answer as compact `name(arguments)` lines. Ollama sends
+# arguments as an object, OpenAI as a JSON string, streamed in fragments.
+tool_calls() {
+    local url=$1
+    local route=$2
+    local body=$3
+    curl -s -X POST "$url$route" \
+        -H "X-User-ID: $USER_ID" \
+        -H "Content-Type: application/json" \
+        -d "$body" | sed 's/^data: //' | grep -v '^\[DONE\]$' | jq -rs '
+        [.[] | (.message.tool_calls // [])[] | {name: .function.name, args: (.function.arguments | tojson)}] as $ollama
+        | [.[] | (.choices // [])[] | (.message.tool_calls // .delta.tool_calls // [])[]] as $openai
+        | ($openai | group_by(.index // 0)
+            | map({name: (map(.function.name // "") | join("")), args: (map(.function.arguments // "") | join("") | fromjson? // . | tojson)})) as $merged
+        | ($ollama + $merged) | map("\(.name)(\(.args))") | join("\n")'
+}
+
+compare() {
+    local name=$1
+    local route=$2
+    local body=$3
+    local direct proxied
+    direct=$(tool_calls "$OLLAMA_URL" "$route" "$body")
+    proxied=$(tool_calls "$BASE_URL" "$route" "$body")
+
+    if [ -z "$direct" ]; then
+        echo "⚠️  [SKIPPED] $name: no tool call from Ollama directly"
+    elif [ "$direct" == "$proxied" ]; then
+        echo "✅ [PASS] $name: ${direct:0:60}"
+        passed=$((passed + 1))
+    else
+        echo "❌ [FAIL] $name"
+        echo "   direct:  ${direct:0:120}"
+        echo "   proxied: ${proxied:0:120}"
+        failed=$((failed + 1))
+    fi
+}
+
+echo "🧪 Tool calling tests"
+echo "Dispatcher: $BASE_URL"
+echo "Ollama:     $OLLAMA_URL"
+echo "----------------------------------------"
+
+before=$(curl -s "$BASE_URL/api/me" -H "X-User-ID: $USER_ID" | jq '.tool_calls // 0')
+
+compare "/api/chat" "/api/chat" \
+    "{\"model\": \"$MODEL\", \"messages\": [$QUESTION], \"tools\": $TOOLS, \"stream\": false, \"options\": $OPTIONS}"
+
+compare "/api/chat streamed" "/api/chat" \
+    "{\"model\": \"$MODEL\", \"messages\": [$QUESTION], \"tools\": $TOOLS, \"options\": $OPTIONS}"
+
+compare "/v1/chat/completions" "/v1/chat/completions" \
+    "{\"model\": \"$MODEL\", \"messages\": [$QUESTION], \"tools\": $TOOLS, \"tool_choice\": \"auto\", \"temperature\": 0, \"seed\": 42}"
+
+compare "/v1/chat/completions streamed" "/v1/chat/completions" \
+    "{\"model\": \"$MODEL\", \"messages\": [$QUESTION], \"tools\": $TOOLS, \"tool_choice\": \"auto\", \"stream\": true, \"temperature\": 0, \"seed\": 42}"
+
+compare "/v1/chat/completions forced tool_choice" "/v1/chat/completions" \
+    "{\"model\": \"$MODEL\", \"messages\": [$QUESTION], \"tools\": $TOOLS, \"tool_choice\": {\"type\": \"function\", \"function\": {\"name\": \"get_weather\"}}, \"temperature\": 0, \"seed\": 42}"
+
+# The model's answer once the tool result is sent back
+FOLLOW_UP="{\"model\": \"$MODEL\", \"messages\": [$QUESTION, {\"role\": \"assistant\", \"content\": \"\", \"tool_calls\": [{\"function\": {\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}}]}, {\"role\": \"tool\", \"content\": \"22 degrees, sunny\", \"tool_name\": \"get_weather\"}], \"tools\": $TOOLS, \"stream\": false, \"options\": $OPTIONS}"
+direct=$(curl -s -X POST "$OLLAMA_URL/api/chat" -H "Content-Type: application/json" -d "$FOLLOW_UP" | jq -r '.message.content // empty')
+proxied=$(curl -s -X POST "$BASE_URL/api/chat" -H "X-User-ID: $USER_ID" -H "Content-Type: application/json" -d "$FOLLOW_UP" | jq -r '.message.content // empty')
+if [ -z "$direct" ]; then
+    echo "⚠️  [SKIPPED] tool result: no answer from Ollama directly"
+elif [ "$direct" == "$proxied" ]; then
+    echo "✅ [PASS] tool result: ${direct:0:60}"
+    passed=$((passed + 1))
+else
+    echo "❌ [FAIL] tool result"
+    echo "   direct:  ${direct:0:120}"
+    echo "   proxied: ${proxied:0:120}"
+    failed=$((failed + 1))
+fi
+
+after=$(curl -s "$BASE_URL/api/me" -H "X-User-ID: $USER_ID" | jq '.tool_calls // 0')
+if [ "$passed" -gt 0 ] && [ "$after" -le "$before" ]; then
+    echo "❌ [FAIL] /api/me counted no tool calls for $USER_ID"
+    failed=$((failed + 1))
+else
+    echo "✅ [PASS] /api/me counts $((after - before)) tool call(s) for $USER_ID"
+fi
+
+echo "----------------------------------------"
+echo "Passed: $passed, failed: $failed"
+[ "$failed" -eq 0 ]